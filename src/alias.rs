@@ -0,0 +1,157 @@
+//! `cargo apfs-compress init-alias`: a cargo alias in the project's `.cargo/config.toml` that runs
+//! the tool with the settings this invocation resolved, to run after each build.
+
+use std::fs;
+use std::path::Path;
+
+use crate::config::{self, Settings};
+use crate::error::{Error, Result};
+use crate::{Cli, workspace_root};
+
+/// The name the alias gets unless `--name` says otherwise, as in `cargo compress`.
+pub const DEFAULT_ALIAS_NAME: &str = "compress";
+
+/// Prints the alias with `print`, or else adds it to the workspace's cargo config.
+pub(crate) fn run(cli: &Cli, name: &str, print: bool) -> Result<()> {
+    let env = |name: &str| std::env::var(name).ok();
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let files = config::config_files(&cwd, config::cargo_home(&env).as_deref())?;
+    let settings = config::resolve(cli.cli_settings(), &files, &env)?;
+    let args = alias_args(cli, &settings);
+    if print {
+        print!("{}", snippet(name, &args));
+        return Ok(());
+    }
+    let dir = workspace_root(&cwd)?.join(".cargo");
+    // Cargo still reads the extensionless name when there is no `config.toml`.
+    let path = match dir.join("config") {
+        legacy if legacy.is_file() && !dir.join("config.toml").exists() => legacy,
+        _ => dir.join("config.toml"),
+    };
+    add_to_config(&path, name, &args)?;
+    println!(
+        "Added `cargo {name}` to {}; run it after building, e.g. `cargo build && cargo {name}`",
+        path.display()
+    );
+    Ok(())
+}
+
+/// The arguments after `cargo` that the alias stands for: the subcommand, then each setting that
+/// didn't come from the defaults and each profile, target, and package picked on the command line.
+pub(crate) fn alias_args(cli: &Cli, settings: &Settings) -> Vec<String> {
+    let mut args = vec!["apfs-compress".to_owned()];
+    let kind = |kind: applesauce::compressor::Kind| kind.name().to_ascii_lowercase();
+    let mut setting = |flag: &str, value: String, source: &config::Source| {
+        if *source != config::Source::Default {
+            args.extend([flag.to_owned(), value]);
+        }
+    };
+    setting(
+        "--compression",
+        kind(settings.compression.value),
+        &settings.compression.source,
+    );
+    setting(
+        "--small-file-compression",
+        kind(settings.small_file_compression.value),
+        &settings.small_file_compression.source,
+    );
+    setting(
+        "--small-file-cutoff",
+        settings.small_file_cutoff.value.to_string(),
+        &settings.small_file_cutoff.source,
+    );
+    for (flag, values) in [
+        ("--profile", &cli.profiles),
+        ("--target", &cli.targets),
+        ("--package", &cli.packages),
+    ] {
+        for value in values {
+            args.extend([flag.to_owned(), value.clone()]);
+        }
+    }
+    args
+}
+
+/// The `[alias]` table defining `name` as `args`.
+pub(crate) fn snippet(name: &str, args: &[String]) -> String {
+    let args = toml::Value::Array(args.iter().cloned().map(toml::Value::String).collect());
+    format!("[alias]\n{name} = {args}\n")
+}
+
+/// Adds the alias to the cargo config at `path`, creating it if need be. An existing `[alias]`
+/// table gets the new line at its top, so the rest of the file, comments included, is untouched.
+pub(crate) fn add_to_config(path: &Path, name: &str, args: &[String]) -> Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(Error::read(path)(error)),
+    };
+    let parsed: toml::Table = toml::from_str(&existing).map_err(|source| Error::ConfigParse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let snippet = snippet(name, args);
+    let updated = match parsed.get("alias") {
+        Some(aliases) if aliases.get(name).is_some() => {
+            return Err(Error::AliasExists {
+                name: name.to_owned(),
+                path: path.to_path_buf(),
+            });
+        }
+        None => {
+            let separator = match existing.as_str() {
+                "" => "",
+                text if text.ends_with("\n\n") => "",
+                text if text.ends_with('\n') => "\n",
+                _ => "\n\n",
+            };
+            format!("{existing}{separator}{snippet}")
+        }
+        Some(_) => {
+            let header = existing
+                .match_indices("[alias]")
+                .find(|&(at, _)| at == 0 || existing[..at].ends_with('\n'))
+                .map(|(at, header)| at + header.len())
+                .and_then(|end| Some(end + existing[end..].find('\n')? + 1))
+                .ok_or_else(|| Error::AliasTableNotEditable {
+                    path: path.to_path_buf(),
+                })?;
+            let line = &snippet["[alias]\n".len()..];
+            format!("{}{line}{}", &existing[..header], &existing[header..])
+        }
+    };
+    toml::from_str::<toml::Table>(&updated).map_err(|_| Error::AliasTableNotEditable {
+        path: path.to_path_buf(),
+    })?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|source| Error::WriteConfig {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+    }
+    fs::write(path, updated).map_err(|source| Error::WriteConfig {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// An `--name` for the alias: a TOML bare key that isn't a cargo built-in.
+pub fn parse_alias_name(value: &str) -> Result<String, String> {
+    const BUILT_INS: &[&str] = &[
+        "b", "build", "c", "check", "d", "doc", "r", "run", "t", "test",
+    ];
+    if value.is_empty()
+        || !value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    {
+        return Err(format!(
+            "{value:?} is not a valid alias name; use letters, digits, `-`, and `_`"
+        ));
+    }
+    if BUILT_INS.contains(&value) {
+        return Err(format!("{value:?} would shadow cargo's own command"));
+    }
+    Ok(value.to_owned())
+}
@@ -0,0 +1,293 @@
+//! `cargo apfs-compress analyze`: read-only reports on what the work dirs hold, for deciding what
+//! to prune before compressing what is left.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::dedup::{self, Found};
+use crate::disk::{self, SizeFormat};
+use crate::summary::relative_to;
+
+/// How many groups each table lists before summing up the rest.
+pub(crate) const TOP: usize = 20;
+
+/// How far apart in size copies of an artifact can be and still count as similar: within a tenth
+/// of the largest.
+const SIMILAR_SLACK: f64 = 0.1;
+
+/// `analyze duplicates`: files that are identical across the work dirs, and artifacts that cargo
+/// built under the same name in several of them with about the same size, such as the same crate
+/// for debug and release or for two triples.
+pub(crate) fn duplicates(dirs: &[PathBuf], cwd: Option<&Path>, sizes: SizeFormat) -> String {
+    let files = dedup::large_files(dirs);
+    let groups = dedup::find_duplicates(&files);
+    let show = |path: &Path| relative_to(path, cwd).display().to_string();
+
+    let wasted = |paths: &[PathBuf]| -> u64 {
+        paths[1..]
+            .iter()
+            .map(|path| disk::private_size(path).unwrap_or(0))
+            .sum()
+    };
+    let mut identical: Vec<_> = groups
+        .iter()
+        .map(|group| (wasted(&group.paths), group))
+        .collect();
+    identical.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    let total_wasted: u64 = identical.iter().map(|(wasted, _)| wasted).sum();
+    let mut text = if identical.is_empty() {
+        format!(
+            "No identical files of {} or more in {} work dir(s).",
+            sizes.format(dedup::MIN_SIZE),
+            dirs.len()
+        )
+    } else {
+        format!(
+            "Identical files ({} group(s), {} wasted):",
+            identical.len(),
+            sizes.format(total_wasted)
+        )
+    };
+    for (wasted, group) in identical.iter().take(TOP) {
+        text.push_str(&format!(
+            "\n  {:016x}  {}  {} copies, {} wasted",
+            group.hash,
+            sizes.format(group.len),
+            group.paths.len(),
+            sizes.format(*wasted)
+        ));
+        for path in &group.paths {
+            text.push_str(&format!("\n    {}", show(path)));
+        }
+    }
+    if identical.len() > TOP {
+        text.push_str(&format!("\n  ... and {} more", identical.len() - TOP));
+    }
+
+    let identical_paths: HashSet<&Path> = groups
+        .iter()
+        .flat_map(|group| group.paths.iter().map(PathBuf::as_path))
+        .collect();
+    let similar = similar(dirs, &files, &identical_paths);
+    if !similar.is_empty() {
+        let prunable: u64 = similar.iter().map(|(_, _, prunable)| prunable).sum();
+        text.push_str(&format!(
+            "\nSimilar artifacts in several work dirs ({} name(s), about {} if all but the largest \
+             copy of each were pruned):",
+            similar.len(),
+            sizes.format(prunable)
+        ));
+        for (name, copies, prunable) in similar.iter().take(TOP) {
+            text.push_str(&format!("\n  {name}  {}", sizes.format(*prunable)));
+            for copy in copies {
+                text.push_str(&format!(
+                    "\n    {}  {}",
+                    show(&copy.path),
+                    sizes.format(copy.len)
+                ));
+            }
+        }
+        if similar.len() > TOP {
+            text.push_str(&format!("\n  ... and {} more", similar.len() - TOP));
+        }
+    }
+    text
+}
+
+/// Where in each work dir `analyze crates` looks: rustc's output for each crate in `deps/`, and
+/// each build script and its `OUT_DIR` in `build/<package>-<hash>/`.
+const CRATE_DIRS: [&str; 2] = ["deps", "build"];
+
+/// What one crate takes up across the work dirs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct CrateSize {
+    deps: u64,
+    build: u64,
+    files: u64,
+}
+
+impl CrateSize {
+    fn total(&self) -> u64 {
+        self.deps + self.build
+    }
+}
+
+/// `analyze crates`: the allocated size of `deps/` and `build/` in every work dir, attributed to
+/// the crates whose artifacts they hold, largest first.
+pub(crate) fn crates(dirs: &[PathBuf], sizes: SizeFormat) -> String {
+    let mut crates: HashMap<String, CrateSize> = HashMap::new();
+    let mut seen = HashSet::new();
+    for dir in dirs {
+        for (index, sub) in CRATE_DIRS.iter().enumerate() {
+            let root = dir.join(sub);
+            let Ok(entries) = fs::read_dir(&root) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let Some(owner) = crate_name(&name.to_string_lossy()) else {
+                    continue;
+                };
+                let Some((bytes, files)) = allocated(&entry.path(), &mut seen) else {
+                    continue;
+                };
+                let size = crates.entry(owner).or_default();
+                size.files += files;
+                if index == 0 {
+                    size.deps += bytes;
+                } else {
+                    size.build += bytes;
+                }
+            }
+        }
+    }
+    let mut ranked: Vec<_> = crates
+        .into_iter()
+        .filter(|(_, size)| size.files > 0)
+        .collect();
+    ranked.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then_with(|| a.0.cmp(&b.0)));
+    if ranked.is_empty() {
+        return format!("Nothing in deps/ or build/ in {} work dir(s).", dirs.len());
+    }
+    let total: u64 = ranked.iter().map(|(_, size)| size.total()).sum();
+    let width = ranked
+        .iter()
+        .take(TOP)
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut text = format!(
+        "Size by crate in deps/ and build/ of {} work dir(s) ({} crate(s), {}):",
+        dirs.len(),
+        ranked.len(),
+        sizes.format(total)
+    );
+    for (name, size) in ranked.iter().take(TOP) {
+        text.push_str(&format!(
+            "\n  {name:<width$}  {}  deps {}, build {}, {} file(s)",
+            sizes.format(size.total()),
+            sizes.format(size.deps),
+            sizes.format(size.build),
+            size.files
+        ));
+    }
+    if ranked.len() > TOP {
+        let rest: u64 = ranked[TOP..].iter().map(|(_, size)| size.total()).sum();
+        text.push_str(&format!(
+            "\n  ... and {} more, {}",
+            ranked.len() - TOP,
+            sizes.format(rest)
+        ));
+    }
+    text
+}
+
+/// The crate an entry directly inside `deps/` or `build/` belongs to, with `-` as `_` since rustc
+/// names files after the crate and cargo names build dirs after the package: `libfoo_sys-<hash>.rlib`
+/// and `build/foo-sys-<hash>/` are both `foo_sys`. `None` for names that aren't cargo's.
+pub(crate) fn crate_name(entry: &str) -> Option<String> {
+    let (stem, extension) = entry.split_once('.').unwrap_or((entry, ""));
+    let (name, hash) = stem.rsplit_once('-')?;
+    if hash.len() != 16 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    // Libraries get a `lib` prefix; binaries, objects, and `.d` files don't.
+    let name = match extension {
+        "rlib" | "rmeta" | "dylib" | "so" | "a" => name.strip_prefix("lib").unwrap_or(name),
+        _ => name,
+    };
+    (!name.is_empty()).then(|| name.replace('-', "_"))
+}
+
+/// The allocated size and count of the regular files at or under `path`, counting each file once
+/// however many hardlinks it has. `None` for anything else and for files already `seen`.
+fn allocated(path: &Path, seen: &mut HashSet<(u64, u64)>) -> Option<(u64, u64)> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    if metadata.is_dir() {
+        let mut total = (0, 0);
+        for entry in fs::read_dir(path).ok()?.flatten() {
+            if let Some((bytes, files)) = allocated(&entry.path(), seen) {
+                total.0 += bytes;
+                total.1 += files;
+            }
+        }
+        return Some(total);
+    }
+    if !metadata.is_file() || !seen.insert((metadata.dev(), metadata.ino())) {
+        return None;
+    }
+    Some((disk::allocated_size(path).unwrap_or(0), 1))
+}
+
+/// Artifacts with the same path in more than one work dir once cargo's `-<hash>` suffixes are
+/// left out, within [`SIMILAR_SLACK`] of each other in size, and not all identical. Each comes
+/// with its copies and what pruning all but the largest would free, largest first.
+fn similar<'a>(
+    dirs: &[PathBuf],
+    files: &'a [Found],
+    identical: &HashSet<&Path>,
+) -> Vec<(String, Vec<&'a Found>, u64)> {
+    let mut by_name: BTreeMap<String, Vec<&Found>> = BTreeMap::new();
+    for file in files {
+        let Ok(relative) = file.path.strip_prefix(&dirs[file.dir]) else {
+            continue;
+        };
+        by_name.entry(unhashed(relative)).or_default().push(file);
+    }
+    let mut similar: Vec<_> = by_name
+        .into_iter()
+        .filter_map(|(name, mut copies)| {
+            let work_dirs: HashSet<usize> = copies.iter().map(|copy| copy.dir).collect();
+            if work_dirs.len() < 2
+                || copies
+                    .iter()
+                    .all(|copy| identical.contains(copy.path.as_path()))
+            {
+                return None;
+            }
+            copies.sort_by(|a, b| b.len.cmp(&a.len).then_with(|| a.path.cmp(&b.path)));
+            let largest = copies[0].len;
+            if copies
+                .iter()
+                .any(|copy| (largest - copy.len) as f64 > largest as f64 * SIMILAR_SLACK)
+            {
+                return None;
+            }
+            let prunable: u64 = copies[1..].iter().map(|copy| copy.len).sum();
+            Some((name, copies, prunable))
+        })
+        .collect();
+    similar.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+    similar
+}
+
+/// `relative` with the `-<16 hex digits>` cargo adds to names in `deps/`, `build/`, and the like
+/// left out, so `deps/libfoo-0123456789abcdef.rlib` becomes `deps/libfoo.rlib`.
+pub(crate) fn unhashed(relative: &Path) -> String {
+    relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .map(|name| {
+            let (stem, extension) = name.split_once('.').unwrap_or((&*name, ""));
+            let stem = match stem.rsplit_once('-') {
+                Some((crate_name, hash))
+                    if hash.len() == 16 && hash.bytes().all(|byte| byte.is_ascii_hexdigit()) =>
+                {
+                    crate_name
+                }
+                _ => stem,
+            };
+            if extension.is_empty() {
+                stem.to_owned()
+            } else {
+                format!("{stem}.{extension}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,147 @@
+//! `--by-kind`: what sort of build output each compressed file is, judged from where cargo puts
+//! it and its extension, and how much each sort saved over a run.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+use std::sync::Mutex;
+
+use crate::disk::SizeFormat;
+use crate::stats::CompressionStats;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ArtifactKind {
+    /// Extensionless executables: final binaries, tests, and build scripts.
+    Binary,
+    /// `.rlib` and `.rmeta` files.
+    Rlib,
+    Dylib,
+    Object,
+    /// cargo's and rustc's `.d` dependency lists.
+    DepInfo,
+    /// Anything under `.fingerprint`.
+    Fingerprint,
+    /// Anything a build script wrote to its `build/<pkg>-<hash>/out` dir.
+    OutDir,
+    Other,
+}
+
+impl ArtifactKind {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Rlib => "rlib",
+            Self::Dylib => "dylib",
+            Self::Object => "object",
+            Self::DepInfo => "dep-info",
+            Self::Fingerprint => "fingerprint",
+            Self::OutDir => "OUT_DIR",
+            Self::Other => "other",
+        }
+    }
+
+    /// The kind of the file at `path`. Only extensionless files are looked at on disk, for their
+    /// executable bit.
+    pub(crate) fn classify(path: &Path) -> Self {
+        let names: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let parents = &names[..names.len().saturating_sub(1)];
+        if parents.iter().any(|name| *name == ".fingerprint") {
+            return Self::Fingerprint;
+        }
+        if parents
+            .windows(3)
+            .any(|window| window[0] == "build" && window[2] == "out")
+        {
+            return Self::OutDir;
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("rlib" | "rmeta") => Self::Rlib,
+            Some("dylib" | "so" | "dll") => Self::Dylib,
+            Some("o") => Self::Object,
+            Some("d") => Self::DepInfo,
+            Some(_) => Self::Other,
+            None if fs::symlink_metadata(path)
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0) =>
+            {
+                Self::Binary
+            }
+            None => Self::Other,
+        }
+    }
+}
+
+/// Sizes before and after of every compressed file over a run, by kind.
+#[derive(Default)]
+pub(crate) struct KindSummary {
+    stats: Mutex<BTreeMap<ArtifactKind, CompressionStats>>,
+}
+
+impl KindSummary {
+    pub(crate) fn record(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        *self
+            .stats
+            .lock()
+            .unwrap()
+            .entry(ArtifactKind::classify(path))
+            .or_default() += CompressionStats {
+            files_compressed: 1,
+            files_skipped: 0,
+            bytes_in,
+            bytes_out,
+        };
+    }
+
+    /// Each kind seen and its stats, most saved first and ties in the order of [`ArtifactKind`].
+    pub(crate) fn ranked(&self) -> Vec<(ArtifactKind, CompressionStats)> {
+        let mut rows: Vec<_> = self
+            .stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&kind, &stats)| (kind, stats))
+            .collect();
+        rows.sort_by(|a, b| {
+            b.1.bytes_saved()
+                .cmp(&a.1.bytes_saved())
+                .then(a.0.cmp(&b.0))
+        });
+        rows
+    }
+
+    /// An aligned table of [`Self::ranked`] under a heading.
+    pub(crate) fn render(&self, sizes: SizeFormat) -> String {
+        let rows: Vec<_> = self
+            .ranked()
+            .into_iter()
+            .map(|(kind, stats)| {
+                (
+                    kind.label(),
+                    format!("{} file(s)", stats.files_compressed),
+                    sizes.format(stats.bytes_saved()),
+                    format!(
+                        "({} -> {})",
+                        sizes.format(stats.bytes_in),
+                        sizes.format(stats.bytes_out)
+                    ),
+                )
+            })
+            .collect();
+        let label_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let files_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        let saved_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+        let mut table = String::from("Saved by artifact kind:");
+        for (label, files, saved, sizes) in rows {
+            table.push_str(&format!(
+                "\n  {label:<label_width$}  {files:>files_width$}  {saved:>saved_width$} {sizes}"
+            ));
+        }
+        table
+    }
+}
@@ -0,0 +1,97 @@
+//! `--audit-metadata`: the parts of a file's metadata that compressing it should leave alone,
+//! recorded before and compared after, for artifacts whose signature or notarization covers them.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::platform;
+
+/// xattrs that compressing a file is expected to add or rewrite.
+const COMPRESSION_XATTRS: [&str; 2] = ["com.apple.decmpfs", "com.apple.ResourceFork"];
+
+/// `UF_COMPRESSED`, which compressing a file is expected to set.
+#[cfg(target_os = "macos")]
+const UF_COMPRESSED: u32 = 0x20;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct MetadataSnapshot {
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    /// BSD file flags other than `UF_COMPRESSED`, on macOS.
+    pub(crate) flags: Option<u32>,
+    /// Every xattr other than [`COMPRESSION_XATTRS`], where they can be read.
+    pub(crate) xattrs: Option<BTreeMap<OsString, Vec<u8>>>,
+}
+
+impl MetadataSnapshot {
+    pub(crate) fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+        #[cfg(target_os = "macos")]
+        let flags = {
+            use std::os::macos::fs::MetadataExt;
+            Some(metadata.st_flags() & !UF_COMPRESSED)
+        };
+        #[cfg(not(target_os = "macos"))]
+        let flags = None;
+        let xattrs = match platform::xattr_names(path) {
+            Ok(names) => Some(
+                names
+                    .into_iter()
+                    .filter(|name| !COMPRESSION_XATTRS.iter().any(|skip| name == skip))
+                    .map(|name| {
+                        let value = platform::xattr(path, &name)?;
+                        Ok((name, value))
+                    })
+                    .collect::<io::Result<_>>()?,
+            ),
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => None,
+            Err(error) => return Err(error),
+        };
+        Ok(Self {
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            flags,
+            xattrs,
+        })
+    }
+
+    /// What differs in `after`, one short description each, or nothing if it's unchanged.
+    pub(crate) fn differences(&self, after: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.mode != after.mode {
+            differences.push(format!("mode {:o} -> {:o}", self.mode, after.mode));
+        }
+        if (self.uid, self.gid) != (after.uid, after.gid) {
+            differences.push(format!(
+                "owner {}:{} -> {}:{}",
+                self.uid, self.gid, after.uid, after.gid
+            ));
+        }
+        if let (Some(before), Some(now)) = (self.flags, after.flags)
+            && before != now
+        {
+            differences.push(format!("flags {before:#x} -> {now:#x}"));
+        }
+        if let (Some(before), Some(now)) = (&self.xattrs, &after.xattrs) {
+            for (name, value) in before {
+                match now.get(name) {
+                    None => differences.push(format!("xattr {} removed", name.display())),
+                    Some(now) if now != value => {
+                        differences.push(format!("xattr {} changed", name.display()))
+                    }
+                    Some(_) => {}
+                }
+            }
+            for name in now.keys().filter(|name| !before.contains_key(*name)) {
+                differences.push(format!("xattr {} added", name.display()));
+            }
+        }
+        differences
+    }
+}
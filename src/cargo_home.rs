@@ -0,0 +1,65 @@
+//! Caches cargo keeps under `$CARGO_HOME`, outside any project's target dir.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The lock cargo holds while it downloads into or extracts from its caches.
+pub(crate) const PACKAGE_CACHE_LOCK: &str = ".package-cache";
+
+/// A part of cargo's caches that `cache` can compress.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Component {
+    /// `registry/index`: sparse registries keep a cache there of one small JSON file per crate,
+    /// which adds up to a lot of files on a long-lived machine.
+    RegistryIndex,
+    /// `registry/src`: the extracted source of every crate downloaded.
+    RegistrySrc,
+    /// `git/checkouts`: working trees of git dependencies. The bare repos in `git/db` are left
+    /// alone, since their packfiles are compressed already.
+    GitCheckouts,
+}
+
+impl Component {
+    pub(crate) const ALL: [Self; 3] = [Self::RegistryIndex, Self::RegistrySrc, Self::GitCheckouts];
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::RegistryIndex => "registry index",
+            Self::RegistrySrc => "registry src",
+            Self::GitCheckouts => "git checkouts",
+        }
+    }
+
+    /// Where this component lives under `home`.
+    pub(crate) fn path(self, home: &Path) -> PathBuf {
+        match self {
+            Self::RegistryIndex => home.join("registry").join("index"),
+            Self::RegistrySrc => home.join("registry").join("src"),
+            Self::GitCheckouts => home.join("git").join("checkouts"),
+        }
+    }
+
+    /// One directory per registry or git repository, compressed as a unit.
+    pub(crate) fn dirs(self, home: &Path) -> io::Result<Vec<PathBuf>> {
+        subdirs(&self.path(home))
+    }
+}
+
+/// The directories inside `dir`, sorted; none if `dir` doesn't exist.
+pub(crate) fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
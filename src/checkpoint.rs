@@ -0,0 +1,132 @@
+use applesauce::progress::{SkipReason, Task};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::error::{Error, Result};
+use crate::reporter::Reporter;
+
+/// Lists the files already handled in an interrupted run, relative to the work dir and separated
+/// by NUL bytes. Removed once the work dir finishes.
+pub(crate) const CHECKPOINT_NAME: &str = ".apfs-compress-checkpoint";
+
+/// Reads the files recorded by a previous, interrupted run over `dir`.
+pub(crate) fn load(dir: &Path) -> Result<HashSet<PathBuf>> {
+    let path = dir.join(CHECKPOINT_NAME);
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(error) => return Err(Error::read(path)(error)),
+    };
+    Ok(content
+        .split(|&byte| byte == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| dir.join(OsStr::from_bytes(entry)))
+        .collect())
+}
+
+/// Wraps a [`Reporter`] and records every file the compressor finishes with in the work dir's
+/// checkpoint.
+pub(crate) struct Checkpoint<'a> {
+    inner: &'a dyn Reporter,
+    dir: &'a Path,
+    /// `None` unless the run was started with `--resume`.
+    file: Option<Mutex<File>>,
+    had_errors: AtomicBool,
+}
+
+impl<'a> Checkpoint<'a> {
+    /// Starts a checkpoint for `dir`, after the entries an earlier run left. Only a run that may be
+    /// resumed writes one, so unless `resume` is set nothing is written to `dir`, and this only
+    /// notes whether there were errors.
+    pub(crate) fn create(dir: &'a Path, resume: bool, inner: &'a dyn Reporter) -> Result<Self> {
+        let path = dir.join(CHECKPOINT_NAME);
+        let file = resume
+            .then(|| OpenOptions::new().create(true).append(true).open(&path))
+            .transpose()
+            .map_err(|source| Error::Checkpoint { path, source })?;
+        Ok(Self {
+            inner,
+            dir,
+            file: file.map(Mutex::new),
+            had_errors: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether any file in the work dir was reported as an error.
+    pub(crate) fn had_errors(&self) -> bool {
+        self.had_errors.load(Ordering::Relaxed)
+    }
+
+    /// Deletes the checkpoint after the work dir completed, including one an interrupted run left
+    /// that this run didn't resume from.
+    pub(crate) fn finish(self) -> Result<()> {
+        let path = self.dir.join(CHECKPOINT_NAME);
+        drop(self.file);
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Checkpoint {
+                path,
+                source: error,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn record(&self, path: &Path) {
+        let (Some(file), Ok(relative)) = (&self.file, path.strip_prefix(self.dir)) else {
+            return;
+        };
+        let mut entry = relative.as_os_str().as_bytes().to_vec();
+        entry.push(0);
+        // Best effort: a lost entry only means the file is checked again on resume.
+        let _ = file.lock().unwrap().write_all(&entry);
+    }
+}
+
+impl Reporter for Checkpoint<'_> {
+    fn verbosity(&self) -> Verbosity {
+        self.inner.verbosity()
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.inner.size_format()
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        self.inner.message(verbosity, message);
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.had_errors.store(true, Ordering::Relaxed);
+        self.inner.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        self.record(path);
+        self.inner.file_skipped(path, why);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        self.inner.file_task(path, size)
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        self.record(path);
+        self.inner.file_compressed(path, bytes_in, bytes_out);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
@@ -0,0 +1,397 @@
+//! Layered settings: command line, then environment, then project config, then user config.
+//!
+//! Project config is the `[apfs-compress]` table of the cargo config files from the filesystem
+//! root down to the project, where nearer files win, and below those the nearest
+//! `cargo-apfs-compress.toml` and then the `[package.metadata.apfs-compress]` and
+//! `[workspace.metadata.apfs-compress]` tables of the project's manifests. User config is the
+//! `[apfs-compress]` table in `$CARGO_HOME/config.toml`.
+
+use applesauce::compressor::Kind;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::policy::{self, DirPolicy};
+use crate::{CompressionArg, DEFAULT_SMALL_FILE_CUTOFF, load_cargo_configs, parse_byte_size};
+
+pub(crate) const COMPRESSION_ENV: &str = "CARGO_APFS_COMPRESS_COMPRESSION";
+pub(crate) const SMALL_FILE_COMPRESSION_ENV: &str = "CARGO_APFS_COMPRESS_SMALL_FILE_COMPRESSION";
+pub(crate) const SMALL_FILE_CUTOFF_ENV: &str = "CARGO_APFS_COMPRESS_SMALL_FILE_CUTOFF";
+pub(crate) const CHECK_DIRS_ENV: &str = "CARGO_APFS_COMPRESS_CHECK_DIRS";
+pub(crate) const SKIP_EXTENSIONS_ENV: &str = "CARGO_APFS_COMPRESS_SKIP_EXTENSIONS";
+
+/// Where a setting's value came from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Source {
+    Cli,
+    Env(&'static str),
+    File(PathBuf),
+    /// Worked out by the tool or cargo rather than set anywhere, such as the target directory.
+    Derived(&'static str),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cli => f.write_str("command line"),
+            Self::Env(name) => write!(f, "env {name}"),
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Derived(how) => f.write_str(how),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Layered<T> {
+    pub(crate) value: T,
+    pub(crate) source: Source,
+}
+
+/// Settings that apply to every work dir unless a more specific setting overrides them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) compression: Layered<Kind>,
+    pub(crate) small_file_compression: Layered<Kind>,
+    pub(crate) small_file_cutoff: Layered<u64>,
+    /// Extra dirs to search for nested target layouts, on top of any `--check-dir`.
+    pub(crate) check_dirs: Layered<Vec<String>>,
+    /// Extensions to leave alone on top of the defaults.
+    pub(crate) skip_extensions: Layered<Vec<String>>,
+    pub(crate) dir_policies: Layered<Vec<DirPolicy>>,
+}
+
+/// Values given on the command line, which take precedence over everything else.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CliSettings {
+    pub(crate) compression: Option<CompressionArg>,
+    pub(crate) small_file_compression: Option<CompressionArg>,
+    pub(crate) small_file_cutoff: Option<u64>,
+}
+
+/// `$CARGO_HOME`, defaulting to `~/.cargo` like cargo does.
+pub(crate) fn cargo_home(env: &dyn Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    match env("CARGO_HOME") {
+        Some(home) if !home.is_empty() => Some(PathBuf::from(home)),
+        _ => std::env::home_dir().map(|home| home.join(".cargo")),
+    }
+}
+
+/// The tool's own config file, which `init` writes at the workspace root. It holds what an
+/// `[apfs-compress]` table would, at its top level.
+pub(crate) const PROJECT_CONFIG_NAME: &str = "cargo-apfs-compress.toml";
+
+/// Every config file that applies to `cwd`, lowest precedence first: the user's, the manifests'
+/// metadata tables, the project's [`PROJECT_CONFIG_NAME`], then the project's cargo config. The
+/// user's stays lowest even if `cwd` is inside the home directory.
+pub(crate) fn config_files(
+    cwd: &Path,
+    cargo_home: Option<&Path>,
+) -> Result<Vec<(PathBuf, toml::Value)>> {
+    let mut project = load_cargo_configs(cwd)?;
+    let mut files = cargo_home_configs(cargo_home, &mut project)?;
+    files.extend(manifest_metadata(cwd)?);
+    files.extend(project_config(cwd)?);
+    files.extend(project);
+    Ok(files)
+}
+
+/// The [`PROJECT_CONFIG_NAME`] nearest `cwd`, rewrapped as an `[apfs-compress]` table so it layers
+/// like a cargo config file.
+pub(crate) fn project_config(cwd: &Path) -> Result<Option<(PathBuf, toml::Value)>> {
+    let Some(path) = cwd
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    let content = fs::read_to_string(&path).map_err(Error::read(&path))?;
+    let table: toml::Value = toml::from_str(&content).map_err(|source| Error::ConfigParse {
+        path: path.clone(),
+        source,
+    })?;
+    let mut wrapped = toml::Table::new();
+    wrapped.insert("apfs-compress".to_owned(), table);
+    Ok(Some((path, toml::Value::Table(wrapped))))
+}
+
+/// `$CARGO_HOME/config` and `$CARGO_HOME/config.toml`, taken out of `project` if they are among
+/// its files because the project is inside the home directory: they are the user's config, below
+/// everything the project sets, wherever the walk up from the project found them.
+pub(crate) fn cargo_home_configs(
+    cargo_home: Option<&Path>,
+    project: &mut Vec<(PathBuf, toml::Value)>,
+) -> Result<Vec<(PathBuf, toml::Value)>> {
+    let mut files = Vec::new();
+    let Some(home) = cargo_home else {
+        return Ok(files);
+    };
+    for candidate in [home.join("config"), home.join("config.toml")] {
+        if let Some(index) = project.iter().position(|(path, _)| *path == candidate) {
+            files.push(project.remove(index));
+            continue;
+        }
+        if !candidate.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(&candidate).map_err(Error::read(&candidate))?;
+        let value = toml::from_str(&content).map_err(|source| Error::ConfigParse {
+            path: candidate.clone(),
+            source,
+        })?;
+        files.push((candidate, value));
+    }
+    Ok(files)
+}
+
+/// The `apfs-compress` metadata of the workspace root manifest, then of the package manifest
+/// nearest `cwd`, each rewrapped as an `[apfs-compress]` table so they layer like config files.
+fn manifest_metadata(cwd: &Path) -> Result<Vec<(PathBuf, toml::Value)>> {
+    let mut package = None;
+    let mut workspace = None;
+    for dir in cwd.ancestors() {
+        let manifest = dir.join("Cargo.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+        let content = fs::read_to_string(&manifest).map_err(Error::read(&manifest))?;
+        let value: toml::Value = toml::from_str(&content).map_err(|source| Error::ConfigParse {
+            path: manifest.clone(),
+            source,
+        })?;
+        let metadata = |section: &str| {
+            value
+                .get(section)
+                .and_then(|table| table.get("metadata"))
+                .and_then(|metadata| metadata.get("apfs-compress"))
+                .cloned()
+        };
+        if package.is_none() && value.get("package").is_some() {
+            package = Some((manifest.clone(), metadata("package")));
+        }
+        if value.get("workspace").is_some() {
+            workspace = Some((manifest, metadata("workspace")));
+            break;
+        }
+    }
+
+    Ok([workspace, package]
+        .into_iter()
+        .flatten()
+        .filter_map(|(path, table)| {
+            let mut wrapped = toml::Table::new();
+            wrapped.insert("apfs-compress".to_owned(), table?);
+            Some((path, toml::Value::Table(wrapped)))
+        })
+        .collect())
+}
+
+pub(crate) fn resolve(
+    cli: CliSettings,
+    files: &[(PathBuf, toml::Value)],
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Settings> {
+    let kind = |name: &str| CompressionArg::from_name(name).map(CompressionArg::to_kind);
+    let size = |value: &str| parse_byte_size(value).ok();
+    let list = |value: &str| {
+        Some(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>(),
+        )
+    };
+    Ok(Settings {
+        compression: layer(
+            cli.compression.map(CompressionArg::to_kind),
+            COMPRESSION_ENV,
+            "compression",
+            files,
+            env,
+            kind,
+            Kind::Lzfse,
+        )?,
+        small_file_compression: layer(
+            cli.small_file_compression.map(CompressionArg::to_kind),
+            SMALL_FILE_COMPRESSION_ENV,
+            "small-file-compression",
+            files,
+            env,
+            kind,
+            Kind::Lzvn,
+        )?,
+        small_file_cutoff: layer(
+            cli.small_file_cutoff,
+            SMALL_FILE_CUTOFF_ENV,
+            "small-file-cutoff",
+            files,
+            env,
+            size,
+            DEFAULT_SMALL_FILE_CUTOFF,
+        )?,
+        check_dirs: layer(
+            None,
+            CHECK_DIRS_ENV,
+            "check-dirs",
+            files,
+            env,
+            list,
+            Vec::new(),
+        )?,
+        skip_extensions: layer(
+            None,
+            SKIP_EXTENSIONS_ENV,
+            "skip-extensions",
+            files,
+            env,
+            list,
+            Vec::new(),
+        )?,
+        dir_policies: dir_policies(files)?,
+    })
+}
+
+/// The `apfs-compress.dir-policies` table of the last config file that has one. Tables from
+/// different files are not merged, so a project's policies replace the user's outright.
+fn dir_policies(files: &[(PathBuf, toml::Value)]) -> Result<Layered<Vec<DirPolicy>>> {
+    for (path, config) in files.iter().rev() {
+        let Some(configured) = config
+            .get("apfs-compress")
+            .and_then(|table| table.get("dir-policies"))
+        else {
+            continue;
+        };
+        let invalid = |key: String, value: &toml::Value| Error::InvalidConfig {
+            path: path.clone(),
+            key,
+            value: value.to_string(),
+        };
+        let table = configured
+            .as_table()
+            .ok_or_else(|| invalid("apfs-compress.dir-policies".to_owned(), configured))?;
+        let policies = table
+            .iter()
+            .map(|(pattern, value)| {
+                policy::parse_action(value)
+                    .and_then(|action| DirPolicy::new(pattern, action))
+                    .ok_or_else(|| {
+                        invalid(format!("apfs-compress.dir-policies.{pattern:?}"), value)
+                    })
+            })
+            .collect::<Result<_>>()?;
+        return Ok(Layered {
+            value: policies,
+            source: Source::File(path.clone()),
+        });
+    }
+    Ok(Layered {
+        value: Vec::new(),
+        source: Source::Default,
+    })
+}
+
+/// Picks the first of the command line, `env_name`, and `apfs-compress.<key>` in the last config
+/// file that sets it, falling back to `default`. Config values may be strings, integers, or arrays
+/// of strings, which are parsed the way a comma-separated env value would be.
+fn layer<T>(
+    cli: Option<T>,
+    env_name: &'static str,
+    key: &str,
+    files: &[(PathBuf, toml::Value)],
+    env: &dyn Fn(&str) -> Option<String>,
+    parse: impl Fn(&str) -> Option<T>,
+    default: T,
+) -> Result<Layered<T>> {
+    if let Some(value) = cli {
+        return Ok(Layered {
+            value,
+            source: Source::Cli,
+        });
+    }
+    if let Some(text) = env(env_name).filter(|text| !text.is_empty()) {
+        let value = parse(&text).ok_or_else(|| Error::InvalidEnv {
+            name: env_name.to_owned(),
+            value: text.clone(),
+        })?;
+        return Ok(Layered {
+            value,
+            source: Source::Env(env_name),
+        });
+    }
+    for (path, config) in files.iter().rev() {
+        let Some(configured) = config.get("apfs-compress").and_then(|table| table.get(key)) else {
+            continue;
+        };
+        let parsed = match configured {
+            toml::Value::String(text) => parse(text),
+            toml::Value::Integer(number) => parse(&number.to_string()),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(toml::Value::as_str)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|items| parse(&items.join(","))),
+            _ => None,
+        };
+        let value = parsed.ok_or_else(|| Error::InvalidConfig {
+            path: path.clone(),
+            key: format!("apfs-compress.{key}"),
+            value: configured.to_string(),
+        })?;
+        return Ok(Layered {
+            value,
+            source: Source::File(path.clone()),
+        });
+    }
+    Ok(Layered {
+        value: default,
+        source: Source::Default,
+    })
+}
+
+/// Per-profile `dir-name` and `apfs-compress` settings from `files`, with the file each came from,
+/// keyed the way they are written in config (such as `profile.dev.dir-name`).
+pub(crate) fn profile_settings(
+    files: &[(PathBuf, toml::Value)],
+) -> BTreeMap<String, Layered<String>> {
+    let mut settings = BTreeMap::new();
+    for (path, config) in files {
+        let tables = [
+            ("profile", config.get("profile"), "dir-name"),
+            (
+                "apfs-compress.profile",
+                config
+                    .get("apfs-compress")
+                    .and_then(|table| table.get("profile")),
+                "compression",
+            ),
+        ];
+        for (prefix, table, key) in tables {
+            let Some(table) = table.and_then(toml::Value::as_table) else {
+                continue;
+            };
+            for (profile, values) in table {
+                if let Some(value) = values.get(key).and_then(toml::Value::as_str) {
+                    settings.insert(
+                        format!("{prefix}.{profile}.{key}"),
+                        Layered {
+                            value: value.to_owned(),
+                            source: Source::File(path.clone()),
+                        },
+                    );
+                }
+            }
+        }
+    }
+    settings
+}
+
+/// The `name = value  # source` line `config show` prints for one setting.
+pub(crate) fn show_line(name: &str, value: &str, source: &Source) -> String {
+    format!("{name} = {value:?}  # {source}")
+}
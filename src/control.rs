@@ -0,0 +1,148 @@
+//! `--control-socket`: a Unix socket a run listens on while it compresses, so `cargo apfs-compress
+//! ctl` can ask how far it has got, pause, resume, or stop it instead of killing it.
+//!
+//! Each connection sends one command on a line and reads one line back. Replies to commands that
+//! can't be carried out start with `error: `.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::disk::SizeFormat;
+use crate::error::{Error, Result};
+use crate::heartbeat::Heartbeat;
+use crate::{PauseSignal, StopSignal};
+
+/// How often the listener looks for a connection while idle.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a client gets to send its command before it is hung up on.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the socket reports on and acts on.
+pub(crate) struct Control<'a> {
+    pub(crate) heartbeat: &'a Heartbeat,
+    pub(crate) stop: &'a StopSignal,
+    pub(crate) pause: &'a PauseSignal,
+    pub(crate) sizes: SizeFormat,
+}
+
+/// Listens at `path`, replacing a socket file no run is listening on any more. Anything else at
+/// `path` is left alone and refused, so a mistyped `--control-socket` can't delete a file.
+pub(crate) fn bind(path: &Path) -> Result<UnixListener> {
+    let failed = |source| Error::ControlSocket {
+        path: path.to_path_buf(),
+        source,
+    };
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(Error::ControlSocketNotASocket {
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(_) => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(Error::ControlSocketInUse {
+                    path: path.to_path_buf(),
+                });
+            }
+            std::fs::remove_file(path).map_err(failed)?;
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(failed(error)),
+    }
+    let listener = UnixListener::bind(path).map_err(failed)?;
+    listener.set_nonblocking(true).map_err(failed)?;
+    Ok(listener)
+}
+
+/// Answers connections to `listener` until `stop` is signalled or disconnected.
+pub(crate) fn serve(listener: &UnixListener, control: &Control<'_>, stop: &Receiver<()>) {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // A client that goes away early only loses its own reply.
+                let _ = answer(stream, control);
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                if let Err(RecvTimeoutError::Disconnected) | Ok(()) =
+                    stop.recv_timeout(ACCEPT_POLL_INTERVAL)
+                {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+fn answer(stream: UnixStream, control: &Control<'_>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+    let mut reply = self::reply(control, command.trim());
+    reply.push('\n');
+    (&stream).write_all(reply.as_bytes())
+}
+
+/// The reply to `command`, after carrying it out.
+pub(crate) fn reply(control: &Control<'_>, command: &str) -> String {
+    let state = || {
+        if control.stop.should_stop() {
+            "stopping"
+        } else if control.pause.is_paused() {
+            "paused"
+        } else {
+            "running"
+        }
+    };
+    match command {
+        "status" => format!("{}: {}", state(), control.heartbeat.line(control.sizes)),
+        "pause" => {
+            control.pause.pause();
+            "paused".to_owned()
+        }
+        "resume" => {
+            control.pause.resume();
+            "resumed".to_owned()
+        }
+        "stop" => {
+            control.stop.stop();
+            "stopping".to_owned()
+        }
+        // There is no schedule to jump ahead of, but a paused run can carry on now.
+        "trigger-now" if control.pause.is_paused() => {
+            control.pause.resume();
+            "resumed".to_owned()
+        }
+        "trigger-now" => format!("already {}", state()),
+        _ => format!("error: unknown command `{command}`"),
+    }
+}
+
+/// Sends `command` to the run listening at `path` and returns its reply.
+pub(crate) fn send(path: &Path, command: &str) -> Result<String> {
+    let failed = |source| Error::ControlSocket {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut stream = UnixStream::connect(path).map_err(failed)?;
+    stream
+        .write_all(format!("{command}\n").as_bytes())
+        .map_err(failed)?;
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(failed)?;
+    let reply = reply.trim_end().to_owned();
+    match reply.strip_prefix("error: ") {
+        Some(message) => Err(Error::ControlRefused {
+            message: message.to_owned(),
+        }),
+        None => Ok(reply),
+    }
+}
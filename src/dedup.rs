@@ -0,0 +1,220 @@
+//! `--dedup`: once the work dirs are compressed, replacing files that are byte-for-byte identical
+//! to another, such as a final artifact whose hardlink to `deps/` was broken or an output two
+//! profile dirs built alike, with APFS clones of it.
+//!
+//! This runs after compressing rather than before: a clone of a compressed file is compressed
+//! too, while compressing clones would rewrite each of them into blocks of its own.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::reporter::Reporter;
+use crate::{FileTimes, disk, platform};
+
+/// Files smaller than this aren't worth reading twice for the few blocks a clone would save.
+pub(crate) const MIN_SIZE: u64 = 64 * 1024;
+
+/// What a dedup pass freed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Outcome {
+    pub(crate) files: u64,
+    pub(crate) bytes: u64,
+}
+
+/// A regular file of at least [`MIN_SIZE`] under one of the dirs [`large_files`] was given.
+#[derive(Clone, Debug)]
+pub(crate) struct Found {
+    pub(crate) path: PathBuf,
+    /// Which of the dirs it is under.
+    pub(crate) dir: usize,
+    device: u64,
+    inode: u64,
+    /// How many hardlinks it has.
+    links: u64,
+    pub(crate) len: u64,
+}
+
+/// Files that are byte-for-byte identical, the one to keep first: the one with the most
+/// hardlinks, since replacing a name of a hardlinked file frees nothing, then the first by path.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Duplicates {
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) len: u64,
+    pub(crate) hash: u64,
+}
+
+/// Every regular file of at least [`MIN_SIZE`] under `dirs`, not crossing into other volumes.
+pub(crate) fn large_files(dirs: &[PathBuf]) -> Vec<Found> {
+    let mut found = Vec::new();
+    for (index, dir) in dirs.iter().enumerate() {
+        let Ok(device) = fs::symlink_metadata(dir).map(|metadata| metadata.dev()) else {
+            continue;
+        };
+        let mut pending = vec![dir.clone()];
+        while let Some(current) = pending.pop() {
+            let Ok(entries) = fs::read_dir(&current) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.dev() != device {
+                    continue;
+                }
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else if metadata.is_file() && metadata.len() >= MIN_SIZE {
+                    found.push(Found {
+                        path: entry.path(),
+                        dir: index,
+                        device,
+                        inode: metadata.ino(),
+                        links: metadata.nlink(),
+                        len: metadata.len(),
+                    });
+                }
+            }
+        }
+    }
+    found
+}
+
+/// The groups of identical files among `files`. Only files on the same volume are grouped, and
+/// hardlinks to one file count once.
+pub(crate) fn find_duplicates(files: &[Found]) -> Vec<Duplicates> {
+    let mut by_size: HashMap<(u64, u64), Vec<&Found>> = HashMap::new();
+    for file in files {
+        by_size
+            .entry((file.device, file.len))
+            .or_default()
+            .push(file);
+    }
+
+    let mut groups = Vec::new();
+    for ((_, len), mut files) in by_size {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut seen = Vec::new();
+        files.retain(|file| {
+            let first = !seen.contains(&file.inode);
+            seen.push(file.inode);
+            first
+        });
+        if files.len() < 2 {
+            continue;
+        }
+        let mut by_hash: HashMap<u64, Vec<&Found>> = HashMap::new();
+        for file in files {
+            if let Ok(hash) = hash_contents(&file.path) {
+                by_hash.entry(hash).or_default().push(file);
+            }
+        }
+        for (hash, mut files) in by_hash {
+            files.sort_by(|a, b| (Reverse(a.links), &a.path).cmp(&(Reverse(b.links), &b.path)));
+            let mut paths: Vec<PathBuf> = files.into_iter().map(|file| file.path.clone()).collect();
+            let keep = paths[0].clone();
+            paths.retain(|path| *path == keep || same_contents(&keep, path).unwrap_or(false));
+            if paths.len() > 1 {
+                groups.push(Duplicates { paths, len, hash });
+            }
+        }
+    }
+    groups.sort();
+    groups
+}
+
+/// Replaces every file in each of `groups` but the first with a clone of the first, keeping its
+/// permissions and times, and returns what that freed. A file with other hardlinks is left alone:
+/// its blocks would stay behind for the other names.
+pub(crate) fn dedup(groups: &[Duplicates], progress: &dyn Reporter) -> Outcome {
+    let mut outcome = Outcome::default();
+    for group in groups {
+        let (keep, duplicates) = group
+            .paths
+            .split_first()
+            .expect("groups have two or more files");
+        for duplicate in duplicates {
+            if fs::symlink_metadata(duplicate).map_or(true, |metadata| metadata.nlink() > 1) {
+                continue;
+            }
+            // Nothing left to free if it already shares all its blocks.
+            let private = disk::private_size(duplicate).unwrap_or(0);
+            if private == 0 {
+                continue;
+            }
+            match replace_with_clone(keep, duplicate) {
+                Ok(()) => {
+                    // What the clone still holds of its own wasn't freed.
+                    let left = disk::private_size(duplicate).unwrap_or(0);
+                    outcome.files += 1;
+                    outcome.bytes += private.saturating_sub(left);
+                    progress.println_verbose(|| {
+                        format!("{}: Cloned from {}", duplicate.display(), keep.display())
+                    });
+                }
+                Err(error) if error.kind() == io::ErrorKind::Unsupported => {
+                    progress.println_normal(|| format!("note: --dedup skipped: {error}"));
+                    return outcome;
+                }
+                Err(error) => progress.error(duplicate, &format!("failed to clone: {error}")),
+            }
+        }
+    }
+    outcome
+}
+
+/// Clones `keep` next to `duplicate` and renames the clone over it, so `duplicate` is never
+/// missing or half-written. A clone left behind by a run that was killed midway is replaced.
+fn replace_with_clone(keep: &Path, duplicate: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(duplicate)?;
+    let times = FileTimes::of(duplicate)?;
+    let mut name = duplicate.file_name().unwrap_or_default().to_owned();
+    name.push(".apfs-compress-clone");
+    let clone = duplicate.with_file_name(name);
+    match fs::remove_file(&clone) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    platform::clone_file(keep, &clone)?;
+    let finished = fs::set_permissions(&clone, metadata.permissions())
+        .and_then(|()| times.apply(&clone))
+        .and_then(|()| fs::rename(&clone, duplicate));
+    if finished.is_err() {
+        let _ = fs::remove_file(&clone);
+    }
+    finished
+}
+
+fn hash_contents(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = std::hash::DefaultHasher::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buffer[..read]);
+    }
+}
+
+/// Whether `a` and `b` hold the same bytes, so a hash collision can't clone the wrong file.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut left, mut right) = (vec![0; 1 << 16], vec![0; 1 << 16]);
+    loop {
+        let read = a.read(&mut left)?;
+        if read == 0 {
+            return Ok(b.read(&mut right[..1])? == 0);
+        }
+        b.read_exact(&mut right[..read])?;
+        if left[..read] != right[..read] {
+            return Ok(false);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! `--skip-unchanged-dirs`: each directory's modification time and subdirectories as of the last
+//! run that compressed the whole work dir cleanly, so later walks can skip listing directories
+//! nothing was added to, removed from, or renamed in since. `--debounce` keeps when the last run
+//! on the work dir finished in the same file.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+
+/// JSON written in the work dir after a clean run, and read by the next walk.
+pub(crate) const STATE_NAME: &str = ".apfs-compress-state";
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DirState {
+    /// Keyed by path relative to the work dir, with `""` for the work dir itself.
+    dirs: BTreeMap<String, Listing>,
+    /// When the last run on the work dir that wasn't cut short finished, in seconds since the
+    /// epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finished_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Listing {
+    pub(crate) mtime_ns: u64,
+    pub(crate) subdirs: Vec<String>,
+}
+
+impl DirState {
+    /// The state saved in `dir`, or an empty one if there is none or it doesn't parse.
+    pub(crate) fn load(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(STATE_NAME))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// The listing recorded for `relative` if the directory hasn't been modified since.
+    pub(crate) fn unchanged(&self, relative: &str, mtime_ns: u64) -> Option<&Listing> {
+        self.dirs
+            .get(relative)
+            .filter(|listing| listing.mtime_ns == mtime_ns)
+    }
+
+    pub(crate) fn insert(&mut self, relative: String, listing: Listing) {
+        self.dirs.insert(relative, listing);
+    }
+
+    /// Re-reads every directory's modification time, since compressing a file replaces it and so
+    /// touches the directory it is in, then writes the state into `dir`.
+    pub(crate) fn save(mut self, dir: &Path) -> Result<()> {
+        self.dirs
+            .retain(|relative, listing| match mtime_ns(&dir.join(relative)) {
+                Ok(mtime) => {
+                    listing.mtime_ns = mtime;
+                    true
+                }
+                Err(_) => false,
+            });
+        self.finished_secs = Self::load(dir).finished_secs;
+        self.write(dir)
+    }
+
+    fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(STATE_NAME);
+        let json = serde_json::to_string(self).map_err(io::Error::other);
+        json.and_then(|json| fs::write(&path, json))
+            .map_err(|source| Error::DirState { path, source })
+    }
+}
+
+/// When the last run on `dir` finished, if one was recorded.
+pub(crate) fn last_finished(dir: &Path) -> Option<SystemTime> {
+    let secs = DirState::load(dir).finished_secs?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Records that a run on `dir` finished `at`, keeping the listings as they were saved.
+pub(crate) fn record_finished(dir: &Path, at: SystemTime) -> Result<()> {
+    let mut state = DirState::load(dir);
+    state.finished_secs = Some(at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs());
+    state.write(dir)
+}
+
+/// `path`'s modification time in nanoseconds since the epoch.
+pub(crate) fn mtime_ns(path: &Path) -> io::Result<u64> {
+    let modified = fs::symlink_metadata(path)?.modified()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// `path` relative to `dir` as the state keys it, or `None` if it isn't UTF-8 and so can't be
+/// recorded.
+pub(crate) fn relative_key(dir: &Path, path: &Path) -> Option<String> {
+    path.strip_prefix(dir)
+        .ok()
+        .and_then(Path::to_str)
+        .map(ToOwned::to_owned)
+}
@@ -0,0 +1,369 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::walk::WALK_THREADS;
+
+const UNITS: &[(&str, u64)] = &[
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("KB", 1_000),
+    ("T", 1 << 40),
+    ("G", 1 << 30),
+    ("M", 1 << 20),
+    ("K", 1 << 10),
+    ("B", 1),
+];
+
+/// Parses a byte size such as `50GiB`, `500MB`, `2G`, or `1024`.
+///
+/// Single-letter suffixes are binary, matching `du -h` and `df -h`.
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = UNITS
+        .iter()
+        .find_map(|&(suffix, multiplier)| {
+            let number = value.len().checked_sub(suffix.len()).and_then(|split| {
+                value[split..]
+                    .eq_ignore_ascii_case(suffix)
+                    .then(|| &value[..split])
+            })?;
+            Some((number.trim_end(), multiplier))
+        })
+        .unwrap_or((value, 1));
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{value}`"))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("invalid size `{value}`"));
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// A minimum amount of space a run must free, as an absolute size or a share of what it looked at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinSavings {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl MinSavings {
+    /// Parses `10GiB`-style sizes (see [`parse_byte_size`]) or percentages such as `25%`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid percentage `{value}`"))?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(format!("invalid percentage `{value}`"));
+                }
+                Ok(Self::Percent(percent))
+            }
+            None => parse_byte_size(value).map(Self::Bytes),
+        }
+    }
+
+    pub fn is_met(self, bytes_in: u64, bytes_saved: u64) -> bool {
+        match self {
+            Self::Bytes(bytes) => bytes_saved >= bytes,
+            Self::Percent(percent) => {
+                bytes_in > 0 && bytes_saved as f64 * 100.0 / bytes_in as f64 >= percent
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MinSavings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bytes(bytes) => f.write_str(&format_bytes(*bytes)),
+            Self::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+/// A `--volume-jobs` value: the most work dirs to compress at once on the volume holding `path`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VolumeJobs {
+    pub path: PathBuf,
+    pub jobs: usize,
+}
+
+impl VolumeJobs {
+    /// Parses `<PATH>=<JOBS>`, such as `/Volumes/Archive=1`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (path, jobs) = value
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected PATH=JOBS, got `{value}`"))?;
+        let jobs = jobs
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&jobs| jobs > 0)
+            .ok_or_else(|| format!("invalid job count `{jobs}`; expected 1 or more"))?;
+        if path.is_empty() {
+            return Err(format!("expected PATH=JOBS, got `{value}`"));
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            jobs,
+        })
+    }
+}
+
+/// How byte counts are shown in log lines and summaries.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SizeFormat {
+    /// A binary unit with one decimal, e.g. `12.3 GiB`.
+    #[default]
+    Human,
+    /// The exact number, e.g. `13207024435 B`.
+    Bytes,
+}
+
+impl SizeFormat {
+    pub fn format(self, bytes: u64) -> String {
+        match self {
+            Self::Human => format_bytes(bytes),
+            Self::Bytes => format!("{bytes} B"),
+        }
+    }
+}
+
+/// Formats `bytes` with a binary unit, e.g. `12.3 GiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    for &(suffix, multiplier) in &UNITS[..4] {
+        if bytes >= multiplier {
+            return format!("{:.1} {suffix}", bytes as f64 / multiplier as f64);
+        }
+    }
+    format!("{bytes} B")
+}
+
+/// Returns the space `path` occupies on disk, as `du` and `df` count it.
+///
+/// For a compressed file `st_blocks` covers the decmpfs data held in the resource fork, so this
+/// matches how applesauce measures [`AfscFileInfo::on_disk_size`](applesauce::info::AfscFileInfo).
+pub(crate) fn allocated_size(path: &Path) -> io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let size = metadata.blocks() * 512;
+    let block_size = metadata.blksize().max(1);
+    Ok(size.div_ceil(block_size) * block_size)
+}
+
+/// Returns the part of [`allocated_size`] that `path` does not share with APFS clones, i.e. what
+/// rewriting it could actually free.
+#[cfg(target_os = "macos")]
+pub(crate) fn private_size(path: &Path) -> io::Result<u64> {
+    #[repr(C, packed(4))]
+    struct Attributes {
+        length: u32,
+        returned: libc::attribute_set_t,
+        private_size: libc::off_t,
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let mut request = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_RETURNED_ATTRS,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        // With `FSOPT_ATTR_CMN_EXTENDED`, this field selects `ATTR_CMNEXT_*` attributes.
+        forkattr: libc::ATTR_CMNEXT_PRIVATESIZE,
+    };
+    let mut attributes = MaybeUninit::<Attributes>::zeroed();
+    // SAFETY: `request` and `attributes` are valid for the duration of the call, and the buffer
+    // size passed matches `attributes`.
+    let attributes = unsafe {
+        if libc::getattrlist(
+            c_path.as_ptr(),
+            (&raw mut request).cast(),
+            attributes.as_mut_ptr().cast(),
+            size_of::<Attributes>(),
+            libc::FSOPT_ATTR_CMN_EXTENDED | libc::FSOPT_NOFOLLOW,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        attributes.assume_init()
+    };
+    let returned = attributes.returned;
+    if returned.forkattr & libc::ATTR_CMNEXT_PRIVATESIZE == 0 {
+        // Not APFS, so nothing is shared.
+        return allocated_size(path);
+    }
+    Ok(u64::try_from(attributes.private_size).unwrap_or(0))
+}
+
+/// Returns the part of [`allocated_size`] that `path` does not share with other files. Only APFS
+/// shares extents between files, so elsewhere this is the allocated size.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn private_size(path: &Path) -> io::Result<u64> {
+    allocated_size(path)
+}
+
+/// Returns the space available to unprivileged users on the volume containing `path`.
+pub(crate) fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read after `statvfs` succeeds.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)] // The field widths differ between platforms.
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// A quick guess at how much a work dir holds: the logical size of the files directly in it and in
+/// its `deps`, where a cargo profile dir keeps nearly all of its bytes. Only two directories are
+/// read, so it costs little even on trees too big to walk twice.
+pub(crate) fn estimate_size(dir: &Path) -> u64 {
+    [dir.to_path_buf(), dir.join("deps")]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// One existing path per volume among `paths`, so free space can be summed without counting a
+/// volume twice.
+pub(crate) fn one_per_volume(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut devices = Vec::new();
+    let mut representatives = Vec::new();
+    for path in paths {
+        if let Ok(metadata) = path.metadata()
+            && !devices.contains(&metadata.dev())
+        {
+            devices.push(metadata.dev());
+            representatives.push(path.clone());
+        }
+    }
+    representatives
+}
+
+/// The free space of every volume in `volumes` (see [`one_per_volume`]) together, or `None` if
+/// there are none or any can't be read.
+pub(crate) fn total_free_space(volumes: &[PathBuf]) -> Option<u64> {
+    if volumes.is_empty() {
+        return None;
+    }
+    volumes
+        .iter()
+        .map(|path| free_space(path).ok())
+        .sum::<Option<u64>>()
+}
+
+/// The filesystem a path lives on.
+pub(crate) struct Volume {
+    /// The filesystem type as `mount` shows it, e.g. `apfs`.
+    pub(crate) fs_type: String,
+    pub(crate) mount_point: PathBuf,
+    /// False for network filesystems such as SMB and NFS shares.
+    pub(crate) local: bool,
+}
+
+impl Volume {
+    /// Whether files on this volume can be compressed transparently.
+    pub(crate) fn supports_compression(&self) -> bool {
+        matches!(self.fs_type.as_str(), "apfs" | "hfs")
+    }
+}
+
+/// The kind of device a volume is on, as far as how hard it can be driven.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))] // Only `diskutil` tells them apart.
+pub(crate) enum Storage {
+    /// Internal flash, which keeps up with every worker.
+    Internal,
+    /// A solid-state disk on USB or Thunderbolt, usually slower and behind a single bus.
+    External,
+    /// A spinning disk, where parallel reads and writes turn into seeks.
+    Rotational,
+}
+
+impl Storage {
+    /// The most work dirs on this storage to compress at once, or `None` for as many as the open
+    /// file limit allows.
+    pub(crate) fn max_workers(self) -> Option<usize> {
+        match self {
+            Self::Internal => None,
+            Self::External => Some(2),
+            Self::Rotational => Some(1),
+        }
+    }
+
+    /// How many threads to read a work dir on this storage with.
+    pub(crate) fn walk_threads(self) -> usize {
+        match self {
+            Self::Internal => WALK_THREADS,
+            Self::External => WALK_THREADS / 2,
+            Self::Rotational => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Internal => "internal storage",
+            Self::External => "an external SSD",
+            Self::Rotational => "a spinning disk",
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn volume(path: &Path) -> io::Result<Volume> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `path` is NUL-terminated and `stat` is only read after `statfs` succeeds.
+    let stat = unsafe {
+        if libc::statfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    let text = |chars: &[libc::c_char]| {
+        let bytes: Vec<u8> = chars
+            .iter()
+            .map(|&c| c as u8)
+            .take_while(|&byte| byte != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    Ok(Volume {
+        fs_type: text(&stat.f_fstypename),
+        mount_point: PathBuf::from(text(&stat.f_mntonname)),
+        local: i64::from(stat.f_flags) & i64::from(libc::MNT_LOCAL) != 0,
+    })
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn volume(_path: &Path) -> io::Result<Volume> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem details are only available on macOS",
+    ))
+}
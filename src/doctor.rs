@@ -0,0 +1,223 @@
+//! `cargo apfs-compress doctor`: environment checks that explain why a run would fail or be slow.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::{Error, Result};
+use crate::lock_holder;
+use crate::{CARGO_LOCK_NAME, Cli, disk, list_work_dirs, resolve_cargo_exe, resolve_target_dir};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+pub(crate) struct Finding {
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{label}: {}", self.message)
+    }
+}
+
+/// Runs every check, printing findings as they come, and fails if any of them is an error.
+pub(crate) fn run(cli: &Cli) -> Result<()> {
+    let mut errors = 0;
+    let mut report = |finding: Finding| {
+        if finding.severity == Severity::Error {
+            errors += 1;
+        }
+        println!("{finding}");
+    };
+
+    report(check_cargo(&resolve_cargo_exe()));
+    match resolve_target_dir(cli) {
+        Ok(target_dir) => {
+            report(Finding::new(
+                Severity::Ok,
+                format!("target directory is {}", target_dir.display()),
+            ));
+            if target_dir.is_dir() {
+                check_volume(&target_dir).into_iter().for_each(&mut report);
+                report(check_locking(&target_dir));
+            } else {
+                report(Finding::new(
+                    Severity::Warning,
+                    format!(
+                        "{} does not exist yet; build something first",
+                        target_dir.display()
+                    ),
+                ));
+            }
+        }
+        Err(error) => report(Finding::new(
+            Severity::Error,
+            format!("could not resolve the target directory: {}", error.chain()),
+        )),
+    }
+    if let Ok(dirs) = list_work_dirs(cli) {
+        for dir in dirs {
+            if let Some(finding) = check_held_lock(&dir.path) {
+                report(finding);
+            }
+        }
+    }
+
+    if errors > 0 {
+        Err(Error::DoctorFailed { errors })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_cargo(cargo: &str) -> Finding {
+    match Command::new(cargo).arg("--version").output() {
+        Ok(output) if output.status.success() => Finding::new(
+            Severity::Ok,
+            String::from_utf8_lossy(&output.stdout).trim().to_owned(),
+        ),
+        Ok(output) => Finding::new(
+            Severity::Error,
+            format!(
+                "`{cargo} --version` failed with status {}; check the `CARGO` environment variable",
+                output.status
+            ),
+        ),
+        Err(error) => Finding::new(
+            Severity::Error,
+            format!("could not run `{cargo}` ({error}); install Rust or set `CARGO`"),
+        ),
+    }
+}
+
+fn check_volume(target_dir: &Path) -> Vec<Finding> {
+    let volume = match disk::volume(target_dir) {
+        Ok(volume) => volume,
+        Err(error) => {
+            return vec![Finding::new(
+                Severity::Error,
+                format!(
+                    "cannot inspect the filesystem of {}: {error}; APFS compression needs macOS",
+                    target_dir.display()
+                ),
+            )];
+        }
+    };
+
+    let mut findings = Vec::new();
+    findings.push(match volume.fs_type.as_str() {
+        _ if volume.supports_compression() => Finding::new(
+            Severity::Ok,
+            format!(
+                "{} is on {} ({}), which supports transparent compression",
+                target_dir.display(),
+                volume.mount_point.display(),
+                volume.fs_type
+            ),
+        ),
+        other => Finding::new(
+            Severity::Error,
+            format!(
+                "{} is on a {other} volume, which does not support transparent compression; \
+                 move the target dir to an APFS volume with `CARGO_TARGET_DIR`",
+                target_dir.display()
+            ),
+        ),
+    });
+    if !volume.local {
+        findings.push(Finding::new(
+            Severity::Warning,
+            format!(
+                "{} is a network volume; compression will be slow and locks may not work",
+                volume.mount_point.display()
+            ),
+        ));
+    } else if volume.mount_point.starts_with("/Volumes") {
+        findings.push(Finding::new(
+            Severity::Warning,
+            format!(
+                "{} looks like an external disk; expect slower runs, and unmount it only after \
+                 they finish",
+                volume.mount_point.display()
+            ),
+        ));
+    }
+    findings
+}
+
+/// Checks that `flock` works in `target_dir` using a scratch file.
+fn check_locking(target_dir: &Path) -> Finding {
+    let path = target_dir.join(".apfs-compress-doctor");
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .and_then(|file| {
+            let locked = file.try_lock();
+            drop(file);
+            fs::remove_file(&path)?;
+            Ok(locked)
+        });
+    match result {
+        Ok(Ok(())) => Finding::new(Severity::Ok, "file locking works"),
+        Ok(Err(TryLockError::WouldBlock)) => {
+            Finding::new(Severity::Ok, "file locking works (scratch file was busy)")
+        }
+        Ok(Err(TryLockError::Error(error))) => Finding::new(
+            Severity::Warning,
+            format!(
+                "file locks are not supported in {} ({error}); runs cannot coordinate with cargo",
+                target_dir.display()
+            ),
+        ),
+        Err(error) => Finding::new(
+            Severity::Error,
+            format!(
+                "cannot write to {} ({error}); check its permissions",
+                target_dir.display()
+            ),
+        ),
+    }
+}
+
+/// Reports a work dir whose `.cargo-lock` is currently held, since a run would block on it.
+fn check_held_lock(dir: &Path) -> Option<Finding> {
+    let path = dir.join(CARGO_LOCK_NAME);
+    let file = File::open(&path).ok()?;
+    match file.try_lock_shared() {
+        Err(TryLockError::WouldBlock) => Some(Finding::new(
+            Severity::Warning,
+            match lock_holder::current(&path) {
+                Some(holder) => format!("{} is {holder}; a run will wait for it", path.display()),
+                None => format!(
+                    "{} is locked, probably by a running build; a run will wait for it",
+                    path.display()
+                ),
+            },
+        )),
+        _ => None,
+    }
+}
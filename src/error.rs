@@ -0,0 +1,383 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+/// A boxed error returned by [`Compressor`](crate::Compressor) implementations.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A name that isn't one of [`CompressionArg`](crate::CompressionArg)'s.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+#[error("unknown compression `{name}`; expected lzfse, zlib, or lzvn")]
+pub struct ParseCompressionError {
+    pub name: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("failed to get current directory")]
+    CurrentDir(#[source] io::Error),
+
+    #[error("could not find cargo's home directory; set CARGO_HOME")]
+    NoCargoHome,
+
+    #[error("could not find the home directory; set HOME")]
+    NoHomeDir,
+
+    #[error("--interactive needs a terminal on stdin")]
+    NotInteractive,
+
+    #[error("failed to read the selection")]
+    Prompt(#[source] io::Error),
+
+    #[error("failed to set up the terminal for --tui")]
+    Terminal(#[source] io::Error),
+
+    #[error("failed to execute `{cargo} metadata`")]
+    MetadataSpawn {
+        cargo: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("`{cargo} metadata` failed with status {status}: {stderr}")]
+    MetadataFailed {
+        cargo: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+
+    #[error("failed to parse `{cargo} metadata` output")]
+    MetadataParse {
+        cargo: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("could not find `Cargo.toml` in {} or any parent directory", cwd.display())]
+    NoManifest { cwd: PathBuf },
+
+    #[error("could not find `Package.swift` in {} or any parent directory", path.display())]
+    NoSwiftPackage { path: PathBuf },
+
+    #[error("failed reading {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed parsing {}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+
+    #[error("invalid value {value} for `{key}` in {}", path.display())]
+    InvalidConfig {
+        path: PathBuf,
+        key: String,
+        value: String,
+    },
+
+    #[error("invalid value `{value}` for environment variable {name}")]
+    InvalidEnv { name: String, value: String },
+
+    #[error("failed to query free space for {}", path.display())]
+    FreeSpace {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("cannot tell whether the machine is on AC power")]
+    PowerCheck(#[source] io::Error),
+
+    #[error("cannot tell whether the machine is idle")]
+    IdleCheck(#[source] io::Error),
+
+    #[error("could not resolve git ref `{reference}`: {message}")]
+    GitRef { reference: String, message: String },
+
+    #[error("package `{name}` is not a member of this workspace")]
+    UnknownPackage { name: String },
+
+    #[error("failed to remove {}", path.display())]
+    Remove {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write {}", path.display())]
+    WriteMarker {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write {}", path.display())]
+    WriteConfig {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{} already exists; pass --force to replace it", path.display())]
+    ConfigExists { path: PathBuf },
+
+    #[error("alias `{name}` is already defined in {}; pick another with --name", path.display())]
+    AliasExists { name: String, path: PathBuf },
+
+    #[error(
+        "{} defines aliases outside an `[alias]` table; add the alias by hand (see --print)",
+        path.display()
+    )]
+    AliasTableNotEditable { path: PathBuf },
+
+    #[error("failed to exclude {} from Time Machine", path.display())]
+    BackupExclusion {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to update checkpoint {}", path.display())]
+    Checkpoint {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to save directory state {}", path.display())]
+    DirState {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to save compression ratios {}", path.display())]
+    RatioCache {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to open: {}", path.display())]
+    LockOpen {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to lock file: {}", path.display())]
+    Lock {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Opening or locking a work dir's lock file failed, so the dir was left alone.
+    #[error("failed to lock {}", path.display())]
+    LockWorkDir {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("failed to rename {} to {}", from.display(), to.display())]
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(
+        "refusing to compress {}: {reason} (pass --i-know-what-im-doing to override)",
+        path.display()
+    )]
+    DangerousTargetDir { path: PathBuf, reason: &'static str },
+
+    #[error(
+        "target dir {} is a symlink to {}, which does not exist",
+        path.display(),
+        destination.display()
+    )]
+    BrokenTargetLink { path: PathBuf, destination: PathBuf },
+
+    #[error(
+        "target dir {} resolves to {} on a {fs_type} volume, which does not support transparent \
+         compression",
+        path.display(),
+        destination.display()
+    )]
+    UnsupportedTargetVolume {
+        path: PathBuf,
+        destination: PathBuf,
+        fs_type: String,
+    },
+
+    #[error("{} is not a directory", path.display())]
+    NotADirectory { path: PathBuf },
+
+    #[error("compression failed for {}", path.display())]
+    CompressionFailed {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("doctor found {errors} problem(s)")]
+    DoctorFailed { errors: usize },
+
+    #[error("strict mode: {}", problems.join("; "))]
+    Strict { problems: Vec<String> },
+
+    #[error(
+        "saved {} of {}, less than --min-savings {required}",
+        crate::disk::format_bytes(*saved),
+        crate::disk::format_bytes(*scanned)
+    )]
+    MinSavings {
+        saved: u64,
+        scanned: u64,
+        required: crate::disk::MinSavings,
+    },
+
+    #[error("failed to update run history {}", path.display())]
+    History {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write report {}", path.display())]
+    WriteReport {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write the job summary to {}", path.display())]
+    StepSummary {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("worker panicked: {message}")]
+    WorkerPanicked { message: String },
+
+    #[error("one or more directories failed")]
+    DirectoriesFailed { failed: usize },
+
+    #[error("failed to open log file {}", path.display())]
+    LogFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write inventory {}", path.display())]
+    Inventory {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to use control socket {}", path.display())]
+    ControlSocket {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("another run is already listening on {}", path.display())]
+    ControlSocketInUse { path: PathBuf },
+
+    #[error("{} is in the way of the control socket and is not a socket", path.display())]
+    ControlSocketNotASocket { path: PathBuf },
+
+    #[error("the run refused: {message}")]
+    ControlRefused { message: String },
+
+    #[error("{failed} of {total} jobs failed")]
+    JobsFailed { failed: usize, total: usize },
+
+    #[error("timed out after {after}; the run is partial")]
+    TimedOut { after: String },
+
+    #[error("interrupted; the run is partial")]
+    Interrupted,
+}
+
+impl Error {
+    pub(crate) fn read(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Read { path, source }
+    }
+
+    /// The process exit status for this error: 124 for [`Error::TimedOut`], like `timeout(1)`, 130
+    /// for [`Error::Interrupted`], as after SIGINT, and 1 for everything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::TimedOut { .. } => 124,
+            Error::Interrupted => 130,
+            _ => 1,
+        }
+    }
+
+    /// Formats this error followed by each of its sources, separated by `: `.
+    pub fn chain(&self) -> Chain<'_> {
+        Chain(self)
+    }
+
+    /// The file or dir the error is about, if it names one.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Read { path, .. }
+            | Self::FreeSpace { path, .. }
+            | Self::Remove { path, .. }
+            | Self::WriteMarker { path, .. }
+            | Self::WriteConfig { path, .. }
+            | Self::BackupExclusion { path, .. }
+            | Self::Checkpoint { path, .. }
+            | Self::DirState { path, .. }
+            | Self::RatioCache { path, .. }
+            | Self::LockOpen { path, .. }
+            | Self::Lock { path, .. }
+            | Self::LockWorkDir { path, .. }
+            | Self::CompressionFailed { path, .. }
+            | Self::History { path, .. }
+            | Self::WriteReport { path, .. }
+            | Self::StepSummary { path, .. }
+            | Self::LogFile { path, .. }
+            | Self::Inventory { path, .. }
+            | Self::ControlSocket { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Display adapter returned by [`Error::chain`].
+pub struct Chain<'a>(&'a Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = std::error::Error::source(self.0);
+        while let Some(error) = source {
+            write!(f, ": {error}")?;
+            source = error.source();
+        }
+        if let Some(hint) = crate::hints::for_error(self.0) {
+            write!(f, "; hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
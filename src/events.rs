@@ -0,0 +1,111 @@
+//! [`run_with_events`]: a run on its own thread that reports what it does as typed [`Event`]s,
+//! for hosts such as GUI apps that want live progress without parsing log lines.
+
+use applesauce::progress::Task;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::metrics::NoopMetrics;
+use crate::reporter::Reporter;
+use crate::stats::CompressionStats;
+use crate::{ApplesauceCompressor, Cli, Result, run_with_compressor};
+
+/// Something a run started by [`run_with_events`] did.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A worker started on a work dir.
+    DirStarted { dir: PathBuf },
+    /// A file was compressed, with its size on disk before and after.
+    FileDone {
+        path: PathBuf,
+        bytes_in: u64,
+        bytes_out: u64,
+    },
+    /// A file or work dir couldn't be compressed.
+    Error { path: PathBuf, message: String },
+    /// A worker finished with a work dir, with its stats unless it failed.
+    DirFinished {
+        dir: PathBuf,
+        stats: Option<CompressionStats>,
+    },
+    /// The run is over, with its combined stats unless it failed. Always the last event; the
+    /// run's error, if any, comes from the thread's [`JoinHandle`].
+    RunFinished { stats: Option<CompressionStats> },
+}
+
+/// Starts a run like [`crate::run`] on a new thread, sending an [`Event`] for each step over the
+/// returned channel instead of printing progress. Log messages are dropped.
+///
+/// The channel is unbounded; if the receiver is dropped, the run carries on regardless.
+pub fn run_with_events(cli: Cli) -> (JoinHandle<Result<CompressionStats>>, Receiver<Event>) {
+    let (sender, events) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let reporter = EventReporter::new(sender);
+        let result =
+            run_with_compressor(cli, &ApplesauceCompressor, &reporter, Arc::new(NoopMetrics));
+        reporter.send(Event::RunFinished {
+            stats: result.as_ref().ok().copied(),
+        });
+        result
+    });
+    (handle, events)
+}
+
+pub(crate) struct EventReporter {
+    sender: Sender<Event>,
+}
+
+impl EventReporter {
+    pub(crate) fn new(sender: Sender<Event>) -> Self {
+        Self { sender }
+    }
+
+    fn send(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+}
+
+struct NoTask;
+
+impl Task for NoTask {
+    fn increment(&self, _amt: u64) {}
+
+    fn error(&self, _message: &str) {}
+}
+
+impl Reporter for EventReporter {
+    fn error(&self, path: &Path, message: &str) {
+        self.send(Event::Error {
+            path: path.to_path_buf(),
+            message: message.to_owned(),
+        });
+    }
+
+    fn file_task(&self, _path: &Path, _size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(NoTask)
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        self.send(Event::FileDone {
+            path: path.to_path_buf(),
+            bytes_in,
+            bytes_out,
+        });
+    }
+
+    fn work_dir_started(&self, dir: &Path) {
+        self.send(Event::DirStarted {
+            dir: dir.to_path_buf(),
+        });
+    }
+
+    fn work_dir_finished(&self, dir: &Path, stats: Option<&CompressionStats>) {
+        self.send(Event::DirFinished {
+            dir: dir.to_path_buf(),
+            stats: stats.copied(),
+        });
+    }
+}
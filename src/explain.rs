@@ -0,0 +1,227 @@
+//! `cargo apfs-compress explain`: how a run would find its work dirs, step by step, for when it
+//! picks up the wrong ones: where each target dir came from, which file set a profile's
+//! `dir-name`, and what discovery made of every dir it looked at.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::Source;
+use crate::error::Result;
+use crate::{
+    Cli, DEFAULT_CHECK_DIRS, WorkDirSource, config_env_target_dir, discover_traced, env_target_dir,
+    follow_target_dir_link, guess_target_dir, load_cargo_configs_with_home, load_cargo_metadata,
+    load_default_targets, load_settings, profile_dir_name_env_overrides, project_roots,
+    project_target_dirs, resolve, resolve_cargo_exe, resolve_profile_dir_name,
+};
+
+/// The steps, one per line.
+pub(crate) fn lines(cli: &Cli) -> Result<Vec<String>> {
+    let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+    let mut lines = Vec::new();
+    for root in project_roots(cli)? {
+        lines.push(format!("project {}", root.display()));
+
+        let env_name = ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"]
+            .into_iter()
+            .find(|name| var(name).is_some());
+        let source = if let Some(name) = env_name {
+            Source::Env(name)
+        } else {
+            load_cargo_configs_with_home(&root)?
+                .into_iter()
+                .rev()
+                .find(|(_, config)| {
+                    config
+                        .get("build")
+                        .and_then(|build| build.get("target-dir"))
+                        .is_some()
+                })
+                .map_or(Source::Default, |(path, _)| Source::File(path))
+        };
+        let (mut target_dir, how) = if cli.no_metadata {
+            let dir = guess_target_dir(&root, env_target_dir(&|name| var(name)).as_deref())?;
+            (dir, "guessed from Cargo.toml, as with --no-metadata")
+        } else {
+            let metadata = load_cargo_metadata(&resolve_cargo_exe(), &root)?;
+            (metadata.target_directory, "as cargo metadata reports it")
+        };
+        lines.push(format!(
+            "  target dir {} ({how})  # {source}",
+            target_dir.display()
+        ));
+        if let Some(real) = follow_target_dir_link(&target_dir)? {
+            lines.push(format!("  target dir is a symlink to {}", real.display()));
+            target_dir = real;
+        }
+        let mut target_dirs = vec![target_dir];
+        if let Some((dir, path)) = config_env_target_dir(&root, env_name.is_some())? {
+            lines.push(format!(
+                "  what cargo runs builds into {}  # {}",
+                dir.display(),
+                Source::File(path)
+            ));
+            if project_target_dirs(&root, target_dirs[0].clone())?.len() > 1 {
+                target_dirs.push(follow_target_dir_link(&dir)?.unwrap_or(dir));
+            } else if dir.is_dir() {
+                lines.push("  which is the target dir".to_owned());
+            } else {
+                lines.push("  which nothing has built into yet".to_owned());
+            }
+        }
+
+        let dir_names = dir_names(&root)?;
+        for (profile, (dir_name, source)) in &dir_names {
+            lines.push(format!(
+                "  profile.{profile}.dir-name = {dir_name:?}  # {source}"
+            ));
+        }
+
+        if cli.profiles.is_empty() {
+            let settings = load_settings(cli, &root)?;
+            lines.push("  no --profile, so every dir in the target dir is considered".to_owned());
+            let check_dirs: Vec<(&str, String)> = DEFAULT_CHECK_DIRS
+                .iter()
+                .map(|&dir| (dir, Source::Default.to_string()))
+                .chain(
+                    cli.check_dirs
+                        .iter()
+                        .map(|dir| (dir.as_str(), Source::Cli.to_string())),
+                )
+                .chain(
+                    settings
+                        .check_dirs
+                        .value
+                        .iter()
+                        .map(|dir| (dir.as_str(), settings.check_dirs.source.to_string())),
+                )
+                .collect();
+            for (dir, source) in &check_dirs {
+                lines.push(format!("  check dir {dir}  # {source}"));
+            }
+            let check_dirs: Vec<String> = check_dirs
+                .into_iter()
+                .skip(DEFAULT_CHECK_DIRS.len())
+                .map(|(dir, _)| dir.to_owned())
+                .collect();
+            if !cli.targets.is_empty() {
+                lines.push(format!(
+                    "  only under targets {}  # command line",
+                    cli.targets.join(", ")
+                ));
+            }
+            for target_dir in &target_dirs {
+                if target_dirs.len() > 1 {
+                    lines.push(format!("  in {}:", target_dir.display()));
+                }
+                let mut traced = Vec::new();
+                discover_traced(target_dir, &cli.targets, &check_dirs, &mut |path, what| {
+                    let path = path.strip_prefix(target_dir).unwrap_or(path);
+                    traced.push(format!("    {}: {what}", path.display()));
+                })?;
+                lines.extend(traced);
+            }
+        } else {
+            let (targets, source) = if !cli.targets.is_empty() {
+                (cli.targets.clone(), Source::Cli)
+            } else {
+                let targets = load_default_targets(&root, var("CARGO_BUILD_TARGET").as_deref())?;
+                let source = if var("CARGO_BUILD_TARGET").is_some() {
+                    Source::Env("CARGO_BUILD_TARGET")
+                } else {
+                    load_cargo_configs_with_home(&root)?
+                        .into_iter()
+                        .rev()
+                        .find(|(_, config)| {
+                            config
+                                .get("build")
+                                .and_then(|build| build.get("target"))
+                                .is_some()
+                        })
+                        .map_or(Source::Default, |(path, _)| Source::File(path))
+                };
+                (targets, source)
+            };
+            let targets = if targets.is_empty() {
+                "the host".to_owned()
+            } else {
+                targets.join(", ")
+            };
+            lines.push(format!("  building for {targets}  # {source}"));
+            let overrides = dir_names
+                .iter()
+                .map(|(profile, (dir_name, _))| (profile.clone(), dir_name.clone()))
+                .collect();
+            for profile in &cli.profiles {
+                let how = if dir_names.contains_key(profile) {
+                    "its dir-name"
+                } else {
+                    "cargo's default"
+                };
+                lines.push(format!(
+                    "  profile {profile} builds into {} ({how})",
+                    resolve_profile_dir_name(profile, &overrides)
+                ));
+            }
+        }
+    }
+
+    let resolved = resolve(cli)?;
+    if cli.host_only {
+        lines.push("dropped dirs under a target triple  # --host-only".to_owned());
+    }
+    if cli.cross_only {
+        lines.push("dropped dirs not under a target triple  # --cross-only".to_owned());
+    }
+    for (dir, outer) in &resolved.nested {
+        lines.push(format!(
+            "dropped {}: inside {}, which covers it",
+            dir.display(),
+            outer.display()
+        ));
+    }
+    lines.push("work dirs:".to_owned());
+    for dir in &resolved.dirs {
+        let source = match dir.source {
+            WorkDirSource::Discovered => "discovered",
+            WorkDirSource::Requested => "requested",
+        };
+        let mut line = format!("  {}  # {source}", dir.path.display());
+        if resolved.wasm_dirs.contains(&dir.path) {
+            line.push_str(", wasm output");
+        } else if let Some(profile) = &dir.profile {
+            line.push_str(&format!(", profile {profile}"));
+        }
+        if let Some(target) = &dir.target {
+            line.push_str(&format!(", target {target}"));
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Each profile's `dir-name` with where it was set, the last config file to set it or a
+/// `CARGO_PROFILE_<NAME>_DIR_NAME` env var, which wins as it does for cargo.
+fn dir_names(root: &Path) -> Result<BTreeMap<String, (String, String)>> {
+    let mut names = BTreeMap::new();
+    for (path, config) in load_cargo_configs_with_home(root)? {
+        let Some(profiles) = config.get("profile").and_then(toml::Value::as_table) else {
+            continue;
+        };
+        for (profile, table) in profiles {
+            if let Some(dir_name) = table.get("dir-name").and_then(toml::Value::as_str) {
+                let source = Source::File(path.clone()).to_string();
+                names.insert(profile.clone(), (dir_name.to_owned(), source));
+            }
+        }
+    }
+    for (name, value) in std::env::vars_os() {
+        let Some(name) = name.to_str().map(ToOwned::to_owned) else {
+            continue;
+        };
+        let overrides = profile_dir_name_env_overrides([(name.clone().into(), value)]);
+        for (profile, dir_name) in overrides {
+            names.insert(profile, (dir_name, format!("env {name}")));
+        }
+    }
+    Ok(names)
+}
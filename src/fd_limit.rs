@@ -0,0 +1,160 @@
+//! Keeping a run within the open file limit: the default soft limit on macOS is 256, which one
+//! worker per dir, each with applesauce's own threads behind it, can exhaust on a big target dir.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::walk::WALK_THREADS;
+
+/// The highest soft limit to ask for. macOS rejects `RLIM_INFINITY` and anything above
+/// `kern.maxfilesperproc`, which defaults to `OPEN_MAX` (10240) on most machines.
+const WANTED_LIMIT: u64 = 10240;
+
+/// Descriptors kept back for everything other than the workers: stdio, locks, pipes to `cargo`
+/// and `lsof`, and the report.
+const RESERVED: u64 = 64;
+
+/// Descriptors one file being compressed holds open: the file itself, the resource fork being
+/// written, and the temporary copy applesauce verifies against.
+const PER_FILE: u64 = 3;
+
+/// How long a batch that ran out of descriptors waits before retrying the files that failed, so
+/// other workers' files can close.
+pub(crate) const BACKOFF: Duration = Duration::from_millis(500);
+
+/// How many times files that failed for want of descriptors are retried before the failure is
+/// reported.
+pub(crate) const RETRIES: usize = 3;
+
+/// Raises the soft open file limit as far as [`WANTED_LIMIT`] allows and returns it, or `None` if
+/// it can't be read.
+pub(crate) fn raise_open_file_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid, writable `rlimit`.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    let wanted = WANTED_LIMIT.min(limit.rlim_max);
+    if limit.rlim_cur < wanted {
+        let raised = libc::rlimit {
+            rlim_cur: wanted,
+            rlim_max: limit.rlim_max,
+        };
+        // SAFETY: `raised` is a valid `rlimit`.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            return Some(wanted);
+        }
+    }
+    Some(limit.rlim_cur)
+}
+
+/// How many work dirs can be compressed at once within `limit` descriptors, when applesauce keeps
+/// about one file per CPU in flight for each. Always at least one.
+pub(crate) fn max_workers(limit: u64, cpus: usize) -> usize {
+    let per_worker = PER_FILE * cpus as u64 + WALK_THREADS as u64;
+    (limit.saturating_sub(RESERVED) / per_worker).max(1) as usize
+}
+
+/// Whether an error message from applesauce is `EMFILE` or `ENFILE`, which go away once other
+/// files close rather than being the file's fault.
+pub(crate) fn is_exhaustion(message: &str) -> bool {
+    message.contains("os error 24") || message.contains("os error 23")
+}
+
+/// Runs `open`, retrying after [`BACKOFF`] up to [`RETRIES`] times while it fails with `EMFILE`
+/// or `ENFILE`.
+pub(crate) fn retrying<T>(mut open: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut retries = 0;
+    loop {
+        match open() {
+            Err(error)
+                if retries < RETRIES
+                    && matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) =>
+            {
+                retries += 1;
+                std::thread::sleep(BACKOFF);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A counting semaphore for work dir workers, with a smaller budget of its own for each volume
+/// that has one, keyed by device number.
+pub(crate) struct Slots {
+    free: Mutex<Free>,
+    released: Condvar,
+}
+
+struct Free {
+    workers: usize,
+    volumes: HashMap<u64, usize>,
+}
+
+pub(crate) struct Slot<'a> {
+    slots: &'a Slots,
+    volume: Option<u64>,
+}
+
+impl Slots {
+    pub(crate) fn new(count: usize, volumes: HashMap<u64, usize>) -> Self {
+        Self {
+            free: Mutex::new(Free {
+                workers: count.max(1),
+                volumes: volumes
+                    .into_iter()
+                    .map(|(volume, count)| (volume, count.max(1)))
+                    .collect(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits until one of the dirs on `volumes`, waiting in that order, can start, and takes a
+    /// slot for the first that can: a dir on a busy volume doesn't hold up those behind it on
+    /// other volumes. Returns the dir's index and a guard that gives the slot back when dropped.
+    pub(crate) fn acquire_first(&self, volumes: &[Option<u64>]) -> (usize, Slot<'_>) {
+        let mut free = self.free.lock().unwrap();
+        loop {
+            let ready = (free.workers > 0)
+                .then(|| {
+                    volumes.iter().position(|volume| {
+                        volume.is_none_or(|volume| free.volumes.get(&volume).is_none_or(|&n| n > 0))
+                    })
+                })
+                .flatten();
+            if let Some(index) = ready {
+                free.workers -= 1;
+                let volume = volumes[index].filter(|volume| free.volumes.contains_key(volume));
+                if let Some(volume) = volume {
+                    *free.volumes.get_mut(&volume).unwrap() -= 1;
+                }
+                return (
+                    index,
+                    Slot {
+                        slots: self,
+                        volume,
+                    },
+                );
+            }
+            free = self.released.wait(free).unwrap();
+        }
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut free = self.slots.free.lock().unwrap();
+        free.workers += 1;
+        if let Some(volume) = self.volume {
+            *free.volumes.get_mut(&volume).unwrap() += 1;
+        }
+        // Whoever is waiting may be waiting for this volume in particular.
+        self.slots.released.notify_all();
+    }
+}
@@ -15,9 +15,8 @@ use std::io;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Display, Path, PathBuf};
 
-use anyhow::{Context as _, Result};
-
-use crate::progress::ProgressBars;
+use crate::error::{Error, Result};
+use crate::reporter::Reporter;
 
 #[derive(Debug)]
 pub struct FileLock {
@@ -38,7 +37,7 @@ impl FileLock {
         self.path.parent().expect("lock has no parent")
     }
 
-    pub fn remove_siblings(&self) -> Result<()> {
+    pub fn remove_siblings(&self) -> io::Result<()> {
         let path = self.path();
         for entry in path.parent().expect("lock has no parent").read_dir()? {
             let entry = entry?;
@@ -57,12 +56,10 @@ impl FileLock {
 
     pub fn rename<P: AsRef<Path>>(&mut self, new_path: P) -> Result<()> {
         let new_path = new_path.as_ref();
-        std::fs::rename(&self.path, new_path).with_context(|| {
-            format!(
-                "failed to rename {} to {}",
-                self.path.display(),
-                new_path.display()
-            )
+        std::fs::rename(&self.path, new_path).map_err(|source| Error::Rename {
+            from: self.path.clone(),
+            to: new_path.to_path_buf(),
+            source,
         })?;
         self.path = new_path.to_path_buf();
         Ok(())
@@ -125,7 +122,7 @@ impl Filesystem {
         &self.root
     }
 
-    pub fn create_dir(&self) -> Result<()> {
+    pub fn create_dir(&self) -> io::Result<()> {
         std::fs::create_dir_all(&self.root)?;
         Ok(())
     }
@@ -138,7 +135,7 @@ impl Filesystem {
         &self,
         path: P,
         msg: &str,
-        progress: &ProgressBars,
+        progress: &dyn Reporter,
     ) -> Result<FileLock>
     where
         P: AsRef<Path>,
@@ -150,6 +147,19 @@ impl Filesystem {
         Ok(FileLock { f: Some(f), path })
     }
 
+    /// Opens an existing lock file read-only and takes a shared lock on it, so other readers can
+    /// proceed but anything holding it exclusively (such as a build) is waited out first.
+    pub fn open_ro_shared<P>(&self, path: P, msg: &str, progress: &dyn Reporter) -> Result<FileLock>
+    where
+        P: AsRef<Path>,
+    {
+        let (path, f) = self.open(path.as_ref(), OpenOptions::new().read(true), false)?;
+        acquire(msg, &path, progress, &|| f.try_lock_shared(), &|| {
+            f.lock_shared()
+        })?;
+        Ok(FileLock { f: Some(f), path })
+    }
+
     fn open(&self, path: &Path, opts: &OpenOptions, create: bool) -> Result<(PathBuf, File)> {
         let path = self.root.join(path);
         let f = opts
@@ -157,12 +167,15 @@ impl Filesystem {
             .or_else(|e| {
                 if e.kind() == io::ErrorKind::NotFound && create {
                     std::fs::create_dir_all(path.parent().expect("lock file has no parent"))?;
-                    Ok(opts.open(&path)?)
+                    opts.open(&path)
                 } else {
-                    Err(anyhow::Error::from(e))
+                    Err(e)
                 }
             })
-            .with_context(|| format!("failed to open: {}", path.display()))?;
+            .map_err(|source| Error::LockOpen {
+                path: path.clone(),
+                source,
+            })?;
         Ok((path, f))
     }
 }
@@ -187,11 +200,10 @@ fn try_acquire(path: &Path, lock_try: &dyn Fn() -> Result<(), TryLockError>) ->
     match lock_try() {
         Ok(()) => Ok(true),
         Err(TryLockError::Error(e)) if error_unsupported(&e) => Ok(true),
-        Err(TryLockError::Error(e)) => {
-            let e = anyhow::Error::from(e);
-            let cx = format!("failed to lock file: {}", path.display());
-            Err(e.context(cx))
-        }
+        Err(TryLockError::Error(source)) => Err(Error::Lock {
+            path: path.to_path_buf(),
+            source,
+        }),
         Err(TryLockError::WouldBlock) => Ok(false),
     }
 }
@@ -199,7 +211,7 @@ fn try_acquire(path: &Path, lock_try: &dyn Fn() -> Result<(), TryLockError>) ->
 fn acquire(
     msg: &str,
     path: &Path,
-    progress: &ProgressBars,
+    progress: &dyn Reporter,
     lock_try: &dyn Fn() -> Result<(), TryLockError>,
     lock_block: &dyn Fn() -> io::Result<()>,
 ) -> Result<()> {
@@ -207,8 +219,14 @@ fn acquire(
         return Ok(());
     }
 
-    progress.println_normal(|| format!("Blocking waiting for file lock on {msg}"));
-    lock_block().with_context(|| format!("failed to lock file: {}", path.display()))?;
+    let holder = crate::lock_holder::current(path)
+        .map(|holder| format!(" ({holder})"))
+        .unwrap_or_default();
+    progress.println_normal(|| format!("Blocking waiting for file lock on {msg}{holder}"));
+    lock_block().map_err(|source| Error::Lock {
+        path: path.to_path_buf(),
+        source,
+    })?;
     Ok(())
 }
 
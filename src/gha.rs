@@ -0,0 +1,93 @@
+//! `--output gha`: GitHub Actions workflow commands and the job summary.
+//!
+//! See <https://docs.github.com/en/actions/reference/workflow-commands-for-github-actions>.
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+
+use crate::summary::RunSummary;
+
+/// The environment variable naming the file a step appends its markdown job summary to.
+pub(crate) const STEP_SUMMARY_ENV: &str = "GITHUB_STEP_SUMMARY";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Annotation {
+    Notice,
+    Warning,
+}
+
+/// A workflow command line such as `::warning title=...::message`.
+pub(crate) fn annotation(kind: Annotation, title: &str, message: &str) -> String {
+    let kind = match kind {
+        Annotation::Notice => "notice",
+        Annotation::Warning => "warning",
+    };
+    format!(
+        "::{kind} title={}::{}",
+        escape_property(title),
+        escape_data(message)
+    )
+}
+
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// The markdown job summary: a table of totals, followed by any dirs that failed.
+pub(crate) fn job_summary(summary: &RunSummary, failures: &[(PathBuf, String)]) -> String {
+    let sizes = summary.sizes;
+    let stats = &summary.stats;
+    let mut markdown = String::from("### cargo apfs-compress\n\n| | |\n| --- | --- |\n");
+    let mut row = |name: &str, value: String| writeln!(markdown, "| {name} | {value} |").unwrap();
+    row("Dirs", summary.dirs.to_string());
+    if summary.failed > 0 {
+        row("Failed dirs", summary.failed.to_string());
+    }
+    row("Files compressed", stats.files_compressed.to_string());
+    row("Files skipped", stats.files_skipped.to_string());
+    row("Saved", sizes.format(stats.bytes_saved()));
+    row(
+        "Size",
+        format!(
+            "{} → {}",
+            sizes.format(stats.bytes_in),
+            sizes.format(stats.bytes_out)
+        ),
+    );
+    row("Duration", summary.elapsed_text());
+    if let Some(throughput) = summary.throughput_text() {
+        row("Throughput", throughput);
+    }
+    if let Some(free_space) = summary.free_space_text() {
+        row("Free space", free_space);
+    }
+
+    if !failures.is_empty() {
+        markdown.push_str("\n#### Failed directories\n\n");
+        for (dir, error) in failures {
+            writeln!(
+                markdown,
+                "- `{}`: {}",
+                dir.display(),
+                error.replace('\n', " ")
+            )
+            .unwrap();
+        }
+    }
+    markdown
+}
+
+/// Appends `markdown` to the step summary file, as other steps may have written to it already.
+pub(crate) fn append_step_summary(path: &Path, markdown: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(markdown.as_bytes())
+}
@@ -0,0 +1,98 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// Entries cargo leaves in a target directory. `CACHEDIR.TAG` and `.rustc_info.json` are written
+/// at the root; the others are profile dirs or their contents.
+const TARGET_DIR_MARKERS: &[&str] = &["CACHEDIR.TAG", ".rustc_info.json", "debug", "release"];
+const PROFILE_DIR_MARKERS: &[&str] = &[".cargo-lock", ".fingerprint"];
+
+fn looks_like_target_dir(dir: &Path) -> bool {
+    if TARGET_DIR_MARKERS
+        .iter()
+        .any(|marker| dir.join(marker).exists())
+    {
+        return true;
+    }
+    std::fs::read_dir(dir).is_ok_and(|entries| {
+        entries.flatten().any(|entry| {
+            PROFILE_DIR_MARKERS
+                .iter()
+                .any(|marker| entry.path().join(marker).exists())
+        })
+    })
+}
+
+fn is_mount_point(dir: &Path) -> bool {
+    let Some(parent) = dir.parent() else {
+        return true;
+    };
+    match (dir.metadata(), parent.metadata()) {
+        (Ok(dir), Ok(parent)) => dir.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+/// Why `canonical` is never build output, whatever is in it, if it isn't.
+fn never_build_output(canonical: &Path, home: Option<&Path>) -> Option<&'static str> {
+    if canonical.parent().is_none() {
+        Some("it is the filesystem root")
+    } else if home
+        .and_then(|home| home.canonicalize().ok())
+        .is_some_and(|home| home == canonical)
+    {
+        Some("it is the home directory")
+    } else {
+        None
+    }
+}
+
+/// Why compressing everything under `canonical` would reach far beyond build output, if it would.
+fn too_broad(canonical: &Path, home: Option<&Path>) -> Option<&'static str> {
+    never_build_output(canonical, home)
+        .or_else(|| is_mount_point(canonical).then_some("it is a volume root"))
+}
+
+/// Refuses to treat `target_dir` as a cargo target directory if it is `/` or `home`, or if it has
+/// nothing cargo would have created in it while holding something else. A volume of its own is
+/// fine once cargo has built into it, and a missing or empty dir has nothing to touch.
+pub(crate) fn check_target_dir(target_dir: &Path, home: Option<&Path>) -> Result<()> {
+    let Ok(canonical) = target_dir.canonicalize() else {
+        return Ok(());
+    };
+    if std::fs::read_dir(&canonical).is_ok_and(|mut entries| entries.next().is_none()) {
+        return Ok(());
+    }
+    let reason = never_build_output(&canonical, home).or_else(|| {
+        if looks_like_target_dir(&canonical) {
+            None
+        } else if is_mount_point(&canonical) {
+            Some("it is a volume root")
+        } else {
+            Some("it doesn't contain anything cargo creates")
+        }
+    });
+    match reason {
+        Some(reason) => Err(Error::DangerousTargetDir {
+            path: target_dir.to_path_buf(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Like [`check_target_dir`] for a `--generic` dir, which can hold anything and so is only
+/// refused for being `/`, `home`, or a volume root.
+pub(crate) fn check_generic_dir(dir: &Path, home: Option<&Path>) -> Result<()> {
+    let Ok(canonical) = dir.canonicalize() else {
+        return Ok(());
+    };
+    match too_broad(&canonical, home) {
+        Some(reason) => Err(Error::DangerousTargetDir {
+            path: dir.to_path_buf(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::disk::SizeFormat;
+use crate::reporter::Reporter;
+
+/// How often a heartbeat line is printed when stdout is not a terminal and `--heartbeat` is not
+/// given.
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(60);
+
+/// Run-wide progress, summarised into a single log line for non-interactive output.
+///
+/// Byte counts are logical file sizes, so they line up with the progress bars.
+#[derive(Default)]
+pub(crate) struct Heartbeat {
+    bytes_queued: AtomicU64,
+    bytes_done: AtomicU64,
+    dirs_remaining: AtomicUsize,
+    /// Set once a pre-scan has fixed `bytes_queued`.
+    total_fixed: AtomicBool,
+}
+
+impl Heartbeat {
+    pub(crate) fn queued(&self, bytes: u64) {
+        if !self.total_fixed.load(Relaxed) {
+            self.bytes_queued.fetch_add(bytes, Relaxed);
+        }
+    }
+
+    pub(crate) fn set_total(&self, bytes: u64) {
+        self.total_fixed.store(true, Relaxed);
+        self.bytes_queued.store(bytes, Relaxed);
+    }
+
+    /// Bytes done so far and the total queued.
+    pub(crate) fn completed(&self) -> (u64, u64) {
+        (
+            self.bytes_done.load(Relaxed),
+            self.bytes_queued.load(Relaxed),
+        )
+    }
+
+    pub(crate) fn done(&self, bytes: u64) {
+        self.bytes_done.fetch_add(bytes, Relaxed);
+    }
+
+    pub(crate) fn set_dirs_remaining(&self, dirs: usize) {
+        self.dirs_remaining.store(dirs, Relaxed);
+    }
+
+    pub(crate) fn dir_finished(&self) {
+        self.dirs_remaining.fetch_sub(1, Relaxed);
+    }
+
+    pub(crate) fn line(&self, sizes: SizeFormat) -> String {
+        let dirs = self.dirs_remaining.load(Relaxed);
+        format!(
+            "compressed {}/{}, {} {} remaining",
+            sizes.format(self.bytes_done.load(Relaxed)),
+            sizes.format(self.bytes_queued.load(Relaxed)),
+            dirs,
+            if dirs == 1 { "dir" } else { "dirs" },
+        )
+    }
+
+    /// Prints [`Heartbeat::line`] every `interval` until `stop` is signalled or disconnected.
+    pub(crate) fn run(&self, interval: Duration, stop: &Receiver<()>, progress: &dyn Reporter) {
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+            progress.println_normal(|| self.line(progress.size_format()));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! What to do about the failures macOS users run into most, such as privacy protection (TCC)
+//! blocking a dir or the volume being read-only, added to the error instead of a bare errno.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::Error;
+use crate::platform;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Hint {
+    FullDiskAccess,
+    SystemIntegrityProtection,
+    Permissions,
+    ReadOnlyVolume,
+    QuotaExceeded,
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::FullDiskAccess => {
+                "macOS privacy protection blocked this; give your terminal, or the program a \
+                 launchd job runs, Full Disk Access in System Settings > Privacy & Security"
+            }
+            Self::SystemIntegrityProtection => {
+                "System Integrity Protection guards this path and nothing may change it; leave \
+                 it out"
+            }
+            Self::Permissions => {
+                "you can't write here; if it was built with sudo, `sudo chown -R $(whoami)` it, or \
+                 run this as the same user"
+            }
+            Self::ReadOnlyVolume => {
+                "the volume is mounted read-only; compressing rewrites files in place, so remount \
+                 it read-write or leave it out"
+            }
+            Self::QuotaExceeded => {
+                "the disk quota is used up; each file needs room for its compressed copy for a \
+                 moment, so free some space or raise the quota"
+            }
+        })
+    }
+}
+
+/// The hint for the OS error number `errno` when it happened at `path`, if there is one.
+pub(crate) fn for_errno(errno: i32, path: Option<&Path>) -> Option<Hint> {
+    match errno {
+        libc::EPERM if path.is_some_and(platform::sip_protected) => {
+            Some(Hint::SystemIntegrityProtection)
+        }
+        libc::EPERM => Some(Hint::FullDiskAccess),
+        libc::EACCES => Some(Hint::Permissions),
+        libc::EROFS => Some(Hint::ReadOnlyVolume),
+        libc::EDQUOT => Some(Hint::QuotaExceeded),
+        _ => None,
+    }
+}
+
+/// The hint for the first OS error among `error`'s sources.
+pub(crate) fn for_error(error: &Error) -> Option<Hint> {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(current) = source {
+        if let Some(errno) = current
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::raw_os_error)
+        {
+            return for_errno(errno, error.path());
+        }
+        source = current.source();
+    }
+    None
+}
+
+/// The hint for a message that ends in an OS error, as `Operation not permitted (os error 1)`.
+pub(crate) fn for_message(message: &str, path: &Path) -> Option<Hint> {
+    for_errno(errno(message)?, Some(path))
+}
+
+/// The OS error number a message like `Operation not permitted (os error 1)` ends in.
+pub(crate) fn errno(message: &str) -> Option<i32> {
+    let (_, rest) = message.rsplit_once("(os error ")?;
+    rest.strip_suffix(')')?.parse().ok()
+}
+
+/// Whether a message from [`errno`] is `EACCES` or `EPERM`, as for root-owned leftovers of a
+/// `sudo cargo build`.
+pub(crate) fn is_permission_denied(message: &str) -> bool {
+    matches!(errno(message), Some(libc::EACCES | libc::EPERM))
+}
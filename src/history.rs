@@ -0,0 +1,80 @@
+//! `--history`: a JSON-lines log with one entry per run, used to show trends at the end of a run.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::stats::CompressionStats;
+
+/// How many past runs the end-of-run trend covers, including the current one.
+pub(crate) const TREND_RUNS: usize = 20;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    /// Seconds since the Unix epoch.
+    pub(crate) time: u64,
+    pub(crate) bytes_saved: u64,
+    /// What the compressed files took up after the run; the closest thing to the target dir's size
+    /// that a run measures.
+    pub(crate) bytes_after: u64,
+}
+
+impl Entry {
+    pub(crate) fn now(stats: &CompressionStats) -> Self {
+        Self {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            bytes_saved: stats.bytes_saved(),
+            bytes_after: stats.bytes_out,
+        }
+    }
+}
+
+/// The last `limit` entries in `path`, oldest first. A missing file has no entries, and lines that
+/// don't parse (say, from a newer version) are ignored.
+pub(crate) fn load(path: &Path, limit: usize) -> io::Result<Vec<Entry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let entries: Vec<Entry> = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let start = entries.len().saturating_sub(limit);
+    Ok(entries[start..].to_vec())
+}
+
+pub(crate) fn append(path: &Path, entry: &Entry) -> io::Result<()> {
+    let mut line = serde_json::to_string(entry).map_err(io::Error::other)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// One block character per value, scaled between the smallest and largest of them.
+pub(crate) fn sparkline(values: &[u64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let (Some(&min), Some(&max)) = (values.iter().min(), values.iter().max()) else {
+        return String::new();
+    };
+    values
+        .iter()
+        .map(|&value| {
+            if max == min {
+                BLOCKS[0]
+            } else {
+                let step = (value - min) as u128 * (BLOCKS.len() - 1) as u128 / (max - min) as u128;
+                BLOCKS[step as usize]
+            }
+        })
+        .collect()
+}
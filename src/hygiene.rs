@@ -0,0 +1,107 @@
+//! Markers that keep Spotlight, Time Machine, and other backup tools out of build output.
+
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+
+/// An empty file that tells Spotlight not to index the directory containing it.
+pub(crate) const NEVER_INDEX_NAME: &str = ".metadata_never_index";
+
+/// The standard tag (<https://bford.info/cachedir/>) that backup and sync tools look for. Cargo
+/// writes one into new target directories, but older ones predate that.
+pub(crate) const CACHEDIR_TAG_NAME: &str = "CACHEDIR.TAG";
+
+const CACHEDIR_TAG: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55
+# This file is a cache directory tag created by cargo-apfs-compress.
+# For information about cache directory tags see https://bford.info/cachedir/
+";
+
+/// Creates `name` in `dir` unless it already exists. Returns whether it was created.
+fn create_marker(dir: &Path, name: &str, contents: &[u8]) -> io::Result<bool> {
+    use std::io::Write;
+
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dir.join(name))
+    {
+        Ok(mut file) => {
+            file.write_all(contents)?;
+            Ok(true)
+        }
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Writes [`NEVER_INDEX_NAME`] into `dir`. Returns whether it was missing.
+pub(crate) fn exclude_from_spotlight(dir: &Path) -> io::Result<bool> {
+    create_marker(dir, NEVER_INDEX_NAME, b"")
+}
+
+/// Writes [`CACHEDIR_TAG_NAME`] into `dir`. Returns whether it was missing.
+pub(crate) fn tag_cache_dir(dir: &Path) -> io::Result<bool> {
+    create_marker(dir, CACHEDIR_TAG_NAME, CACHEDIR_TAG)
+}
+
+/// The extended attribute `tmutil addexclusion` (without `-p`) sets to make Time Machine skip an
+/// item wherever it moves.
+#[cfg(target_os = "macos")]
+const BACKUP_EXCLUDE_XATTR: &std::ffi::CStr = c"com.apple.metadata:com_apple_backup_excludeItem";
+
+/// The attribute's value: the string `com.apple.backupd` as a binary plist.
+#[cfg(target_os = "macos")]
+const BACKUP_EXCLUDE_VALUE: &[u8] = b"bplist00\x5f\x10\x11com.apple.backupd\x08\
+    \0\0\0\0\0\0\x01\x01\0\0\0\0\0\0\0\x01\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x1c";
+
+/// Marks `dir` as excluded from Time Machine backups, like `tmutil addexclusion`. Returns whether
+/// it was not excluded already.
+#[cfg(target_os = "macos")]
+pub(crate) fn exclude_from_backup(dir: &Path) -> io::Result<bool> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `path` and the attribute name are NUL-terminated, and a null buffer only asks for
+    // the value's size.
+    let existing = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            BACKUP_EXCLUDE_XATTR.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            0,
+            0,
+        )
+    };
+    if existing >= 0 {
+        return Ok(false);
+    }
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::ENOATTR) {
+        return Err(error);
+    }
+
+    // SAFETY: as above, and `BACKUP_EXCLUDE_VALUE` outlives the call.
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            BACKUP_EXCLUDE_XATTR.as_ptr(),
+            BACKUP_EXCLUDE_VALUE.as_ptr().cast(),
+            BACKUP_EXCLUDE_VALUE.len(),
+            0,
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn exclude_from_backup(_dir: &Path) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Time Machine exclusion is only available on macOS",
+    ))
+}
@@ -0,0 +1,289 @@
+//! `cargo apfs-compress init`: a commented `cargo-apfs-compress.toml` at the workspace root with
+//! settings picked for what the workspace has built so far, for a team to commit.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::config::PROJECT_CONFIG_NAME;
+use crate::disk::{self, SizeFormat};
+use crate::error::{Error, Result};
+use crate::{Cli, list_work_dirs, resolve_target_dir, workspace_root};
+
+/// How many of the largest dirs the file's header lists.
+const BIGGEST: usize = 5;
+
+/// What `init` found in the workspace.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Probe {
+    /// Custom profiles declared in the workspace manifest, besides `dev` and `release`.
+    pub(crate) declared: Vec<String>,
+    /// Work dirs relative to the target dir, such as `debug` and `aarch64-apple-darwin/release`.
+    pub(crate) work_dirs: Vec<String>,
+    /// Dirs directly in the target dir that are neither profiles nor triples, such as a tool's
+    /// own build dir.
+    pub(crate) other_dirs: Vec<String>,
+    /// The largest dirs directly inside the work dirs, relative to the target dir, largest first.
+    pub(crate) biggest: Vec<(String, u64)>,
+}
+
+impl Probe {
+    /// The profile each work dir is for, with `debug` as `dev`, without repeats.
+    fn profiles(&self) -> BTreeSet<String> {
+        self.work_dirs
+            .iter()
+            .filter_map(|dir| dir.rsplit('/').next())
+            .map(|name| match name {
+                "debug" => "dev".to_owned(),
+                name => name.to_owned(),
+            })
+            .collect()
+    }
+
+    fn triples(&self) -> BTreeSet<&str> {
+        self.work_dirs
+            .iter()
+            .filter_map(|dir| Some(dir.split_once('/')?.0))
+            .collect()
+    }
+}
+
+/// Writes the config, or prints it with `print`. An existing file is only replaced with `force`.
+pub(crate) fn run(cli: &Cli, print: bool, force: bool) -> Result<()> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let root = workspace_root(&cwd)?;
+    let path = root.join(PROJECT_CONFIG_NAME);
+    if !print && !force && path.exists() {
+        return Err(Error::ConfigExists { path });
+    }
+    let probe = probe(cli, &root)?;
+    let text = render(&probe, cli.size_format());
+    if print {
+        print!("{text}");
+        return Ok(());
+    }
+    fs::write(&path, text).map_err(|source| Error::WriteConfig {
+        path: path.clone(),
+        source,
+    })?;
+    println!(
+        "Wrote {}; check it over and commit it so everyone gets the same settings",
+        path.display()
+    );
+    Ok(())
+}
+
+fn probe(cli: &Cli, root: &Path) -> Result<Probe> {
+    let manifest = root.join("Cargo.toml");
+    let content = fs::read_to_string(&manifest).map_err(Error::read(&manifest))?;
+    let value: toml::Value = toml::from_str(&content).map_err(|source| Error::ConfigParse {
+        path: manifest.clone(),
+        source,
+    })?;
+    let declared = value
+        .get("profile")
+        .and_then(toml::Value::as_table)
+        .map(|profiles| {
+            profiles
+                .keys()
+                .filter(|name| !["dev", "release", "test", "bench"].contains(&name.as_str()))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let target_dir = resolve_target_dir(cli)?;
+    let dirs = list_work_dirs(cli)?;
+    let relative = |path: &Path| {
+        path.strip_prefix(&target_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    };
+    let work_dirs: Vec<String> = dirs.iter().map(|dir| relative(&dir.path)).collect();
+
+    let tops: BTreeSet<&str> = work_dirs
+        .iter()
+        .filter_map(|dir| dir.split('/').next())
+        .collect();
+    let mut other_dirs = Vec::new();
+    for entry in fs::read_dir(&target_dir).map_err(Error::read(&target_dir))? {
+        let entry = entry.map_err(Error::read(&target_dir))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().is_dir()
+            && !name.starts_with('.')
+            && !tops.contains(name.as_str())
+            && !["doc", "package", "tmp"].contains(&name.as_str())
+        {
+            other_dirs.push(name);
+        }
+    }
+    other_dirs.sort();
+
+    let mut biggest = Vec::new();
+    for dir in &dirs {
+        let Ok(entries) = fs::read_dir(&dir.path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                biggest.push((relative(&entry.path()), allocated(&entry.path())));
+            }
+        }
+    }
+    biggest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    biggest.truncate(BIGGEST);
+
+    Ok(Probe {
+        declared,
+        work_dirs,
+        other_dirs,
+        biggest,
+    })
+}
+
+/// The allocated size of the files under `path`.
+fn allocated(path: &Path) -> u64 {
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => pending.push(entry.path()),
+                Ok(file_type) if file_type.is_file() => {
+                    total += disk::allocated_size(&entry.path()).unwrap_or(0);
+                }
+                _ => {}
+            }
+        }
+    }
+    total
+}
+
+/// The config file for `probe`: what was found, in a header, and then each setting with why it was
+/// picked, with the ones nothing called for left commented out.
+pub(crate) fn render(probe: &Probe, sizes: SizeFormat) -> String {
+    let list = |items: Vec<String>| {
+        if items.is_empty() {
+            "none".to_owned()
+        } else {
+            items.join(", ")
+        }
+    };
+    let profiles = probe.profiles();
+    let mut text = String::from(
+        "# Settings for cargo apfs-compress in this workspace, written by `cargo apfs-compress init`.\n\
+         # Anything here can be overridden in an `[apfs-compress]` table in .cargo/config.toml or\n\
+         # on the command line; `cargo apfs-compress config show` says which value wins.\n#\n",
+    );
+    text.push_str(&format!(
+        "# Profiles built: {}\n",
+        list(profiles.iter().cloned().collect())
+    ));
+    if !probe.declared.is_empty() {
+        text.push_str(&format!(
+            "# Profiles declared in Cargo.toml: {}\n",
+            probe.declared.join(", ")
+        ));
+    }
+    text.push_str(&format!(
+        "# Targets: {}\n",
+        list(
+            probe
+                .triples()
+                .iter()
+                .map(|&triple| triple.to_owned())
+                .collect()
+        )
+    ));
+    text.push_str(&format!(
+        "# Biggest dirs: {}\n\n",
+        list(
+            probe
+                .biggest
+                .iter()
+                .map(|(dir, size)| format!("{dir} {}", sizes.format(*size)))
+                .collect()
+        )
+    ));
+
+    text.push_str(
+        "# lzfse saves the most for how fast it is; zlib saves a little more, more slowly.\n\
+         compression = \"lzfse\"\n\n\
+         # Files under small-file-cutoff use this instead; lzvn is quickest on small files.\n\
+         small-file-compression = \"lzvn\"\n\
+         # small-file-cutoff = \"64KiB\"\n\n\
+         # Extensions to leave alone on top of the built-in list of archives, images, and fonts.\n\
+         # skip-extensions = [\"wasm\"]\n\n",
+    );
+    if probe.other_dirs.is_empty() {
+        text.push_str(
+            "# Dirs under the target dir that other tools build into, such as `clippy`.\n\
+             # check-dirs = [\"clippy\"]\n\n",
+        );
+    } else {
+        let dirs = probe
+            .other_dirs
+            .iter()
+            .map(|dir| format!("{dir:?}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        text.push_str(&format!(
+            "# Dirs under the target dir that aren't profiles or targets, where other tools build.\n\
+             check-dirs = [{dirs}]\n\n"
+        ));
+    }
+
+    if profiles.contains("dev") {
+        text.push_str(
+            "# Rebuilt all the time, so keep it cheap to compress.\n\
+             [profile.dev]\n\
+             compression = \"lzvn\"\n\n",
+        );
+    }
+    for profile in profiles.iter().filter(|profile| *profile != "dev") {
+        text.push_str(&format!(
+            "[profile.{}]\ncompression = \"lzfse\"\n\n",
+            toml_key(profile)
+        ));
+    }
+    for profile in probe
+        .declared
+        .iter()
+        .filter(|profile| !profiles.contains(*profile))
+    {
+        text.push_str(&format!(
+            "# [profile.{}]\n# compression = \"lzfse\"\n\n",
+            toml_key(profile)
+        ));
+    }
+
+    text.push_str("[dir-policies]\n");
+    if probe
+        .work_dirs
+        .iter()
+        .any(|dir| dir == "debug" || dir.ends_with("/debug"))
+    {
+        text.push_str("\"incremental\" = \"skip\" # rewritten by every dev build\n");
+    } else {
+        text.push_str("# \"incremental\" = \"skip\" # rewritten by every dev build\n");
+    }
+    text.push_str("# \"build/*/out\" = \"lzvn\" # build script output, often generated sources\n");
+    text
+}
+
+/// `name` as a TOML key, quoted unless it is a bare key.
+fn toml_key(name: &str) -> String {
+    if !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+    {
+        name.to_owned()
+    } else {
+        format!("{name:?}")
+    }
+}
@@ -0,0 +1,201 @@
+//! `--inventory`: one JSON line for every file a run handed to the compressor, with its sizes,
+//! algorithm, and what became of it, for modelling savings across many machines.
+//!
+//! ```json
+//! {"path":"/src/app/target/debug/deps/libserde-1a2b.rlib","size":5242880,"on_disk_before":5246976,"on_disk_after":1720320,"algorithm":"lzfse","outcome":"compressed"}
+//! ```
+
+use applesauce::progress::{SkipReason, Task};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::disk::{self, SizeFormat};
+use crate::ratio_cache::compression_arg;
+use crate::reporter::Reporter;
+use crate::summary::describe_skip;
+use crate::{CompressionArg, Verbosity, WorkDirOptions, wanted_kind};
+
+/// The open inventory file. Write errors are kept until [`Inventory::finish`] rather than
+/// failing the file being compressed.
+#[derive(Debug)]
+pub struct Inventory {
+    path: PathBuf,
+    file: Mutex<(BufWriter<File>, Option<io::Error>)>,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    path: &'a str,
+    /// The logical size.
+    size: u64,
+    /// The blocks the file had to itself, not shared with APFS clones.
+    on_disk_before: u64,
+    on_disk_after: u64,
+    algorithm: Option<&'a str>,
+    /// `compressed`, `not-compressible-enough`, `skipped`, or `error`.
+    outcome: &'a str,
+    /// The skip reason or error message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+}
+
+impl Inventory {
+    /// Creates `path`, replacing what was there, and its dir if need be.
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new((BufWriter::new(File::create(path)?), None)),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, record: &Record<'_>) {
+        let mut line = serde_json::to_vec(record).expect("records serialize");
+        line.push(b'\n');
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (writer, error) = &mut *file;
+        if error.is_none()
+            && let Err(failed) = writer.write_all(&line)
+        {
+            *error = Some(failed);
+        }
+    }
+
+    /// A file left as it was, measured now.
+    fn unchanged(&self, path: &Path, algorithm: Option<&str>, outcome: &str, detail: Option<&str>) {
+        let size = fs::symlink_metadata(path).map_or(0, |metadata| metadata.len());
+        let on_disk = disk::allocated_size(path).unwrap_or(size);
+        self.write(&Record {
+            path: &path.to_string_lossy(),
+            size,
+            on_disk_before: on_disk,
+            on_disk_after: on_disk,
+            algorithm,
+            outcome,
+            detail,
+        });
+    }
+
+    /// Flushes the file, reporting the first write that failed.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (writer, error) = &mut *file;
+        match error.take() {
+            Some(error) => Err(error),
+            None => writer.flush(),
+        }
+    }
+}
+
+/// A [`Reporter`] that passes everything on to `inner` and writes each file's outcome to the
+/// inventory, with the algorithm `options` picks for it.
+pub(crate) struct Inventoried<'a> {
+    pub(crate) inner: &'a dyn Reporter,
+    pub(crate) options: &'a WorkDirOptions,
+    pub(crate) inventory: Arc<Inventory>,
+}
+
+impl Reporter for Inventoried<'_> {
+    fn verbosity(&self) -> Verbosity {
+        self.inner.verbosity()
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.inner.size_format()
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        self.inner.message(verbosity, message);
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.inventory.unchanged(path, None, "error", Some(message));
+        self.inner.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        let reason = describe_skip(&why, SizeFormat::Bytes);
+        self.inventory
+            .unchanged(path, None, "skipped", Some(&reason));
+        self.inner.file_skipped(path, why);
+    }
+
+    fn file_too_small(&self, path: &Path) {
+        self.inner.file_too_small(path);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(InventoriedTask {
+            inner: self.inner.file_task(path, size),
+            inventory: Arc::clone(&self.inventory),
+            path: path.to_path_buf(),
+            algorithm: algorithm(self.options, size),
+        })
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        let size = fs::symlink_metadata(path).map_or(0, |metadata| metadata.len());
+        self.inventory.write(&Record {
+            path: &path.to_string_lossy(),
+            size,
+            on_disk_before: bytes_in,
+            on_disk_after: bytes_out,
+            algorithm: algorithm(self.options, size),
+            outcome: "compressed",
+            detail: None,
+        });
+        self.inner.file_compressed(path, bytes_in, bytes_out);
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        self.inner.files_queued(bytes);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+}
+
+fn algorithm(options: &WorkDirOptions, size: u64) -> Option<&'static str> {
+    compression_arg(wanted_kind(options, size)).map(CompressionArg::name)
+}
+
+struct InventoriedTask {
+    inner: Box<dyn Task + Send + Sync>,
+    inventory: Arc<Inventory>,
+    path: PathBuf,
+    algorithm: Option<&'static str>,
+}
+
+impl Task for InventoriedTask {
+    fn increment(&self, amt: u64) {
+        self.inner.increment(amt);
+    }
+
+    fn error(&self, message: &str) {
+        self.inventory
+            .unchanged(&self.path, self.algorithm, "error", Some(message));
+        self.inner.error(message);
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        self.inventory
+            .unchanged(path, self.algorithm, "not-compressible-enough", None);
+        self.inner.not_compressible_enough(path);
+    }
+}
@@ -0,0 +1,170 @@
+//! [`Job`]: a run on its own thread that the caller can watch, pause, and cancel while getting on
+//! with other work, for long-running hosts such as build orchestrators.
+
+use applesauce::progress::Task;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+
+use crate::metrics::NoopMetrics;
+use crate::reporter::Reporter;
+use crate::stats::CompressionStats;
+use crate::{ApplesauceCompressor, Cli, PauseSignal, Result, StopSignal, run_with_compressor};
+
+/// A run started by [`Job::start`].
+///
+/// Dropping a `Job` detaches it: the run carries on to the end on its own thread.
+pub struct Job {
+    handle: JoinHandle<Result<CompressionStats>>,
+    counters: Arc<Counters>,
+    stop: StopSignal,
+    pause: PauseSignal,
+}
+
+/// How far a [`Job`] has got, as of a call to [`Job::progress`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct JobProgress {
+    pub dirs_started: u64,
+    pub dirs_finished: u64,
+    pub files_compressed: u64,
+    pub errors: u64,
+    /// Logical size of every file enumerated so far, compressed or not.
+    pub bytes_queued: u64,
+    /// Space the compressed files occupied on disk before and after.
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl Job {
+    /// Starts a run like [`crate::run`] on a new thread and returns without waiting for it. Log
+    /// messages are dropped; watch it with [`Job::progress`] instead.
+    ///
+    /// The job shares `cli.stop` and `cli.pause`, so signals cloned from them before the call
+    /// work as well as [`Job::cancel`] and [`Job::pause`].
+    pub fn start(cli: Cli) -> Self {
+        let counters = Arc::new(Counters::default());
+        let stop = cli.stop.clone();
+        let pause = cli.pause.clone();
+        let reporter = JobReporter {
+            counters: Arc::clone(&counters),
+        };
+        let handle = thread::spawn(move || {
+            run_with_compressor(cli, &ApplesauceCompressor, &reporter, Arc::new(NoopMetrics))
+        });
+        Self {
+            handle,
+            counters,
+            stop,
+            pause,
+        }
+    }
+
+    pub fn progress(&self) -> JobProgress {
+        self.counters.snapshot()
+    }
+
+    /// Holds every work dir before its next batch of files until [`Job::resume`]. Files already
+    /// being compressed finish first.
+    pub fn pause(&self) {
+        self.pause.pause();
+    }
+
+    pub fn resume(&self) {
+        self.pause.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Asks the run to stop as it would at `--timeout`: work dirs not yet started are skipped and
+    /// those in progress stop before their next batch of files. Also resumes a paused job so it
+    /// can wind down. [`Job::join`] then returns [`crate::Error::Interrupted`] if anything was
+    /// left undone; the summary and any report still cover what was.
+    pub fn cancel(&self) {
+        self.stop.stop();
+        self.pause.resume();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the run to end and returns what [`crate::run`] would have.
+    pub fn join(self) -> Result<CompressionStats> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    dirs_started: AtomicU64,
+    dirs_finished: AtomicU64,
+    files_compressed: AtomicU64,
+    errors: AtomicU64,
+    bytes_queued: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> JobProgress {
+        JobProgress {
+            dirs_started: self.dirs_started.load(Ordering::Relaxed),
+            dirs_finished: self.dirs_finished.load(Ordering::Relaxed),
+            files_compressed: self.files_compressed.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_queued: self.bytes_queued.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct JobReporter {
+    counters: Arc<Counters>,
+}
+
+struct NoTask;
+
+impl Task for NoTask {
+    fn increment(&self, _amt: u64) {}
+
+    fn error(&self, _message: &str) {}
+}
+
+impl Reporter for JobReporter {
+    fn error(&self, _path: &Path, _message: &str) {
+        self.counters.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn file_task(&self, _path: &Path, _size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(NoTask)
+    }
+
+    fn file_compressed(&self, _path: &Path, bytes_in: u64, bytes_out: u64) {
+        let counters = &self.counters;
+        counters.files_compressed.fetch_add(1, Ordering::Relaxed);
+        counters.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+        counters.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        self.counters
+            .bytes_queued
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn work_dir_started(&self, _dir: &Path) {
+        self.counters.dirs_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn work_dir_finished(&self, _dir: &Path, _stats: Option<&CompressionStats>) {
+        self.counters.dirs_finished.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,133 @@
+//! `--jobs-file`: several runs described in one TOML file, one `[[job]]` table each, done one
+//! after another with a combined summary at the end.
+//!
+//! ```toml
+//! [[job]]
+//! workspace = "/Users/me/src/app"
+//! profiles = ["debug"]
+//! compression = "zlib"
+//! exclude = ["incremental", "build/*/out"]
+//!
+//! [[job]]
+//! path = "/Users/me/Library/Caches/build-output"
+//! ```
+
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::policy::{DirPolicy, PolicyAction};
+use crate::{Cli, CompressionArg};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    #[serde(default)]
+    job: Vec<Entry>,
+}
+
+/// One `[[job]]` table. Exactly one of `workspace` and `path` is set; relative paths are taken
+/// from the jobs file's dir.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Entry {
+    /// A cargo project, as with `--project`.
+    workspace: Option<PathBuf>,
+    /// A plain dir, as with `--generic`.
+    path: Option<PathBuf>,
+    #[serde(default)]
+    profiles: Vec<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+    compression: Option<String>,
+    small_file_compression: Option<String>,
+    small_file_cutoff: Option<u64>,
+    /// Dir patterns under each work dir to leave alone, as `"skip"` dir policies.
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// A job from the file: what to call it in the log and the run it stands for.
+#[derive(Debug)]
+pub(crate) struct Job {
+    pub(crate) label: String,
+    pub(crate) cli: Cli,
+}
+
+/// The jobs in the file at `path`, each a copy of `base` with the job's settings on top. Settings
+/// a job leaves out keep the value `base` has.
+pub(crate) fn load(path: &Path, base: &Cli) -> Result<Vec<Job>> {
+    let text = fs::read_to_string(path).map_err(Error::read(path))?;
+    let file: JobsFile = toml::from_str(&text).map_err(|source| Error::ConfigParse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    file.job
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| job(path, dir, index, entry, base))
+        .collect()
+}
+
+fn job(file: &Path, dir: &Path, index: usize, entry: Entry, base: &Cli) -> Result<Job> {
+    let invalid = |key: &str, value: String| Error::InvalidConfig {
+        path: file.to_path_buf(),
+        key: format!("job[{index}].{key}"),
+        value,
+    };
+    let compression = |key: &str, name: Option<String>| {
+        name.map(|name| {
+            CompressionArg::from_str(&name).map_err(|_| invalid(key, format!("{name:?}")))
+        })
+        .transpose()
+    };
+    let mut cli = base.clone();
+    cli.jobs_file = None;
+    let label = match (entry.workspace, entry.path) {
+        (Some(workspace), None) => {
+            let workspace = dir.join(workspace);
+            cli.projects = vec![workspace.clone()];
+            cli.generic.clear();
+            workspace
+        }
+        (None, Some(path)) => {
+            let path = dir.join(path);
+            cli.generic = vec![path.clone()];
+            cli.projects.clear();
+            path
+        }
+        _ => {
+            return Err(invalid(
+                "workspace",
+                "(a job needs exactly one of `workspace` and `path`)".to_owned(),
+            ));
+        }
+    };
+    if !entry.profiles.is_empty() {
+        cli.profiles = entry.profiles;
+    }
+    if !entry.targets.is_empty() {
+        cli.targets = entry.targets;
+    }
+    if let Some(kind) = compression("compression", entry.compression)? {
+        cli.compression = Some(kind);
+    }
+    if let Some(kind) = compression("small-file-compression", entry.small_file_compression)? {
+        cli.small_file_compression = Some(kind);
+    }
+    if let Some(cutoff) = entry.small_file_cutoff {
+        cli.small_file_cutoff = Some(cutoff);
+    }
+    for pattern in entry.exclude {
+        let policy = DirPolicy::new(&pattern, PolicyAction::Skip)
+            .ok_or_else(|| invalid("exclude", format!("{pattern:?}")))?;
+        cli.extra_dir_policies.push(policy);
+    }
+    Ok(Job {
+        label: label.display().to_string(),
+        cli,
+    })
+}
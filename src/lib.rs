@@ -1,34 +1,150 @@
-use anyhow::{Context, Result, anyhow};
-use applesauce::FileCompressor;
 use applesauce::compressor::Kind;
-use applesauce::progress::Progress as _;
-use clap::{ArgAction, Parser, ValueEnum};
-use serde::Deserialize;
-use std::collections::{BTreeSet, HashMap};
-use std::ffi::OsStr;
+use applesauce::{FileCompressor, info};
+#[cfg(feature = "cli")]
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::MetadataExt;
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant, SystemTime};
 
+mod alias;
+mod analyze;
+mod artifact;
+mod audit;
+mod cargo_home;
+mod checkpoint;
+mod config;
+mod control;
+mod dedup;
+mod dir_state;
+mod disk;
+mod doctor;
+mod error;
+mod events;
+mod explain;
+mod fd_limit;
 mod flock;
+mod gha;
+mod guard;
+mod heartbeat;
+mod hints;
+mod history;
+mod hygiene;
+mod init;
+mod inventory;
+mod job;
+mod jobs_file;
+mod lock_holder;
+mod log_file;
+mod log_format;
+mod metrics;
+mod os_log;
+mod package;
+mod plain_progress;
+mod platform;
+mod policy;
+#[cfg(feature = "cli")]
 mod progress;
+mod ratio_cache;
+mod report;
+mod reporter;
+mod select;
+mod since;
+mod stats;
+mod summary;
+mod sweep;
+mod swiftpm;
+mod tool_caches;
+#[cfg(feature = "tui")]
+mod tui;
+mod verbosity;
+mod walk;
+mod wasm;
 
+pub use crate::alias::{DEFAULT_ALIAS_NAME, parse_alias_name};
+use crate::audit::MetadataSnapshot;
+use crate::checkpoint::Checkpoint;
+pub use crate::disk::{MinSavings, SizeFormat, VolumeJobs, parse_byte_size};
+pub use crate::error::{BoxError, Chain, Error, ParseCompressionError, Result};
+pub use crate::events::{Event, run_with_events};
 use crate::flock::Filesystem;
-use crate::progress::{ProgressBars, Verbosity};
+pub use crate::heartbeat::DEFAULT_HEARTBEAT;
+pub use crate::inventory::Inventory;
+pub use crate::job::{Job, JobProgress};
+pub use crate::log_file::DEFAULT_LOG_FILE_MAX_SIZE;
+pub use crate::log_format::{LogFormat, LogLevel};
+pub use crate::metrics::{Counter, Gauge, MetricsSink, NoopMetrics};
+pub use crate::package::PackageFilter;
+use crate::plain_progress::PlainProgress;
+pub use crate::policy::{DirPolicy, PolicyAction};
+#[cfg(feature = "cli")]
+use crate::progress::ProgressBars;
+pub use crate::report::ReportFormat;
+use crate::reporter::{AsProgress, ErrorsOnly, Metered};
+pub use crate::reporter::{ProgressReporter, Reporter};
+pub use crate::since::TimeRef;
+pub use crate::stats::CompressionStats;
+use crate::summary::RunSummary;
+pub use crate::sweep::parse_duration;
+pub use crate::verbosity::Verbosity;
+pub use crate::walk::{DEFAULT_SKIP_EXTENSIONS, DEPS_DIRS, INLINE_THRESHOLD};
 
 const CARGO_LOCK_NAME: &str = ".cargo-lock";
 
+/// Files smaller than this use [`WorkDirOptions::small_file_compression`].
+pub const DEFAULT_SMALL_FILE_CUTOFF: u64 = 64 * 1024;
+
+/// The level applesauce compresses at unless a dir policy sets one. Only zlib has levels.
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 2;
+
+/// `--only-when-idle` waits while the load average per logical CPU is above this.
+const IDLE_MAX_LOAD_PER_CPU: f64 = 0.25;
+
 const ROOT_SKIP_DIRS: &[&str] = &["tmp"];
-const PROFILE_SKIP_DIRS: &[&str] = &[".fingerprint", "build", "deps", "examples", "incremental"];
+/// Where cargo keeps each profile's fingerprints, left out of the walk by `--exclude-fingerprint`.
+const FINGERPRINT_DIR: &str = ".fingerprint";
+/// Dirs under the target dir that hold a whole nested target layout of their own, such as the one
+/// rust-analyzer builds into when `rust-analyzer.cargo.targetDir` is `true`.
+pub const DEFAULT_CHECK_DIRS: &[&str] = &["rust-analyzer"];
+const PROFILE_SKIP_DIRS: &[&str] = &[FINGERPRINT_DIR, "build", "deps", "examples", "incremental"];
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+/// A compression algorithm, named as on the command line and in config files: `lzfse`, `zlib`,
+/// or `lzvn`.
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
 pub enum CompressionArg {
+    #[default]
     Lzfse,
     Zlib,
     Lzvn,
 }
 
 impl CompressionArg {
+    pub const ALL: [Self; 3] = [Self::Lzfse, Self::Zlib, Self::Lzvn];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lzfse => "lzfse",
+            Self::Zlib => "zlib",
+            Self::Lzvn => "lzvn",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|arg| arg.name() == name)
+    }
+
     fn to_kind(self) -> Kind {
         match self {
             Self::Lzfse => Kind::Lzfse,
@@ -38,32 +154,896 @@ impl CompressionArg {
     }
 }
 
-#[derive(Debug, Parser)]
-#[command(name = "cargo-apfs-compress")]
+impl fmt::Display for CompressionArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CompressionArg {
+    type Err = ParseCompressionError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::from_name(name).ok_or_else(|| ParseCompressionError {
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl From<CompressionArg> for Kind {
+    fn from(arg: CompressionArg) -> Self {
+        arg.to_kind()
+    }
+}
+
+/// How the CLI shows progress while compressing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum ProgressArg {
+    /// `fancy` when stdout is a terminal, `plain` otherwise.
+    #[default]
+    Auto,
+    /// Live progress bars. Falls back to `plain` without the `cli` feature.
+    Fancy,
+    /// Log lines only, plus a heartbeat line every minute unless `--heartbeat` says otherwise.
+    Plain,
+    /// Nothing but errors and warnings, as with `-q`. A `--log-file` still gets everything.
+    None,
+}
+
+/// What does the compressing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum CompressorArg {
+    /// applesauce, writing decmpfs compression.
+    #[default]
+    Applesauce,
+    /// Nothing: every file is found, filtered, and stat'ed but left as it is, and each work dir
+    /// prints how long each stage took. For telling walking from compressing as the bottleneck.
+    Null,
+}
+
+impl ProgressArg {
+    fn resolve(self, is_terminal: bool) -> Self {
+        match self {
+            Self::Auto if is_terminal => Self::Fancy.resolve(is_terminal),
+            Self::Auto => Self::Plain,
+            Self::Fancy if cfg!(not(feature = "cli")) => Self::Plain,
+            style => style,
+        }
+    }
+}
+
+/// What to do when a run starts with macOS Low Power Mode on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum LowPowerArg {
+    /// Print a note and carry on as usual.
+    Note,
+    /// Print a note and compress one work dir at a time.
+    Serial,
+    /// Exit without doing anything.
+    Skip,
+}
+
+/// Extra output for the environment the CLI runs in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum OutputArg {
+    /// Log lines only.
+    #[default]
+    Text,
+    /// GitHub Actions annotations for the summary and failed dirs, plus a markdown job summary in
+    /// `$GITHUB_STEP_SUMMARY`.
+    Gha,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(Subcommand))]
+pub enum CliCommand {
+    /// Check that cargo, the target dir's volume, and file locking are usable, and print what to
+    /// fix if not.
+    Doctor,
+    /// Show how the work dirs a run would compress were found: where the target dir came from,
+    /// which config set each profile's dir name, and why discovery skipped the dirs it skipped.
+    Explain,
+    /// Inspect the settings a run would use.
+    Config {
+        #[cfg_attr(feature = "cli", command(subcommand))]
+        action: ConfigCommand,
+    },
+    /// Send a command to a run started with `--control-socket` and print its reply.
+    Ctl {
+        /// The run's `--control-socket`.
+        #[cfg_attr(feature = "cli", arg(long = "socket", value_name = "PATH"))]
+        socket: PathBuf,
+        #[cfg_attr(feature = "cli", arg(value_enum))]
+        command: CtlCommand,
+    },
+    /// Report on what the work dirs hold without changing anything.
+    Analyze {
+        #[cfg_attr(feature = "cli", command(subcommand))]
+        action: AnalyzeCommand,
+    },
+    /// Add a cargo alias to the workspace's `.cargo/config.toml` that runs this tool with the
+    /// settings this invocation resolves, to run after builds.
+    InitAlias {
+        /// What to call the alias, as in `cargo compress`.
+        #[cfg_attr(
+            feature = "cli",
+            arg(long = "name", default_value = DEFAULT_ALIAS_NAME, value_parser = parse_alias_name)
+        )]
+        name: String,
+        /// Print the `[alias]` snippet instead of writing it.
+        #[cfg_attr(feature = "cli", arg(long = "print"))]
+        print: bool,
+    },
+    /// Write a commented `cargo-apfs-compress.toml` at the workspace root with settings picked
+    /// for the profiles, targets, and dirs the workspace has built so far.
+    Init {
+        /// Print the file instead of writing it.
+        #[cfg_attr(feature = "cli", arg(long = "print"))]
+        print: bool,
+        /// Replace a `cargo-apfs-compress.toml` that is already there.
+        #[cfg_attr(feature = "cli", arg(long = "force"))]
+        force: bool,
+    },
+    /// Compress cargo's caches under `$CARGO_HOME` instead of a project's target dir, holding
+    /// cargo's package cache lock meanwhile. Covers every component unless some are picked.
+    Cache {
+        /// The sparse registry index caches in `registry/index`.
+        #[cfg_attr(feature = "cli", arg(long = "index"))]
+        index: bool,
+        /// Extracted crate sources in `registry/src`.
+        #[cfg_attr(feature = "cli", arg(long = "registry-src"))]
+        registry_src: bool,
+        /// Git dependency working trees in `git/checkouts`.
+        #[cfg_attr(feature = "cli", arg(long = "git"))]
+        git: bool,
+    },
+    /// Compress Swift Package Manager `.build` dirs instead of a cargo project's target dir.
+    Swiftpm {
+        /// Packages whose `.build` dir to compress; each may be any path inside the package.
+        /// Defaults to the package containing the current directory.
+        #[cfg_attr(feature = "cli", arg(value_name = "PATH"))]
+        paths: Vec<PathBuf>,
+        /// Also compress the dependency checkouts Xcode keeps for each project in DerivedData.
+        #[cfg_attr(feature = "cli", arg(long = "xcode"))]
+        xcode: bool,
+    },
+    /// Compress the caches Rust tools other than cargo keep under `~/Library/Caches`:
+    /// rust-analyzer, cargo-nextest, cargo-tarpaulin, and wasm-pack. Covers all of them unless
+    /// some are named.
+    ToolCaches {
+        #[cfg_attr(
+            feature = "cli",
+            arg(value_name = "TOOL", value_parser = parse_tool_cache)
+        )]
+        tools: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(Subcommand))]
+pub enum ConfigCommand {
+    /// Print the effective value of each setting and where it came from.
+    Show,
+}
+
+/// What `ctl` asks a running run to do.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum CtlCommand {
+    /// Print how far it has got and whether it is paused.
+    Status,
+    /// Hold every work dir before its next batch of files.
+    Pause,
+    Resume,
+    /// Stop as after Ctrl-C, leaving the run partial.
+    Stop,
+    /// Carry on now if paused; a run is otherwise already under way.
+    TriggerNow,
+}
+
+impl CtlCommand {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Stop => "stop",
+            Self::TriggerNow => "trigger-now",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(Subcommand))]
+pub enum AnalyzeCommand {
+    /// List files that are identical across the work dirs, such as the same artifact under debug
+    /// and release or two triples, and artifacts built under the same name in several of them,
+    /// with what they waste.
+    Duplicates,
+    /// Rank the crates whose artifacts in `deps/` and `build/` take the most space.
+    Crates,
+    /// Show the compression ratios `--ratio-cache` has recorded, by file extension, size, and
+    /// algorithm, and which classes of file it skips or picks an algorithm for.
+    Ratios,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "cli", derive(Parser))]
+#[cfg_attr(feature = "cli", command(name = "cargo-apfs-compress"))]
 pub struct Cli {
+    #[cfg_attr(feature = "cli", command(subcommand))]
+    pub command: Option<CliCommand>,
+
     /// Finds and compresses all profiles by default. Use this to restrict which profiles are
     /// compressed.
-    #[arg(long = "profile")]
+    #[cfg_attr(feature = "cli", arg(long = "profile"))]
     pub profiles: Vec<String>,
 
     /// Finds all platform targets by default. Use this to restrict which target platforms are
     /// compressed.
-    #[arg(long = "target")]
+    #[cfg_attr(feature = "cli", arg(long = "target"))]
     pub targets: Vec<String>,
 
-    #[arg(long = "compression", value_enum, default_value = "lzfse")]
-    pub compression: CompressionArg,
+    /// Only compress profile dirs for the host, not the per-triple dirs of cross builds.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "host-only", conflicts_with_all = ["targets", "cross_only"])
+    )]
+    pub host_only: bool,
+
+    /// Only compress the per-triple profile dirs of cross builds (`<target-dir>/<triple>/...`).
+    #[cfg_attr(feature = "cli", arg(long = "cross-only"))]
+    pub cross_only: bool,
+
+    /// Resolve work dirs for this project directory instead of the current one. Repeat it to
+    /// cover several projects in one run; a target dir they share is only processed once.
+    #[cfg_attr(feature = "cli", arg(long = "project", value_name = "PATH"))]
+    pub projects: Vec<PathBuf>,
+
+    /// Also compress the target dirs of the other workspaces the project's packages have path
+    /// dependencies in, and of the workspaces nested under its root, and theirs in turn.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "recurse-workspaces", conflicts_with = "no_metadata")
+    )]
+    pub recurse_workspaces: bool,
+
+    /// Also treat this dir under the target dir as a nested target dir, like `rust-analyzer`, when
+    /// discovering profiles. For editors or tools configured with their own check dir.
+    #[cfg_attr(feature = "cli", arg(long = "check-dir", value_name = "NAME"))]
+    pub check_dirs: Vec<String>,
+
+    /// Only compress artifacts that belong to this workspace package (its binaries, libraries,
+    /// and build script outputs), leaving dependency artifacts alone.
+    #[cfg_attr(feature = "cli", arg(short = 'p', long = "package"))]
+    pub packages: Vec<String>,
+
+    /// Don't run `cargo metadata`; guess the target directory from `CARGO_TARGET_DIR`,
+    /// `build.target-dir`, or the workspace's `Cargo.toml` location instead.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "no-metadata", conflicts_with = "packages")
+    )]
+    pub no_metadata: bool,
+
+    /// Also compress cargo's registry index caches under `$CARGO_HOME/registry/index`, holding
+    /// cargo's package cache lock meanwhile.
+    #[cfg_attr(feature = "cli", arg(long = "registry-index"))]
+    pub registry_index: bool,
+
+    /// Also compress the `dist` dirs trunk builds and the `pkg` dirs wasm-pack writes next to each
+    /// package, which hold another copy of what is built under `target/wasm32-*`.
+    #[cfg_attr(feature = "cli", arg(long = "wasm-dist"))]
+    pub wasm_dist: bool,
+
+    /// Compress this directory of non-cargo build output, such as a CMake build dir or a Bazel
+    /// output base, instead of any cargo project's. Repeatable. Nothing cargo-specific is assumed
+    /// about it and it isn't locked.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "generic",
+            value_name = "DIR",
+            conflicts_with_all = ["projects", "packages", "registry_index"]
+        )
+    )]
+    pub generic: Vec<PathBuf>,
+
+    /// Run each `[[job]]` in this TOML file in turn, with its own `workspace` or `path`,
+    /// `profiles`, `targets`, compression settings, and `exclude` patterns on top of the other
+    /// options, and sum them up at the end.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "jobs-file",
+            value_name = "PATH",
+            conflicts_with_all = ["projects", "generic", "packages", "report", "history"]
+        )
+    )]
+    pub jobs_file: Option<PathBuf>,
+
+    /// Directory name not to descend into under a `--generic` dir, at any depth. Repeatable.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "generic-skip", value_name = "NAME", requires = "generic")
+    )]
+    pub generic_skip: Vec<String>,
+
+    /// Defaults to the profile's `compression` from `[apfs-compress.profile.<name>]` in
+    /// `.cargo/config.toml`, or `lzfse`.
+    #[cfg_attr(feature = "cli", arg(long = "compression", value_enum))]
+    pub compression: Option<CompressionArg>,
+
+    /// Algorithm for files smaller than `--small-file-cutoff`, `lzvn` by default. Pass the same
+    /// value as `--compression` to use one algorithm for everything.
+    #[cfg_attr(feature = "cli", arg(long = "small-file-compression", value_enum))]
+    pub small_file_compression: Option<CompressionArg>,
+
+    /// Size below which `--small-file-compression` applies, `64KiB` by default.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "small-file-cutoff", value_name = "SIZE", value_parser = parse_byte_size)
+    )]
+    pub small_file_cutoff: Option<u64>,
+
+    /// Also compress files with extensions that are usually compressed already (archives, images,
+    /// fonts), which are skipped by default.
+    #[cfg_attr(feature = "cli", arg(long = "no-default-ext-skips"))]
+    pub no_default_ext_skips: bool,
+
+    /// Recompress files that are already compressed, e.g. after switching algorithms.
+    #[cfg_attr(feature = "cli", arg(long = "force"))]
+    pub force: bool,
+
+    /// Recompress only the files already compressed with a different algorithm than this run
+    /// would pick for them, as read from their decmpfs header.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "migrate-compression", conflicts_with = "force")
+    )]
+    pub migrate_compression: bool,
+
+    /// Leave Mach-O executables and libraries uncompressed so they stay byte-identical on disk.
+    #[cfg_attr(feature = "cli", arg(long = "skip-executables"))]
+    pub skip_executables: bool,
+
+    /// Leave the final executables directly inside each profile dir uncompressed, since they are
+    /// relinked on every build, while still compressing `deps/`, `build/`, and `incremental/`.
+    #[cfg_attr(feature = "cli", arg(long = "skip-bins"))]
+    pub skip_bins: bool,
+
+    /// Leave the `.fingerprint` dir inside each profile dir uncompressed: it holds a great many
+    /// tiny files that cargo rewrites on every build, for very little space.
+    #[cfg_attr(feature = "cli", arg(long = "exclude-fingerprint"))]
+    pub exclude_fingerprint: bool,
+
+    /// Don't descend into directories with this name anywhere under the work dirs, such as
+    /// `out` or `.nextest`. Repeatable. The walk never reads what is inside them.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "exclude-dir", value_name = "NAME", value_parser = parse_lock_name)
+    )]
+    pub exclude_dirs: Vec<String>,
+
+    /// Only compress `deps/` and `build/` in each profile dir: the dependencies and build script
+    /// output that stay put between builds, not the final artifacts that are relinked each time.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "deps-only", conflicts_with = "artifacts_only")
+    )]
+    pub deps_only: bool,
+
+    /// Only compress the final artifacts in each profile dir: the files directly inside it and
+    /// `examples/`, leaving `deps/`, `build/`, `incremental/`, and `.fingerprint/` alone.
+    #[cfg_attr(feature = "cli", arg(long = "artifacts-only"))]
+    pub artifacts_only: bool,
+
+    /// Only compress files modified at or after this point: a date such as `2024-01-01`, a UTC
+    /// time such as `2024-01-01T09:30`, `@<unix seconds>`, or a git ref such as `HEAD~20`, whose
+    /// commit time in the current dir's repository is used.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "since", value_name = "DATE|REF", value_parser = TimeRef::parse)
+    )]
+    pub since: Option<TimeRef>,
+
+    /// Only compress files last modified before this point, given like `--since`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "before", value_name = "DATE|REF", value_parser = TimeRef::parse)
+    )]
+    pub before: Option<TimeRef>,
+
+    /// Do nothing unless the target directory's volume has less than this much free space, e.g.
+    /// `50GiB`. Useful for running from frequent hooks.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "only-if-free-below", value_name = "SIZE", value_parser = parse_byte_size)
+    )]
+    pub only_if_free_below: Option<u64>,
+
+    /// Do nothing unless there has been no keyboard or mouse input for this long, e.g. `10m`, and
+    /// the CPUs are mostly idle. For runs started by a scheduler. macOS only.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "only-when-idle", value_name = "DURATION", value_parser = parse_duration)
+    )]
+    pub only_when_idle: Option<Duration>,
+
+    /// Do nothing while on battery power, and pause if the machine is unplugged mid-run, letting go
+    /// of the work dir it was on until it is plugged back in. macOS only.
+    #[cfg_attr(feature = "cli", arg(long = "only-on-ac"))]
+    pub only_on_ac: bool,
+
+    /// Wait before each work dir for as long as this file exists, so other automation can hold
+    /// compression off by creating it. A dir already started is finished first. Defaults to
+    /// `~/.config/cargo-apfs-compress/pause`.
+    #[cfg_attr(feature = "cli", arg(long = "pause-file", value_name = "PATH"))]
+    pub pause_file: Option<PathBuf>,
+
+    /// Pause while macOS reports serious or critical thermal pressure, letting go of the work dir
+    /// being compressed until it eases.
+    #[cfg_attr(feature = "cli", arg(long = "pause-on-thermal-pressure"))]
+    pub pause_on_thermal_pressure: bool,
+
+    /// What to do if Low Power Mode is on when the run starts. Without it, the mode is only
+    /// checked, for the note, when stderr is a terminal.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "low-power", value_name = "POLICY", value_enum)
+    )]
+    pub low_power: Option<LowPowerArg>,
+
+    /// At most this many work dirs at once on the volume holding PATH, such as
+    /// `/Volumes/Archive=1`. Repeatable. Overrides the limit picked for an external disk.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "volume-jobs", value_name = "PATH=JOBS", value_parser = VolumeJobs::parse)
+    )]
+    pub volume_jobs: Vec<VolumeJobs>,
+
+    /// On Apple Silicon, compress as many dirs at once as there are efficiency cores and run in
+    /// the background QoS class, so the work stays off the performance cores a build wants.
+    #[cfg_attr(feature = "cli", arg(long = "efficiency-cores"))]
+    pub efficiency_cores: bool,
+
+    /// Take one lock on `<target-dir>/.cargo-lock` for the whole run instead of locking each
+    /// profile directory, for wrapper scripts that coordinate at that level.
+    #[cfg_attr(feature = "cli", arg(long = "lock-target-dir"))]
+    pub lock_target_dir: bool,
+
+    /// Lock this file in each directory instead of `.cargo-lock`, for build setups that
+    /// coordinate through a different file.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "lock-name", value_name = "NAME", value_parser = parse_lock_name)
+    )]
+    pub lock_name: Option<String>,
+
+    /// Take one lock on this file for the whole run instead of locking each directory, so no
+    /// `.cargo-lock` is created in them.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "lock-path",
+            value_name = "PATH",
+            conflicts_with_all = ["lock_target_dir", "lock_name"]
+        )
+    )]
+    pub lock_path: Option<PathBuf>,
+
+    /// Before compressing, delete artifacts whose fingerprints have not been used for this long,
+    /// e.g. `30d`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "sweep-older-than", value_name = "AGE", value_parser = parse_duration)
+    )]
+    pub sweep_older_than: Option<Duration>,
+
+    /// Keep track of the files finished in each work dir, so that if this run is interrupted,
+    /// running again with `--resume` skips them. Also picks up from such a run.
+    #[cfg_attr(feature = "cli", arg(long = "resume"))]
+    pub resume: bool,
+
+    /// Don't list directories that haven't been modified since the last run that finished them
+    /// cleanly. Faster on big, mostly unchanged trees, but misses files rewritten in place.
+    #[cfg_attr(feature = "cli", arg(long = "skip-unchanged-dirs"))]
+    pub skip_unchanged_dirs: bool,
+
+    /// Leave a work dir alone if the last run on it finished less than this long ago, e.g. `60s`.
+    /// For running after every build from bacon or cargo-watch.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "debounce", value_name = "INTERVAL", value_parser = parse_duration)
+    )]
+    pub debounce: Option<Duration>,
+
+    /// Leave the workspace members' own artifacts alone until they have gone this long without
+    /// being rebuilt, e.g. `10m`, so runs after every build compress the dependencies, which
+    /// rarely change, instead of the crates being edited. Needs `cargo metadata`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "members-idle-for",
+            value_name = "AGE",
+            value_parser = parse_duration,
+            conflicts_with = "no_metadata"
+        )
+    )]
+    pub members_idle_for: Option<Duration>,
+
+    /// Compress at most this many files in the whole run, the largest first and the oldest of
+    /// those the same size, leaving the rest for later runs. For spreading the work over many
+    /// short scheduled runs.
+    #[cfg_attr(feature = "cli", arg(long = "max-files", value_name = "N"))]
+    pub max_files: Option<usize>,
+
+    /// Don't descend more than this many directory levels below each work dir; `0` compresses
+    /// only the files directly inside it.
+    #[cfg_attr(feature = "cli", arg(long = "max-depth", value_name = "N"))]
+    pub max_depth: Option<usize>,
+
+    /// Descend into directories on other filesystems, such as volumes mounted inside an
+    /// `OUT_DIR`. By default traversal stays on the work dir's device.
+    #[cfg_attr(feature = "cli", arg(long = "cross-device"))]
+    pub cross_device: bool,
+
+    /// Create `.metadata_never_index` in each work dir so Spotlight stops indexing it.
+    #[cfg_attr(feature = "cli", arg(long = "exclude-from-spotlight"))]
+    pub exclude_from_spotlight: bool,
+
+    /// Create a `CACHEDIR.TAG` in the target directory if it lacks one, so backup and sync tools
+    /// skip it.
+    #[cfg_attr(feature = "cli", arg(long = "cachedir-tag"))]
+    pub cachedir_tag: bool,
+
+    /// Exclude each work dir from Time Machine backups, like `tmutil addexclusion`.
+    #[cfg_attr(feature = "cli", arg(long = "exclude-from-backup"))]
+    pub exclude_from_backup: bool,
+
+    /// Leave files another process has open for writing, such as a running test binary's output,
+    /// until the rest of their dir is done, and skip the ones still open then.
+    #[cfg_attr(feature = "cli", arg(long = "skip-open-files"))]
+    pub skip_open_files: bool,
+
+    /// When a cargo build starts waiting for a work dir's lock, stop compressing it after the
+    /// current batch and let the build have the lock, then carry on with the dir once the build
+    /// is done. macOS only.
+    #[cfg_attr(feature = "cli", arg(long = "yield-to-cargo"))]
+    pub yield_to_cargo: bool,
+
+    /// Read each file back after compressing it and check it matches what was there before.
+    /// Costs an extra read of everything compressed.
+    #[cfg_attr(feature = "cli", arg(long = "verify-writes"))]
+    pub verify_writes: bool,
+
+    /// Check that each file's modification time, and creation time on macOS, are unchanged after
+    /// compressing it, and put them back if not, for build systems that compare mtimes.
+    #[cfg_attr(feature = "cli", arg(long = "preserve-times"))]
+    pub preserve_times: bool,
+
+    /// Record each file's permissions, flags, owner, and extended attributes (other than the ones
+    /// compression writes) before compressing it, and report any that differ afterwards.
+    #[cfg_attr(feature = "cli", arg(long = "audit-metadata"))]
+    pub audit_metadata: bool,
+
+    /// Run `codesign --verify` on each Mach-O file before and after compressing it, and report
+    /// any whose signature was valid before but isn't after. macOS only.
+    #[cfg_attr(feature = "cli", arg(long = "verify-signatures"))]
+    pub verify_signatures: bool,
+
+    /// Remember how well each kind of file, by extension and size, compressed in each work dir, and
+    /// on later runs leave out kinds that never save anything and use whichever algorithm saved the
+    /// most on kinds that have been compressed with several. See `analyze ratios`.
+    #[cfg_attr(feature = "cli", arg(long = "ratio-cache"))]
+    pub ratio_cache: bool,
+
+    /// After compressing, replace files identical to another one in any of the work dirs with
+    /// APFS clones of it. macOS only.
+    #[cfg_attr(feature = "cli", arg(long = "dedup"))]
+    pub dedup: bool,
+
+    /// Compress files smaller than decmpfs's inline threshold (3802 bytes) too. By default the
+    /// walk leaves them out: each saves one block at most, for as much work as a large file.
+    #[cfg_attr(feature = "cli", arg(long = "include-tiny-files"))]
+    pub include_tiny_files: bool,
+
+    /// Run even if the target directory looks like `/`, the home directory, a volume root, or
+    /// anything other than a cargo target directory.
+    #[cfg_attr(feature = "cli", arg(long = "i-know-what-im-doing"))]
+    pub i_know_what_im_doing: bool,
+
+    /// Print the work dirs that would be compressed, one per line, and exit.
+    #[cfg_attr(feature = "cli", arg(long = "list"))]
+    pub list: bool,
+
+    /// Print the workspace's target directory, as cargo resolves it, and exit.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "print-target-dir", conflicts_with = "list")
+    )]
+    pub print_target_dir: bool,
+
+    /// Show a full-screen dashboard instead of progress bars when stdout is a terminal.
+    #[cfg(feature = "tui")]
+    #[cfg_attr(feature = "cli", arg(long = "tui", conflicts_with = "progress"))]
+    pub tui: bool,
+
+    /// How to show progress.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "progress", value_name = "STYLE", value_enum, default_value_t)
+    )]
+    pub progress: ProgressArg,
+
+    /// What compresses the files; `null` runs everything else and times each stage.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "compressor", value_name = "NAME", value_enum, default_value_t)
+    )]
+    pub compressor: CompressorArg,
+
+    /// Listen on a Unix socket at this path while compressing, for `ctl` to check on, pause,
+    /// resume, or stop the run.
+    #[cfg_attr(feature = "cli", arg(long = "control-socket", value_name = "PATH"))]
+    pub control_socket: Option<PathBuf>,
+
+    /// Print a one-line progress summary this often, e.g. `30s`; `0s` turns it off. Defaults to
+    /// every minute with `--progress plain`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "heartbeat", value_name = "INTERVAL", value_parser = parse_duration)
+    )]
+    pub heartbeat: Option<Duration>,
+
+    /// How to write log lines: bare text, or timestamped `logfmt` or JSON for log collectors.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "log-format",
+            value_name = "FORMAT",
+            value_enum,
+            default_value_t
+        )
+    )]
+    pub log_format: LogFormat,
+
+    /// Also append log lines and errors to this file, timestamped, whatever the console shows.
+    #[cfg_attr(feature = "cli", arg(long = "log-file", value_name = "PATH"))]
+    pub log_file: Option<PathBuf>,
+
+    /// Write a JSON line for every file the run handles to this file: its path, size, bytes on
+    /// disk before and after, algorithm, and outcome. The file is compressed once it is written.
+    #[cfg_attr(feature = "cli", arg(long = "inventory", value_name = "PATH"))]
+    pub inventory: Option<PathBuf>,
+
+    /// Rotate `--log-file` to `<path>.1` once it would grow past this size, keeping three old
+    /// files. Defaults to 10 MiB.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "log-file-max-size",
+            value_name = "SIZE",
+            value_parser = parse_byte_size,
+            requires = "log_file"
+        )
+    )]
+    pub log_file_max_size: Option<u64>,
+
+    /// Also log the run summary and errors to the macOS unified log. On by default when launchd
+    /// started the run.
+    #[cfg_attr(feature = "cli", arg(long = "os-log"))]
+    pub os_log: bool,
+
+    /// Also report results in a CI-specific format.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "output", value_name = "FORMAT", value_enum, default_value_t)
+    )]
+    pub output: OutputArg,
+
+    /// Append this run's savings to a history file, and show the trend over recent runs.
+    #[cfg_attr(feature = "cli", arg(long = "history", value_name = "PATH"))]
+    pub history: Option<PathBuf>,
+
+    /// Write a report of per-dir and per-extension savings to this file when the run finishes.
+    #[cfg_attr(feature = "cli", arg(long = "report", value_name = "PATH"))]
+    pub report: Option<PathBuf>,
+
+    /// The format of `--report`.
+    #[cfg_attr(
+        feature = "cli",
+        arg(
+            long = "report-format",
+            value_name = "FORMAT",
+            value_enum,
+            default_value_t,
+            requires = "report"
+        )
+    )]
+    pub report_format: ReportFormat,
+
+    /// Fail if files were skipped for being on an unsupported filesystem or unreadable, or if
+    /// nothing at all was compressed, so CI notices when a run silently does nothing.
+    #[cfg_attr(feature = "cli", arg(long = "strict"))]
+    pub strict: bool,
 
-    #[arg(short = 'v', long = "verbose", action = ArgAction::Count, conflicts_with = "quiet")]
+    /// Stop every other directory before its next batch of files as soon as one directory fails.
+    #[cfg_attr(feature = "cli", arg(long = "fail-fast"))]
+    pub fail_fast: bool,
+
+    /// Fail if the run frees less than this, as a size (`1GiB`) or a percentage of the bytes it
+    /// looked at (`20%`).
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "min-savings", value_name = "SIZE|PERCENT", value_parser = MinSavings::parse)
+    )]
+    pub min_savings: Option<MinSavings>,
+
+    /// Walk every directory once before compressing, so progress totals are right from the start,
+    /// a stopped run can say how far it got, and the summary can say how much was compressed
+    /// already.
+    #[cfg_attr(feature = "cli", arg(long = "prescan"))]
+    pub prescan: bool,
+
+    /// Stop starting new batches of files after this long, e.g. `20m`, and exit with status 124
+    /// once the files already being compressed finish.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "timeout", value_name = "DURATION", value_parser = parse_duration)
+    )]
+    pub timeout: Option<Duration>,
+
+    /// After discovery, show a checklist of the work dirs with their sizes and only compress the
+    /// ones left checked. Needs a terminal on stdin.
+    #[cfg_attr(feature = "cli", arg(long = "interactive"))]
+    pub interactive: bool,
+
+    /// After every directory finishes, list the `N` that saved the most, largest savings first.
+    #[cfg_attr(feature = "cli", arg(long = "top", value_name = "N"))]
+    pub top: Option<usize>,
+
+    /// After every directory finishes, list how much each kind of build output saved: final
+    /// binaries, rlibs, dylibs, object files, dep-info, fingerprints, and build script output.
+    #[cfg_attr(feature = "cli", arg(long = "by-kind"))]
+    pub by_kind: bool,
+
+    /// Print nothing but errors and a closing summary of dirs, files, bytes saved, and duration.
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "summary-only", conflicts_with_all = ["verbose", "quiet"])
+    )]
+    pub summary_only: bool,
+
+    /// Write sizes as exact byte counts instead of units like `12.4 GiB`.
+    #[cfg_attr(feature = "cli", arg(long = "bytes"))]
+    pub bytes: bool,
+
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'v', long = "verbose", action = ArgAction::Count, conflicts_with = "quiet")
+    )]
     pub verbose: u8,
 
-    #[arg(short = 'q', long = "quiet", action = ArgAction::Count, conflicts_with = "verbose")]
+    #[cfg_attr(
+        feature = "cli",
+        arg(short = 'q', long = "quiet", action = ArgAction::Count, conflicts_with = "verbose")
+    )]
     pub quiet: u8,
+
+    /// Stops the run from outside, for library callers such as [`Job::cancel`]. `--timeout` adds
+    /// its deadline to this signal. Not a command-line option.
+    #[cfg_attr(feature = "cli", arg(skip))]
+    pub stop: StopSignal,
+
+    /// Pauses the run from outside, for library callers such as [`Job::pause`]. Not a
+    /// command-line option.
+    #[cfg_attr(feature = "cli", arg(skip))]
+    pub pause: PauseSignal,
+
+    /// Dir policies that apply after the configured ones, such as a `--jobs-file` job's
+    /// `exclude` patterns. Not a command-line option.
+    #[cfg_attr(feature = "cli", arg(skip))]
+    pub extra_dir_policies: Vec<DirPolicy>,
 }
 
 impl Cli {
+    fn work_dir_options(&self) -> WorkDirOptions {
+        WorkDirOptions {
+            compression: self.compression.unwrap_or_default().to_kind(),
+            small_file_compression: Some(
+                self.small_file_compression
+                    .map_or(Kind::Lzvn, CompressionArg::to_kind),
+            ),
+            small_file_cutoff: self.small_file_cutoff.unwrap_or(DEFAULT_SMALL_FILE_CUTOFF),
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            dir_policies: Vec::new(),
+            walk_threads: walk::WALK_THREADS,
+            force: self.force,
+            migrate_compression: self.migrate_compression,
+            skip_executables: self.skip_executables,
+            skip_bins: self.skip_bins,
+            deps_only: self.deps_only,
+            artifacts_only: self.artifacts_only,
+            modified_since: None,
+            modified_before: None,
+            listed_at: None,
+            lock_work_dir: !self.lock_target_dir && self.lock_path.is_none(),
+            lock_name: self
+                .lock_name
+                .clone()
+                .unwrap_or_else(|| CARGO_LOCK_NAME.to_owned()),
+            packages: Vec::new(),
+            sweep_older_than: self.sweep_older_than,
+            resume: self.resume,
+            skip_unchanged_dirs: self.skip_unchanged_dirs,
+            debounce: self.debounce,
+            members_idle_for: self.members_idle_for,
+            members: Vec::new(),
+            selected: None,
+            inventory: None,
+            stage_timings: self.compressor == CompressorArg::Null,
+            max_depth: self.max_depth,
+            cross_device: self.cross_device,
+            skip_extensions: if self.no_default_ext_skips {
+                Vec::new()
+            } else {
+                WorkDirOptions::default().skip_extensions
+            },
+            skip_dirs: self
+                .exclude_fingerprint
+                .then(|| FINGERPRINT_DIR.to_owned())
+                .into_iter()
+                .chain(self.exclude_dirs.iter().cloned())
+                .collect(),
+            exclude_from_spotlight: self.exclude_from_spotlight,
+            exclude_from_backup: self.exclude_from_backup,
+            skip_open_files: self.skip_open_files,
+            yield_to_cargo: self.yield_to_cargo,
+            min_file_size: if self.include_tiny_files {
+                0
+            } else {
+                walk::INLINE_THRESHOLD
+            },
+            verify_writes: self.verify_writes,
+            preserve_times: self.preserve_times,
+            audit_metadata: self.audit_metadata,
+            verify_signatures: self.verify_signatures,
+            ratio_cache: self.ratio_cache,
+            pause_on_battery: self.only_on_ac,
+            pause_on_thermal_pressure: self.pause_on_thermal_pressure,
+            pause: self.pause.clone(),
+            pause_file: self
+                .pause_file
+                .clone()
+                .or_else(|| std::env::home_dir().map(|home| home.join(DEFAULT_PAUSE_FILE))),
+            stop: self.stop.clone(),
+            yield_to_pause: false,
+        }
+    }
+
+    fn cli_settings(&self) -> config::CliSettings {
+        config::CliSettings {
+            compression: self.compression,
+            small_file_compression: self.small_file_compression,
+            small_file_cutoff: self.small_file_cutoff,
+        }
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        if self.bytes {
+            SizeFormat::Bytes
+        } else {
+            SizeFormat::Human
+        }
+    }
+
     fn verbosity(&self) -> Verbosity {
-        if self.quiet > 0 {
+        if self.quiet > 0 || self.summary_only {
             Verbosity::Quiet
         } else if self.verbose > 0 {
             Verbosity::Verbose
@@ -83,28 +1063,268 @@ pub fn resolve_cargo_exe() -> String {
 #[derive(Deserialize)]
 struct MetadataOutput {
     target_directory: PathBuf,
+    #[serde(default)]
+    packages: Vec<MetadataPackage>,
+}
+
+#[derive(Deserialize)]
+struct MetadataPackage {
+    name: String,
+    #[serde(default)]
+    manifest_path: Option<PathBuf>,
+    targets: Vec<MetadataTarget>,
+    #[serde(default)]
+    dependencies: Vec<MetadataDependency>,
+}
+
+#[derive(Deserialize)]
+struct MetadataTarget {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MetadataDependency {
+    /// Where a path dependency is.
+    #[serde(default)]
+    path: Option<PathBuf>,
 }
 
 pub fn run_cargo_metadata(cargo_exe: &str, cwd: &Path) -> Result<PathBuf> {
+    Ok(load_cargo_metadata(cargo_exe, cwd)?.target_directory)
+}
+
+fn load_cargo_metadata(cargo_exe: &str, cwd: &Path) -> Result<MetadataOutput> {
     let output = Command::new(cargo_exe)
         .args(["metadata", "--no-deps", "--format-version", "1"])
         .current_dir(cwd)
         .output()
-        .with_context(|| format!("failed to execute `{cargo_exe} metadata`"))?;
+        .map_err(|source| Error::MetadataSpawn {
+            cargo: cargo_exe.to_owned(),
+            source,
+        })?;
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "`{cargo_exe} metadata` failed with status {}: {stderr}",
-            output.status
-        ));
+        return Err(Error::MetadataFailed {
+            cargo: cargo_exe.to_owned(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
     }
 
-    let metadata: MetadataOutput = serde_json::from_slice(&output.stdout)
-        .with_context(|| format!("failed to parse `{cargo_exe} metadata` output"))?;
-    Ok(metadata.target_directory)
+    serde_json::from_slice(&output.stdout).map_err(|source| Error::MetadataParse {
+        cargo: cargo_exe.to_owned(),
+        source,
+    })
 }
 
-pub fn load_profile_dir_name_overrides(cwd: &Path) -> Result<HashMap<String, String>> {
+/// The target dirs to compress for the project at `root` whose own target dir is
+/// `target_directory`: that one, and the one cargo config's `[env]` sets, if it is another and
+/// something has built into it.
+fn project_target_dirs(root: &Path, target_directory: PathBuf) -> Result<Vec<PathBuf>> {
+    let env_set = env_target_dir(&|name| std::env::var_os(name)).is_some();
+    let real = |dir: &Path| fs::canonicalize(dir).unwrap_or_else(|_| dir.to_owned());
+    let mut dirs = vec![target_directory];
+    if let Some((dir, _)) = config_env_target_dir(root, env_set)?
+        && dir.is_dir()
+        && real(&dir) != real(&dirs[0])
+    {
+        dirs.push(dir);
+    }
+    Ok(dirs)
+}
+
+/// The target dir an `[env]` table in cargo config sets with `CARGO_TARGET_DIR`, else
+/// `CARGO_BUILD_TARGET_DIR`, and the file that sets it, the last one to. cargo passes `[env]` to
+/// everything it runs but never moves its own target dir for it, so this is a second target dir:
+/// builds started from build scripts, `xtask`s, and wrappers land there.
+///
+/// Like cargo, the real environment wins unless the entry has `force = true`, and a path is
+/// relative to `cwd` unless it has `relative = true`, when it is relative to the dir holding
+/// `.cargo/`.
+fn config_env_target_dir(cwd: &Path, env_set: bool) -> Result<Option<(PathBuf, PathBuf)>> {
+    let mut found: [Option<(PathBuf, PathBuf)>; 2] = [None, None];
+    for (path, value) in load_cargo_configs_with_home(cwd)? {
+        let Some(table) = value.get("env") else {
+            continue;
+        };
+        for (slot, name) in found
+            .iter_mut()
+            .zip(["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"])
+        {
+            let (dir, force, relative) = match table.get(name) {
+                Some(toml::Value::String(dir)) => (dir.as_str(), false, false),
+                Some(toml::Value::Table(entry)) => {
+                    let Some(dir) = entry.get("value").and_then(toml::Value::as_str) else {
+                        continue;
+                    };
+                    let flag = |key| entry.get(key).and_then(toml::Value::as_bool) == Some(true);
+                    (dir, flag("force"), flag("relative"))
+                }
+                _ => continue,
+            };
+            if dir.is_empty() || env_set && !force {
+                continue;
+            }
+            let base = if relative {
+                path.parent().and_then(Path::parent).unwrap_or(cwd)
+            } else {
+                cwd
+            };
+            *slot = Some((base.join(dir), path.clone()));
+        }
+    }
+    let [target_dir, build_target_dir] = found;
+    Ok(target_dir.or(build_target_dir))
+}
+
+/// The target dir the environment sets: `CARGO_TARGET_DIR`, else `CARGO_BUILD_TARGET_DIR`, which is
+/// how cargo spells `build.target-dir` as an env var. Empty values don't count.
+pub fn env_target_dir(env: &dyn Fn(&str) -> Option<OsString>) -> Option<OsString> {
+    ["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET_DIR"]
+        .into_iter()
+        .find_map(|name| env(name).filter(|dir| !dir.is_empty()))
+}
+
+/// Guesses the target directory without running cargo: `CARGO_TARGET_DIR`, then `build.target-dir`
+/// from cargo config, `$CARGO_HOME/config.toml` included, then `target/` next to the workspace
+/// root manifest. Like cargo, this ignores cargo config's `[env]`; see [`config_env_target_dir`].
+///
+/// The workspace root is the nearest `Cargo.toml` above `cwd` with a `[workspace]` table, or the
+/// nearest `Cargo.toml` if none has one.
+pub fn guess_target_dir(cwd: &Path, env_target_dir: Option<&OsStr>) -> Result<PathBuf> {
+    if let Some(dir) = env_target_dir.filter(|dir| !dir.is_empty()) {
+        return Ok(cwd.join(dir));
+    }
+
+    let mut configured = None;
+    for (path, value) in load_cargo_configs_with_home(cwd)? {
+        if let Some(dir) = value
+            .get("build")
+            .and_then(|build| build.get("target-dir"))
+            .and_then(toml::Value::as_str)
+        {
+            // Relative paths are relative to the directory containing `.cargo/`.
+            let base = path.parent().and_then(Path::parent).unwrap_or(cwd);
+            configured = Some(base.join(dir));
+        }
+    }
+    if let Some(dir) = configured {
+        return Ok(dir);
+    }
+
+    Ok(workspace_root(cwd)?.join("target"))
+}
+
+/// The roots of the workspaces `packages` have path dependencies in, and of the workspaces nested
+/// in the dirs under `root`'s workspace root, for `--recurse-workspaces`. Among them may be
+/// `root`'s own workspace.
+fn linked_workspaces(root: &Path, packages: &[MetadataPackage]) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = packages
+        .iter()
+        .flat_map(|package| &package.dependencies)
+        .filter_map(|dependency| workspace_root(dependency.path.as_deref()?).ok())
+        .collect();
+    let Ok(own) = workspace_root(root) else {
+        return found;
+    };
+    // Build output and hidden dirs such as `.git` hold no workspaces worth compressing.
+    let mut pending = vec![own.clone()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if is_hidden(&name)
+                || name == "target"
+                || !entry.file_type().is_ok_and(|file_type| file_type.is_dir())
+            {
+                continue;
+            }
+            let manifest = entry.path().join("Cargo.toml");
+            if let Ok(content) = fs::read_to_string(&manifest)
+                && toml::from_str::<toml::Value>(&content)
+                    .is_ok_and(|value| value.get("workspace").is_some())
+            {
+                found.push(entry.path());
+            }
+            pending.push(entry.path());
+        }
+    }
+    found
+}
+
+/// The nearest dir above `cwd` whose `Cargo.toml` has a `[workspace]` table, or the nearest with
+/// a `Cargo.toml` if none has one.
+fn workspace_root(cwd: &Path) -> Result<PathBuf> {
+    let mut nearest = None;
+    for dir in cwd.ancestors() {
+        let manifest = dir.join("Cargo.toml");
+        if !manifest.is_file() {
+            continue;
+        }
+        nearest.get_or_insert(dir);
+        let content = fs::read_to_string(&manifest).map_err(Error::read(&manifest))?;
+        let value: toml::Value = toml::from_str(&content).map_err(|source| Error::ConfigParse {
+            path: manifest.clone(),
+            source,
+        })?;
+        if value.get("workspace").is_some() {
+            return Ok(dir.to_path_buf());
+        }
+    }
+    nearest
+        .map(Path::to_path_buf)
+        .ok_or_else(|| Error::NoManifest {
+            cwd: cwd.to_path_buf(),
+        })
+}
+
+/// Returns the default target triples for builds in `cwd`: `CARGO_BUILD_TARGET`, else
+/// `build.target` from cargo config, `$CARGO_HOME/config.toml` included (a string or an array).
+/// Empty means the host.
+///
+/// Custom target specs (`foo.json`) are reduced to their file stem, which is the directory cargo
+/// uses for them.
+pub fn load_default_targets(cwd: &Path, env_build_target: Option<&OsStr>) -> Result<Vec<String>> {
+    let target_dir_name = |target: &str| {
+        if target.ends_with(".json") {
+            Path::new(target).file_stem().map_or_else(
+                || target.to_owned(),
+                |stem| stem.to_string_lossy().into_owned(),
+            )
+        } else {
+            target.to_owned()
+        }
+    };
+
+    if let Some(target) = env_build_target.filter(|target| !target.is_empty()) {
+        return Ok(vec![target_dir_name(&target.to_string_lossy())]);
+    }
+
+    let mut targets = Vec::new();
+    for (path, value) in load_cargo_configs_with_home(cwd)? {
+        let Some(configured) = value.get("build").and_then(|build| build.get("target")) else {
+            continue;
+        };
+        let names = match configured {
+            toml::Value::String(target) => vec![target.as_str()],
+            toml::Value::Array(array) => array.iter().filter_map(toml::Value::as_str).collect(),
+            other => {
+                return Err(Error::InvalidConfig {
+                    path,
+                    key: "build.target".to_owned(),
+                    value: other.to_string(),
+                });
+            }
+        };
+        targets = names.into_iter().map(target_dir_name).collect();
+    }
+    Ok(targets)
+}
+
+/// Parses every cargo config file from the filesystem root down to `cwd`, so that later entries
+/// take precedence.
+fn load_cargo_configs(cwd: &Path) -> Result<Vec<(PathBuf, toml::Value)>> {
     let mut roots = Vec::new();
     let mut current = Some(cwd);
     while let Some(path) = current {
@@ -113,7 +1333,7 @@ pub fn load_profile_dir_name_overrides(cwd: &Path) -> Result<HashMap<String, Str
     }
     roots.reverse();
 
-    let mut overrides = HashMap::new();
+    let mut configs = Vec::new();
     for root in roots {
         for candidate in [
             root.join(".cargo").join("config"),
@@ -122,19 +1342,41 @@ pub fn load_profile_dir_name_overrides(cwd: &Path) -> Result<HashMap<String, Str
             if !candidate.is_file() {
                 continue;
             }
-            let content = fs::read_to_string(&candidate)
-                .with_context(|| format!("failed reading {}", candidate.display()))?;
-            let value: toml::Value = toml::from_str(&content)
-                .with_context(|| format!("failed parsing {}", candidate.display()))?;
-            if let Some(profile_table) = value.get("profile").and_then(toml::Value::as_table) {
-                for (name, profile_value) in profile_table {
-                    let dir_name = profile_value
-                        .get("dir-name")
-                        .and_then(toml::Value::as_str)
-                        .map(ToOwned::to_owned);
-                    if let Some(dir_name) = dir_name {
-                        overrides.insert(name.to_owned(), dir_name);
-                    }
+            let content = fs::read_to_string(&candidate).map_err(Error::read(&candidate))?;
+            let value: toml::Value =
+                toml::from_str(&content).map_err(|source| Error::ConfigParse {
+                    path: candidate.clone(),
+                    source,
+                })?;
+            configs.push((candidate, value));
+        }
+    }
+
+    Ok(configs)
+}
+
+/// [`load_cargo_configs`] preceded by the user's `$CARGO_HOME` config, which cargo reads last and
+/// so gives the lowest precedence, even when the walk up from a project in the home directory
+/// found it.
+fn load_cargo_configs_with_home(cwd: &Path) -> Result<Vec<(PathBuf, toml::Value)>> {
+    let home = config::cargo_home(&|name| std::env::var(name).ok());
+    let mut project = load_cargo_configs(cwd)?;
+    let mut configs = config::cargo_home_configs(home.as_deref(), &mut project)?;
+    configs.extend(project);
+    Ok(configs)
+}
+
+pub fn load_profile_dir_name_overrides(cwd: &Path) -> Result<HashMap<String, String>> {
+    let mut overrides = HashMap::new();
+    for (_, value) in load_cargo_configs_with_home(cwd)? {
+        if let Some(profile_table) = value.get("profile").and_then(toml::Value::as_table) {
+            for (name, profile_value) in profile_table {
+                let dir_name = profile_value
+                    .get("dir-name")
+                    .and_then(toml::Value::as_str)
+                    .map(ToOwned::to_owned);
+                if let Some(dir_name) = dir_name {
+                    overrides.insert(name.to_owned(), dir_name);
                 }
             }
         }
@@ -143,6 +1385,79 @@ pub fn load_profile_dir_name_overrides(cwd: &Path) -> Result<HashMap<String, Str
     Ok(overrides)
 }
 
+/// `profile.<name>.dir-name` set through cargo's `CARGO_PROFILE_<NAME>_DIR_NAME` env vars, which
+/// take precedence over config files as they do for cargo.
+///
+/// cargo uppercases profile names and turns `-` into `_` for these, so each is recorded under
+/// both the `-` and `_` spellings of the lowercased name.
+pub fn profile_dir_name_env_overrides(
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> HashMap<String, String> {
+    let mut overrides = HashMap::new();
+    for (name, value) in vars {
+        let (Some(name), Some(value)) = (name.to_str(), value.to_str()) else {
+            continue;
+        };
+        let Some(profile) = name
+            .strip_prefix("CARGO_PROFILE_")
+            .and_then(|rest| rest.strip_suffix("_DIR_NAME"))
+            .filter(|profile| !profile.is_empty() && !value.is_empty())
+        else {
+            continue;
+        };
+        let profile = profile.to_ascii_lowercase();
+        overrides.insert(profile.replace('_', "-"), value.to_owned());
+        overrides.insert(profile, value.to_owned());
+    }
+    overrides
+}
+
+/// Reads per-profile compression defaults from `[apfs-compress.profile.<name>]` tables in cargo
+/// config files and `[profile.<name>]` in `cargo-apfs-compress.toml`, keyed by profile name.
+pub fn load_profile_compression_defaults(cwd: &Path) -> Result<BTreeMap<String, Kind>> {
+    let mut defaults = BTreeMap::new();
+    let home = config::cargo_home(&|name| std::env::var(name).ok());
+    let mut project = load_cargo_configs(cwd)?;
+    let mut files = config::cargo_home_configs(home.as_deref(), &mut project)?;
+    files.extend(config::project_config(cwd)?);
+    files.extend(project);
+    for (path, value) in files {
+        let profile_table = value
+            .get("apfs-compress")
+            .and_then(|table| table.get("profile"))
+            .and_then(toml::Value::as_table);
+        let Some(profile_table) = profile_table else {
+            continue;
+        };
+        for (name, profile_value) in profile_table {
+            let Some(compression) = profile_value.get("compression") else {
+                continue;
+            };
+            let kind = compression
+                .as_str()
+                .and_then(CompressionArg::from_name)
+                .ok_or_else(|| Error::InvalidConfig {
+                    path: path.clone(),
+                    key: format!("apfs-compress.profile.{name}.compression"),
+                    value: compression.to_string(),
+                })?;
+            defaults.insert(name.to_owned(), kind.to_kind());
+        }
+    }
+
+    Ok(defaults)
+}
+
+/// The order profiles that build into the same dir get to set its compression in: cargo's own
+/// first, `dev` before `test` for `debug` and `release` before `bench`, then the rest by name.
+fn profile_precedence(profile: &str) -> (usize, &str) {
+    let rank = ["dev", "release", "test", "bench"]
+        .iter()
+        .position(|&builtin| builtin == profile)
+        .unwrap_or(4);
+    (rank, profile)
+}
+
 pub fn resolve_profile_dir_name(profile: &str, overrides: &HashMap<String, String>) -> String {
     if let Some(override_dir) = overrides.get(profile) {
         return override_dir.clone();
@@ -155,115 +1470,385 @@ pub fn resolve_profile_dir_name(profile: &str, overrides: &HashMap<String, Strin
     }
 }
 
-pub fn resolve_work_dirs(
-    target_dir: &Path,
-    profiles: &[String],
-    targets: &[String],
-    overrides: &HashMap<String, String>,
-) -> Vec<PathBuf> {
-    let mut out = BTreeSet::new();
+/// A work dir and what is known about where it came from.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct WorkDir {
+    #[serde(serialize_with = "serialize_lossy")]
+    pub path: PathBuf,
+    /// The profile asked for, or for a discovered dir the dir's name, such as `debug`, which cargo
+    /// shares between profiles.
+    pub profile: Option<String>,
+    /// The target triple the dir is under, if any.
+    pub target: Option<String>,
+    pub source: WorkDirSource,
+}
+
+/// How a [`WorkDir`] was found.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkDirSource {
+    /// Found by looking through the target dir.
+    Discovered,
+    /// Named by `--profile`, and `--target` or `build.target`.
+    Requested,
+}
+
+impl WorkDir {
+    fn discovered(path: PathBuf, target: Option<&OsStr>) -> Self {
+        Self {
+            profile: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            target: target.map(|target| target.to_string_lossy().into_owned()),
+            path,
+            source: WorkDirSource::Discovered,
+        }
+    }
+}
+
+fn serialize_lossy<S: serde::Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+pub fn resolve_work_dirs(
+    target_dir: &Path,
+    profiles: &[String],
+    targets: &[String],
+    overrides: &HashMap<String, String>,
+) -> Vec<WorkDir> {
+    // `dev` and `test` share `debug`; the first profile asked for names it.
+    let mut out = BTreeMap::new();
+    let targets: Vec<Option<&String>> = if targets.is_empty() {
+        vec![None]
+    } else {
+        targets.iter().map(Some).collect()
+    };
 
     for profile in profiles {
         let profile_dir = resolve_profile_dir_name(profile, overrides);
-        if targets.is_empty() {
-            out.insert(target_dir.join(&profile_dir));
-        } else {
-            for target in targets {
-                out.insert(target_dir.join(target).join(&profile_dir));
-            }
+        for &target in &targets {
+            let path = match target {
+                Some(target) => target_dir.join(target).join(&profile_dir),
+                None => target_dir.join(&profile_dir),
+            };
+            out.entry(path.clone()).or_insert_with(|| WorkDir {
+                path,
+                profile: Some(profile.clone()),
+                target: target.cloned(),
+                source: WorkDirSource::Requested,
+            });
         }
     }
 
-    out.into_iter().collect()
+    out.into_values().collect()
 }
 
-fn is_hidden(name: &str) -> bool {
-    name.starts_with('.')
+// Directory names are matched as raw bytes so that non-UTF-8 names are neither mangled nor
+// mistaken for something they aren't.
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
 }
 
-fn looks_like_target_triple(name: &str) -> bool {
-    name.matches('-').count() >= 2
+fn looks_like_target_triple(name: &OsStr) -> bool {
+    name.as_encoded_bytes()
+        .iter()
+        .filter(|&&byte| byte == b'-')
+        .count()
+        >= 2
 }
 
-fn should_skip_root_dir(name: &str) -> bool {
-    is_hidden(name) || ROOT_SKIP_DIRS.contains(&name)
+fn is_one_of(name: &OsStr, names: &[&str]) -> bool {
+    names.iter().any(|candidate| name == *candidate)
 }
 
-fn should_skip_profile_dir(name: &str) -> bool {
-    is_hidden(name) || PROFILE_SKIP_DIRS.contains(&name)
+fn should_skip_profile_dir(name: &OsStr) -> bool {
+    is_hidden(name) || is_one_of(name, PROFILE_SKIP_DIRS)
 }
 
-pub fn discover_default_work_dirs(target_dir: &Path, targets: &[String]) -> Result<Vec<PathBuf>> {
-    let mut out = BTreeSet::new();
-    let target_filters: BTreeSet<&str> = targets.iter().map(String::as_str).collect();
+/// Finds the work dirs under `target_dir`, descending into [`DEFAULT_CHECK_DIRS`] and
+/// `check_dirs` as nested target dirs.
+pub fn discover_default_work_dirs(
+    target_dir: &Path,
+    targets: &[String],
+    check_dirs: &[String],
+) -> Result<Vec<WorkDir>> {
+    discover_traced(target_dir, targets, check_dirs, &mut |_, _| {})
+}
 
-    for entry in fs::read_dir(target_dir)
-        .with_context(|| format!("failed reading {}", target_dir.display()))?
-    {
-        let entry =
-            entry.with_context(|| format!("failed reading entry in {}", target_dir.display()))?;
-        if !entry.file_type()?.is_dir() {
-            continue;
+/// [`discover_default_work_dirs`], telling `trace` what it made of each dir it looked at.
+fn discover_traced(
+    target_dir: &Path,
+    targets: &[String],
+    check_dirs: &[String],
+    trace: &mut dyn FnMut(&Path, &str),
+) -> Result<Vec<WorkDir>> {
+    let mut out = BTreeMap::new();
+    let target_filters: BTreeSet<&OsStr> = targets.iter().map(OsStr::new).collect();
+    let subdirs = |dir: &Path| -> Result<Vec<fs::DirEntry>> {
+        let mut subdirs = Vec::new();
+        for entry in fs::read_dir(dir).map_err(Error::read(dir))? {
+            let entry = entry.map_err(Error::read(dir))?;
+            if entry
+                .file_type()
+                .map_err(Error::read(entry.path()))?
+                .is_dir()
+            {
+                subdirs.push(entry);
+            }
         }
+        subdirs.sort_by_key(fs::DirEntry::file_name);
+        Ok(subdirs)
+    };
 
-        let root_name = entry.file_name().to_string_lossy().to_string();
-        if should_skip_root_dir(&root_name) {
+    for entry in subdirs(target_dir)? {
+        let root_name = entry.file_name();
+        if is_hidden(&root_name) {
+            trace(&entry.path(), "skipped: hidden");
+            continue;
+        }
+        if is_one_of(&root_name, ROOT_SKIP_DIRS) {
+            trace(&entry.path(), "skipped: cargo's scratch dir");
+            continue;
+        }
+        if is_one_of(&root_name, DEFAULT_CHECK_DIRS)
+            || check_dirs.iter().any(|name| root_name == name.as_str())
+        {
+            trace(&entry.path(), "check dir: looked inside as a target dir");
+            out.extend(
+                discover_traced(&entry.path(), targets, &[], trace)?
+                    .into_iter()
+                    .map(|dir| (dir.path.clone(), dir)),
+            );
             continue;
         }
 
-        if !target_filters.is_empty() {
-            if !target_filters.contains(root_name.as_str()) {
+        let triple = if target_filters.is_empty() {
+            looks_like_target_triple(&root_name)
+        } else if target_filters.contains(root_name.as_os_str()) {
+            true
+        } else {
+            trace(&entry.path(), "skipped: not one of the requested targets");
+            continue;
+        };
+        if !triple {
+            trace(&entry.path(), "work dir");
+            out.insert(entry.path(), WorkDir::discovered(entry.path(), None));
+            continue;
+        }
+        trace(&entry.path(), "target triple: looked inside for profiles");
+        for child in subdirs(&entry.path())? {
+            if should_skip_profile_dir(&child.file_name()) {
+                trace(&child.path(), "skipped: part of a profile, not one");
                 continue;
             }
-            for child in fs::read_dir(entry.path())
-                .with_context(|| format!("failed reading {}", entry.path().display()))?
-            {
-                let child = child.with_context(|| {
-                    format!("failed reading entry in {}", entry.path().display())
-                })?;
-                if !child.file_type()?.is_dir() {
-                    continue;
-                }
-                let child_name = child.file_name().to_string_lossy().to_string();
-                if should_skip_profile_dir(&child_name) {
-                    continue;
-                }
-                out.insert(child.path());
-            }
-            continue;
+            trace(&child.path(), "work dir");
+            out.insert(
+                child.path(),
+                WorkDir::discovered(child.path(), Some(&root_name)),
+            );
         }
+    }
 
-        if looks_like_target_triple(&root_name) {
-            for child in fs::read_dir(entry.path())
-                .with_context(|| format!("failed reading {}", entry.path().display()))?
-            {
-                let child = child.with_context(|| {
-                    format!("failed reading entry in {}", entry.path().display())
-                })?;
-                if !child.file_type()?.is_dir() {
-                    continue;
-                }
-                let child_name = child.file_name().to_string_lossy().to_string();
-                if should_skip_profile_dir(&child_name) {
-                    continue;
-                }
-                out.insert(child.path());
+    Ok(out.into_values().collect())
+}
+
+/// A compression backend for the files under one work dir.
+///
+/// Implementations should call [`Reporter::file_compressed`] as each file finishes so that metrics
+/// and summaries work the same regardless of backend.
+pub trait Compressor: Send + Sync {
+    fn compress_paths(
+        &self,
+        paths: &[PathBuf],
+        options: &WorkDirOptions,
+        progress: &dyn Reporter,
+    ) -> Result<CompressionStats, BoxError>;
+}
+
+/// Flushes `inventory` and compresses it like any other file, as it can run to a line for every
+/// file in the target dir.
+fn finish_inventory(
+    inventory: &Inventory,
+    options: &WorkDirOptions,
+    compressor: &dyn Compressor,
+) -> Result<()> {
+    inventory.finish().map_err(|source| Error::Inventory {
+        path: inventory.path().to_path_buf(),
+        source,
+    })?;
+    let options = WorkDirOptions {
+        selected: None,
+        inventory: None,
+        ..options.clone()
+    };
+    // A file left uncompressed still holds every record.
+    let _ = compressor.compress_paths(
+        &[inventory.path().to_path_buf()],
+        &options,
+        &PlainProgress::new(Verbosity::Quiet),
+    );
+    Ok(())
+}
+
+/// How many of the largest files that didn't compress well enough the run summary and report
+/// list.
+const INCOMPRESSIBLE_TOP: usize = 10;
+
+/// How many of the paths the run had no permission for the summary lists.
+const DENIED_TOP: usize = 10;
+
+/// How many found files can wait between the walk and the compressor before the walk is held back.
+const ENUMERATION_QUEUE: usize = 8 * 1024;
+
+/// How many files [`process_work_dir`] hands to the [`Compressor`] per call.
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The most files [`ApplesauceCompressor`] hands to applesauce at once.
+const COMPRESS_BATCH_SIZE: usize = 16 * 1024;
+
+/// How many bytes of files [`ApplesauceCompressor`] hands to applesauce at once. Batches are cut
+/// by size rather than count, so a dir of fingerprints and `.d` files goes in a few large batches
+/// instead of paying for thread pools, stop checks, and pause checks every thousand files, while
+/// the modification times of big files are still re-checked about as often.
+const COMPRESS_BATCH_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The algorithm `options` picks for a file of `size` bytes.
+pub(crate) fn wanted_kind(options: &WorkDirOptions, size: u64) -> Kind {
+    match options.small_file_compression {
+        Some(kind) if size < options.small_file_cutoff => kind,
+        _ => options.compression,
+    }
+}
+
+/// Whether a compressed file using `kind` (`None` if its decmpfs header is unreadable or unknown)
+/// should be decompressed so it can be compressed again.
+fn needs_recompression(options: &WorkDirOptions, kind: Option<Kind>, size: u64) -> bool {
+    options.force
+        || options.migrate_compression
+            && kind.is_some_and(|kind| kind != wanted_kind(options, size))
+}
+
+/// The files in `paths` that might be compressed: all but those their directory's bulk attribute
+/// listing shows with the compressed flag clear, and every file in directories that can't be
+/// listed that way. This saves reading the decmpfs header of the (usually many) files that aren't
+/// compressed at all.
+fn possibly_compressed<'a>(paths: &[&'a Path]) -> Vec<&'a Path> {
+    let mut listings: HashMap<&Path, Option<HashSet<OsString>>> = HashMap::new();
+    paths
+        .iter()
+        .copied()
+        .filter(|path| {
+            let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+                return true;
+            };
+            listings
+                .entry(dir)
+                .or_insert_with(|| platform::uncompressed_entries(dir).ok())
+                .as_ref()
+                .is_none_or(|names| !names.contains(name))
+        })
+        .collect()
+}
+
+/// How often a paused run checks whether it can carry on.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often a run paused through [`PauseSignal`] checks whether it has been resumed.
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The [`pause_reason`] while a [`PauseSignal`] is paused.
+const PAUSED: &str = "paused";
+
+/// The [`pause_reason`] while [`WorkDirOptions::pause_file`] exists.
+const PAUSE_FILE_EXISTS: &str = "pause file exists";
+
+/// Where the pause file is unless `--pause-file` says otherwise, under the home dir.
+pub const DEFAULT_PAUSE_FILE: &str = ".config/cargo-apfs-compress/pause";
+
+/// Why the run should wait, if it should. A condition that can't be checked doesn't pause.
+///
+/// The pause file only counts `before_lock`: whoever creates it is usually about to build, and a
+/// dir given up halfway would only be waited on again once the file is gone, so a dir already
+/// locked is finished and the ones after it wait.
+fn pause_reason(options: &WorkDirOptions, before_lock: bool) -> Option<&'static str> {
+    if options.pause.is_paused() {
+        return Some(PAUSED);
+    }
+    if before_lock
+        && options
+            .pause_file
+            .as_ref()
+            .is_some_and(|path| path.exists())
+    {
+        return Some(PAUSE_FILE_EXISTS);
+    }
+    if options.pause_on_battery && platform::on_battery().unwrap_or(false) {
+        return Some("on battery power");
+    }
+    if options.pause_on_thermal_pressure && platform::under_thermal_pressure().unwrap_or(false) {
+        return Some("under thermal pressure");
+    }
+    None
+}
+
+/// Blocks until [`pause_reason`] clears or the run is stopped, saying so when the run pauses and
+/// resumes. Called with no work dir lock held, so a build is never kept waiting on a pause.
+fn wait_until_unpaused(options: &WorkDirOptions, progress: &dyn Reporter) {
+    let Some(mut reason) = pause_reason(options, true) else {
+        return;
+    };
+    progress.println_normal(|| format!("pause: {reason}"));
+    let mut checked = Instant::now();
+    while !options.stop.should_stop() {
+        std::thread::sleep(PAUSED_POLL_INTERVAL);
+        // Whoever paused is waiting to see it resume; power and heat change slowly, but Ctrl-C
+        // is looked for just as often either way.
+        if reason == PAUSED
+            || reason == PAUSE_FILE_EXISTS
+            || checked.elapsed() >= PAUSE_POLL_INTERVAL
+        {
+            match pause_reason(options, true) {
+                Some(next) => reason = next,
+                None => break,
             }
-        } else {
-            out.insert(entry.path());
+            checked = Instant::now();
         }
     }
+    progress.println_normal(|| "resume".to_owned());
+}
 
-    Ok(out.into_iter().collect())
+/// A stage's duration for `--compressor null`, in milliseconds while that is short.
+fn format_stage(elapsed: Duration) -> String {
+    if elapsed < Duration::from_secs(10) {
+        format!("{}ms", elapsed.as_millis())
+    } else {
+        summary::format_elapsed(elapsed)
+    }
 }
 
-pub trait Compressor: Send + Sync {
+/// `--compressor null`: leaves every file as it is, so a run measures everything but compressing.
+/// Each file is still stat'ed, as the compressor would, and counted as skipped.
+#[derive(Default)]
+pub struct NullCompressor;
+
+impl Compressor for NullCompressor {
     fn compress_paths(
         &self,
         paths: &[PathBuf],
-        compression: Kind,
-        progress: &ProgressBars,
-    ) -> Result<()>;
+        _options: &WorkDirOptions,
+        _progress: &dyn Reporter,
+    ) -> Result<CompressionStats, BoxError> {
+        let mut stats = CompressionStats::default();
+        for path in paths {
+            if let Ok(metadata) = fs::symlink_metadata(path) {
+                stats.files_skipped += 1;
+                stats.bytes_in += metadata.len();
+                stats.bytes_out += metadata.len();
+            }
+        }
+        Ok(stats)
+    }
 }
 
 #[derive(Default)]
@@ -273,295 +1858,6546 @@ impl Compressor for ApplesauceCompressor {
     fn compress_paths(
         &self,
         paths: &[PathBuf],
-        compression: Kind,
-        progress: &ProgressBars,
-    ) -> Result<()> {
-        let mut compressor = FileCompressor::new();
+        options: &WorkDirOptions,
+        progress: &dyn Reporter,
+    ) -> Result<CompressionStats, BoxError> {
         let refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
-        compressor.recursive_compress(refs, compression, 1.0, 2, &progress, false);
-        Ok(())
+        // Taken before anything is rewritten, decompression included.
+        let original_times: HashMap<&Path, FileTimes> = if options.preserve_times {
+            refs.iter()
+                .filter_map(|&path| Some((path, FileTimes::of(path).ok()?)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let original_metadata: HashMap<&Path, MetadataSnapshot> = if options.audit_metadata {
+            refs.iter()
+                .filter_map(|&path| match MetadataSnapshot::of(path) {
+                    Ok(snapshot) => Some((path, snapshot)),
+                    Err(error) => {
+                        progress.error(path, &format!("failed to record metadata: {error}"));
+                        None
+                    }
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let signed = if options.verify_signatures {
+            validly_signed(&refs, &platform::verify_code_signature)
+        } else {
+            HashSet::new()
+        };
+
+        // applesauce never recompresses a file, so with `force` anything already compressed is
+        // decompressed first, and with `migrate_compression` anything compressed differently.
+        let mut decompress_growth = 0;
+        if options.force || options.migrate_compression {
+            let compressed: Vec<&Path> = possibly_compressed(&refs)
+                .into_iter()
+                .filter(|path| {
+                    info::get(path).is_ok_and(|info| {
+                        let kind = info
+                            .decmpfs_info
+                            .as_ref()
+                            .and_then(|decmpfs| decmpfs.as_ref().ok())
+                            .and_then(|decmpfs| decmpfs.compression_type.compression_storage())
+                            .map(|(kind, _)| kind);
+                        info.is_compressed && needs_recompression(options, kind, info.stat_size)
+                    })
+                })
+                .collect();
+            if !compressed.is_empty() {
+                let (errors, failed) = mpsc::channel();
+                let stats = FileCompressor::new().recursive_decompress(
+                    compressed,
+                    false,
+                    &ErrorsOnly::new(progress, errors),
+                    false,
+                );
+                for (path, message) in failed.try_iter() {
+                    progress.error(&path, &message);
+                }
+                decompress_growth = stats
+                    .compressed_size_final
+                    .load(Ordering::Relaxed)
+                    .saturating_sub(stats.compressed_size_start.load(Ordering::Relaxed));
+            }
+        }
+
+        let listed_at = options.listed_at.unwrap_or_else(SystemTime::now);
+        let small_file_kind = options
+            .small_file_compression
+            .filter(|&kind| kind != options.compression);
+        let (small, large): (Vec<&Path>, Vec<&Path>) = refs.into_iter().partition(|path| {
+            small_file_kind.is_some()
+                && fs::metadata(path)
+                    .is_ok_and(|metadata| metadata.len() < options.small_file_cutoff)
+        });
+
+        // Work in batches and re-check modification times before each one, so files that a
+        // build rewrote after enumeration are skipped rather than compressed and then replaced.
+        let mut stats = CompressionStats::default();
+        'passes: for (paths, kind) in [
+            (large, options.compression),
+            (small, small_file_kind.unwrap_or(options.compression)),
+        ] {
+            let mut paths = paths.into_iter().peekable();
+            while paths.peek().is_some() {
+                if options.yield_to_pause && pause_reason(options, false).is_some() {
+                    options.stop.stop();
+                }
+                if options.stop.should_stop() {
+                    options.stop.mark_cut_short();
+                    break 'passes;
+                }
+                let (batch, modified) = take_batch(&mut paths, listed_at);
+                for path in modified {
+                    stats.files_skipped += 1;
+                    progress.println_verbose(|| {
+                        format!("{}: Skipped: Modified during run", path.display())
+                    });
+                }
+                if !batch.is_empty() {
+                    let compressed = batch.clone();
+                    stats += compress_pass(
+                        batch,
+                        kind,
+                        options.compression_level,
+                        options.verify_writes,
+                        progress,
+                    );
+                    if options.preserve_times {
+                        restore_times(&compressed, &original_times, progress);
+                    }
+                    if options.audit_metadata {
+                        audit_metadata(&compressed, &original_metadata, progress);
+                    }
+                    if options.verify_signatures {
+                        verify_signatures(
+                            &compressed,
+                            &signed,
+                            &platform::verify_code_signature,
+                            progress,
+                        );
+                    }
+                }
+            }
+        }
+        stats.bytes_in = stats.bytes_in.saturating_sub(decompress_growth);
+        Ok(stats)
+    }
+}
+
+/// The timestamps `--preserve-times` keeps: modification time, and creation time where the
+/// platform has one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileTimes {
+    modified: SystemTime,
+    created: Option<SystemTime>,
+}
+
+impl FileTimes {
+    fn of(path: &Path) -> std::io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(Self {
+            modified: metadata.modified()?,
+            created: metadata.created().ok(),
+        })
+    }
+
+    /// Sets these times on `path`. Creation time is only written on macOS.
+    fn apply(self, path: &Path) -> std::io::Result<()> {
+        #[allow(unused_mut)]
+        let mut times = fs::FileTimes::new().set_modified(self.modified);
+        #[cfg(target_os = "macos")]
+        if let Some(created) = self.created {
+            use std::os::macos::fs::FileTimesExt;
+            times = times.set_created(created);
+        }
+        // Only the owner needs to be able to set times, so read-only files work too.
+        fs::File::open(path)?.set_times(times)
+    }
+}
+
+/// Puts back the times of any of `paths` that compressing changed, reporting those it can't.
+fn restore_times(paths: &[&Path], original: &HashMap<&Path, FileTimes>, progress: &dyn Reporter) {
+    for &path in paths {
+        let Some(&before) = original.get(path) else {
+            continue;
+        };
+        if FileTimes::of(path).is_ok_and(|after| after == before) {
+            continue;
+        }
+        match before.apply(path) {
+            Ok(()) => progress.println_verbose(|| format!("{}: Restored times", path.display())),
+            Err(error) => progress.error(path, &format!("failed to restore times: {error}")),
+        }
+    }
+}
+
+/// Reports any of `paths` whose metadata no longer matches what was recorded before compressing.
+fn audit_metadata(
+    paths: &[&Path],
+    original: &HashMap<&Path, MetadataSnapshot>,
+    progress: &dyn Reporter,
+) {
+    for &path in paths {
+        let Some(before) = original.get(path) else {
+            continue;
+        };
+        match MetadataSnapshot::of(path) {
+            Ok(after) => {
+                let differences = before.differences(&after);
+                if !differences.is_empty() {
+                    progress.error(
+                        path,
+                        &format!("metadata changed: {}", differences.join(", ")),
+                    );
+                }
+            }
+            Err(error) => progress.error(path, &format!("failed to audit metadata: {error}")),
+        }
+    }
+}
+
+/// Checks a file's code signature, as [`platform::verify_code_signature`] does.
+type SignatureCheck<'a> = dyn Fn(&Path) -> std::io::Result<Result<(), String>> + 'a;
+
+/// The Mach-O files among `paths` whose code signature `verify` accepts.
+fn validly_signed<'a>(paths: &[&'a Path], verify: &SignatureCheck<'_>) -> HashSet<&'a Path> {
+    paths
+        .iter()
+        .copied()
+        .filter(|path| walk::is_mach_o(path).unwrap_or(false) && matches!(verify(path), Ok(Ok(()))))
+        .collect()
+}
+
+/// Reports each of `paths` that was among the `signed` files before compressing and whose
+/// signature `verify` now rejects.
+fn verify_signatures(
+    paths: &[&Path],
+    signed: &HashSet<&Path>,
+    verify: &SignatureCheck<'_>,
+    progress: &dyn Reporter,
+) {
+    for &path in paths.iter().filter(|path| signed.contains(*path)) {
+        match verify(path) {
+            Ok(Ok(())) => {}
+            Ok(Err(message)) => {
+                progress.error(path, &format!("code signature no longer valid: {message}"));
+            }
+            Err(error) => progress.error(path, &format!("failed to check code signature: {error}")),
+        }
+    }
+}
+
+/// Takes the next batch off `paths`, up to [`COMPRESS_BATCH_SIZE`] files or
+/// [`COMPRESS_BATCH_BYTES`], along with the files passed over because they were modified after
+/// `listed_at`. A modification time still in the future is a skewed clock or an extracted archive,
+/// not a build, so such a file is taken.
+fn take_batch<'a>(
+    paths: &mut impl Iterator<Item = &'a Path>,
+    listed_at: SystemTime,
+) -> (Vec<&'a Path>, Vec<&'a Path>) {
+    let mut batch = Vec::new();
+    let mut modified = Vec::new();
+    let mut bytes = 0;
+    let now = SystemTime::now();
+    while bytes < COMPRESS_BATCH_BYTES
+        && batch.len() < COMPRESS_BATCH_SIZE
+        && let Some(path) = paths.next()
+    {
+        match fs::symlink_metadata(path) {
+            Ok(metadata)
+                if metadata
+                    .modified()
+                    .is_ok_and(|time| time <= listed_at || time > now) =>
+            {
+                bytes += metadata.len();
+                batch.push(path);
+            }
+            _ => modified.push(path),
+        }
+    }
+    (batch, modified)
+}
+
+/// Compresses `paths` with applesauce. Files that fail because the process ran out of file
+/// descriptors are retried, after a growing pause, once the rest of the batch has closed its files.
+fn compress_pass(
+    paths: Vec<&Path>,
+    kind: Kind,
+    level: u32,
+    verify: bool,
+    progress: &dyn Reporter,
+) -> CompressionStats {
+    let (mut stats, mut exhausted) = compress_attempt(paths, kind, level, verify, progress, true);
+    for retry in 1..=fd_limit::RETRIES {
+        if exhausted.is_empty() {
+            break;
+        }
+        progress.println_verbose(|| {
+            format!(
+                "out of file descriptors; retrying {} file(s)",
+                exhausted.len()
+            )
+        });
+        std::thread::sleep(fd_limit::BACKOFF * retry as u32);
+        let paths = exhausted.iter().map(PathBuf::as_path).collect();
+        let (more, still) = compress_attempt(
+            paths,
+            kind,
+            level,
+            verify,
+            progress,
+            retry < fd_limit::RETRIES,
+        );
+        stats += more;
+        exhausted = still;
+    }
+    stats
+}
+
+/// One applesauce pass over `paths`. With `retry`, files that ran out of file descriptors are
+/// returned rather than reported.
+fn compress_attempt(
+    paths: Vec<&Path>,
+    kind: Kind,
+    level: u32,
+    verify: bool,
+    progress: &dyn Reporter,
+    retry: bool,
+) -> (CompressionStats, Vec<PathBuf>) {
+    // Per-file savings are measured in allocated blocks, like the totals in `Stats`, rather
+    // than the logical size applesauce passes to `file_task`. Extents shared with APFS clones
+    // stay allocated after a rewrite, so only a file's private blocks count as its input.
+    let sizes_before: HashMap<&Path, (u64, u64)> = paths
+        .iter()
+        .filter_map(|&path| {
+            let allocated = disk::allocated_size(path).ok()?;
+            let private = disk::private_size(path).unwrap_or(allocated);
+            Some((path, (allocated, private.min(allocated))))
+        })
+        .collect();
+    let (completed, completions) = mpsc::channel::<(PathBuf, u64)>();
+    let (ran_out, exhausted) = mpsc::channel();
+    let (stats, shared) = std::thread::scope(|scope| {
+        let sizes_before = &sizes_before;
+        let drain = scope.spawn(move || {
+            let mut shared = 0;
+            for (path, size) in completions {
+                let (allocated, private) = sizes_before
+                    .get(path.as_path())
+                    .copied()
+                    .unwrap_or((size, size));
+                shared += allocated - private;
+                let bytes_out = disk::allocated_size(&path).unwrap_or(allocated);
+                progress.file_compressed(&path, private, bytes_out);
+            }
+            shared
+        });
+
+        let mut adapter = AsProgress::new(progress, completed);
+        if retry {
+            adapter = adapter.retry_exhausted(ran_out);
+        }
+        let stats =
+            FileCompressor::new().recursive_compress(paths, kind, 1.0, level, &adapter, verify);
+        // Every file's task has been dropped by now, so this is the last sender and the
+        // completion loop above ends.
+        drop(adapter);
+        (stats, drain.join().expect("completion thread panicked"))
+    });
+    let mut stats = CompressionStats::from(&stats);
+    stats.bytes_in = stats.bytes_in.saturating_sub(shared);
+    (stats, exhausted.try_iter().collect())
+}
+
+/// Tells work dirs to stop before their next batch of files, at a deadline or on request.
+///
+/// Clones share their state, so a signal can be handed to every worker and stopped from anywhere.
+#[derive(Clone, Debug, Default)]
+pub struct StopSignal {
+    deadline: Option<Instant>,
+    stopped: Arc<AtomicBool>,
+    cut_short: Arc<AtomicBool>,
+    /// The [`Self::stopped`] of the signal this one was [`Self::linked`] to.
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl StopSignal {
+    pub fn new(deadline: Option<Instant>) -> Self {
+        Self {
+            deadline,
+            ..Self::default()
+        }
+    }
+
+    /// This signal, with its shared state, also firing at `deadline`.
+    pub fn with_deadline(self, deadline: Option<Instant>) -> Self {
+        Self { deadline, ..self }
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// A signal of its own that also fires whenever this one does, so one work dir can be stopped
+    /// without stopping the rest.
+    pub(crate) fn linked(&self) -> Self {
+        Self {
+            deadline: self.deadline,
+            stopped: Arc::default(),
+            cut_short: Arc::default(),
+            parent: Some(Arc::clone(&self.stopped)),
+        }
+    }
+
+    pub fn should_stop(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::Relaxed))
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Records that some files were left alone because of this signal.
+    pub fn mark_cut_short(&self) {
+        self.cut_short.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether any work was actually skipped, as opposed to the signal firing after it all finished.
+    pub fn was_cut_short(&self) -> bool {
+        self.cut_short.load(Ordering::Relaxed)
+    }
+
+    /// Whether work was skipped because [`Self::stop`] was called, such as on Ctrl-C.
+    pub fn cancelled(&self) -> bool {
+        self.was_cut_short() && self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Whether work was skipped and the deadline has passed, rather than only [`Self::stop`] being
+    /// called.
+    pub fn timed_out(&self) -> bool {
+        self.was_cut_short()
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Holds work dirs before their next batch of files for as long as it is paused.
+///
+/// Clones share their state, like [`StopSignal`]'s.
+#[derive(Clone, Debug, Default)]
+pub struct PauseSignal(Arc<AtomicBool>);
+
+impl PauseSignal {
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Settings applied to every file under a work dir.
+#[derive(Clone, Debug)]
+pub struct WorkDirOptions {
+    pub compression: Kind,
+    /// Algorithm for files smaller than `small_file_cutoff`, or `None` to use `compression` for
+    /// everything. Defaults to LZVN, which suits files small enough to be stored inline in the
+    /// decmpfs xattr.
+    pub small_file_compression: Option<Kind>,
+    pub small_file_cutoff: u64,
+    /// Passed to applesauce with `compression`; see [`DEFAULT_COMPRESSION_LEVEL`].
+    pub compression_level: u32,
+    /// Algorithms and levels for particular dirs under the work dir, or dirs to leave out, from
+    /// `apfs-compress.dir-policies`. The most specific matching policy wins.
+    pub dir_policies: Vec<DirPolicy>,
+    /// The most threads to read the work dir on: [`walk::WALK_THREADS`], or fewer when it is on
+    /// slower storage.
+    pub walk_threads: usize,
+    /// Recompress files that are already compressed.
+    pub force: bool,
+    /// Recompress files that are already compressed with a different algorithm than this run
+    /// would use for them.
+    pub migrate_compression: bool,
+    /// Leave Mach-O binaries (detected by their magic bytes) uncompressed.
+    pub skip_executables: bool,
+    /// Leave executable files directly inside the work dir (cargo's final binaries) uncompressed.
+    pub skip_bins: bool,
+    /// Only walk [`DEPS_DIRS`] directly inside the work dir, leaving out everything else at the
+    /// top, final artifacts included.
+    pub deps_only: bool,
+    /// Leave [`DEPS_DIRS`], `incremental/`, and `.fingerprint/` directly inside the work dir out
+    /// of the walk, so only final artifacts are compressed.
+    pub artifacts_only: bool,
+    /// Leave out files last modified before this.
+    pub modified_since: Option<SystemTime>,
+    /// Leave out files last modified at or after this.
+    pub modified_before: Option<SystemTime>,
+    /// When the walk that found the files being compressed started. A file modified since, but
+    /// not in the future, is being rebuilt and is skipped. `None` means when compressing starts.
+    pub listed_at: Option<SystemTime>,
+    /// Hold the work dir's `.cargo-lock` while compressing. Only disable this when the caller
+    /// already holds a lock that covers the directory.
+    pub lock_work_dir: bool,
+    /// The lock file's name inside each directory that is locked.
+    pub lock_name: String,
+    /// Only compress artifacts belonging to one of these packages. Empty means everything.
+    pub packages: Vec<PackageFilter>,
+    /// Delete artifacts that have not been used for this long before compressing.
+    pub sweep_older_than: Option<Duration>,
+    /// Skip files that an interrupted run recorded as done in the work dir's checkpoint, and record
+    /// those done in this run there. Without it, no checkpoint is written.
+    pub resume: bool,
+    /// Skip listing directories recorded as unchanged in the work dir's state from the last clean
+    /// run, and save it again after this one.
+    pub skip_unchanged_dirs: bool,
+    /// Skip the work dir if a run on it finished less than this long ago, and record when this
+    /// one finishes.
+    pub debounce: Option<Duration>,
+    /// Skip files of any of [`WorkDirOptions::members`] modified less than this long ago.
+    pub members_idle_for: Option<Duration>,
+    /// The workspace members, for `members_idle_for`.
+    pub members: Vec<PackageFilter>,
+    /// Only these files are compressed, with the rest left for a later run, as `--max-files`
+    /// picks them.
+    pub selected: Option<Arc<HashSet<PathBuf>>>,
+    /// Where each file's sizes and outcome are recorded for `--inventory`.
+    pub inventory: Option<Arc<Inventory>>,
+    /// Print how long locking, walking, and compressing the work dir took, and leave no record
+    /// of the run that would let a later one skip the dir, as the compressor doesn't compress.
+    pub stage_timings: bool,
+    /// How many directory levels below the work dir to descend into. `None` means no limit.
+    pub max_depth: Option<usize>,
+    /// Descend into directories on a different device than the work dir.
+    pub cross_device: bool,
+    /// File extensions (without the leading dot) to leave alone. Defaults to
+    /// [`DEFAULT_SKIP_EXTENSIONS`].
+    pub skip_extensions: Vec<String>,
+    /// Names of directories not to descend into, at any depth.
+    pub skip_dirs: Vec<String>,
+    /// Create `.metadata_never_index` in the work dir so Spotlight stops indexing it.
+    pub exclude_from_spotlight: bool,
+    /// Mark the work dir as excluded from Time Machine backups.
+    pub exclude_from_backup: bool,
+    /// Hold back files another process has open for writing until the end of the dir, then skip
+    /// those still open.
+    pub skip_open_files: bool,
+    /// Give the lock up to a cargo process waiting on it and take the dir up again afterwards.
+    /// Only applies with `lock_work_dir`.
+    pub yield_to_cargo: bool,
+    /// Non-empty files smaller than this are left out by the walk and counted as tiny; empty
+    /// files always are. The CLI uses [`INLINE_THRESHOLD`] unless `--include-tiny-files`.
+    pub min_file_size: u64,
+    /// Have applesauce read each file back after compressing it and compare it with the original.
+    pub verify_writes: bool,
+    /// Put back any modification or creation time that compressing a file changed.
+    pub preserve_times: bool,
+    /// Report each file whose permissions, flags, owner, or non-compression xattrs compressing it
+    /// changed.
+    pub audit_metadata: bool,
+    /// Report each Mach-O file whose code signature compressing it invalidated.
+    pub verify_signatures: bool,
+    /// Skip and pick algorithms for classes of file by the work dir's [`ratio_cache`], and record
+    /// what this run saw in it.
+    pub ratio_cache: bool,
+    /// Wait while the machine is on battery power.
+    pub pause_on_battery: bool,
+    /// Wait while the system is under serious thermal pressure.
+    pub pause_on_thermal_pressure: bool,
+    /// Waits while paused.
+    pub pause: PauseSignal,
+    /// Waits before each work dir while this file exists; a dir already locked is finished.
+    pub pause_file: Option<PathBuf>,
+    /// Checked before each batch of files; once it fires, the rest are left for another run.
+    pub stop: StopSignal,
+    /// Stop the compressor when a pause starts rather than leave it be, so that [`process_work_dir`]
+    /// can let go of the work dir's lock while it waits and take the dir up again afterwards.
+    /// Only for a `stop` of its own.
+    pub yield_to_pause: bool,
+}
+
+impl Default for WorkDirOptions {
+    fn default() -> Self {
+        Self {
+            compression: Kind::Lzfse,
+            small_file_compression: Some(Kind::Lzvn),
+            small_file_cutoff: DEFAULT_SMALL_FILE_CUTOFF,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            dir_policies: Vec::new(),
+            walk_threads: walk::WALK_THREADS,
+            force: false,
+            migrate_compression: false,
+            skip_executables: false,
+            skip_bins: false,
+            deps_only: false,
+            artifacts_only: false,
+            modified_since: None,
+            modified_before: None,
+            listed_at: None,
+            lock_work_dir: true,
+            lock_name: CARGO_LOCK_NAME.to_owned(),
+            packages: Vec::new(),
+            sweep_older_than: None,
+            resume: false,
+            skip_unchanged_dirs: false,
+            debounce: None,
+            members_idle_for: None,
+            members: Vec::new(),
+            selected: None,
+            inventory: None,
+            stage_timings: false,
+            max_depth: None,
+            cross_device: false,
+            skip_extensions: DEFAULT_SKIP_EXTENSIONS
+                .iter()
+                .map(|&extension| extension.to_owned())
+                .collect(),
+            skip_dirs: Vec::new(),
+            exclude_from_spotlight: false,
+            exclude_from_backup: false,
+            skip_open_files: false,
+            yield_to_cargo: false,
+            min_file_size: 0,
+            verify_writes: false,
+            preserve_times: false,
+            audit_metadata: false,
+            verify_signatures: false,
+            ratio_cache: false,
+            pause_on_battery: false,
+            pause_on_thermal_pressure: false,
+            pause: PauseSignal::default(),
+            pause_file: None,
+            stop: StopSignal::default(),
+            yield_to_pause: false,
+        }
     }
 }
 
 pub fn process_work_dir(
     dir: &Path,
-    compression: Kind,
-    progress: &ProgressBars,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
     compressor: &dyn Compressor,
-) -> Result<()> {
+) -> Result<CompressionStats> {
     if !dir.exists() {
         progress.println_normal(|| format!("skip {} (missing)", dir.display()));
-        return Ok(());
+        return Ok(CompressionStats::default());
     }
     if !dir.is_dir() {
-        return Err(anyhow!("{} is not a directory", dir.display()));
+        return Err(Error::NotADirectory {
+            path: dir.to_path_buf(),
+        });
     }
-
-    let fs = Filesystem::new(dir.to_path_buf());
-    let _lock = fs
-        .open_rw_exclusive_create(CARGO_LOCK_NAME, "build directory", progress)
-        .with_context(|| format!("failed to lock {}", dir.display()))?;
-
-    let mut inputs = Vec::new();
-    for entry in fs::read_dir(dir).with_context(|| format!("failed reading {}", dir.display()))? {
-        let entry = entry.with_context(|| format!("failed reading entry in {}", dir.display()))?;
-        if entry.file_name() == OsStr::new(CARGO_LOCK_NAME) {
-            progress
-                .println_verbose(|| format!("exclude {} from {}", CARGO_LOCK_NAME, dir.display()));
+    if let Some(debounce) = options.debounce
+        && let Some(finished) = dir_state::last_finished(dir)
+        && let Ok(ago) = SystemTime::now().duration_since(finished)
+        && ago < debounce
+    {
+        progress.println_normal(|| {
+            format!(
+                "skip {} (last run finished {} ago)",
+                dir.display(),
+                summary::format_elapsed(Duration::from_secs(ago.as_secs()))
+            )
+        });
+        return Ok(CompressionStats::default());
+    }
+    let lock_path = dir.join(&options.lock_name);
+    let watch = options.yield_to_cargo && options.lock_work_dir;
+    let mut stats = CompressionStats::default();
+    // Each attempt stops on its own when a pause starts or cargo wants the lock, and then lets go
+    // of the lock before waiting and taking the dir up again.
+    for tries in 0.. {
+        wait_until_unpaused(options, progress);
+        if options.stop.should_stop() {
+            options.stop.mark_cut_short();
+            if tries == 0 {
+                progress.println_normal(|| format!("skip {} (stopped)", dir.display()));
+            }
+            return Ok(stats);
+        }
+        let attempt = WorkDirOptions {
+            stop: options.stop.linked(),
+            yield_to_pause: true,
+            ..options.clone()
+        };
+        let yielded = AtomicBool::new(false);
+        let (finished, watching) = mpsc::channel::<()>();
+        let compressed = std::thread::scope(|scope| {
+            let (lock_path, attempt, yielded) = (&lock_path, &attempt, &yielded);
+            if watch {
+                scope.spawn(move || {
+                    watch_for_cargo(lock_path, &attempt.stop, yielded, watching, progress);
+                });
+            }
+            let compressed = compress_work_dir(dir, attempt, progress, compressor);
+            drop(finished);
+            compressed
+        });
+        stats += compressed?;
+        if yielded.load(Ordering::Relaxed) {
+            progress.println_normal(|| format!("yield {} to cargo", dir.display()));
+            // Long enough for the build to wake up and take the lock before it is asked for again.
+            std::thread::sleep(YIELD_GRACE);
             continue;
         }
-        inputs.push(entry.path());
+        if attempt.stop.should_stop() && !options.stop.should_stop() {
+            // Paused: the next attempt waits for it to end.
+            continue;
+        }
+        if attempt.stop.was_cut_short() {
+            options.stop.mark_cut_short();
+        }
+        break;
     }
-
-    compressor
-        .compress_paths(&inputs, compression, progress)
-        .with_context(|| format!("compression failed for {}", dir.display()))
+    Ok(stats)
 }
 
-pub fn run(cli: Cli) -> Result<()> {
-    run_with_compressor(cli, &ApplesauceCompressor)
+/// How often [`watch_for_cargo`] looks for a build waiting on a work dir's lock.
+const YIELD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a work dir that was given up to cargo waits before asking for its lock again.
+const YIELD_GRACE: Duration = Duration::from_secs(1);
+
+/// Stops `attempt` and sets `yielded` once a cargo process is found with `lock_path` open, which
+/// while it is held means waiting for it, checking until `finished` is dropped.
+fn watch_for_cargo(
+    lock_path: &Path,
+    attempt: &StopSignal,
+    yielded: &AtomicBool,
+    finished: mpsc::Receiver<()>,
+    progress: &dyn Reporter,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(YIELD_POLL_INTERVAL) {
+        match platform::cargo_has_open(lock_path) {
+            Ok(false) => {}
+            Ok(true) => {
+                yielded.store(true, Ordering::Relaxed);
+                attempt.stop();
+                return;
+            }
+            Err(error) => {
+                progress.println_normal(|| {
+                    format!(
+                        "can't check for cargo waiting on {}: {error}",
+                        lock_path.display()
+                    )
+                });
+                return;
+            }
+        }
+    }
 }
 
-pub fn run_with_compressor(cli: Cli, compressor: &dyn Compressor) -> Result<()> {
-    let verbosity = cli.verbosity();
-    let progress = ProgressBars::new(verbosity);
-    let cwd = std::env::current_dir().context("failed to get current directory")?;
-    let cargo_exe = resolve_cargo_exe();
-    let target_dir = run_cargo_metadata(&cargo_exe, &cwd)?;
-    let dirs = if cli.profiles.is_empty() {
-        discover_default_work_dirs(&target_dir, &cli.targets)?
+/// [`process_work_dir`] once the dir is known to be there and due: takes its lock and compresses
+/// everything under it.
+fn compress_work_dir(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+    compressor: &dyn Compressor,
+) -> Result<CompressionStats> {
+    let started = Instant::now();
+    let _lock = if options.lock_work_dir {
+        let fs = Filesystem::new(dir.to_path_buf());
+        let lock = fs
+            .open_rw_exclusive_create(&options.lock_name, "build directory", progress)
+            .map_err(|source| Error::LockWorkDir {
+                path: dir.to_path_buf(),
+                source: Box::new(source),
+            })?;
+        Some(lock_holder::record(lock, "compressing"))
     } else {
-        let overrides = load_profile_dir_name_overrides(&cwd)?;
-        resolve_work_dirs(&target_dir, &cli.profiles, &cli.targets, &overrides)
+        None
     };
+    let locking = started.elapsed();
 
-    let mut had_error = false;
-
-    std::thread::scope(|scope| {
-        let mut handles = Vec::new();
-        let progress_ref = &progress;
-        for dir in dirs {
-            handles.push(scope.spawn(move || {
-                let result =
-                    process_work_dir(&dir, cli.compression.to_kind(), progress_ref, compressor);
-                (dir, result)
-            }));
+    if options.exclude_from_spotlight {
+        let created =
+            hygiene::exclude_from_spotlight(dir).map_err(|source| Error::WriteMarker {
+                path: dir.join(hygiene::NEVER_INDEX_NAME),
+                source,
+            })?;
+        if created {
+            progress.println_verbose(|| format!("exclude {} from Spotlight", dir.display()));
+        }
+    }
+    if options.exclude_from_backup {
+        let excluded =
+            hygiene::exclude_from_backup(dir).map_err(|source| Error::BackupExclusion {
+                path: dir.to_path_buf(),
+                source,
+            })?;
+        if excluded {
+            progress.println_verbose(|| format!("exclude {} from Time Machine", dir.display()));
         }
+    }
 
-        for handle in handles {
-            let (dir, result) = handle.join().expect("worker thread panicked");
-            match result {
-                Ok(()) => progress.println_normal(|| format!("Compressed {}", dir.display())),
-                Err(error) => {
-                    had_error = true;
-                    progress.error(&dir, &format!("{error:#}"));
-                }
-            }
+    if let Some(older_than) = options.sweep_older_than {
+        let removed = sweep::sweep(dir, older_than, progress)?;
+        if removed > 0 {
+            progress.println_normal(|| {
+                format!(
+                    "Swept {} of stale artifacts from {}",
+                    progress.format_bytes(removed),
+                    dir.display()
+                )
+            });
         }
-    });
-    progress.finish();
+    }
 
-    if had_error {
-        Err(anyhow!("one or more directories failed"))
+    // Recompressing means looking at files no matter how long they have been there, and a time
+    // window leaves files out that a later run without one must still see.
+    let windowed = options.modified_since.is_some()
+        || options.modified_before.is_some()
+        || options.selected.is_some();
+    let previous = (options.skip_unchanged_dirs
+        && !options.force
+        && !options.migrate_compression
+        && !windowed)
+        .then(|| dir_state::DirState::load(dir));
+    let done = if options.resume {
+        checkpoint::load(dir)?
     } else {
-        Ok(())
-    }
+        HashSet::new()
+    };
+    if !done.is_empty() {
+        progress.println_normal(|| {
+            format!(
+                "resume {} ({} files already done)",
+                dir.display(),
+                done.len()
+            )
+        });
+    }
+
+    // Compressing a file while another process writes it fails or loses the write, so those wait
+    // until the end in the hope that they have been closed by then.
+    let open = if options.skip_open_files {
+        open_for_writing(dir, progress)
+    } else {
+        HashSet::new()
+    };
+    let mut deferred = Vec::new();
+
+    let ratios = options
+        .ratio_cache
+        .then(|| ratio_cache::RatioCache::load(dir));
+    let observed = Arc::new(Mutex::new(ratio_cache::RatioCache::default()));
+
+    let checkpoint = Checkpoint::create(dir, options.resume, progress)?;
+    let compressing = std::cell::Cell::new(Duration::ZERO);
+    // Set again before the deferred files are listed a second time.
+    let listed_at = std::cell::Cell::new(SystemTime::now());
+    let compress = |paths: &[PathBuf]| {
+        let options = &WorkDirOptions {
+            listed_at: Some(listed_at.get()),
+            ..options.clone()
+        };
+        let compress_with = |paths: &[PathBuf], options: &WorkDirOptions| {
+            let started = Instant::now();
+            let recording;
+            let reporter: &dyn Reporter = if options.ratio_cache {
+                recording = ratio_cache::Recording {
+                    inner: &checkpoint,
+                    options,
+                    cache: Arc::clone(&observed),
+                };
+                &recording
+            } else {
+                &checkpoint
+            };
+            let stats = match &options.inventory {
+                Some(inventory) => {
+                    let inventoried = inventory::Inventoried {
+                        inner: reporter,
+                        options,
+                        inventory: Arc::clone(inventory),
+                    };
+                    compressor.compress_paths(paths, options, &inventoried)
+                }
+                None => compressor.compress_paths(paths, options, reporter),
+            };
+            compressing.set(compressing.get() + started.elapsed());
+            stats.map_err(|source| Error::CompressionFailed {
+                path: dir.to_path_buf(),
+                source,
+            })
+        };
+        let compress_learned = |paths: &[PathBuf], options: &WorkDirOptions| {
+            let Some(ratios) = &ratios else {
+                return compress_with(paths, options);
+            };
+            let mut stats = CompressionStats::default();
+            for (options, paths) in ratio_cache::split(dir, paths, options, ratios) {
+                stats += compress_with(&paths, &options)?;
+            }
+            Ok(stats)
+        };
+        if options.dir_policies.is_empty() {
+            return compress_learned(paths, options);
+        }
+        let mut stats = CompressionStats::default();
+        for (options, paths) in policy::split(dir, paths, options) {
+            stats += compress_learned(&paths, &options)?;
+        }
+        Ok(stats)
+    };
+
+    // The walk feeds a bounded queue and files go to the compressor a chunk at a time, so memory
+    // stays flat however big the tree is. A dir smaller than a chunk goes in one sorted call.
+    let (files, found) = mpsc::sync_channel(ENUMERATION_QUEUE);
+    let (walked, compressed) = std::thread::scope(|scope| {
+        let walker = scope.spawn(|| {
+            let started = Instant::now();
+            let walked = walk::stream_inputs(
+                dir,
+                options,
+                progress,
+                previous.as_ref(),
+                ratios.as_ref(),
+                files,
+            );
+            (walked, started.elapsed())
+        });
+        let mut compress_all = || {
+            let mut stats = CompressionStats::default();
+            let mut chunk = Vec::new();
+            let mut flush = |chunk: &mut Vec<PathBuf>| {
+                chunk.sort();
+                progress.files_queued(total_size(chunk));
+                stats += compress(chunk)?;
+                chunk.clear();
+                Ok::<_, Error>(())
+            };
+            let mut flushed = false;
+            for path in found.iter() {
+                if done.contains(&path)
+                    || options
+                        .selected
+                        .as_ref()
+                        .is_some_and(|selected| !selected.contains(&path))
+                {
+                    continue;
+                }
+                if open.contains(&path) {
+                    deferred.push(path);
+                    continue;
+                }
+                chunk.push(path);
+                if chunk.len() == STREAM_CHUNK_SIZE {
+                    flush(&mut chunk)?;
+                    flushed = true;
+                }
+            }
+            if !chunk.is_empty() || !flushed {
+                flush(&mut chunk)?;
+            }
+            Ok(stats)
+        };
+        let compressed = compress_all();
+        // Lets the walk give up if compressing failed before it finished.
+        drop(found);
+        (walker.join().expect("walk thread panicked"), compressed)
+    });
+    let (state, walking) = walked;
+    let state = state?;
+    let mut stats = compressed?;
+
+    if !deferred.is_empty() {
+        listed_at.set(SystemTime::now());
+        let open = open_for_writing(dir, progress);
+        let (open, closed): (Vec<_>, Vec<_>) =
+            deferred.into_iter().partition(|path| open.contains(path));
+        for path in &open {
+            progress.println_verbose(|| format!("{}: Skipped: Open for writing", path.display()));
+        }
+        stats.files_skipped += open.len() as u64;
+        if !closed.is_empty() {
+            progress.files_queued(total_size(&closed));
+            stats += compress(&closed)?;
+        }
+    }
+    // Only a run that left nothing behind may let the next one skip directories.
+    let clean = !checkpoint.had_errors()
+        && stats.files_skipped == 0
+        && !options.stop.was_cut_short()
+        && !windowed
+        && !options.stage_timings;
+    // A dir that was cut short is what `--resume` is for, so it keeps its checkpoint.
+    if !options.stop.was_cut_short() {
+        checkpoint.finish()?;
+    }
+    if options.ratio_cache {
+        ratio_cache::finish(dir, &observed)?;
+    }
+    if options.skip_unchanged_dirs && clean {
+        state.save(dir)?;
+    }
+    if options.stage_timings {
+        progress.println_normal(|| {
+            format!(
+                "timings {}: lock {}, walk {}, compress {}, total {}",
+                dir.display(),
+                format_stage(locking),
+                format_stage(walking),
+                format_stage(compressing.get()),
+                format_stage(started.elapsed())
+            )
+        });
+    }
+    if options.debounce.is_some() && !options.stop.was_cut_short() && !options.stage_timings {
+        dir_state::record_finished(dir, SystemTime::now())?;
+    }
+    Ok(stats)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::{Arc, Mutex};
-    use std::thread;
-    use std::time::{Duration, Instant};
-    use tempfile::tempdir;
+/// `--dedup` across `dirs`, each locked again meanwhile if work dirs are locked at all.
+fn clone_duplicates(
+    dirs: &[PathBuf],
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+) -> Result<()> {
+    let mut locks = Vec::new();
+    if options.lock_work_dir {
+        let mut sorted = dirs.to_vec();
+        // Always in the same order, so two runs can't each hold a lock the other waits for.
+        sorted.sort();
+        for dir in sorted {
+            let lock = Filesystem::new(dir).open_rw_exclusive_create(
+                &options.lock_name,
+                "build directory",
+                progress,
+            )?;
+            locks.push(lock_holder::record(lock, "deduplicating"));
+        }
+    }
+    let files = dedup::large_files(dirs);
+    let outcome = dedup::dedup(&dedup::find_duplicates(&files), progress);
+    if outcome.files > 0 {
+        progress.println_normal(|| {
+            format!(
+                "Deduplicated {} files, freeing {}",
+                outcome.files,
+                progress.format_bytes(outcome.bytes)
+            )
+        });
+    }
+    Ok(())
+}
+
+/// The total logical size of `paths`, leaving out any that are gone.
+fn total_size(paths: &[PathBuf]) -> u64 {
+    paths
+        .iter()
+        .filter_map(|path| fs::symlink_metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// [`platform::open_for_writing`], or nothing after saying why not if it fails.
+fn open_for_writing(dir: &Path, progress: &dyn Reporter) -> HashSet<PathBuf> {
+    platform::open_for_writing(dir).unwrap_or_else(|error| {
+        progress
+            .println_normal(|| format!("can't check for open files in {}: {error}", dir.display()));
+        HashSet::new()
+    })
+}
+
+/// Everything a run derives from the workspace before touching any files.
+struct Resolved {
+    /// The directory each project was resolved from, with its `profile.<name>.dir-name`
+    /// overrides.
+    projects: Vec<(PathBuf, HashMap<String, String>)>,
+    /// The distinct target dirs of all projects, sorted so that locks are always taken in the
+    /// same order.
+    target_dirs: Vec<PathBuf>,
+    packages: Vec<MetadataPackage>,
+    dirs: Vec<WorkDir>,
+    /// Work dirs dropped because they are inside another one, with the dir that contains them.
+    nested: Vec<(PathBuf, PathBuf)>,
+    /// Target dirs reached through a symlink, with where they really are. Everything else in
+    /// here uses the real location.
+    links: Vec<(PathBuf, PathBuf)>,
+    /// With `--wasm-dist`, the wasm toolchain output dirs among `dirs`, which have no cargo lock.
+    wasm_dirs: HashSet<PathBuf>,
+    /// The first project's layered settings.
+    settings: config::Settings,
+}
+
+/// Resolves `cli`'s layered settings against the config that applies to `root`.
+fn load_settings(cli: &Cli, root: &Path) -> Result<config::Settings> {
+    let env = |name: &str| std::env::var(name).ok();
+    let files = config::config_files(root, config::cargo_home(&env).as_deref())?;
+    config::resolve(cli.cli_settings(), &files, &env)
+}
+
+/// Canonicalizes `dirs` and removes duplicates, including ones reached through symlinks.
+///
+/// A dir nested inside another one is dropped too, since the outer dir's worker covers its files;
+/// it is returned alongside the dir that contains it. Dirs that don't exist are kept as given.
+pub fn dedup_work_dirs(dirs: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<(PathBuf, PathBuf)>) {
+    let mut canonical: Vec<PathBuf> = dirs
+        .into_iter()
+        .map(|dir| fs::canonicalize(&dir).unwrap_or(dir))
+        .collect();
+    canonical.sort();
+    canonical.dedup();
+
+    // Sorting puts every dir before the dirs nested inside it.
+    let mut kept: Vec<PathBuf> = Vec::new();
+    let mut nested = Vec::new();
+    for dir in canonical {
+        match kept.iter().find(|outer| dir.starts_with(outer)) {
+            Some(outer) => nested.push((dir, outer.clone())),
+            None => kept.push(dir),
+        }
+    }
+    (kept, nested)
+}
+
+/// The dirs `cli`'s projects are resolved from: each `--project`, or the current dir.
+fn project_roots(cli: &Cli) -> Result<Vec<PathBuf>> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    Ok(if cli.projects.is_empty() {
+        vec![cwd]
+    } else {
+        cli.projects
+            .iter()
+            .map(|project| cwd.join(project))
+            .collect()
+    })
+}
+
+fn resolve(cli: &Cli) -> Result<Resolved> {
+    let mut roots = project_roots(cli)?;
+    let real = |dir: &Path| fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    // With `--recurse-workspaces`, the workspace roots seen so far, so each is resolved once.
+    let mut workspaces: HashSet<PathBuf> = roots
+        .iter()
+        .filter_map(|root| workspace_root(root).ok())
+        .map(|root| real(&root))
+        .collect();
+
+    let mut projects = Vec::new();
+    let mut settings = None;
+    let mut target_dirs: Vec<PathBuf> = Vec::new();
+    let mut packages = Vec::new();
+    let mut dirs = Vec::new();
+    let mut wasm_dirs = HashSet::new();
+    let mut links = Vec::new();
+    let mut next = 0;
+    while let Some(root) = roots.get(next).cloned() {
+        next += 1;
+        if cli.stop.should_stop() {
+            return Err(Error::Interrupted);
+        }
+        let metadata = if cli.no_metadata {
+            MetadataOutput {
+                target_directory: guess_target_dir(
+                    &root,
+                    env_target_dir(&|name| std::env::var_os(name)).as_deref(),
+                )?,
+                packages: Vec::new(),
+            }
+        } else {
+            load_cargo_metadata(&resolve_cargo_exe(), &root)?
+        };
+        let mut overrides = load_profile_dir_name_overrides(&root)?;
+        overrides.extend(profile_dir_name_env_overrides(std::env::vars_os()));
+        let project_settings = load_settings(cli, &root)?;
+        let check_dirs: Vec<String> = cli
+            .check_dirs
+            .iter()
+            .chain(&project_settings.check_dirs.value)
+            .cloned()
+            .collect();
+        for target_directory in project_target_dirs(&root, metadata.target_directory.clone())? {
+            let target_dir = &match follow_target_dir_link(&target_directory)? {
+                Some(real) => {
+                    links.push((target_directory.clone(), real.clone()));
+                    real
+                }
+                None => target_directory.clone(),
+            };
+            let mut root_dirs = if cli.profiles.is_empty() {
+                discover_default_work_dirs(target_dir, &cli.targets, &check_dirs)?
+            } else if cli.targets.is_empty() {
+                let targets =
+                    load_default_targets(&root, std::env::var_os("CARGO_BUILD_TARGET").as_deref())?;
+                resolve_work_dirs(target_dir, &cli.profiles, &targets, &overrides)
+            } else {
+                resolve_work_dirs(target_dir, &cli.profiles, &cli.targets, &overrides)
+            };
+            if cli.host_only || cli.cross_only {
+                // Host output sits right in the target dir or in a check dir, a nested target dir
+                // of its own; anything else is under a triple, whatever the target dir is called.
+                let host_parents: Vec<PathBuf> = std::iter::once(target_dir.clone())
+                    .chain(
+                        DEFAULT_CHECK_DIRS
+                            .iter()
+                            .map(|&dir| dir.to_owned())
+                            .chain(check_dirs.iter().cloned())
+                            .map(|dir| target_dir.join(dir)),
+                    )
+                    .collect();
+                root_dirs.retain(|dir| {
+                    let cross = dir
+                        .path
+                        .parent()
+                        .is_none_or(|parent| !host_parents.iter().any(|host| host == parent));
+                    !(cli.host_only && cross || cli.cross_only && !cross)
+                });
+            }
+            dirs.extend(root_dirs);
+
+            // Projects sharing one `CARGO_TARGET_DIR` may spell it differently.
+            let canonical = fs::canonicalize(target_dir).unwrap_or_else(|_| target_dir.clone());
+            if !target_dirs
+                .iter()
+                .any(|dir| fs::canonicalize(dir).unwrap_or_else(|_| dir.clone()) == canonical)
+            {
+                target_dirs.push(target_dir.clone());
+            }
+        }
+        if cli.wasm_dist {
+            let package_dirs = metadata
+                .packages
+                .iter()
+                .filter_map(|package| Some(package.manifest_path.as_deref()?.parent()?.to_owned()));
+            for package_dir in std::iter::once(root.clone()).chain(package_dirs) {
+                for dir in wasm::output_dirs(&package_dir) {
+                    wasm_dirs.insert(fs::canonicalize(&dir).unwrap_or(dir));
+                }
+            }
+        }
+        if cli.recurse_workspaces {
+            for workspace in linked_workspaces(&root, &metadata.packages) {
+                if workspaces.insert(real(&workspace)) {
+                    roots.push(workspace);
+                }
+            }
+        }
+        packages.extend(metadata.packages);
+        settings.get_or_insert(project_settings);
+        projects.push((root, overrides));
+    }
+    target_dirs.sort();
+
+    let mut found: HashMap<PathBuf, WorkDir> = dirs
+        .into_iter()
+        .chain(
+            wasm_dirs
+                .iter()
+                .map(|dir| WorkDir::discovered(dir.clone(), None)),
+        )
+        .map(|dir| (fs::canonicalize(&dir.path).unwrap_or(dir.path.clone()), dir))
+        .collect();
+    let (dirs, nested) = dedup_work_dirs(found.keys().cloned().collect());
+    let dirs = dirs
+        .into_iter()
+        .map(|path| WorkDir {
+            path: path.clone(),
+            ..found.remove(&path).expect("every kept dir was found")
+        })
+        .collect();
+    Ok(Resolved {
+        projects,
+        settings: settings.expect("at least one project"),
+        target_dirs,
+        packages,
+        dirs,
+        nested,
+        links,
+        wasm_dirs,
+    })
+}
+
+/// Where `target_dir` really is if the path to it goes through a symlink, or `None` if it
+/// doesn't or the dir doesn't exist yet. Fails for a dangling symlink, or if the real location is
+/// on a volume that can't compress files.
+fn follow_target_dir_link(target_dir: &Path) -> Result<Option<PathBuf>> {
+    let real = match fs::canonicalize(target_dir) {
+        Ok(real) => real,
+        Err(_) if target_dir.is_symlink() => {
+            return Err(Error::BrokenTargetLink {
+                path: target_dir.to_path_buf(),
+                destination: fs::read_link(target_dir).unwrap_or_default(),
+            });
+        }
+        Err(_) => return Ok(None),
+    };
+    if real == target_dir {
+        return Ok(None);
+    }
+    // A volume that can't be inspected, as on anything but macOS, gets the benefit of the doubt.
+    if let Ok(volume) = disk::volume(&real)
+        && !volume.supports_compression()
+    {
+        return Err(Error::UnsupportedTargetVolume {
+            path: target_dir.to_path_buf(),
+            destination: real,
+            fs_type: volume.fs_type,
+        });
+    }
+    Ok(Some(real))
+}
+
+/// Returns the work dirs a run with `cli` would process, after profile, target, and override
+/// resolution and discovery.
+pub fn list_work_dirs(cli: &Cli) -> Result<Vec<WorkDir>> {
+    Ok(resolve(cli)?.dirs)
+}
+
+/// Returns the target directory of the workspace containing the current directory, honoring
+/// `CARGO_TARGET_DIR` and `build.target-dir` the way cargo does.
+///
+/// Asks `cargo metadata` unless `cli.no_metadata` is set, in which case see [`guess_target_dir`].
+pub fn resolve_target_dir(cli: &Cli) -> Result<PathBuf> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    if cli.no_metadata {
+        guess_target_dir(
+            &cwd,
+            env_target_dir(&|name| std::env::var_os(name)).as_deref(),
+        )
+    } else {
+        run_cargo_metadata(&resolve_cargo_exe(), &cwd)
+    }
+}
+
+/// The lines `config show` prints: each setting's effective value and its source.
+fn show_config(cli: &Cli) -> Result<Vec<String>> {
+    let env = |name: &str| std::env::var(name).ok();
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let files = config::config_files(&cwd, config::cargo_home(&env).as_deref())?;
+    let settings = config::resolve(cli.cli_settings(), &files, &env)?;
+
+    let kind = |kind: Kind| kind.name().to_ascii_lowercase();
+    let mut lines = vec![
+        config::show_line(
+            "compression",
+            &kind(settings.compression.value),
+            &settings.compression.source,
+        ),
+        config::show_line(
+            "small-file-compression",
+            &kind(settings.small_file_compression.value),
+            &settings.small_file_compression.source,
+        ),
+        config::show_line(
+            "small-file-cutoff",
+            &settings.small_file_cutoff.value.to_string(),
+            &settings.small_file_cutoff.source,
+        ),
+        config::show_line(
+            "check-dirs",
+            &settings.check_dirs.value.join(","),
+            &settings.check_dirs.source,
+        ),
+        config::show_line(
+            "skip-extensions",
+            &settings.skip_extensions.value.join(","),
+            &settings.skip_extensions.source,
+        ),
+    ];
+    for policy in &settings.dir_policies.value {
+        lines.push(config::show_line(
+            &format!("dir-policies.{:?}", policy.pattern()),
+            &policy.action().to_string(),
+            &settings.dir_policies.source,
+        ));
+    }
+    for (name, setting) in config::profile_settings(&files) {
+        lines.push(config::show_line(&name, &setting.value, &setting.source));
+    }
+
+    let targets = if !cli.targets.is_empty() {
+        Some((cli.targets.join(","), config::Source::Cli))
+    } else if let Some(target) = env("CARGO_BUILD_TARGET").filter(|target| !target.is_empty()) {
+        Some((target, config::Source::Env("CARGO_BUILD_TARGET")))
+    } else {
+        files.iter().rev().find_map(|(path, config)| {
+            let target = config.get("build")?.get("target")?;
+            let target = match target {
+                toml::Value::String(target) => target.clone(),
+                other => other.to_string(),
+            };
+            Some((target, config::Source::File(path.clone())))
+        })
+    };
+    if let Some((targets, source)) = targets {
+        lines.push(config::show_line("build.target", &targets, &source));
+    }
+
+    let cargo = match std::env::var("CARGO") {
+        Ok(value) if !value.trim().is_empty() => config::Source::Env("CARGO"),
+        _ => config::Source::Default,
+    };
+    lines.push(config::show_line("cargo", &resolve_cargo_exe(), &cargo));
+    let how = if cli.no_metadata {
+        "guessed from Cargo.toml"
+    } else {
+        "cargo metadata"
+    };
+    lines.push(config::show_line(
+        "target-dir",
+        &resolve_target_dir(cli)?.display().to_string(),
+        &config::Source::Derived(how),
+    ));
+    Ok(lines)
+}
+
+pub fn run(mut cli: Cli) -> Result<CompressionStats> {
+    if cli.command == Some(CliCommand::Doctor) {
+        doctor::run(&cli)?;
+        return Ok(CompressionStats::default());
+    }
+    if cli.command == Some(CliCommand::Explain) {
+        for line in explain::lines(&cli)? {
+            println!("{line}");
+        }
+        return Ok(CompressionStats::default());
+    }
+    if let Some(CliCommand::Config {
+        action: ConfigCommand::Show,
+    }) = cli.command
+    {
+        for line in show_config(&cli)? {
+            println!("{line}");
+        }
+        return Ok(CompressionStats::default());
+    }
+    if let Some(CliCommand::Ctl { socket, command }) = &cli.command {
+        println!("{}", control::send(socket, command.name())?);
+        return Ok(CompressionStats::default());
+    }
+    if let Some(CliCommand::Analyze { action }) = cli.command {
+        let dirs: Vec<PathBuf> = list_work_dirs(&cli)?
+            .into_iter()
+            .map(|dir| dir.path)
+            .collect();
+        let cwd = std::env::current_dir().ok();
+        let report = match action {
+            AnalyzeCommand::Duplicates => {
+                analyze::duplicates(&dirs, cwd.as_deref(), cli.size_format())
+            }
+            AnalyzeCommand::Crates => analyze::crates(&dirs, cli.size_format()),
+            AnalyzeCommand::Ratios => ratio_cache::stats(&dirs, cli.size_format()),
+        };
+        println!("{report}");
+        return Ok(CompressionStats::default());
+    }
+    if let Some(CliCommand::InitAlias { name, print }) = &cli.command {
+        alias::run(&cli, name, *print)?;
+        return Ok(CompressionStats::default());
+    }
+    if let Some(CliCommand::Init { print, force }) = &cli.command {
+        init::run(&cli, *print, *force)?;
+        return Ok(CompressionStats::default());
+    }
+    if cli.print_target_dir {
+        println!("{}", resolve_target_dir(&cli)?.display());
+        return Ok(CompressionStats::default());
+    }
+    if cli.list {
+        for dir in list_work_dirs(&cli)? {
+            if cli.log_format == LogFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&dir).expect("work dirs serialize")
+                );
+            } else {
+                println!("{}", dir.path.display());
+            }
+        }
+        return Ok(CompressionStats::default());
+    }
+    let style = if cli.summary_only {
+        ProgressArg::None
+    } else {
+        cli.progress.resolve(std::io::stdout().is_terminal())
+    };
+    cli.os_log |= os_log::under_launchd();
+    if cli.heartbeat.is_none() && style == ProgressArg::Plain {
+        cli.heartbeat = Some(DEFAULT_HEARTBEAT);
+    }
+    #[cfg(feature = "tui")]
+    let dashboard = if cli.tui && style == ProgressArg::Fancy {
+        Some(
+            tui::Dashboard::new(cli.verbosity())
+                .map_err(Error::Terminal)?
+                .with_log_format(cli.log_format)
+                .with_size_format(cli.size_format()),
+        )
+    } else {
+        None
+    };
+    let progress: Box<dyn Reporter> = match style {
+        #[cfg(feature = "tui")]
+        _ if let Some(dashboard) = dashboard => Box::new(dashboard),
+        #[cfg(feature = "cli")]
+        ProgressArg::Fancy => Box::new(
+            ProgressBars::new(cli.verbosity())
+                .with_log_format(cli.log_format)
+                .with_size_format(cli.size_format()),
+        ),
+        ProgressArg::None => Box::new(
+            PlainProgress::new(Verbosity::Quiet)
+                .with_log_format(cli.log_format)
+                .with_size_format(cli.size_format()),
+        ),
+        _ => Box::new(
+            PlainProgress::new(cli.verbosity())
+                .with_log_format(cli.log_format)
+                .with_size_format(cli.size_format()),
+        ),
+    };
+    let log_file = cli
+        .log_file
+        .as_deref()
+        .map(|path| {
+            let max_size = cli.log_file_max_size.unwrap_or(DEFAULT_LOG_FILE_MAX_SIZE);
+            log_file::LogFile::open(path, max_size, cli.log_format).map_err(|source| {
+                Error::LogFile {
+                    path: path.to_path_buf(),
+                    source,
+                }
+            })
+        })
+        .transpose()?;
+    let progress: Box<dyn Reporter> = match &log_file {
+        Some(log) => Box::new(log_file::Logged {
+            inner: progress,
+            log,
+        }),
+        None => progress,
+    };
+    let os_log = cli.os_log;
+    let compressor: &dyn Compressor = match cli.compressor {
+        CompressorArg::Applesauce => &ApplesauceCompressor,
+        CompressorArg::Null => &NullCompressor,
+    };
+    let result = run_with_compressor(cli, compressor, &*progress, Arc::new(NoopMetrics));
+    if let Err(error) = &result {
+        if os_log {
+            os_log::log(os_log::OsLogLevel::Error, &error.chain().to_string());
+        }
+        if let Some(log) = &log_file {
+            log.log(LogLevel::Error, None, &error.chain().to_string());
+        }
+    }
+    result
+}
+
+/// Every file in `dir` for `--report`. Only reads, so it holds a shared lock on the work dir: that
+/// waits out a build that started since compressing but doesn't block other readers.
+fn collect_report_files(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+) -> Vec<PathBuf> {
+    let _lock = if options.lock_work_dir {
+        match Filesystem::new(dir.to_path_buf()).open_ro_shared(
+            &options.lock_name,
+            "build directory",
+            progress,
+        ) {
+            Ok(lock) => Some(lock),
+            Err(_) => return Vec::new(),
+        }
+    } else {
+        None
+    };
+    walk::collect_inputs(dir, options, &PlainProgress::new(Verbosity::Quiet)).unwrap_or_default()
+}
+
+/// A `--lock-name`: a plain file name, so it stays inside the directory being locked.
+pub fn parse_lock_name(value: &str) -> Result<String, String> {
+    if value.is_empty() || value == "." || value == ".." || value.contains('/') {
+        return Err(format!("{value:?} is not a file name"));
+    }
+    Ok(value.to_owned())
+}
+
+/// A `tool-caches` argument: the name of a cache in [`tool_caches::KNOWN`].
+pub fn parse_tool_cache(value: &str) -> Result<String, String> {
+    if tool_caches::KNOWN.iter().any(|cache| cache.name == value) {
+        return Ok(value.to_owned());
+    }
+    let known: Vec<_> = tool_caches::KNOWN.iter().map(|cache| cache.name).collect();
+    Err(format!(
+        "unknown tool cache {value:?}; known ones are {}",
+        known.join(", ")
+    ))
+}
+
+/// `--prescan`: the total logical size of the files the run will look at in each dir, found by
+/// walking it without locking it, and how much of that is compressed already, from `listing`'s
+/// compressed entries in each parent dir (see [`platform::compressed_entries`]). Builds running
+/// meanwhile can make this a little off.
+///
+/// Files in dirs that can't be listed count towards neither side of the baseline, and if none
+/// can, there's no baseline.
+fn prescan(
+    dirs: &[PathBuf],
+    options: &WorkDirOptions,
+    listing: &dyn Fn(&Path) -> io::Result<HashSet<OsString>>,
+) -> (Vec<u64>, Option<summary::Baseline>) {
+    let quiet = PlainProgress::new(Verbosity::Quiet);
+    let mut baseline = summary::Baseline::default();
+    let mut listings: HashMap<PathBuf, Option<HashSet<OsString>>> = HashMap::new();
+    let mut sizes = Vec::new();
+    for dir in dirs {
+        let mut size = 0;
+        for path in walk::collect_inputs(dir, options, &quiet).unwrap_or_default() {
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            size += metadata.len();
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+            let Some(compressed) = listings
+                .entry(parent.to_path_buf())
+                .or_insert_with(|| listing(parent).ok())
+            else {
+                continue;
+            };
+            baseline.bytes += metadata.len();
+            if compressed.contains(name) {
+                baseline.compressed += metadata.len();
+            }
+        }
+        sizes.push(size);
+    }
+    let baseline = listings.values().any(Option::is_some).then_some(baseline);
+    (sizes, baseline)
+}
+
+/// `--max-files`: the `max` files the run should compress in `dirs`, the largest first and the
+/// oldest first among those the same size, and how many there were to pick from. Files `listing`
+/// (see [`platform::compressed_entries`]) flags as compressed aren't candidates, so each run
+/// moves on to new ones.
+fn largest_files(
+    dirs: &[PathBuf],
+    options: &WorkDirOptions,
+    max: usize,
+    listing: &dyn Fn(&Path) -> io::Result<HashSet<OsString>>,
+) -> (HashSet<PathBuf>, usize) {
+    let quiet = PlainProgress::new(Verbosity::Quiet);
+    let mut listings: HashMap<PathBuf, Option<HashSet<OsString>>> = HashMap::new();
+    let mut candidates = Vec::new();
+    for dir in dirs {
+        for path in walk::collect_inputs(dir, options, &quiet).unwrap_or_default() {
+            let compressed = path
+                .parent()
+                .zip(path.file_name())
+                .is_some_and(|(parent, name)| {
+                    listings
+                        .entry(parent.to_path_buf())
+                        .or_insert_with(|| listing(parent).ok())
+                        .as_ref()
+                        .is_some_and(|names| names.contains(name))
+                });
+            if compressed && !options.force && !options.migrate_compression {
+                continue;
+            }
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            candidates.push((std::cmp::Reverse(metadata.len()), modified, path));
+        }
+    }
+    let count = candidates.len();
+    candidates.sort();
+    let selected = candidates
+        .into_iter()
+        .take(max)
+        .map(|(.., path)| path)
+        .collect();
+    (selected, count)
+}
+
+/// Reorders `dirs` largest first by `sizes`, so the big dirs start early and overlap with the
+/// small ones rather than running on alone at the end. Ties keep their order.
+fn largest_first<T>(dirs: Vec<T>, sizes: Vec<u64>) -> Vec<T> {
+    let mut sized: Vec<_> = sizes.into_iter().zip(dirs).collect();
+    sized.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    sized.into_iter().map(|(_, dir)| dir).collect()
+}
+
+/// What the files a run would look at in `dir` take up on disk, or 0 if it can't be walked.
+fn on_disk_size(dir: &Path, options: &WorkDirOptions) -> u64 {
+    let quiet = PlainProgress::new(Verbosity::Quiet);
+    walk::collect_inputs(dir, options, &quiet)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|path| disk::allocated_size(path).ok())
+        .sum()
+}
+
+/// The `cache` subcommand: compresses `components` of `$CARGO_HOME` under cargo's package cache
+/// lock and prints a summary for each before the overall one.
+fn compress_cargo_caches(
+    cli: &Cli,
+    components: Vec<cargo_home::Component>,
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let env = |name: &str| std::env::var(name).ok();
+    let home = config::cargo_home(&env).ok_or(Error::NoCargoHome)?;
+    let options = unlocked_dir_options(cli, &home, started)?;
+
+    let mut work = Vec::new();
+    for component in components {
+        let dirs = component
+            .dirs(&home)
+            .map_err(Error::read(component.path(&home)))?;
+        work.extend(dirs.into_iter().map(|dir| (component.label(), dir)));
+    }
+    let _lock = if work.is_empty() {
+        None
+    } else {
+        let lock = Filesystem::new(home.clone()).open_rw_exclusive_create(
+            cargo_home::PACKAGE_CACHE_LOCK,
+            "package cache",
+            progress,
+        )?;
+        Some(lock_holder::record(lock, "compressing"))
+    };
+    compress_labelled_dirs(cli, &options, work, compressor, progress, started)
+}
+
+fn compress_swiftpm(
+    cli: &Cli,
+    paths: &[PathBuf],
+    xcode: bool,
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let paths = if paths.is_empty() {
+        vec![cwd.clone()]
+    } else {
+        paths.iter().map(|path| cwd.join(path)).collect()
+    };
+    let mut work = Vec::new();
+    for path in &paths {
+        let dir =
+            swiftpm::build_dir(path).ok_or_else(|| Error::NoSwiftPackage { path: path.clone() })?;
+        // Several paths inside one package name the same dir.
+        if !dir.is_dir() {
+            progress.println_verbose(|| format!("skip {} (not built yet)", dir.display()));
+        } else if let Ok(dir) = fs::canonicalize(&dir)
+            && !work.iter().any(|(_, seen)| *seen == dir)
+        {
+            work.push(("swiftpm .build", dir));
+        }
+    }
+    if xcode {
+        let home = std::env::home_dir().ok_or(Error::NoHomeDir)?;
+        let dirs = swiftpm::xcode_source_packages(&home).map_err(Error::read(&home))?;
+        work.extend(dirs.into_iter().map(|dir| ("xcode packages", dir)));
+    }
+    let mut options = unlocked_dir_options(cli, &paths[0], started)?;
+    options
+        .skip_dirs
+        .extend(swiftpm::SKIP_DIRS.iter().map(|&name| name.to_owned()));
+    compress_labelled_dirs(cli, &options, work, compressor, progress, started)
+}
+
+fn compress_tool_caches(
+    cli: &Cli,
+    tools: &[String],
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let home = std::env::home_dir().ok_or(Error::NoHomeDir)?;
+    let caches = tool_caches::caches_dir(&home);
+    let mut work = Vec::new();
+    for cache in tool_caches::KNOWN {
+        if !tools.is_empty() && !tools.iter().any(|tool| tool == cache.name) {
+            continue;
+        }
+        let dirs = cache.existing_dirs(&caches);
+        if dirs.is_empty() {
+            progress.println_verbose(|| format!("skip {} (no cache found)", cache.name));
+        }
+        work.extend(dirs.into_iter().map(|dir| (cache.name, dir)));
+    }
+    let options = unlocked_dir_options(cli, &home, started)?;
+    compress_labelled_dirs(cli, &options, work, compressor, progress, started)
+}
+
+fn compress_generic(
+    cli: &Cli,
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    let home = std::env::home_dir();
+    let mut dirs = Vec::new();
+    for dir in &cli.generic {
+        let dir = cwd.join(dir);
+        if !cli.i_know_what_im_doing {
+            guard::check_generic_dir(&dir, home.as_deref())?;
+        }
+        dirs.push(fs::canonicalize(&dir).map_err(Error::read(&dir))?);
+    }
+    let (dirs, nested) = dedup_work_dirs(dirs);
+    for (dir, outer) in &nested {
+        progress.println_verbose(|| format!("skip {} (inside {})", dir.display(), outer.display()));
+    }
+
+    let mut options = unlocked_dir_options(cli, &cwd, started)?;
+    options.skip_dirs.extend(cli.generic_skip.iter().cloned());
+    let work = dirs.into_iter().map(|dir| ("generic", dir)).collect();
+    compress_labelled_dirs(cli, &options, work, compressor, progress, started)
+}
+
+/// [`process_work_dir`] for one worker: tells `progress` when the dir starts and finishes, and
+/// returns how long it took. A panic fails only this dir, with [`Error::WorkerPanicked`]; its
+/// lock is released as the panic unwinds.
+fn run_work_dir(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+    compressor: &dyn Compressor,
+) -> (Result<CompressionStats>, Duration) {
+    let started = Instant::now();
+    progress.work_dir_started(dir);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        process_work_dir(dir, options, progress, compressor)
+    }))
+    .unwrap_or_else(|panic| {
+        Err(Error::WorkerPanicked {
+            message: panic_message(panic.as_ref()),
+        })
+    });
+    let elapsed = started.elapsed();
+    progress.work_dir_finished(dir, result.as_ref().ok());
+    (result, elapsed)
+}
+
+/// Room for as many work dir workers at once as the open file limit allows, after raising it,
+/// with a budget of its own for each volume that `volume_jobs` names or whose storage is slower
+/// than the internal SSD.
+fn worker_slots(
+    volumes: &[DirVolume],
+    volume_jobs: &[VolumeJobs],
+    efficiency_cores: Option<usize>,
+    progress: &dyn Reporter,
+) -> Result<fd_limit::Slots> {
+    let dirs = volumes.len();
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    let mut workers = dirs.max(1);
+    if let Some(limit) = fd_limit::raise_open_file_limit() {
+        workers = fd_limit::max_workers(limit, cpus);
+        if workers < dirs {
+            progress.println_verbose(|| {
+                format!("note: open file limit is {limit}; compressing {workers} dirs at a time")
+            });
+        }
+    }
+    if let Some(cores) = efficiency_cores
+        && cores < workers.min(dirs)
+    {
+        workers = cores;
+        progress.println_verbose(|| {
+            format!("note: compressing {cores} dirs at a time, one per efficiency core")
+        });
+    }
+    let mut overrides = HashMap::new();
+    for VolumeJobs { path, jobs } in volume_jobs {
+        let device = fs::metadata(path).map_err(Error::read(path))?.dev();
+        overrides.insert(device, (*jobs, path));
+    }
+    let mut limits = HashMap::new();
+    for volume in volumes {
+        let Some(device) = volume.device else {
+            continue;
+        };
+        if limits.contains_key(&device) {
+            continue;
+        }
+        let on_volume = volumes
+            .iter()
+            .filter(|other| other.device == Some(device))
+            .count();
+        let limit = match overrides.get(&device) {
+            Some(&(jobs, path)) => Some((jobs, format!("the volume holding {}", path.display()))),
+            None => volume
+                .storage
+                .and_then(|storage| Some((storage.max_workers()?, storage.to_string()))),
+        };
+        if let Some((max, what)) = limit {
+            if max < on_volume.min(workers) {
+                progress.println_verbose(|| {
+                    format!("note: compressing at most {max} dirs at a time on {what}")
+                });
+            }
+            limits.insert(device, max);
+        }
+    }
+    Ok(fd_limit::Slots::new(workers, limits))
+}
+
+/// `--efficiency-cores`: moves the run to the background QoS class before any workers start, so
+/// they inherit it, and returns how many efficiency cores there are to size the pool to. Without
+/// them, says why and carries on as if the flag wasn't given.
+fn efficiency_cores(cli: &Cli, progress: &dyn Reporter) -> Option<usize> {
+    if !cli.efficiency_cores {
+        return None;
+    }
+    match platform::efficiency_cores()
+        .and_then(|cores| platform::prefer_efficiency_cores().map(|()| cores))
+    {
+        Ok(cores) => Some(cores.max(1)),
+        Err(error) => {
+            progress.println_normal(|| {
+                format!("note: can't keep to efficiency cores ({error}); using every core")
+            });
+            None
+        }
+    }
+}
+
+/// The volume a work dir is on, for scheduling.
+#[derive(Clone, Copy, Debug)]
+struct DirVolume {
+    /// The device number, or `None` if the dir can't be read.
+    device: Option<u64>,
+    /// `None` where it can't be told.
+    storage: Option<disk::Storage>,
+}
+
+/// The volume each of `dirs` is on, asking about the storage once per volume.
+fn dir_volumes<'a>(dirs: impl IntoIterator<Item = &'a Path>) -> Vec<DirVolume> {
+    let mut storage_by_device: HashMap<u64, Option<disk::Storage>> = HashMap::new();
+    dirs.into_iter()
+        .map(|dir| {
+            let device = fs::metadata(dir).ok().map(|metadata| metadata.dev());
+            let storage = device.and_then(|device| {
+                *storage_by_device.entry(device).or_insert_with(|| {
+                    let volume = disk::volume(dir).ok()?;
+                    platform::storage(&volume.mount_point).ok()
+                })
+            });
+            DirVolume { device, storage }
+        })
+        .collect()
+}
+
+/// The text a panic was raised with, if it was a string.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| (*message).to_owned())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_owned())
+}
+
+/// A finished dir's summary line, if it still exists. Printed once every worker is done, in
+/// order of dir, so the output doesn't depend on which finished first.
+fn print_dir_summary(
+    dir: &Path,
+    stats: CompressionStats,
+    elapsed: Duration,
+    cwd: Option<&Path>,
+    progress: &dyn Reporter,
+) {
+    if dir.is_dir() {
+        progress.println_normal(|| {
+            summary::DirSummary {
+                dir: summary::relative_to(dir, cwd),
+                stats,
+                elapsed,
+                sizes: progress.size_format(),
+            }
+            .to_string()
+        });
+    }
+}
+
+/// Sets `options`' modification time window from `--since` and `--before`, resolving git refs in
+/// the current dir.
+fn resolve_time_window(cli: &Cli, options: &mut WorkDirOptions) -> Result<()> {
+    if cli.since.is_none() && cli.before.is_none() {
+        return Ok(());
+    }
+    let cwd = std::env::current_dir().map_err(Error::CurrentDir)?;
+    options.modified_since = cli
+        .since
+        .as_ref()
+        .map(|since| since.resolve(&cwd))
+        .transpose()?;
+    options.modified_before = cli
+        .before
+        .as_ref()
+        .map(|before| before.resolve(&cwd))
+        .transpose()?;
+    Ok(())
+}
+
+/// Options for dirs outside any cargo target dir, which have no `.cargo-lock` of their own. The
+/// layered compression settings are resolved as if from `root`.
+fn unlocked_dir_options(cli: &Cli, root: &Path, started: Instant) -> Result<WorkDirOptions> {
+    let settings = load_settings(cli, root)?;
+    let mut options = cli.work_dir_options();
+    options.lock_work_dir = false;
+    options.deps_only = false;
+    options.artifacts_only = false;
+    resolve_time_window(cli, &mut options)?;
+    options.compression = settings.compression.value;
+    options.small_file_compression = Some(settings.small_file_compression.value);
+    options.small_file_cutoff = settings.small_file_cutoff.value;
+    options.dir_policies = settings.dir_policies.value;
+    options
+        .dir_policies
+        .extend(cli.extra_dir_policies.iter().cloned());
+    options
+        .skip_extensions
+        .extend(settings.skip_extensions.value);
+    options.stop = cli
+        .stop
+        .clone()
+        .with_deadline(cli.timeout.map(|timeout| started + timeout));
+    Ok(options)
+}
+
+/// Compresses each dir on its own thread, then prints a summary per label in the order the labels
+/// first appear in `work`, followed by the overall one.
+fn compress_labelled_dirs(
+    cli: &Cli,
+    options: &WorkDirOptions,
+    work: Vec<(&'static str, PathBuf)>,
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let sizes = work
+        .iter()
+        .map(|(_, dir)| disk::estimate_size(dir))
+        .collect();
+    let work = largest_first(work, sizes);
+    let volumes = dir_volumes(work.iter().map(|(_, dir)| dir.as_path()));
+    let cores = efficiency_cores(cli, progress);
+    let slots = worker_slots(&volumes, &cli.volume_jobs, cores, progress)?;
+    let mut pending: Vec<_> = work.into_iter().zip(volumes).collect();
+    let mut results: Vec<_> = std::thread::scope(|scope| {
+        let mut handles = Vec::new();
+        while !pending.is_empty() {
+            let devices: Vec<_> = pending.iter().map(|(_, volume)| volume.device).collect();
+            let (index, slot) = slots.acquire_first(&devices);
+            let ((label, dir), volume) = pending.remove(index);
+            let mut options = options.clone();
+            if let Some(storage) = volume.storage {
+                options.walk_threads = storage.walk_threads();
+            }
+            if let Some(cores) = cores {
+                options.walk_threads = options.walk_threads.min(cores);
+            }
+            handles.push(scope.spawn(move || {
+                let (result, elapsed) = run_work_dir(&dir, &options, progress, compressor);
+                drop(slot);
+                (label, dir, result, elapsed)
+            }));
+        }
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    });
+
+    struct Totals {
+        label: &'static str,
+        stats: CompressionStats,
+        dirs: usize,
+        failed: usize,
+    }
+    // Labels keep the order of `work`; dirs are listed sorted.
+    let mut by_label: Vec<Totals> = Vec::new();
+    for (label, _, _, _) in &results {
+        if !by_label.iter().any(|totals| totals.label == *label) {
+            by_label.push(Totals {
+                label,
+                stats: CompressionStats::default(),
+                dirs: 0,
+                failed: 0,
+            });
+        }
+    }
+    results.sort_by(|a, b| a.1.cmp(&b.1));
+    for (label, dir, result, elapsed) in results {
+        let totals = by_label
+            .iter_mut()
+            .find(|totals| totals.label == label)
+            .unwrap();
+        match result {
+            Ok(stats) => {
+                print_dir_summary(&dir, stats, elapsed, None, progress);
+                totals.stats += stats;
+                totals.dirs += 1;
+            }
+            Err(error) => {
+                totals.failed += 1;
+                progress.error(&dir, &error.chain().to_string());
+            }
+        }
+    }
+
+    let summary = |stats, dirs, failed| RunSummary {
+        stats,
+        dirs,
+        failed,
+        elapsed: started.elapsed(),
+        sizes: progress.size_format(),
+        completed: None,
+        free_space: None,
+        baseline: None,
+    };
+    let mut total = summary(CompressionStats::default(), 0, 0);
+    for totals in &by_label {
+        progress.println_normal(|| {
+            format!(
+                "{}: {}",
+                totals.label,
+                summary(totals.stats, totals.dirs, totals.failed)
+            )
+        });
+        total.stats += totals.stats;
+        total.dirs += totals.dirs;
+        total.failed += totals.failed;
+    }
+    if cli.summary_only {
+        progress.message(Verbosity::Quiet, &total.to_string());
+    } else {
+        progress.println_normal(|| total.to_string());
+    }
+    progress.finish();
+
+    if options.stop.timed_out()
+        && let Some(timeout) = cli.timeout
+    {
+        return Err(Error::TimedOut {
+            after: summary::format_elapsed(timeout),
+        });
+    }
+    if total.failed > 0 {
+        return Err(Error::DirectoriesFailed {
+            failed: total.failed,
+        });
+    }
+    if options.stop.cancelled() {
+        return Err(Error::Interrupted);
+    }
+    Ok(total.stats)
+}
+
+/// `--jobs-file`: each job in turn, then a line summing them up. A job that fails doesn't stop
+/// the ones after it, but being stopped does.
+fn run_jobs(
+    cli: &Cli,
+    path: &Path,
+    compressor: &dyn Compressor,
+    progress: &dyn Reporter,
+    metrics: Arc<dyn MetricsSink>,
+    started: Instant,
+) -> Result<CompressionStats> {
+    let jobs = jobs_file::load(path, cli)?;
+    let mut total = CompressionStats::default();
+    let mut failed = 0;
+    for (index, job) in jobs.iter().enumerate() {
+        progress.println_normal(|| format!("job {}/{}: {}", index + 1, jobs.len(), job.label));
+        match run_with_compressor(job.cli.clone(), compressor, progress, Arc::clone(&metrics)) {
+            Ok(stats) => total += stats,
+            Err(error @ (Error::Interrupted | Error::TimedOut { .. })) => return Err(error),
+            Err(error) => {
+                failed += 1;
+                progress.println_normal(|| format!("job {} failed: {}", job.label, error.chain()));
+            }
+        }
+    }
+    if jobs.len() > 1 {
+        progress.println_normal(|| {
+            format!(
+                "Ran {} jobs from {}, {failed} failed: compressed {} files ({} skipped), saving {} \
+                 ({} -> {}) in {}",
+                jobs.len(),
+                path.display(),
+                total.files_compressed,
+                total.files_skipped,
+                progress.format_bytes(total.bytes_saved()),
+                progress.format_bytes(total.bytes_in),
+                progress.format_bytes(total.bytes_out),
+                summary::format_elapsed(started.elapsed())
+            )
+        });
+    }
+    if failed > 0 {
+        return Err(Error::JobsFailed {
+            failed,
+            total: jobs.len(),
+        });
+    }
+    Ok(total)
+}
+
+/// Runs with a custom compressor backend, progress reporter, and metrics sink.
+///
+/// `cli.verbose` and `cli.quiet` are ignored here; `reporter` decides what to display.
+///
+/// On success, returns the combined stats of every work dir.
+pub fn run_with_compressor(
+    cli: Cli,
+    compressor: &dyn Compressor,
+    reporter: &dyn Reporter,
+    metrics: Arc<dyn MetricsSink>,
+) -> Result<CompressionStats> {
+    let started = Instant::now();
+    let metered = Metered::new(reporter, Arc::clone(&metrics));
+    let progress: &dyn Reporter = &metered;
+    if let Some(CliCommand::Cache {
+        index,
+        registry_src,
+        git,
+    }) = cli.command
+    {
+        let picked = [index, registry_src, git];
+        let components = cargo_home::Component::ALL
+            .into_iter()
+            .zip(picked)
+            .filter(|&(_, wanted)| wanted || picked == [false; 3])
+            .map(|(component, _)| component)
+            .collect();
+        return compress_cargo_caches(&cli, components, compressor, progress, started);
+    }
+    if let Some(CliCommand::Swiftpm { paths, xcode }) = &cli.command {
+        return compress_swiftpm(&cli, paths, *xcode, compressor, progress, started);
+    }
+    if let Some(CliCommand::ToolCaches { tools }) = &cli.command {
+        return compress_tool_caches(&cli, tools, compressor, progress, started);
+    }
+    if let Some(path) = &cli.jobs_file {
+        return run_jobs(&cli, path, compressor, progress, metrics, started);
+    }
+    if !cli.generic.is_empty() {
+        return compress_generic(&cli, compressor, progress, started);
+    }
+    let Resolved {
+        projects,
+        target_dirs,
+        packages,
+        dirs,
+        nested,
+        links,
+        wasm_dirs,
+        settings,
+    } = resolve(&cli).map_err(|error| {
+        // Ctrl-C also reaches `cargo metadata`, whose failure is then only a symptom.
+        if cli.stop.should_stop() {
+            Error::Interrupted
+        } else {
+            error
+        }
+    })?;
+    let mut dirs: Vec<PathBuf> = dirs.into_iter().map(|dir| dir.path).collect();
+    if cli.compressor == CompressorArg::Null {
+        progress.println_normal(|| {
+            format!(
+                "timings: found {} work dir(s) in {}",
+                dirs.len(),
+                format_stage(started.elapsed())
+            )
+        });
+    }
+    for (link, real) in &links {
+        progress.println_verbose(|| format!("target dir {} -> {}", link.display(), real.display()));
+    }
+    for (dir, outer) in &nested {
+        progress.println_verbose(|| format!("skip {} (inside {})", dir.display(), outer.display()));
+    }
+    if cli.interactive {
+        if !std::io::stdin().is_terminal() {
+            return Err(Error::NotInteractive);
+        }
+        let options = cli.work_dir_options();
+        let sizes: Vec<_> = dirs.iter().map(|dir| on_disk_size(dir, &options)).collect();
+        let cwd = std::env::current_dir().ok();
+        dirs = progress
+            .suspended(|| {
+                select::choose(
+                    dirs,
+                    &sizes,
+                    cwd.as_deref(),
+                    progress.size_format(),
+                    &mut std::io::stdin().lock(),
+                    &mut std::io::stderr(),
+                )
+            })
+            .map_err(Error::Prompt)?;
+        if dirs.is_empty() {
+            progress.println_normal(|| "nothing selected".to_owned());
+            progress.finish();
+            return Ok(CompressionStats::default());
+        }
+    }
+    if let Some(required) = cli.only_when_idle {
+        let idle = platform::hid_idle_time().map_err(Error::IdleCheck)?;
+        let load = platform::load_per_cpu().map_err(Error::IdleCheck)?;
+        let busy = if idle < required {
+            Some(format!(
+                "last input {} ago, waiting for {}",
+                summary::format_elapsed(idle),
+                summary::format_elapsed(required)
+            ))
+        } else if load > IDLE_MAX_LOAD_PER_CPU {
+            Some(format!("load is {load:.2} per CPU"))
+        } else {
+            None
+        };
+        if let Some(busy) = busy {
+            progress.println_normal(|| format!("skip: not idle ({busy})"));
+            progress.finish();
+            return Ok(CompressionStats::default());
+        }
+    }
+    if cli.only_on_ac && platform::on_battery().map_err(Error::PowerCheck)? {
+        progress.println_normal(|| "skip: on battery power".to_owned());
+        progress.finish();
+        return Ok(CompressionStats::default());
+    }
+    // Low Power Mode is best effort: if it can't be read, assume it is off. Reading it runs
+    // `pmset`, which isn't worth it for a note nobody is watching.
+    let low_power = cli
+        .low_power
+        .or_else(|| std::io::stderr().is_terminal().then_some(LowPowerArg::Note));
+    let serial = if let Some(low_power) = low_power
+        && platform::low_power_mode().unwrap_or(false)
+    {
+        if low_power == LowPowerArg::Skip {
+            progress.println_normal(|| "skip: Low Power Mode is on".to_owned());
+            progress.finish();
+            return Ok(CompressionStats::default());
+        }
+        progress.println_normal(|| {
+            if low_power == LowPowerArg::Serial {
+                "note: Low Power Mode is on; compressing one dir at a time".to_owned()
+            } else {
+                "note: Low Power Mode is on; compression will be slower".to_owned()
+            }
+        });
+        low_power == LowPowerArg::Serial
+    } else {
+        false
+    };
+    if let Some(threshold) = cli.only_if_free_below {
+        let mut lowest = u64::MAX;
+        for target_dir in &target_dirs {
+            // A target dir nothing has been built into yet is on the volume its parent is on.
+            let existing = target_dir
+                .ancestors()
+                .find(|dir| dir.exists())
+                .unwrap_or(target_dir);
+            let free = disk::free_space(existing).map_err(|source| Error::FreeSpace {
+                path: existing.to_path_buf(),
+                source,
+            })?;
+            lowest = lowest.min(free);
+        }
+        if lowest >= threshold {
+            progress.println_normal(|| {
+                format!(
+                    "skip: {} free, threshold is {}",
+                    progress.format_bytes(lowest),
+                    progress.format_bytes(threshold)
+                )
+            });
+            progress.finish();
+            return Ok(CompressionStats::default());
+        }
+    }
+
+    for target_dir in &target_dirs {
+        if !cli.i_know_what_im_doing {
+            guard::check_target_dir(target_dir, std::env::home_dir().as_deref())?;
+        }
+
+        if cli.cachedir_tag && target_dir.is_dir() {
+            let created =
+                hygiene::tag_cache_dir(target_dir).map_err(|source| Error::WriteMarker {
+                    path: target_dir.join(hygiene::CACHEDIR_TAG_NAME),
+                    source,
+                })?;
+            if created {
+                progress.println_normal(|| {
+                    format!("tag {} as a cache directory", target_dir.display())
+                });
+            }
+        }
+    }
+
+    // Per-profile config sits between env and the plain `apfs-compress.compression`. With several
+    // projects, the first one to configure a dir decides, and within one, `profile_precedence`.
+    let mut compression_by_dir: BTreeMap<String, (Kind, String)> = BTreeMap::new();
+    if matches!(
+        settings.compression.source,
+        config::Source::File(_) | config::Source::Default
+    ) {
+        for (root, overrides) in &projects {
+            let mut defaults: Vec<_> = load_profile_compression_defaults(root)?
+                .into_iter()
+                .collect();
+            defaults.sort_by(|(a, _), (b, _)| profile_precedence(a).cmp(&profile_precedence(b)));
+            for (profile, kind) in defaults {
+                let dir = resolve_profile_dir_name(&profile, overrides);
+                match compression_by_dir.get(&dir) {
+                    None => {
+                        compression_by_dir.insert(dir, (kind, profile));
+                    }
+                    Some((chosen, by)) if *chosen != kind => {
+                        let name = |kind: Kind| kind.name().to_ascii_lowercase();
+                        progress.println_normal(|| {
+                            format!(
+                                "note: {dir} is compressed with {} as profile.{by} says, not {} \
+                                 as profile.{profile} says; both build into it",
+                                name(*chosen),
+                                name(kind)
+                            )
+                        });
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    let mut target_locks = Vec::new();
+    if cli.lock_target_dir {
+        let name = cli.lock_name.as_deref().unwrap_or(CARGO_LOCK_NAME);
+        // `resolve` sorts these, so two runs sharing target dirs take their locks in the same
+        // order and can't each hold one the other waits for.
+        for target_dir in &target_dirs {
+            let fs = Filesystem::new(target_dir.clone());
+            let lock = fs.open_rw_exclusive_create(name, "target directory", progress)?;
+            target_locks.push(lock_holder::record(lock, "compressing"));
+        }
+    }
+    if let Some(path) = &cli.lock_path {
+        let name = path.file_name().ok_or_else(|| Error::LockOpen {
+            path: path.clone(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"),
+        })?;
+        let fs = Filesystem::new(path.parent().unwrap_or(Path::new("")).to_path_buf());
+        let lock = fs.open_rw_exclusive_create(name, "lock file", progress)?;
+        target_locks.push(lock_holder::record(lock, "compressing"));
+    }
+    // Cache dirs are covered by cargo's package cache lock rather than a lock of their own, and
+    // cargo never locks wasm toolchain output at all.
+    let mut unlocked_dirs = wasm_dirs;
+    if cli.registry_index {
+        let env = |name: &str| std::env::var(name).ok();
+        let home = config::cargo_home(&env).ok_or(Error::NoCargoHome)?;
+        let index = cargo_home::Component::RegistryIndex
+            .dirs(&home)
+            .map_err(Error::read(
+                cargo_home::Component::RegistryIndex.path(&home),
+            ))?;
+        if !index.is_empty() {
+            let lock = Filesystem::new(home).open_rw_exclusive_create(
+                cargo_home::PACKAGE_CACHE_LOCK,
+                "package cache",
+                progress,
+            )?;
+            target_locks.push(lock_holder::record(lock, "compressing"));
+            unlocked_dirs.extend(index.iter().cloned());
+            dirs.extend(index);
+        }
+    }
+
+    metrics.gauge(Gauge::WorkDirs, dirs.len() as u64);
+    let mut base_options = cli.work_dir_options();
+    base_options.stop = cli
+        .stop
+        .clone()
+        .with_deadline(cli.timeout.map(|timeout| started + timeout));
+    base_options.compression = settings.compression.value;
+    base_options.small_file_compression = Some(settings.small_file_compression.value);
+    base_options.small_file_cutoff = settings.small_file_cutoff.value;
+    base_options.dir_policies = settings.dir_policies.value.clone();
+    base_options
+        .dir_policies
+        .extend(cli.extra_dir_policies.iter().cloned());
+    resolve_time_window(&cli, &mut base_options)?;
+    base_options
+        .skip_extensions
+        .extend(settings.skip_extensions.value);
+    if cli.members_idle_for.is_some() {
+        base_options.members = packages
+            .iter()
+            .map(|package| {
+                PackageFilter::new(
+                    package.name.clone(),
+                    package.targets.iter().map(|target| target.name.clone()),
+                )
+            })
+            .collect();
+    }
+    for name in &cli.packages {
+        let package = packages
+            .iter()
+            .find(|package| &package.name == name)
+            .ok_or_else(|| Error::UnknownPackage { name: name.clone() })?;
+        base_options.packages.push(PackageFilter::new(
+            name.clone(),
+            package.targets.iter().map(|target| target.name.clone()),
+        ));
+    }
+    let dir_count = dirs.len();
+    let mut failures = Vec::new();
+    let mut dir_stats = Vec::new();
+    let mut report_files = Vec::new();
+    let mut total = CompressionStats::default();
+    let heartbeat = metered.heartbeat();
+    heartbeat.set_dirs_remaining(dirs.len());
+    // Only a prescan walks everything before the run, so only it can tell how much was
+    // compressed already.
+    let (sizes, baseline) = if cli.prescan {
+        let (sizes, baseline) = prescan(&dirs, &base_options, &platform::compressed_entries);
+        progress.total_known(sizes.iter().sum());
+        (sizes, baseline)
+    } else {
+        let sizes = dirs.iter().map(|dir| disk::estimate_size(dir)).collect();
+        (sizes, None)
+    };
+    if let Some(max) = cli.max_files {
+        let (selected, candidates) =
+            largest_files(&dirs, &base_options, max, &platform::compressed_entries);
+        if candidates > max {
+            progress.println_normal(|| {
+                format!(
+                    "note: compressing the {max} largest of {candidates} files; the rest are left \
+                     for later runs"
+                )
+            });
+        }
+        base_options.selected = Some(Arc::new(selected));
+    }
+    if let Some(path) = &cli.inventory {
+        let inventory = Inventory::create(path).map_err(|source| Error::Inventory {
+            path: path.clone(),
+            source,
+        })?;
+        base_options.inventory = Some(Arc::new(inventory));
+    }
+    let dirs = largest_first(dirs, sizes);
+    let (stop_heartbeat, heartbeat_stopped) = mpsc::channel::<()>();
+    let (stop_control, control_stopped) = mpsc::channel::<()>();
+    let listener = cli
+        .control_socket
+        .as_deref()
+        .map(control::bind)
+        .transpose()?;
+    let control = control::Control {
+        heartbeat,
+        stop: &base_options.stop,
+        pause: &base_options.pause,
+        sizes: progress.size_format(),
+    };
+
+    let cwd = std::env::current_dir().ok();
+    let volumes = disk::one_per_volume(&dirs);
+    let free_before = disk::total_free_space(&volumes);
+    let dir_volumes = dir_volumes(dirs.iter().map(PathBuf::as_path));
+    let cores = efficiency_cores(&cli, progress);
+    let slots = worker_slots(&dir_volumes, &cli.volume_jobs, cores, progress)?;
+    let mut pending: Vec<_> = dirs.into_iter().zip(dir_volumes).collect();
+    std::thread::scope(|scope| {
+        if let Some(interval) = cli.heartbeat.filter(|interval| !interval.is_zero()) {
+            scope.spawn(move || heartbeat.run(interval, &heartbeat_stopped, progress));
+        }
+        if let Some(listener) = &listener {
+            let control = &control;
+            scope.spawn(move || control::serve(listener, control, &control_stopped));
+        }
+        let mut results = Vec::new();
+        let mut handles = Vec::new();
+        while !pending.is_empty() {
+            let devices: Vec<_> = pending.iter().map(|(_, volume)| volume.device).collect();
+            let (index, slot) = slots.acquire_first(&devices);
+            let (dir, volume) = pending.remove(index);
+            let mut options = base_options.clone();
+            if let Some(storage) = volume.storage {
+                options.walk_threads = storage.walk_threads();
+            }
+            if let Some(cores) = cores {
+                options.walk_threads = options.walk_threads.min(cores);
+            }
+            if unlocked_dirs.contains(&dir) {
+                // Registry index and wasm dirs have no profile layout to select from.
+                options.lock_work_dir = false;
+                options.deps_only = false;
+                options.artifacts_only = false;
+            }
+            if let Some(&kind) = dir
+                .file_name()
+                .and_then(OsStr::to_str)
+                .and_then(|name| compression_by_dir.get(name))
+                .map(|(kind, _)| kind)
+            {
+                options.compression = kind;
+            }
+            let report = cli.report.is_some();
+            let fail_fast = cli.fail_fast;
+            let handle = scope.spawn(move || {
+                let _slot = slot;
+                let (result, elapsed) = run_work_dir(&dir, &options, progress, compressor);
+                if fail_fast && result.is_err() {
+                    options.stop.stop();
+                }
+                heartbeat.dir_finished();
+                // Walked again after compressing, so the report sees what is on disk now.
+                let files = if report && result.is_ok() && dir.is_dir() {
+                    collect_report_files(&dir, &options, progress)
+                } else {
+                    Vec::new()
+                };
+                (dir, result, files, elapsed)
+            });
+            if serial {
+                results.push(handle.join().expect("worker thread panicked"));
+            } else {
+                handles.push(handle);
+            }
+        }
+        results.extend(
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("worker thread panicked")),
+        );
+
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        for (dir, result, files, elapsed) in results {
+            report_files.extend(files);
+            match result {
+                Ok(stats) => {
+                    print_dir_summary(&dir, stats, elapsed, cwd.as_deref(), progress);
+                    total += stats;
+                    dir_stats.push(report::DirResult {
+                        dir: dir.clone(),
+                        stats,
+                        elapsed,
+                    });
+                    metrics.increment(Counter::DirsCompressed, 1);
+                }
+                Err(error) => {
+                    metrics.increment(Counter::DirsFailed, 1);
+                    let message = error.chain().to_string();
+                    progress.error(&dir, &message);
+                    failures.push((dir, message));
+                }
+            }
+        }
+        drop(stop_heartbeat);
+        drop(stop_control);
+    });
+    if let Some(path) = &cli.control_socket {
+        let _ = fs::remove_file(path);
+    }
+    if let Some(inventory) = &base_options.inventory {
+        finish_inventory(inventory, &base_options, compressor)?;
+    }
+    if cli.dedup {
+        let compressed: Vec<_> = dir_stats
+            .iter()
+            .map(|result| result.dir.clone())
+            .filter(|dir| !unlocked_dirs.contains(dir))
+            .collect();
+        clone_duplicates(&compressed, &base_options, progress)?;
+    }
+    if !metered.skips().is_empty() {
+        progress.println_normal(|| metered.skips().to_string());
+    }
+    if !metered.incompressible().is_empty() {
+        progress.println_normal(|| {
+            metered.incompressible().render(
+                INCOMPRESSIBLE_TOP,
+                cwd.as_deref(),
+                progress.size_format(),
+            )
+        });
+    }
+    if !metered.denied().is_empty() {
+        progress.println_normal(|| metered.denied().render(DENIED_TOP, cwd.as_deref()));
+    }
+    if cli.by_kind && !metered.kinds().ranked().is_empty() {
+        progress.println_normal(|| metered.kinds().render(progress.size_format()));
+    }
+    if let Some(top) = cli.top
+        && top > 0
+        && !dir_stats.is_empty()
+    {
+        progress.println_normal(|| {
+            summary::top_dirs(&dir_stats, top, cwd.as_deref(), progress.size_format())
+        });
+    }
+    let failed = failures.len();
+    let summary = RunSummary {
+        stats: total,
+        dirs: dir_count - failed,
+        failed,
+        elapsed: started.elapsed(),
+        sizes: progress.size_format(),
+        completed: (cli.prescan && base_options.stop.was_cut_short())
+            .then(|| metered.heartbeat().completed()),
+        free_space: free_before.zip(disk::total_free_space(&volumes)),
+        baseline,
+    };
+    if cli.summary_only {
+        progress.message(Verbosity::Quiet, &summary.to_string());
+    } else {
+        progress.println_normal(|| summary.to_string());
+    }
+    if let Some((before, after)) = summary.free_space {
+        let snapshots = volumes
+            .iter()
+            .filter_map(|path| disk::volume(path).ok())
+            .filter_map(|volume| platform::local_snapshots(&volume.mount_point).ok())
+            .map(|snapshots| snapshots.len())
+            .sum();
+        if let Some(note) = summary::snapshot_note(
+            total.bytes_saved(),
+            after.saturating_sub(before),
+            snapshots,
+            progress.size_format(),
+        ) {
+            progress.println_normal(|| note);
+        }
+    }
+    // A run that compressed nothing on purpose would only flatten the trend.
+    if let Some(path) = cli
+        .history
+        .as_ref()
+        .filter(|_| cli.compressor != CompressorArg::Null)
+    {
+        let history_error = |source| Error::History {
+            path: path.clone(),
+            source,
+        };
+        history::append(path, &history::Entry::now(&total)).map_err(history_error)?;
+        let entries = history::load(path, history::TREND_RUNS).map_err(history_error)?;
+        if entries.len() > 1 {
+            let saved: Vec<_> = entries.iter().map(|entry| entry.bytes_saved).collect();
+            let after: Vec<_> = entries.iter().map(|entry| entry.bytes_after).collect();
+            progress.println_normal(|| {
+                format!(
+                    "Last {} runs: saved {} (now {}), size {} (now {})",
+                    entries.len(),
+                    history::sparkline(&saved),
+                    progress.format_bytes(total.bytes_saved()),
+                    history::sparkline(&after),
+                    progress.format_bytes(total.bytes_out),
+                )
+            });
+        }
+    }
+    progress.finish();
+
+    if cli.os_log {
+        for (dir, message) in &failures {
+            os_log::log(
+                os_log::OsLogLevel::Error,
+                &format!("{}: {message}", dir.display()),
+            );
+        }
+        os_log::log(os_log::OsLogLevel::Default, &summary.to_string());
+    }
+
+    if let Some(path) = &cli.report {
+        let report = report::Report {
+            summary: &summary,
+            dirs: &dir_stats,
+            extensions: &report::extension_usage(&report_files),
+            kinds: &metered.kinds().ranked(),
+            incompressible: metered.incompressible(),
+        };
+        fs::write(path, report.render(cli.report_format)).map_err(|source| Error::WriteReport {
+            path: path.clone(),
+            source,
+        })?;
+    }
+
+    if cli.output == OutputArg::Gha {
+        // Workflow commands go straight to stdout, bypassing `--log-format`.
+        for (dir, message) in &failures {
+            let message = format!("{}: {message}", dir.display());
+            println!(
+                "{}",
+                gha::annotation(gha::Annotation::Warning, "Compression failed", &message)
+            );
+        }
+        println!(
+            "{}",
+            gha::annotation(
+                gha::Annotation::Notice,
+                "cargo apfs-compress",
+                &summary.to_string()
+            )
+        );
+        if let Some(path) = std::env::var_os(gha::STEP_SUMMARY_ENV).map(PathBuf::from) {
+            gha::append_step_summary(&path, &gha::job_summary(&summary, &failures))
+                .map_err(|source| Error::StepSummary { path, source })?;
+        }
+    }
+
+    if let Some(timeout) = cli.timeout
+        && base_options.stop.timed_out()
+    {
+        return Err(Error::TimedOut {
+            after: summary::format_elapsed(timeout),
+        });
+    }
+    if failed > 0 {
+        return Err(Error::DirectoriesFailed { failed });
+    }
+    if base_options.stop.cancelled() {
+        return Err(Error::Interrupted);
+    }
+    if cli.strict {
+        let skips = metered.skips();
+        let mut problems = Vec::new();
+        let unsupported =
+            skips.count(summary::UNSUPPORTED_FILESYSTEM) + skips.count(summary::ZFS_FILESYSTEM);
+        if unsupported > 0 {
+            problems.push(format!(
+                "{unsupported} files are on a filesystem without compression support"
+            ));
+        }
+        let unreadable = skips.count(summary::READ_ERRORS);
+        if unreadable > 0 {
+            problems.push(format!("{unreadable} files could not be read"));
+        }
+        if total.files_compressed == 0 {
+            problems.push("no files were compressed".to_owned());
+        }
+        if !problems.is_empty() {
+            return Err(Error::Strict { problems });
+        }
+    }
+    if let Some(required) = cli.min_savings
+        && !required.is_met(total.bytes_in, total.bytes_saved())
+    {
+        return Err(Error::MinSavings {
+            saved: total.bytes_saved(),
+            scanned: total.bytes_in,
+            required,
+        });
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(feature = "cli"))]
+    use crate::plain_progress::PlainProgress as ProgressBars;
+    use applesauce::progress::{Progress, SkipReason, Task};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
+
+    #[test]
+    fn maps_builtin_profiles_to_dirs() {
+        let overrides = HashMap::new();
+        assert_eq!(resolve_profile_dir_name("dev", &overrides), "debug");
+        assert_eq!(resolve_profile_dir_name("test", &overrides), "debug");
+        assert_eq!(resolve_profile_dir_name("bench", &overrides), "release");
+        assert_eq!(resolve_profile_dir_name("release", &overrides), "release");
+        assert_eq!(resolve_profile_dir_name("custom", &overrides), "custom");
+    }
+
+    #[test]
+    fn applies_profile_dir_name_override_from_config() {
+        let temp = tempdir().unwrap();
+        let cargo_dir = temp.path().join(".cargo");
+        fs::create_dir(&cargo_dir).unwrap();
+        fs::write(
+            cargo_dir.join("config.toml"),
+            "[profile.dev]\ndir-name = \"my-debug\"\nunknown = 1\n",
+        )
+        .unwrap();
+
+        let overrides = load_profile_dir_name_overrides(temp.path()).unwrap();
+        assert_eq!(overrides.get("dev"), Some(&"my-debug".to_owned()));
+    }
+
+    #[test]
+    fn reads_cargo_config_env_vars_for_dirs() {
+        let env = |vars: &[(&str, &str)]| -> Vec<(OsString, OsString)> {
+            vars.iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect()
+        };
+        let overrides = profile_dir_name_env_overrides(env(&[
+            ("CARGO_PROFILE_DEV_DIR_NAME", "my-debug"),
+            ("CARGO_PROFILE_FAST_CI_DIR_NAME", "ci"),
+            ("CARGO_PROFILE_RELEASE_LTO", "true"),
+            ("CARGO_PROFILE_BENCH_DIR_NAME", ""),
+        ]));
+        assert_eq!(overrides.get("dev"), Some(&"my-debug".to_owned()));
+        assert_eq!(overrides.get("fast-ci"), Some(&"ci".to_owned()));
+        assert_eq!(overrides.get("fast_ci"), Some(&"ci".to_owned()));
+        assert_eq!(overrides.len(), 3);
+
+        let vars: HashMap<&str, &str> =
+            [("CARGO_BUILD_TARGET_DIR", "out"), ("CARGO_TARGET_DIR", "")]
+                .into_iter()
+                .collect();
+        let env_var = |name: &str| vars.get(name).map(OsString::from);
+        assert_eq!(env_target_dir(&env_var), Some("out".into()));
+        let vars: HashMap<&str, &str> =
+            [("CARGO_BUILD_TARGET_DIR", "out"), ("CARGO_TARGET_DIR", "t")]
+                .into_iter()
+                .collect();
+        let env_var = |name: &str| vars.get(name).map(OsString::from);
+        assert_eq!(env_target_dir(&env_var), Some("t".into()));
+    }
+
+    #[test]
+    fn loads_profile_compression_defaults_from_config() {
+        let temp = tempdir().unwrap();
+        let cargo_dir = temp.path().join(".cargo");
+        fs::create_dir(&cargo_dir).unwrap();
+        fs::write(
+            cargo_dir.join("config.toml"),
+            "[apfs-compress.profile.dev]\ncompression = \"lzvn\"\n\
+             [apfs-compress.profile.release]\ncompression = \"lzfse\"\n",
+        )
+        .unwrap();
+
+        let defaults = load_profile_compression_defaults(temp.path()).unwrap();
+        assert_eq!(defaults.get("dev"), Some(&Kind::Lzvn));
+        assert_eq!(defaults.get("release"), Some(&Kind::Lzfse));
+
+        fs::write(
+            cargo_dir.join("config.toml"),
+            "[apfs-compress.profile.dev]\ncompression = \"brotli\"\n",
+        )
+        .unwrap();
+        let error = load_profile_compression_defaults(temp.path()).unwrap_err();
+        assert!(matches!(error, Error::InvalidConfig { .. }), "{error}");
+
+        // `dev` and `test` share `debug`; `dev` decides, whatever order the config has.
+        let mut profiles = ["test", "zz", "bench", "custom", "release", "dev"];
+        profiles.sort_by_key(|profile| profile_precedence(profile));
+        assert_eq!(
+            profiles,
+            ["dev", "release", "test", "bench", "custom", "zz"]
+        );
+    }
+
+    #[test]
+    fn loads_default_targets_from_env_and_config() {
+        let temp = tempdir().unwrap();
+        let cargo_dir = temp.path().join(".cargo");
+        fs::create_dir(&cargo_dir).unwrap();
+        assert!(load_default_targets(temp.path(), None).unwrap().is_empty());
+
+        fs::write(
+            cargo_dir.join("config.toml"),
+            "[build]\ntarget = \"thumbv7em-none-eabihf\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_default_targets(temp.path(), None).unwrap(),
+            vec!["thumbv7em-none-eabihf"]
+        );
+        assert_eq!(
+            load_default_targets(temp.path(), Some(OsStr::new("x86_64-apple-darwin"))).unwrap(),
+            vec!["x86_64-apple-darwin"]
+        );
+
+        fs::write(
+            cargo_dir.join("config.toml"),
+            "[build]\ntarget = [\"aarch64-apple-darwin\", \"specs/my-board.json\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_default_targets(temp.path(), Some(OsStr::new(""))).unwrap(),
+            vec!["aarch64-apple-darwin", "my-board"]
+        );
+    }
+
+    #[test]
+    fn guesses_target_dir_without_cargo() {
+        let temp = tempdir().unwrap();
+        let root = temp.path();
+        let member = root.join("crates").join("member");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        fs::write(member.join("Cargo.toml"), "[package]\nname = \"member\"\n").unwrap();
+
+        assert_eq!(
+            guess_target_dir(&member, None).unwrap(),
+            root.join("target")
+        );
+        assert_eq!(
+            guess_target_dir(&member, Some(OsStr::new("/elsewhere"))).unwrap(),
+            PathBuf::from("/elsewhere")
+        );
+
+        fs::create_dir(root.join(".cargo")).unwrap();
+        fs::write(
+            root.join(".cargo").join("config.toml"),
+            "[build]\ntarget-dir = \"out\"\n",
+        )
+        .unwrap();
+        assert_eq!(guess_target_dir(&member, None).unwrap(), root.join("out"));
+
+        fs::write(
+            root.join(".cargo").join("config.toml"),
+            "[build]\ntarget-dir = \"out\"\n\
+             [env]\nCARGO_TARGET_DIR = { value = \"env-out\", relative = true }\n",
+        )
+        .unwrap();
+        // `[env]` is for what cargo runs, not for cargo itself.
+        assert_eq!(guess_target_dir(&member, None).unwrap(), root.join("out"));
+        let env_dir = |env_set| config_env_target_dir(&member, env_set).unwrap().unzip().0;
+        assert_eq!(env_dir(false), Some(root.join("env-out")));
+        assert_eq!(env_dir(true), None);
+        fs::write(
+            root.join(".cargo").join("config.toml"),
+            "[env]\nCARGO_BUILD_TARGET_DIR = \"/shared\"\n\
+             CARGO_TARGET_DIR = { value = \"/forced\", force = true }\n",
+        )
+        .unwrap();
+        assert_eq!(env_dir(true), Some(PathBuf::from("/forced")));
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn adds_the_target_dir_cargo_config_env_sets() {
+        let temp = tempdir().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        fs::create_dir(root.join(".cargo")).unwrap();
+        fs::write(
+            root.join(".cargo").join("config.toml"),
+            "[env]\nCARGO_TARGET_DIR = { value = \"xtask-out\", relative = true, force = true }\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+
+        let cli = Cli::try_parse_from([
+            "cargo-apfs-compress",
+            "--no-metadata",
+            "--project",
+            root.to_str().unwrap(),
+        ])
+        .unwrap();
+        let dirs = |resolved: Resolved| -> Vec<PathBuf> {
+            resolved.dirs.into_iter().map(|dir| dir.path).collect()
+        };
+        // Nothing has built there yet.
+        assert_eq!(dirs(resolve(&cli).unwrap()), [root.join("target/debug")]);
+
+        fs::create_dir_all(root.join("xtask-out").join("release")).unwrap();
+        let resolved = resolve(&cli).unwrap();
+        assert_eq!(
+            resolved.target_dirs,
+            [root.join("target"), root.join("xtask-out")]
+        );
+        assert_eq!(
+            dirs(resolved),
+            [root.join("target/debug"), root.join("xtask-out/release")]
+        );
+    }
+
+    #[test]
+    fn dedups_nested_and_symlinked_work_dirs() {
+        let temp = tempdir().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        let debug = root.join("debug");
+        let nested = debug.join("custom");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(root.join("release")).unwrap();
+        std::os::unix::fs::symlink(&debug, root.join("alias")).unwrap();
+
+        let (kept, dropped) = dedup_work_dirs(vec![
+            root.join("release"),
+            nested.clone(),
+            root.join("alias"),
+            debug.clone(),
+            root.join("missing"),
+        ]);
+        assert_eq!(
+            kept,
+            vec![debug.clone(), root.join("missing"), root.join("release")]
+        );
+        assert_eq!(dropped, vec![(nested, debug)]);
+    }
+
+    #[test]
+    fn lock_errors_name_the_work_dir() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("notes"), b"").unwrap();
+        let options = WorkDirOptions {
+            lock_name: "notes/lock".to_owned(),
+            ..WorkDirOptions::default()
+        };
+        let error = compress_work_dir(
+            temp.path(),
+            &options,
+            &RecordingReporter::default(),
+            &RecordingCompressor::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::LockWorkDir { .. }), "{error}");
+        assert_eq!(error.path(), Some(temp.path()));
+        assert!(
+            error.chain().to_string().starts_with(&format!(
+                "failed to lock {}: failed to open: ",
+                temp.path().display()
+            )),
+            "{}",
+            error.chain()
+        );
+    }
+
+    #[test]
+    fn refuses_suspicious_target_dirs() {
+        let temp = tempdir().unwrap();
+        let home = temp.path().join("home");
+        let target = home.join("project").join("target");
+        fs::create_dir_all(&target).unwrap();
+
+        let error = guard::check_target_dir(&home, Some(&home)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "refusing to compress {}: it is the home directory (pass --i-know-what-im-doing to override)",
+                home.display()
+            )
+        );
+        assert!(guard::check_target_dir(Path::new("/"), None).is_err());
+        // Nothing in it yet, so nothing to touch.
+        guard::check_target_dir(&target, Some(&home)).unwrap();
+        guard::check_target_dir(&home.join("missing"), Some(&home)).unwrap();
+        fs::write(target.join("notes.txt"), b"").unwrap();
+        assert!(guard::check_target_dir(&target, Some(&home)).is_err());
+
+        fs::create_dir(target.join("debug")).unwrap();
+        guard::check_target_dir(&target, Some(&home)).unwrap();
+    }
+
+    #[test]
+    fn generic_dirs_only_need_to_be_narrower_than_home() {
+        let temp = tempdir().unwrap();
+        let home = temp.path().join("home");
+        let build = home.join("project").join("build");
+        fs::create_dir_all(&build).unwrap();
+
+        guard::check_generic_dir(&build, Some(&home)).unwrap();
+        assert!(guard::check_generic_dir(&home, Some(&home)).is_err());
+        assert!(guard::check_generic_dir(Path::new("/"), None).is_err());
+    }
+
+    #[test]
+    fn discovers_dirs_with_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp = tempdir().unwrap();
+        let target = temp.path().join("target");
+        let odd = OsStr::from_bytes(b"prof\xffile");
+        fs::create_dir(&target).unwrap();
+        if fs::create_dir(target.join(odd)).is_err() {
+            // APFS and HFS+ only allow UTF-8 names.
+            return;
+        }
+        fs::create_dir_all(target.join(OsStr::from_bytes(b".\xff"))).unwrap();
+        fs::write(
+            target.join(odd).join(OsStr::from_bytes(b"lib\xfe.gz")),
+            b"gz",
+        )
+        .unwrap();
+        fs::write(target.join(odd).join(OsStr::from_bytes(b"lib\xfe.a")), b"a").unwrap();
+
+        let dirs = discover_default_work_dirs(&target, &[], &[]).unwrap();
+        assert_eq!(
+            dirs,
+            vec![WorkDir {
+                path: target.join(odd),
+                profile: Some(odd.to_string_lossy().into_owned()),
+                target: None,
+                source: WorkDirSource::Discovered,
+            }]
+        );
+        let dirs = [dirs[0].path.clone()];
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        process_work_dir(&dirs[0], &WorkDirOptions::default(), &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![target.join(odd).join(OsStr::from_bytes(b"lib\xfe.a"))]
+        );
+    }
+
+    #[test]
+    fn resolves_dirs_without_target() {
+        let overrides = HashMap::new();
+        let dirs = resolve_work_dirs(
+            Path::new("/tmp/target"),
+            &["dev".to_owned(), "release".to_owned()],
+            &[],
+            &overrides,
+        );
+        assert_eq!(
+            dirs.into_iter().map(|dir| dir.path).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("/tmp/target/debug"),
+                PathBuf::from("/tmp/target/release")
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_dirs_with_target() {
+        let overrides = HashMap::new();
+        let dirs = resolve_work_dirs(
+            Path::new("/tmp/target"),
+            &["dev".to_owned()],
+            &[
+                "aarch64-apple-darwin".to_owned(),
+                "x86_64-apple-darwin".to_owned(),
+            ],
+            &overrides,
+        );
+        assert_eq!(
+            dirs,
+            ["aarch64-apple-darwin", "x86_64-apple-darwin"].map(|target| WorkDir {
+                path: Path::new("/tmp/target").join(target).join("debug"),
+                profile: Some("dev".to_owned()),
+                target: Some(target.to_owned()),
+                source: WorkDirSource::Requested,
+            })
+        );
+    }
+
+    #[test]
+    fn dedups_same_output_dir() {
+        let overrides = HashMap::new();
+        let dirs = resolve_work_dirs(
+            Path::new("/tmp/target"),
+            &["dev".to_owned(), "test".to_owned()],
+            &[],
+            &overrides,
+        );
+        assert_eq!(
+            dirs,
+            [WorkDir {
+                path: PathBuf::from("/tmp/target/debug"),
+                profile: Some("dev".to_owned()),
+                target: None,
+                source: WorkDirSource::Requested,
+            }]
+        );
+    }
+
+    #[test]
+    fn event_reporter_streams_dir_and_file_events() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("debug");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.rlib"), [0; 100]).unwrap();
+
+        let (sender, events) = mpsc::channel();
+        let reporter = events::EventReporter::new(sender);
+        let options = WorkDirOptions {
+            lock_work_dir: false,
+            ..WorkDirOptions::default()
+        };
+        let (result, _) = run_work_dir(&dir, &options, &reporter, &RecordingCompressor::default());
+        let stats = result.unwrap();
+        drop(reporter);
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            [
+                Event::DirStarted { dir: dir.clone() },
+                Event::FileDone {
+                    path: dir.join("app.rlib"),
+                    bytes_in: 100,
+                    bytes_out: 50,
+                },
+                Event::DirFinished {
+                    dir,
+                    stats: Some(stats),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_signal_holds_work_dirs_until_resumed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("debug");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("app.rlib");
+        fs::write(&file, [0; 100]).unwrap();
+        let paths = [file.clone()];
+
+        let options = WorkDirOptions::default();
+        options.pause.pause();
+        let reporter = RecordingReporter::default();
+
+        // Mid-dir, the compressor stops its attempt rather than wait with the lock held.
+        let attempt = WorkDirOptions {
+            stop: options.stop.linked(),
+            yield_to_pause: true,
+            ..options.clone()
+        };
+        ApplesauceCompressor
+            .compress_paths(&paths, &attempt, &reporter)
+            .unwrap();
+        assert!(reporter.tasks.lock().unwrap().is_empty());
+        assert!(attempt.stop.was_cut_short());
+        assert!(!options.stop.should_stop());
+
+        std::thread::scope(|scope| {
+            let run =
+                scope.spawn(|| process_work_dir(&dir, &options, &reporter, &ApplesauceCompressor));
+            std::thread::sleep(PAUSED_POLL_INTERVAL * 2);
+            assert!(reporter.tasks.lock().unwrap().is_empty());
+            options.pause.resume();
+            run.join().unwrap().unwrap();
+        });
+        assert_eq!(reporter.tasks.lock().unwrap().len(), 1);
+        assert!(
+            reporter
+                .messages
+                .lock()
+                .unwrap()
+                .contains(&"pause: paused".to_owned())
+        );
+
+        // Stopping a paused run ends the wait without compressing anything more.
+        fs::write(&file, [1; 100]).unwrap();
+        options.pause.pause();
+        std::thread::scope(|scope| {
+            let run =
+                scope.spawn(|| process_work_dir(&dir, &options, &reporter, &ApplesauceCompressor));
+            std::thread::sleep(PAUSED_POLL_INTERVAL * 2);
+            options.stop.stop();
+            run.join().unwrap().unwrap();
+        });
+        assert_eq!(reporter.tasks.lock().unwrap().len(), 1);
+        assert!(options.stop.was_cut_short());
+    }
+
+    #[test]
+    fn pause_file_holds_work_dirs_while_it_exists() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("debug");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app"), [0; 100]).unwrap();
+        let pause_file = temp.path().join("pause");
+        fs::write(&pause_file, "").unwrap();
+
+        let options = WorkDirOptions {
+            pause_file: Some(pause_file.clone()),
+            ..WorkDirOptions::default()
+        };
+        let reporter = RecordingReporter::default();
+        let compressor = RecordingCompressor::default();
+        std::thread::scope(|scope| {
+            let run = scope.spawn(|| process_work_dir(&dir, &options, &reporter, &compressor));
+            std::thread::sleep(PAUSED_POLL_INTERVAL * 2);
+            assert!(compressor.calls.lock().unwrap().is_empty());
+            fs::remove_file(&pause_file).unwrap();
+            run.join().unwrap().unwrap();
+        });
+        assert_eq!(compressor.calls.lock().unwrap().len(), 1);
+        let messages = reporter.messages.lock().unwrap();
+        assert_eq!(messages[..2], ["pause: pause file exists", "resume"]);
+        drop(messages);
+
+        // Once a dir is locked, it's finished even if the file appears.
+        let file = dir.join("app.rlib");
+        fs::write(&file, [0; 100]).unwrap();
+        fs::write(&pause_file, "").unwrap();
+        let attempt = WorkDirOptions {
+            stop: options.stop.linked(),
+            yield_to_pause: true,
+            ..options.clone()
+        };
+        let reporter = RecordingReporter::default();
+        ApplesauceCompressor
+            .compress_paths(&[file], &attempt, &reporter)
+            .unwrap();
+        assert_eq!(reporter.tasks.lock().unwrap().len(), 1);
+        assert!(!attempt.stop.was_cut_short());
+    }
+
+    #[test]
+    fn log_file_records_quiet_runs_and_rotates() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("logs").join("run.log");
+        let log = log_file::LogFile::open(&path, 300, LogFormat::Plain).unwrap();
+        let logged = log_file::Logged {
+            inner: Box::new(PlainProgress::new(Verbosity::Quiet)),
+            log: &log,
+        };
+        let reporter: &dyn Reporter = &logged;
+        reporter.println_normal(|| "Compressed target/debug".to_owned());
+        reporter.println_verbose(|| "not for a quiet run".to_owned());
+        reporter.error(Path::new("target/debug/app"), "failed to compress");
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2, "{text}");
+        assert!(
+            lines[0].starts_with("time=")
+                && lines[0].ends_with(" level=info msg=\"Compressed target/debug\"")
+        );
+        assert!(lines[1].contains(" level=error path=target/debug/app "));
+
+        for index in 0..20 {
+            log.log(LogLevel::Info, None, &format!("line {index}"));
+        }
+        let rotated = |index: usize| temp.path().join("logs").join(format!("run.log.{index}"));
+        assert!(fs::metadata(&path).unwrap().len() <= 300);
+        assert!(rotated(1).exists() && rotated(3).exists());
+        assert!(!rotated(4).exists());
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .ends_with("msg=\"line 19\"\n")
+        );
+    }
+
+    #[test]
+    fn common_os_errors_get_a_hint_once() {
+        let error = Error::Read {
+            path: PathBuf::from("/Volumes/Archive/target"),
+            source: std::io::Error::from_raw_os_error(libc::EROFS),
+        };
+        assert!(
+            error
+                .chain()
+                .to_string()
+                .ends_with(": Read-only file system (os error 30); hint: the volume is mounted read-only; compressing rewrites files in place, so remount it read-write or leave it out"),
+            "{}",
+            error.chain()
+        );
+        let missing = Error::Read {
+            path: PathBuf::from("target"),
+            source: std::io::Error::from(std::io::ErrorKind::NotFound),
+        };
+        assert!(!missing.chain().to_string().contains("hint"));
+
+        let recording = RecordingReporter::default();
+        let metered = Metered::new(&recording, Arc::new(NoopMetrics));
+        let progress: &dyn Reporter = &metered;
+        let denied = std::io::Error::from_raw_os_error(libc::EPERM).to_string();
+        progress.error(Path::new("Documents/target/debug/app"), &denied);
+        progress.error(Path::new("Documents/target/debug/lib"), &denied);
+        progress.error(
+            Path::new("target/debug/gone"),
+            "No such file or directory (os error 2)",
+        );
+        let messages = recording.messages.lock().unwrap();
+        assert_eq!(
+            messages[0],
+            format!("{denied}; hint: {}", hints::Hint::FullDiskAccess)
+        );
+        assert_eq!(
+            messages[1..],
+            [denied, "No such file or directory (os error 2)".to_owned()]
+        );
+    }
+
+    #[test]
+    fn compression_names_round_trip() {
+        for arg in CompressionArg::ALL {
+            assert_eq!(arg.to_string().parse(), Ok(arg));
+            let json = serde_json::to_string(&arg).unwrap();
+            assert_eq!(json, format!("\"{arg}\""));
+            assert_eq!(serde_json::from_str::<CompressionArg>(&json).unwrap(), arg);
+        }
+        assert_eq!(Kind::from(CompressionArg::Lzvn), Kind::Lzvn);
+        assert_eq!(
+            "LZFSE".parse::<CompressionArg>().unwrap_err().to_string(),
+            "unknown compression `LZFSE`; expected lzfse, zlib, or lzvn"
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn defaults_to_lzfse() {
+        let cli = Cli::try_parse_from(["cargo-apfs-compress"]).unwrap();
+        assert_eq!(cli.compression, None);
+        assert_eq!(cli.work_dir_options().compression, Kind::Lzfse);
+        assert!(cli.profiles.is_empty());
+        assert_eq!(cli.verbose, 0);
+        assert_eq!(cli.quiet, 0);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn parses_verbose_flag() {
+        let cli = Cli::try_parse_from(["cargo-apfs-compress", "-v"]).unwrap();
+        assert_eq!(cli.verbose, 1);
+        assert_eq!(cli.quiet, 0);
+        assert_eq!(cli.verbosity(), Verbosity::Verbose);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn parses_quiet_flag() {
+        let cli = Cli::try_parse_from(["cargo-apfs-compress", "-q"]).unwrap();
+        assert_eq!(cli.quiet, 1);
+        assert_eq!(cli.verbose, 0);
+        assert_eq!(cli.verbosity(), Verbosity::Quiet);
+    }
+
+    #[test]
+    fn discovers_default_target_roots() {
+        let root = tempdir().unwrap();
+        let target = root.path().join("target");
+        fs::create_dir_all(target.join("debug")).unwrap();
+        fs::create_dir_all(target.join("release")).unwrap();
+        fs::create_dir_all(target.join("x86_64-apple-darwin").join("debug")).unwrap();
+        fs::create_dir_all(target.join("x86_64-apple-darwin").join("release")).unwrap();
+        fs::create_dir_all(target.join("doc")).unwrap();
+        fs::create_dir_all(target.join("package")).unwrap();
+        fs::create_dir_all(target.join("tmp")).unwrap();
+
+        let dirs: Vec<_> = discover_default_work_dirs(&target, &[], &[])
+            .unwrap()
+            .into_iter()
+            .map(|dir| dir.path)
+            .collect();
+
+        assert!(dirs.contains(&target.join("debug")));
+        assert!(dirs.contains(&target.join("release")));
+        assert!(dirs.contains(&target.join("x86_64-apple-darwin").join("debug")));
+        assert!(dirs.contains(&target.join("x86_64-apple-darwin").join("release")));
+        assert!(dirs.contains(&target.join("doc")));
+        assert!(dirs.contains(&target.join("package")));
+        assert!(!dirs.contains(&target.join("tmp")));
+    }
+
+    #[test]
+    fn discovers_profiles_inside_check_dirs() {
+        let root = tempdir().unwrap();
+        let target = root.path().join("target");
+        fs::create_dir_all(target.join("debug")).unwrap();
+        fs::create_dir_all(target.join("rust-analyzer").join("debug")).unwrap();
+        fs::create_dir_all(
+            target
+                .join("rust-analyzer")
+                .join("aarch64-apple-darwin")
+                .join("debug"),
+        )
+        .unwrap();
+        fs::create_dir_all(target.join("zed").join("debug")).unwrap();
+
+        let dirs = discover_default_work_dirs(&target, &[], &["zed".to_owned()]).unwrap();
+
+        assert_eq!(
+            dirs.iter().map(|dir| dir.path.clone()).collect::<Vec<_>>(),
+            vec![
+                target.join("debug"),
+                target
+                    .join("rust-analyzer")
+                    .join("aarch64-apple-darwin")
+                    .join("debug"),
+                target.join("rust-analyzer").join("debug"),
+                target.join("zed").join("debug"),
+            ]
+        );
+    }
+
+    #[test]
+    fn discovers_only_requested_targets_when_filtered() {
+        let root = tempdir().unwrap();
+        let target = root.path().join("target");
+        fs::create_dir_all(target.join("x86_64-apple-darwin").join("debug")).unwrap();
+        fs::create_dir_all(target.join("aarch64-apple-darwin").join("debug")).unwrap();
+
+        let dirs =
+            discover_default_work_dirs(&target, &["x86_64-apple-darwin".to_owned()], &[]).unwrap();
+
+        assert_eq!(
+            dirs,
+            [WorkDir {
+                path: target.join("x86_64-apple-darwin").join("debug"),
+                profile: Some("debug".to_owned()),
+                target: Some("x86_64-apple-darwin".to_owned()),
+                source: WorkDirSource::Discovered,
+            }]
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingCompressor {
+        calls: Mutex<Vec<Vec<PathBuf>>>,
+        /// The algorithm and level each call was asked for.
+        kinds: Mutex<Vec<(Kind, u32)>>,
+        delay: Duration,
+        fail_on: Option<String>,
+        panic_on: Option<String>,
+        starts: Mutex<Vec<Instant>>,
+        ends: Mutex<Vec<Instant>>,
+    }
+
+    impl Compressor for RecordingCompressor {
+        fn compress_paths(
+            &self,
+            paths: &[PathBuf],
+            options: &WorkDirOptions,
+            progress: &dyn Reporter,
+        ) -> Result<CompressionStats, BoxError> {
+            self.starts.lock().unwrap().push(Instant::now());
+            self.calls.lock().unwrap().push(paths.to_vec());
+            self.kinds
+                .lock()
+                .unwrap()
+                .push((options.compression, options.compression_level));
+            if self.delay > Duration::ZERO {
+                thread::sleep(self.delay);
+            }
+            self.ends.lock().unwrap().push(Instant::now());
+
+            if let Some(fail_on) = &self.fail_on
+                && paths
+                    .iter()
+                    .any(|path| path.to_string_lossy().contains(fail_on))
+            {
+                return Err("intentional failure".into());
+            }
+            if let Some(panic_on) = &self.panic_on
+                && paths
+                    .iter()
+                    .any(|path| path.to_string_lossy().contains(panic_on))
+            {
+                panic!("intentional panic");
+            }
+
+            // Pretend every file halves in size.
+            let mut stats = CompressionStats::default();
+            for path in paths {
+                if let Ok(metadata) = fs::metadata(path)
+                    && metadata.is_file()
+                {
+                    progress.file_compressed(path, metadata.len(), metadata.len() / 2);
+                    stats += CompressionStats {
+                        files_compressed: 1,
+                        files_skipped: 0,
+                        bytes_in: metadata.len(),
+                        bytes_out: metadata.len() / 2,
+                    };
+                }
+            }
+            Ok(stats)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingMetrics {
+        counters: Mutex<HashMap<Counter, u64>>,
+        gauges: Mutex<HashMap<Gauge, u64>>,
+    }
+
+    impl MetricsSink for RecordingMetrics {
+        fn increment(&self, counter: Counter, amount: u64) {
+            *self.counters.lock().unwrap().entry(counter).or_default() += amount;
+        }
+
+        fn gauge(&self, gauge: Gauge, value: u64) {
+            self.gauges.lock().unwrap().insert(gauge, value);
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingReporter {
+        messages: Mutex<Vec<String>>,
+        tasks: Mutex<Vec<(PathBuf, u64)>>,
+    }
+
+    impl Reporter for RecordingReporter {
+        fn verbosity(&self) -> Verbosity {
+            Verbosity::Verbose
+        }
+
+        fn message(&self, _verbosity: Verbosity, message: &str) {
+            self.messages.lock().unwrap().push(message.to_owned());
+        }
+
+        fn error(&self, _path: &Path, message: &str) {
+            self.messages.lock().unwrap().push(message.to_owned());
+        }
+
+        fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+            self.tasks.lock().unwrap().push((path.to_path_buf(), size));
+            Box::new(NoTask)
+        }
+    }
+
+    struct NoTask;
+
+    impl Task for NoTask {
+        fn increment(&self, _amt: u64) {}
+        fn error(&self, _message: &str) {}
+    }
+
+    #[test]
+    fn skips_files_modified_after_the_walk_starts() {
+        let temp = tempdir().unwrap();
+        let stable = temp.path().join("stable.rlib");
+        let rebuilt = temp.path().join("rebuilt.rlib");
+        let skewed = temp.path().join("skewed.rlib");
+        let hour = Duration::from_secs(60 * 60);
+        for (path, modified) in [
+            (&stable, SystemTime::now() - hour),
+            (&rebuilt, SystemTime::now()),
+            (&skewed, SystemTime::now() + hour),
+        ] {
+            fs::write(path, b"contents").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let reporter = RecordingReporter::default();
+        let options = WorkDirOptions {
+            listed_at: Some(SystemTime::now() - Duration::from_secs(60)),
+            ..WorkDirOptions::default()
+        };
+        ApplesauceCompressor
+            .compress_paths(
+                &[rebuilt.clone(), skewed.clone(), stable.clone()],
+                &options,
+                &reporter,
+            )
+            .unwrap();
+        let tasks = reporter.tasks.lock().unwrap();
+        assert_eq!(
+            tasks.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+            [&skewed, &stable]
+        );
+        assert!(reporter.messages.lock().unwrap().contains(&format!(
+            "{}: Skipped: Modified during run",
+            rebuilt.display()
+        )));
+    }
+
+    #[test]
+    fn custom_reporter_receives_messages() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
+        fs::write(temp.path().join(CARGO_LOCK_NAME), b"").unwrap();
+
+        let reporter = RecordingReporter::default();
+        process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &reporter,
+            &RecordingCompressor::default(),
+        )
+        .unwrap();
+        process_work_dir(
+            &temp.path().join("missing"),
+            &WorkDirOptions::default(),
+            &reporter,
+            &RecordingCompressor::default(),
+        )
+        .unwrap();
+
+        let messages = reporter.messages.lock().unwrap();
+        assert_eq!(
+            *messages,
+            vec![
+                format!("exclude {CARGO_LOCK_NAME} from {}", temp.path().display()),
+                format!("skip {} (missing)", temp.path().join("missing").display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn summarizes_skip_reasons() {
+        let reporter = RecordingReporter::default();
+        let metered = Metered::new(&reporter, Arc::new(NoopMetrics));
+        assert!(metered.skips().is_empty());
+        for _ in 0..12 {
+            metered.file_skipped(Path::new("a"), SkipReason::AlreadyCompressed);
+        }
+        metered.file_skipped(Path::new("b"), SkipReason::HardLink);
+        metered.file_skipped(Path::new("c"), SkipReason::TooLarge(1 << 40));
+        metered.file_skipped(Path::new("d"), SkipReason::TooLarge(1 << 41));
+        assert_eq!(
+            metered.skips().to_string(),
+            "Skipped files:\n  already compressed  12\n  too large            2\n  hard links           1"
+        );
+        assert_eq!(metered.skips().count("too large"), 2);
+        assert_eq!(metered.skips().count(summary::UNSUPPORTED_FILESYSTEM), 0);
+    }
+
+    #[test]
+    fn summarizes_permission_denied_entries() {
+        let reporter = RecordingReporter::default();
+        let metered = Metered::new(&reporter, Arc::new(NoopMetrics));
+        let cwd = Path::new("/work/app");
+        metered.file_skipped(
+            &cwd.join("target/debug/build/sys-1a2b"),
+            SkipReason::ReadError(std::io::Error::from_raw_os_error(libc::EACCES)),
+        );
+        metered.error(
+            &cwd.join("target/debug/deps/liba.rlib"),
+            &std::io::Error::from_raw_os_error(libc::EPERM).to_string(),
+        );
+        metered
+            .file_task(&cwd.join("target/debug/deps/libb.rlib"), 1)
+            .error(&std::io::Error::from_raw_os_error(libc::EACCES).to_string());
+        metered.error(
+            &cwd.join("target/debug/deps/libc.rlib"),
+            &std::io::Error::from_raw_os_error(libc::EIO).to_string(),
+        );
+        assert_eq!(
+            metered.denied().render(2, Some(cwd)),
+            format!(
+                "Permission denied (3):\n  target/debug/build/sys-1a2b\n  \
+                 target/debug/deps/liba.rlib\n  ... and 1 more\nhint: {}",
+                hints::Hint::Permissions
+            )
+        );
+        assert_eq!(metered.skips().count(summary::READ_ERRORS), 1);
+    }
+
+    #[test]
+    fn metered_reporter_forwards_file_tasks() {
+        struct CountingProgress(Mutex<u64>);
+
+        impl Progress for CountingProgress {
+            type Task = NoTask;
+
+            fn error(&self, _path: &Path, _message: &str) {}
+
+            fn file_task(&self, _path: &Path, size: u64) -> Self::Task {
+                *self.0.lock().unwrap() += size;
+                NoTask
+            }
+        }
+
+        let inner = ProgressReporter(CountingProgress(Mutex::new(0)));
+        let metrics = Arc::new(RecordingMetrics::default());
+        let metered = Metered::new(&inner, metrics.clone());
+        let (completed, completions) = mpsc::channel();
+        let progress = AsProgress::new(&metered, completed);
+        progress.file_task(Path::new("a.rlib"), 10).error("boom");
+        progress.file_task(Path::new("b.rlib"), 5);
+        progress.file_skipped(Path::new("c.rlib"), SkipReason::EmptyFile);
+        drop(progress);
+
+        assert_eq!(*inner.0.0.lock().unwrap(), 15);
+        assert_eq!(
+            completions.iter().collect::<Vec<_>>(),
+            vec![(PathBuf::from("b.rlib"), 5)]
+        );
+        let counters = metrics.counters.lock().unwrap();
+        assert_eq!(counters.get(&Counter::FilesProcessed), Some(&2));
+        assert_eq!(counters.get(&Counter::FilesSkipped), Some(&1));
+        assert_eq!(counters.get(&Counter::Errors), Some(&1));
+    }
+
+    #[test]
+    fn restores_times_that_compressing_changed() {
+        let temp = tempdir().unwrap();
+        let changed = temp.path().join("changed.rlib");
+        let kept = temp.path().join("kept.rlib");
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        for path in [&changed, &kept] {
+            fs::write(path, b"lib").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(long_ago)
+                .unwrap();
+        }
+        let mut perms = fs::metadata(&changed).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&changed, perms).unwrap();
+        let original: HashMap<&Path, FileTimes> = [changed.as_path(), kept.as_path()]
+            .into_iter()
+            .map(|path| (path, FileTimes::of(path).unwrap()))
+            .collect();
+
+        fs::File::open(&changed)
+            .unwrap()
+            .set_modified(SystemTime::now())
+            .unwrap();
+        let reporter = RecordingReporter::default();
+        restore_times(&[&changed, &kept], &original, &reporter);
+        assert_eq!(
+            FileTimes::of(&changed).unwrap(),
+            original[changed.as_path()]
+        );
+        assert_eq!(
+            *reporter.messages.lock().unwrap(),
+            [format!("{}: Restored times", changed.display())]
+        );
+    }
+
+    #[test]
+    fn audit_reports_metadata_that_compressing_changed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempdir().unwrap();
+        let changed = temp.path().join("changed.rlib");
+        let kept = temp.path().join("kept.rlib");
+        for path in [&changed, &kept] {
+            fs::write(path, b"lib").unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+        let original: HashMap<&Path, MetadataSnapshot> = [changed.as_path(), kept.as_path()]
+            .into_iter()
+            .map(|path| (path, MetadataSnapshot::of(path).unwrap()))
+            .collect();
+
+        fs::set_permissions(&changed, fs::Permissions::from_mode(0o600)).unwrap();
+        let reporter = RecordingReporter::default();
+        audit_metadata(&[&changed, &kept], &original, &reporter);
+        assert_eq!(
+            *reporter.messages.lock().unwrap(),
+            ["metadata changed: mode 100644 -> 100600"]
+        );
+
+        let mut before = original[kept.as_path()].clone();
+        before.xattrs = Some(
+            [
+                (OsString::from("com.apple.quarantine"), b"0081".to_vec()),
+                (OsString::from("com.apple.provenance"), b"\x01".to_vec()),
+            ]
+            .into(),
+        );
+        let mut after = before.clone();
+        after.uid += 1;
+        after.xattrs = Some(
+            [
+                (OsString::from("com.apple.quarantine"), b"0082".to_vec()),
+                (OsString::from("com.apple.lastuseddate"), b"now".to_vec()),
+            ]
+            .into(),
+        );
+        assert_eq!(
+            before.differences(&after),
+            [
+                format!(
+                    "owner {}:{} -> {}:{}",
+                    before.uid, before.gid, after.uid, after.gid
+                ),
+                "xattr com.apple.provenance removed".to_owned(),
+                "xattr com.apple.quarantine changed".to_owned(),
+                "xattr com.apple.lastuseddate added".to_owned(),
+            ]
+        );
+        assert!(before.differences(&before).is_empty());
+    }
+
+    #[test]
+    fn verify_signatures_reports_only_signatures_compressing_broke() {
+        let temp = tempdir().unwrap();
+        let mach_o = [0xcf, 0xfa, 0xed, 0xfe, 0x07, 0x00];
+        let broken = temp.path().join("broken");
+        let intact = temp.path().join("intact");
+        let unsigned = temp.path().join("unsigned");
+        let script = temp.path().join("build.sh");
+        for path in [&broken, &intact, &unsigned] {
+            fs::write(path, mach_o).unwrap();
+        }
+        fs::write(&script, b"#!/bin/sh\n").unwrap();
+        let paths = [
+            broken.as_path(),
+            intact.as_path(),
+            unsigned.as_path(),
+            script.as_path(),
+        ];
+
+        let compressed = Mutex::new(false);
+        let verify = |path: &Path| -> std::io::Result<Result<(), String>> {
+            if path == unsigned {
+                Ok(Err("code object is not signed at all".to_owned()))
+            } else if path == broken && *compressed.lock().unwrap() {
+                Ok(Err(
+                    "invalid signature (code or signature have been modified)".to_owned(),
+                ))
+            } else {
+                Ok(Ok(()))
+            }
+        };
+        let signed = validly_signed(&paths, &verify);
+        assert_eq!(signed, HashSet::from([broken.as_path(), intact.as_path()]));
+
+        *compressed.lock().unwrap() = true;
+        let reporter = RecordingReporter::default();
+        verify_signatures(&paths, &signed, &verify, &reporter);
+        assert_eq!(
+            *reporter.messages.lock().unwrap(),
+            [
+                "code signature no longer valid: invalid signature (code or signature have been \
+                 modified)"
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_inputs_through_a_bounded_queue() {
+        let temp = tempdir().unwrap();
+        for i in 0..50 {
+            fs::write(temp.path().join(format!("{i}.rlib")), b"x").unwrap();
+        }
+        let options = WorkDirOptions::default();
+        let quiet = PlainProgress::new(Verbosity::Quiet);
+
+        let (files, found) = mpsc::sync_channel(1);
+        let mut received = std::thread::scope(|scope| {
+            let walker = scope
+                .spawn(|| walk::stream_inputs(temp.path(), &options, &quiet, None, None, files));
+            let received: Vec<_> = found.iter().collect();
+            walker.join().unwrap().unwrap();
+            received
+        });
+        received.sort();
+        assert_eq!(
+            received,
+            walk::collect_inputs(temp.path(), &options, &quiet).unwrap()
+        );
+
+        // A receiver that goes away doesn't leave the walk blocked on a full queue.
+        let (files, found) = mpsc::sync_channel(1);
+        std::thread::scope(|scope| {
+            let walker = scope
+                .spawn(|| walk::stream_inputs(temp.path(), &options, &quiet, None, None, files));
+            found.recv().unwrap();
+            drop(found);
+            walker.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn starts_largest_dirs_first() {
+        let temp = tempdir().unwrap();
+        let small = temp.path().join("small");
+        let large = temp.path().join("large");
+        fs::create_dir_all(small.join("deps")).unwrap();
+        fs::create_dir_all(large.join("deps")).unwrap();
+        fs::write(small.join("deps/a.rlib"), [0; 100]).unwrap();
+        fs::write(large.join("app"), [0; 100]).unwrap();
+        fs::write(large.join("deps/b.rlib"), [0; 1000]).unwrap();
+        fs::create_dir_all(large.join("deps/nested")).unwrap();
+        fs::write(large.join("deps/nested/c.rlib"), [0; 1000]).unwrap();
+
+        assert_eq!(disk::estimate_size(&large), 1100);
+        assert_eq!(disk::estimate_size(&temp.path().join("missing")), 0);
+        let dirs = vec![small.clone(), temp.path().join("missing"), large.clone()];
+        let sizes = dirs.iter().map(|dir| disk::estimate_size(dir)).collect();
+        assert_eq!(
+            largest_first(dirs, sizes),
+            [large, small, temp.path().join("missing")]
+        );
+    }
+
+    #[test]
+    fn leaves_out_empty_and_tiny_files() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("empty.d"), b"").unwrap();
+        fs::write(temp.path().join("tiny.d"), b"deps: src/lib.rs").unwrap();
+        fs::write(temp.path().join("app.rlib"), [0; INLINE_THRESHOLD as usize]).unwrap();
+
+        let inner = PlainProgress::new(Verbosity::Quiet);
+        let metered = Metered::new(&inner, Arc::new(NoopMetrics));
+        let options = WorkDirOptions {
+            min_file_size: INLINE_THRESHOLD,
+            ..WorkDirOptions::default()
+        };
+        let inputs = walk::collect_inputs(temp.path(), &options, &metered).unwrap();
+        assert_eq!(inputs, [temp.path().join("app.rlib")]);
+        assert_eq!(metered.skips().count("empty"), 1);
+        assert_eq!(metered.skips().count(summary::TINY_FILES), 1);
+
+        let options = WorkDirOptions::default();
+        let inputs = walk::collect_inputs(temp.path(), &options, &inner).unwrap();
+        assert_eq!(inputs.len(), 2);
+    }
+
+    #[test]
+    fn batches_by_size_rather_than_count() {
+        let temp = tempdir().unwrap();
+        let mut paths = Vec::new();
+        for i in 0..3000 {
+            let path = temp.path().join(format!("{i}.d"));
+            fs::write(&path, b"deps").unwrap();
+            paths.push(path);
+        }
+        let big = temp.path().join("big.rlib");
+        fs::File::create(&big)
+            .unwrap()
+            .set_len(COMPRESS_BATCH_BYTES)
+            .unwrap();
+        paths.insert(0, big.clone());
+        let after = SystemTime::now() + Duration::from_secs(60);
+
+        let mut pending = paths.iter().map(PathBuf::as_path);
+        let (batch, modified) = take_batch(&mut pending, after);
+        assert_eq!(batch, [big.as_path()]);
+        assert!(modified.is_empty());
+        let (batch, _) = take_batch(&mut pending, after);
+        assert_eq!(batch.len(), 3000);
+
+        let (batch, modified) = take_batch(
+            &mut paths.iter().map(PathBuf::as_path).take(2),
+            SystemTime::UNIX_EPOCH,
+        );
+        assert!(batch.is_empty());
+        assert_eq!(modified.len(), 2);
+    }
+
+    #[test]
+    fn holds_back_files_that_ran_out_of_descriptors() {
+        let reporter = RecordingReporter::default();
+        let (completed, completions) = mpsc::channel();
+        let (ran_out, exhausted) = mpsc::channel();
+        let progress = AsProgress::new(&reporter, completed).retry_exhausted(ran_out);
+        progress
+            .file_task(Path::new("a.rlib"), 10)
+            .error("Too many open files (os error 24)");
+        progress.file_task(Path::new("b.rlib"), 5).error("boom");
+        progress.file_task(Path::new("c.rlib"), 3);
+        progress.error(Path::new("deps"), "Too many open files (os error 24)");
+        drop(progress);
+
+        assert_eq!(
+            completions.iter().collect::<Vec<_>>(),
+            [(PathBuf::from("c.rlib"), 3)]
+        );
+        assert_eq!(
+            exhausted.iter().collect::<Vec<_>>(),
+            [PathBuf::from("a.rlib"), PathBuf::from("deps")]
+        );
+
+        assert_eq!(fd_limit::max_workers(256, 10), 5);
+        assert_eq!(fd_limit::max_workers(64, 10), 1);
+        assert_eq!(fd_limit::max_workers(10240, 10), 267);
+    }
+
+    #[test]
+    fn volume_budgets_let_dirs_on_other_volumes_go_first() {
+        let slots = fd_limit::Slots::new(3, HashMap::from([(1, 1)]));
+        let (index, archive) = slots.acquire_first(&[Some(1), Some(1), Some(2), None]);
+        assert_eq!(index, 0);
+        let (index, internal) = slots.acquire_first(&[Some(1), Some(2), None]);
+        assert_eq!(index, 1);
+        let (index, unknown) = slots.acquire_first(&[Some(1), None]);
+        assert_eq!(index, 1);
+        drop((archive, internal));
+        assert_eq!(slots.acquire_first(&[Some(1)]).0, 0);
+        drop(unknown);
+
+        assert_eq!(
+            VolumeJobs::parse("/Volumes/Archive=1"),
+            Ok(VolumeJobs {
+                path: PathBuf::from("/Volumes/Archive"),
+                jobs: 1
+            })
+        );
+        assert!(VolumeJobs::parse("/Volumes/Archive").is_err());
+        assert!(VolumeJobs::parse("/Volumes/Archive=0").is_err());
+        assert!(VolumeJobs::parse("=2").is_err());
+    }
+
+    #[test]
+    fn auto_progress_follows_terminal_detection() {
+        let fancy = if cfg!(feature = "cli") {
+            ProgressArg::Fancy
+        } else {
+            ProgressArg::Plain
+        };
+        assert_eq!(ProgressArg::Auto.resolve(true), fancy);
+        assert_eq!(ProgressArg::Auto.resolve(false), ProgressArg::Plain);
+        assert_eq!(ProgressArg::Fancy.resolve(false), fancy);
+        assert_eq!(ProgressArg::None.resolve(true), ProgressArg::None);
+    }
+
+    #[test]
+    fn formats_log_timestamps_as_utc() {
+        use crate::log_format::rfc3339;
+        use std::time::UNIX_EPOCH;
+
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789)),
+            "2024-02-29T12:34:56.789Z"
+        );
+    }
+
+    #[test]
+    fn renders_each_log_format() {
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(1);
+        let path = Some(Path::new("target/debug/a b"));
+        assert_eq!(
+            LogFormat::Plain.format_line_at(time, LogLevel::Error, path, "boom"),
+            "target/debug/a b: error: boom"
+        );
+        assert_eq!(
+            LogFormat::Plain.format_line_at(time, LogLevel::Info, None, "Compressed x"),
+            "Compressed x"
+        );
+        assert_eq!(
+            LogFormat::Logfmt.format_line_at(time, LogLevel::Error, path, "said \"no\""),
+            r#"time=1970-01-01T00:00:01.000Z level=error path="target/debug/a b" msg="said \"no\"""#
+        );
+        assert_eq!(
+            LogFormat::Json.format_line_at(time, LogLevel::Skipped, path, "Empty file"),
+            r#"{"time":"1970-01-01T00:00:01.000Z","level":"skipped","path":"target/debug/a b","msg":"Empty file"}"#
+        );
+    }
+
+    #[test]
+    fn run_summary_reports_dirs_files_and_savings() {
+        let summary = RunSummary {
+            stats: CompressionStats {
+                files_compressed: 1,
+                files_skipped: 2,
+                bytes_in: 3 << 30,
+                bytes_out: 1 << 30,
+            },
+            dirs: 2,
+            failed: 1,
+            elapsed: Duration::from_secs(83),
+            sizes: SizeFormat::Human,
+            completed: None,
+            free_space: None,
+            baseline: None,
+        };
+        assert_eq!(
+            summary.to_string(),
+            "Compressed 1 file (2 skipped) in 2 dirs, 1 failed, saving 2.0 GiB \
+             (3.0 GiB -> 1.0 GiB) in 1m 23s (37.0 MiB/s in, 12.3 MiB/s out)"
+        );
+
+        let freed = RunSummary {
+            free_space: Some((100 << 30, 101 << 30)),
+            ..summary
+        };
+        assert!(
+            freed
+                .to_string()
+                .ends_with("out); free space 100.0 GiB -> 101.0 GiB (+1.0 GiB)"),
+            "{freed}"
+        );
+        let shrunk = RunSummary {
+            free_space: Some((101 << 30, 100 << 30)),
+            ..freed
+        };
+        assert!(shrunk.to_string().ends_with("(-1.0 GiB)"), "{shrunk}");
+        let measured = RunSummary {
+            baseline: Some(summary::Baseline {
+                bytes: 20 << 30,
+                compressed: 16 << 30,
+            }),
+            ..shrunk
+        };
+        assert!(
+            measured
+                .to_string()
+                .contains("out); 80% of 20.0 GiB was already compressed; free space"),
+            "{measured}"
+        );
+
+        let stopped = RunSummary {
+            completed: Some((3 << 30, 12 << 30)),
+            free_space: None,
+            ..shrunk
+        };
+        assert!(
+            stopped
+                .to_string()
+                .ends_with("12.3 MiB/s out); stopped 25% of the way through 12.0 GiB"),
+            "{stopped}"
+        );
+    }
+
+    #[test]
+    fn prescan_counts_bytes_already_compressed() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("debug");
+        fs::create_dir_all(dir.join("deps")).unwrap();
+        fs::write(dir.join("deps").join("liba.rlib"), vec![0; 3000]).unwrap();
+        fs::write(dir.join("deps").join("libb.rlib"), vec![0; 1000]).unwrap();
+        fs::create_dir_all(dir.join("build")).unwrap();
+        fs::write(dir.join("build").join("unlisted"), vec![0; 5000]).unwrap();
+        let listing = |parent: &Path| {
+            if parent.ends_with("deps") {
+                Ok(HashSet::from([OsString::from("liba.rlib")]))
+            } else {
+                Err(io::Error::from(io::ErrorKind::Unsupported))
+            }
+        };
+        assert_eq!(
+            prescan(
+                std::slice::from_ref(&dir),
+                &WorkDirOptions::default(),
+                &listing
+            ),
+            (
+                vec![9000],
+                Some(summary::Baseline {
+                    bytes: 4000,
+                    compressed: 3000,
+                })
+            )
+        );
+        let unsupported = |_: &Path| Err(io::Error::from(io::ErrorKind::Unsupported));
+        assert_eq!(
+            prescan(&[dir], &WorkDirOptions::default(), &unsupported),
+            (vec![9000], None)
+        );
+    }
+
+    #[test]
+    fn dir_summary_shows_sizes_files_and_time_relative_to_cwd() {
+        let cwd = Path::new("/work/app");
+        let dir = cwd.join("target").join("release");
+        let line = summary::DirSummary {
+            dir: summary::relative_to(&dir, Some(cwd)),
+            stats: CompressionStats {
+                files_compressed: 12_000,
+                files_skipped: 401,
+                bytes_in: 8 << 30,
+                bytes_out: 3 << 30,
+            },
+            elapsed: Duration::from_secs(94),
+            sizes: SizeFormat::Human,
+        };
+        assert_eq!(
+            line.to_string(),
+            "target/release: 8.0 GiB -> 3.0 GiB, 12401 files, 1m 34s"
+        );
+        assert_eq!(
+            summary::relative_to(&dir, Some(Path::new("/elsewhere"))),
+            dir
+        );
+        assert_eq!(summary::relative_to(cwd, Some(cwd)), cwd);
+    }
+
+    #[test]
+    fn top_dirs_ranks_by_savings_and_truncates() {
+        let cwd = Path::new("/work/app");
+        let result = |dir: &str, bytes_in, bytes_out| report::DirResult {
+            dir: cwd.join(dir),
+            stats: CompressionStats {
+                bytes_in,
+                bytes_out,
+                ..CompressionStats::default()
+            },
+            elapsed: Duration::ZERO,
+        };
+        let dirs = [
+            result("target/debug", 300, 200),
+            result("target/release", 1000, 100),
+            result("target/bench", 50, 50),
+        ];
+        assert_eq!(
+            summary::top_dirs(&dirs, 2, Some(cwd), SizeFormat::Bytes),
+            "Most saved (2 of 3 dirs):\n  \
+             target/release  900 B (1000 B -> 100 B)\n  \
+             target/debug    100 B (300 B -> 200 B)"
+        );
+    }
+
+    #[test]
+    fn prescanned_total_is_not_grown_by_queued_dirs() {
+        let reporter = RecordingReporter::default();
+        let metered = Metered::new(&reporter, Arc::new(NoopMetrics));
+        let progress: &dyn Reporter = &metered;
+        progress.total_known(10 << 30);
+        progress.files_queued(4 << 30);
+        progress
+            .file_task(Path::new("a.rlib"), 4 << 30)
+            .increment(1 << 30);
+        assert_eq!(metered.heartbeat().completed(), (1 << 30, 10 << 30));
+    }
+
+    #[test]
+    fn heartbeat_prints_progress_until_stopped() {
+        let reporter = RecordingReporter::default();
+        let metered = Metered::new(&reporter, Arc::new(NoopMetrics));
+        let progress: &dyn Reporter = &metered;
+        progress.files_queued(4 << 30);
+        progress
+            .file_task(Path::new("a.rlib"), 4 << 30)
+            .increment(1 << 30);
+        let heartbeat = metered.heartbeat();
+        heartbeat.set_dirs_remaining(1);
+
+        let (stop, stopped) = mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(move || heartbeat.run(Duration::from_millis(1), &stopped, progress));
+            while reporter.messages.lock().unwrap().is_empty() {
+                std::thread::yield_now();
+            }
+            drop(stop);
+        });
+
+        assert_eq!(
+            reporter.messages.lock().unwrap()[0],
+            "compressed 1.0 GiB/4.0 GiB, 1 dir remaining"
+        );
+    }
+
+    #[test]
+    fn compressor_file_callbacks_feed_metrics() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"artifact").unwrap();
+
+        let inner = RecordingReporter::default();
+        let metrics = Arc::new(RecordingMetrics::default());
+        let metered = Metered::new(&inner, metrics.clone());
+        let stats = process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &metered,
+            &RecordingCompressor::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            stats,
+            CompressionStats {
+                files_compressed: 1,
+                files_skipped: 0,
+                bytes_in: 8,
+                bytes_out: 4,
+            }
+        );
+
+        let counters = metrics.counters.lock().unwrap();
+        assert_eq!(counters.get(&Counter::BytesSaved), Some(&4));
+    }
+
+    #[test]
+    fn converts_applesauce_stats() {
+        use std::sync::atomic::AtomicU64;
+
+        let stats = applesauce::Stats {
+            files: AtomicU64::new(10),
+            compressed_size_start: AtomicU64::new(1000),
+            compressed_size_final: AtomicU64::new(400),
+            compressed_file_count_start: AtomicU64::new(2),
+            compressed_file_count_final: AtomicU64::new(8),
+            ..applesauce::Stats::default()
+        };
+
+        let stats = CompressionStats::from(&stats);
+        assert_eq!(
+            stats,
+            CompressionStats {
+                files_compressed: 6,
+                files_skipped: 4,
+                bytes_in: 1000,
+                bytes_out: 400,
+            }
+        );
+        assert_eq!(stats.bytes_saved(), 600);
+    }
+
+    #[test]
+    fn skips_incompressible_extensions_by_default() {
+        let temp = tempdir().unwrap();
+        let out_dir = temp.path().join("build").join("foo-0123").join("out");
+        fs::create_dir_all(&out_dir).unwrap();
+        fs::write(out_dir.join("assets.tar.GZ"), b"gz").unwrap();
+        fs::write(out_dir.join("app.wasm.br"), b"br").unwrap();
+        fs::write(out_dir.join("gz"), b"no extension").unwrap();
+        fs::write(temp.path().join("libfoo.rlib"), b"rlib").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &progress,
+            &compressor,
+        )
+        .unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![out_dir.join("gz"), temp.path().join("libfoo.rlib")]
+        );
+
+        let compressor = RecordingCompressor::default();
+        let options = WorkDirOptions {
+            skip_extensions: vec![],
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert_eq!(compressor.calls.lock().unwrap()[0].len(), 4);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn parses_no_default_ext_skips_flag() {
+        let cli = Cli::try_parse_from(["cargo-apfs-compress"]).unwrap();
+        assert!(
+            cli.work_dir_options()
+                .skip_extensions
+                .contains(&"zst".to_owned())
+        );
+
+        let cli = Cli::try_parse_from(["cargo-apfs-compress", "--no-default-ext-skips"]).unwrap();
+        assert!(cli.work_dir_options().skip_extensions.is_empty());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn exclude_fingerprint_leaves_out_only_the_fingerprint_dir() {
+        let temp = tempdir().unwrap();
+        let fingerprint = temp.path().join(".fingerprint/foo-0a1b");
+        fs::create_dir_all(&fingerprint).unwrap();
+        fs::write(fingerprint.join("lib-foo"), b"hash").unwrap();
+        fs::write(temp.path().join("libfoo.rlib"), b"rlib").unwrap();
+
+        let cli = Cli::try_parse_from([
+            "cargo-apfs-compress",
+            "--exclude-fingerprint",
+            "--include-tiny-files",
+        ])
+        .unwrap();
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        process_work_dir(temp.path(), &cli.work_dir_options(), &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![temp.path().join("libfoo.rlib")]
+        );
+
+        let cli = Cli::try_parse_from(["cargo-apfs-compress"]).unwrap();
+        assert!(cli.work_dir_options().skip_dirs.is_empty());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn exclude_dir_prunes_dirs_by_name_at_any_depth() {
+        let temp = tempdir().unwrap();
+        for dir in ["build/foo-1a2b/out", "out", ".nextest/archive", "deps"] {
+            fs::create_dir_all(temp.path().join(dir)).unwrap();
+            fs::write(temp.path().join(dir).join("file"), b"data").unwrap();
+        }
+
+        let cli = Cli::try_parse_from([
+            "cargo-apfs-compress",
+            "--exclude-dir",
+            "out",
+            "--exclude-dir",
+            ".nextest",
+            "--include-tiny-files",
+        ])
+        .unwrap();
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        process_work_dir(temp.path(), &cli.work_dir_options(), &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![temp.path().join("deps/file")]
+        );
+
+        assert!(
+            Cli::try_parse_from(["cargo-apfs-compress", "--exclude-dir", "build/out"]).is_err()
+        );
+    }
+
+    #[test]
+    fn selects_deps_or_final_artifacts() {
+        let temp = tempdir().unwrap();
+        for dir in [
+            "deps",
+            "build/foo-1a2b/out",
+            "incremental/foo-3c4d",
+            "examples",
+        ] {
+            fs::create_dir_all(temp.path().join(dir)).unwrap();
+        }
+        for file in [
+            "deps/libfoo.rlib",
+            "build/foo-1a2b/out/bindings.txt",
+            "incremental/foo-3c4d/query.bin",
+            "examples/demo",
+            "foo",
+        ] {
+            fs::write(temp.path().join(file), b"artifact").unwrap();
+        }
+
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let selected = |options: WorkDirOptions| {
+            let compressor = RecordingCompressor::default();
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+            compressor.calls.lock().unwrap().concat()
+        };
+        assert_eq!(
+            selected(WorkDirOptions {
+                deps_only: true,
+                ..WorkDirOptions::default()
+            }),
+            [
+                temp.path().join("build/foo-1a2b/out/bindings.txt"),
+                temp.path().join("deps/libfoo.rlib"),
+            ]
+        );
+        assert_eq!(
+            selected(WorkDirOptions {
+                artifacts_only: true,
+                ..WorkDirOptions::default()
+            }),
+            [temp.path().join("examples/demo"), temp.path().join("foo")]
+        );
+    }
+
+    #[test]
+    fn parses_since_and_before_values() {
+        let day = |days: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86_400);
+        assert_eq!(TimeRef::parse("1970-01-02"), Ok(TimeRef::At(day(1))));
+        assert_eq!(TimeRef::parse("2024-03-01"), Ok(TimeRef::At(day(19_783))));
+        assert_eq!(
+            TimeRef::parse("2024-03-01T01:02:03Z"),
+            Ok(TimeRef::At(day(19_783) + Duration::from_secs(3723)))
+        );
+        assert_eq!(TimeRef::parse("@86400"), Ok(TimeRef::At(day(1))));
+        assert_eq!(
+            TimeRef::parse("HEAD~20"),
+            Ok(TimeRef::GitRef("HEAD~20".to_owned()))
+        );
+        assert!(TimeRef::parse("2024-13-01").is_err());
+        assert!(TimeRef::parse("2024-01-01T25:00").is_err());
+        assert!(TimeRef::parse("--all").is_err());
+    }
+
+    #[test]
+    fn resolves_git_ref_to_its_commit_time() {
+        let temp = tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .env("GIT_COMMITTER_DATE", "@1700000000 +0000")
+                .current_dir(temp.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "one"]);
+        assert_eq!(
+            TimeRef::GitRef("HEAD".to_owned())
+                .resolve(temp.path())
+                .unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert!(matches!(
+            TimeRef::GitRef("no-such-branch".to_owned()).resolve(temp.path()),
+            Err(Error::GitRef { .. })
+        ));
+    }
+
+    #[test]
+    fn leaves_out_files_modified_outside_the_time_window() {
+        let temp = tempdir().unwrap();
+        let old = temp.path().join("old.rlib");
+        let new = temp.path().join("new.rlib");
+        for (path, secs) in [(&old, 1_000_000_000), (&new, 1_700_000_000)] {
+            fs::write(path, b"artifact").unwrap();
+            fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        }
+        let cutoff = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000));
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        for (options, expected) in [
+            (
+                WorkDirOptions {
+                    modified_since: cutoff,
+                    ..WorkDirOptions::default()
+                },
+                &new,
+            ),
+            (
+                WorkDirOptions {
+                    modified_before: cutoff,
+                    ..WorkDirOptions::default()
+                },
+                &old,
+            ),
+        ] {
+            let compressor = RecordingCompressor::default();
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+            assert_eq!(compressor.calls.lock().unwrap()[0], vec![expected.clone()]);
+        }
+    }
+
+    #[test]
+    fn parses_byte_sizes() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("50GiB"), Ok(50 << 30));
+        assert_eq!(parse_byte_size("50 gib"), Ok(50 << 30));
+        assert_eq!(parse_byte_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_byte_size("1.5KiB"), Ok(1536));
+        assert_eq!(parse_byte_size("500MB"), Ok(500_000_000));
+        assert!(parse_byte_size("lots").is_err());
+        assert!(parse_byte_size("-1G").is_err());
+        assert_eq!(disk::format_bytes(50 << 30), "50.0 GiB");
+        assert_eq!(disk::format_bytes(512), "512 B");
+    }
+
+    #[test]
+    fn renders_gha_annotations_and_job_summary() {
+        assert_eq!(
+            gha::annotation(gha::Annotation::Warning, "a: b, c", "100% done\nnext line"),
+            "::warning title=a%3A b%2C c::100%25 done%0Anext line"
+        );
+
+        let summary = RunSummary {
+            stats: CompressionStats {
+                files_compressed: 3,
+                files_skipped: 1,
+                bytes_in: 3 << 30,
+                bytes_out: 1 << 30,
+            },
+            dirs: 1,
+            failed: 1,
+            elapsed: Duration::from_secs(83),
+            sizes: SizeFormat::Human,
+            completed: None,
+            free_space: None,
+            baseline: None,
+        };
+        let markdown = gha::job_summary(
+            &summary,
+            &[(PathBuf::from("target/release"), "busy".to_owned())],
+        );
+        assert!(
+            markdown.starts_with("### cargo apfs-compress\n"),
+            "{markdown}"
+        );
+        assert!(markdown.contains("| Saved | 2.0 GiB |\n"), "{markdown}");
+        assert!(markdown.contains("| Failed dirs | 1 |\n"), "{markdown}");
+        assert!(markdown.contains("| Duration | 1m 23s |\n"), "{markdown}");
+        assert!(
+            markdown.ends_with("- `target/release`: busy\n"),
+            "{markdown}"
+        );
+    }
+
+    #[test]
+    fn html_report_lists_dirs_and_extensions() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("a.rlib"), vec![0; 3000]).unwrap();
+        fs::write(temp.path().join("b.RLIB"), vec![0; 1000]).unwrap();
+        fs::write(temp.path().join("build-script"), b"x").unwrap();
+        let app = temp.path().join("app");
+        fs::write(&app, b"xy").unwrap();
+        fs::set_permissions(&app, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let mut files: Vec<PathBuf> = ["a.rlib", "app", "b.RLIB", "build-script", "gone.d"]
+            .iter()
+            .map(|name| temp.path().join(name))
+            .collect();
+        files.sort();
+
+        let extensions = report::extension_usage(&files);
+        let incompressible = summary::IncompressibleSummary::default();
+        incompressible.record(Path::new("target/debug/logo.png"), 2048);
+        let names: Vec<_> = extensions
+            .iter()
+            .map(|usage| {
+                (
+                    usage.extension.as_str(),
+                    usage.binary,
+                    usage.files,
+                    usage.size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("rlib", false, 2, 4000),
+                ("", true, 1, 2),
+                ("", false, 1, 1)
+            ]
+        );
+
+        let stats = CompressionStats {
+            files_compressed: 2,
+            files_skipped: 1,
+            bytes_in: 4000,
+            bytes_out: 1000,
+        };
+        let summary = RunSummary {
+            stats,
+            dirs: 1,
+            failed: 0,
+            elapsed: Duration::from_secs(1),
+            sizes: SizeFormat::Bytes,
+            completed: None,
+            free_space: None,
+            baseline: None,
+        };
+        let html = report::Report {
+            summary: &summary,
+            dirs: &[report::DirResult {
+                dir: PathBuf::from("target/<debug>"),
+                stats,
+                elapsed: Duration::from_millis(500),
+            }],
+            extensions: &extensions,
+            kinds: &[(artifact::ArtifactKind::Rlib, stats)],
+            incompressible: &incompressible,
+        }
+        .render(ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(
+            html.contains("<td data-sort=\"target/&lt;debug&gt;\">"),
+            "{html}"
+        );
+        assert!(
+            html.contains("<td data-sort=\"3000\">3000 B</td>"),
+            "{html}"
+        );
+        assert!(
+            html.contains("<td data-sort=\".rlib\">.rlib</td>"),
+            "{html}"
+        );
+        assert!(
+            html.contains("<td data-sort=\"500\">0.5s</td><td data-sort=\"8000\">8000 B/s in, 2000 B/s out</td>"),
+            "{html}"
+        );
+        assert!(html.contains("(none)"), "{html}");
+        assert!(
+            html.contains("<td data-sort=\"(binary)\">(binary)</td>"),
+            "{html}"
+        );
+        assert!(html.contains(">Ratio</th>"), "{html}");
+        assert!(html.contains("<td data-sort=\"rlib\">rlib</td>"), "{html}");
+        assert!(
+            html.contains("<p>1 file(s), 2048 B left as they were"),
+            "{html}"
+        );
+        assert!(
+            html.contains("<td data-sort=\"target/debug/logo.png\">"),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn incompressible_files_are_grouped_by_extension_and_ranked() {
+        let cwd = Path::new("/work");
+        let incompressible = summary::IncompressibleSummary::default();
+        assert!(incompressible.is_empty());
+        incompressible.record(&cwd.join("target/debug/a.PNG"), 300);
+        incompressible.record(&cwd.join("target/debug/b.png"), 500);
+        incompressible.record(&cwd.join("target/debug/data.gz"), 1000);
+        incompressible.record(&cwd.join("target/debug/blob"), 100);
+        assert_eq!(incompressible.total(), (4, 1900));
+        assert_eq!(
+            incompressible.render(2, Some(cwd), SizeFormat::Bytes),
+            "Not compressible enough (4 file(s), 1900 B):\n  \
+             .gz     1  1000 B\n  \
+             .png    2  800 B\n  \
+             (none)  1  100 B\n\
+             Largest:\n  \
+             target/debug/data.gz  1000 B\n  \
+             target/debug/b.png    500 B"
+        );
+    }
+
+    #[test]
+    fn classifies_artifacts_by_location_and_extension() {
+        use artifact::ArtifactKind;
+
+        let temp = tempdir().unwrap();
+        let debug = temp.path().join("target").join("debug");
+        fs::create_dir_all(debug.join("deps")).unwrap();
+        let binary = debug.join("app");
+        fs::write(&binary, "bin").unwrap();
+        fs::set_permissions(&binary, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let plain = debug.join("deps").join("LICENSE");
+        fs::write(&plain, "text").unwrap();
+
+        let kind = |path: &str| ArtifactKind::classify(&debug.join(path));
+        assert_eq!(kind("app"), ArtifactKind::Binary);
+        assert_eq!(kind("deps/LICENSE"), ArtifactKind::Other);
+        assert_eq!(kind("deps/libserde-1a2b.rlib"), ArtifactKind::Rlib);
+        assert_eq!(kind("deps/libserde-1a2b.rmeta"), ArtifactKind::Rlib);
+        assert_eq!(kind("deps/libfoo-1a2b.dylib"), ArtifactKind::Dylib);
+        assert_eq!(
+            kind("deps/app-1a2b.app.3c4d-cgu.0.rcgu.o"),
+            ArtifactKind::Object
+        );
+        assert_eq!(kind("deps/app-1a2b.d"), ArtifactKind::DepInfo);
+        assert_eq!(
+            kind(".fingerprint/serde-1a2b/lib-serde.json"),
+            ArtifactKind::Fingerprint
+        );
+        assert_eq!(
+            kind("build/ring-1a2b/out/libring_core.a"),
+            ArtifactKind::OutDir
+        );
+        assert_eq!(
+            kind("build/ring-1a2b/build-script-build.d"),
+            ArtifactKind::DepInfo
+        );
+
+        let kinds = artifact::KindSummary::default();
+        kinds.record(&debug.join("deps/a.rlib"), 100, 40);
+        kinds.record(&debug.join("deps/b.rlib"), 100, 40);
+        kinds.record(&debug.join("deps/a.d"), 10, 10);
+        kinds.record(&binary, 1000, 300);
+        assert_eq!(
+            kinds.render(SizeFormat::Bytes),
+            "Saved by artifact kind:\n  \
+             binary    1 file(s)  700 B (1000 B -> 300 B)\n  \
+             rlib      2 file(s)  120 B (200 B -> 80 B)\n  \
+             dep-info  1 file(s)    0 B (10 B -> 10 B)"
+        );
+    }
+
+    #[test]
+    fn history_keeps_recent_runs_and_draws_sparklines() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("history.jsonl");
+        assert_eq!(history::load(&path, 3).unwrap(), []);
+
+        for bytes_saved in [10, 20, 30, 40] {
+            let entry = history::Entry {
+                time: bytes_saved,
+                bytes_saved,
+                bytes_after: 100,
+            };
+            history::append(&path, &entry).unwrap();
+        }
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("not json\n");
+        fs::write(&path, text).unwrap();
+
+        let saved: Vec<_> = history::load(&path, 3)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.bytes_saved)
+            .collect();
+        assert_eq!(saved, [20, 30, 40]);
+
+        assert_eq!(history::sparkline(&[0, 7, 14, 3]), "▁▄█▂");
+        assert_eq!(history::sparkline(&[5, 5]), "▁▁");
+        assert_eq!(history::sparkline(&[]), "");
+    }
+
+    #[test]
+    fn parses_hid_idle_time_from_ioreg() {
+        let ioreg =
+            "+-o IOHIDSystem  <class IOHIDSystem>\n  {\n    \"HIDIdleTime\" = 2500000000\n  }\n";
+        assert_eq!(
+            platform::parse_hid_idle_time(ioreg),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(
+            platform::parse_hid_idle_time("    |   \"HIDIdleTime\" = 7\n"),
+            Some(Duration::from_nanos(7))
+        );
+        assert_eq!(platform::parse_hid_idle_time("\"Other\" = 1\n"), None);
+        assert!(platform::load_per_cpu().unwrap() >= 0.0);
+    }
+
+    #[test]
+    fn parses_low_power_mode_from_pmset() {
+        let pmset = "System-wide power settings:\nCurrently in use:\n standby              1\n lowpowermode         1\n";
+        assert!(platform::parse_low_power_mode(pmset));
+        assert!(!platform::parse_low_power_mode(
+            &pmset.replace("lowpowermode         1", "lowpowermode         0")
+        ));
+        assert!(!platform::parse_low_power_mode(" standby 1\n"));
+    }
+
+    #[test]
+    fn parses_storage_from_diskutil_info() {
+        let diskutil = "   Device Identifier:         disk3s5\n   Protocol:                  Apple Fabric\n   Solid State:               Yes\n   Device Location:           Internal\n";
+        assert_eq!(
+            platform::parse_storage(diskutil),
+            Some(disk::Storage::Internal)
+        );
+        assert_eq!(
+            platform::parse_storage(&diskutil.replace("Internal", "External")),
+            Some(disk::Storage::External)
+        );
+        assert_eq!(
+            platform::parse_storage(
+                "   Protocol:                  USB\n   Solid State:               No\n   Device Location:           External\n"
+            ),
+            Some(disk::Storage::Rotational)
+        );
+        assert_eq!(platform::parse_storage("   Protocol:   Disk Image\n"), None);
+        assert_eq!(disk::Storage::Internal.max_workers(), None);
+        assert_eq!(disk::Storage::Rotational.max_workers(), Some(1));
+        assert_eq!(disk::Storage::Rotational.walk_threads(), 1);
+    }
+
+    #[test]
+    fn parses_bulk_attribute_entries() {
+        // length, returned attrs (common, vol, dir, file, fork), name ref, flags, name, padding.
+        fn entry(name: &str, returned: u32, flags: u32) -> Vec<u8> {
+            let mut name = name.as_bytes().to_vec();
+            name.push(0);
+            let mut entry = vec![0; 4];
+            for group in [returned, 0, 0, 0, 0] {
+                entry.extend(group.to_ne_bytes());
+            }
+            entry.extend(12_i32.to_ne_bytes());
+            entry.extend((name.len() as u32).to_ne_bytes());
+            entry.extend(flags.to_ne_bytes());
+            entry.extend(name);
+            entry.resize(entry.len().next_multiple_of(8), 0);
+            let length = entry.len() as u32;
+            entry[..4].copy_from_slice(&length.to_ne_bytes());
+            entry
+        }
+        const RETURNED: u32 = 0x8000_0000 | 0x1 | 0x0004_0000;
+        let mut buf = entry("libfoo.rlib", RETURNED, 0x20);
+        buf.extend(entry("build", RETURNED, 0));
+        buf.extend(entry("no-flags", 0x8000_0000 | 0x1, 0));
+
+        assert_eq!(
+            platform::parse_bulk_entries(&buf, 3),
+            Some(vec![
+                (OsString::from("libfoo.rlib"), Some(0x20)),
+                (OsString::from("build"), Some(0)),
+                (OsString::from("no-flags"), None),
+            ])
+        );
+        assert_eq!(platform::parse_bulk_entries(&buf[..10], 1), None);
+    }
+
+    #[test]
+    fn parses_power_source_from_pmset() {
+        assert_eq!(
+            platform::parse_power_source(
+                "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=1)\t80%; discharging\n"
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            platform::parse_power_source("Now drawing from 'AC Power'\n"),
+            Some(false)
+        );
+        assert_eq!(platform::parse_power_source(""), None);
+    }
+
+    #[test]
+    fn interactive_checklist_toggles_dirs() {
+        assert_eq!(select::parse_toggles("1, 3-4", 4), Some(vec![0, 2, 3]));
+        assert_eq!(select::parse_toggles("0", 4), None);
+        assert_eq!(select::parse_toggles("2-5", 4), None);
+        assert_eq!(select::parse_toggles("x", 4), None);
+
+        let cwd = Path::new("/work/app");
+        let dirs = vec![
+            cwd.join("target/debug"),
+            cwd.join("target/release"),
+            cwd.join("target/bench"),
+        ];
+        let mut output = Vec::new();
+        let chosen = select::choose(
+            dirs.clone(),
+            &[3 << 30, 1 << 20, 0],
+            Some(cwd),
+            SizeFormat::Human,
+            &mut "n\n1,3\nbogus\n\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(chosen, [dirs[0].clone(), dirs[2].clone()]);
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.starts_with("Work dirs:\n  [x] 1  target/debug    3.0 GiB\n"),
+            "{output}"
+        );
+        assert!(
+            output.contains("  [ ] 2  target/release  1.0 MiB\n"),
+            "{output}"
+        );
+        assert!(
+            output.contains("Not a number or range of 1-3: bogus"),
+            "{output}"
+        );
+
+        let quit = select::choose(
+            dirs,
+            &[0, 0, 0],
+            None,
+            SizeFormat::Human,
+            &mut "q\n".as_bytes(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(quit.is_empty());
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn dashboard_shows_dirs_totals_and_recent_skips() {
+        use ratatui::Terminal;
+        use ratatui::backend::TestBackend;
+
+        let mut state = tui::State::new(Instant::now());
+        state.bytes_total = 4 << 20;
+        state.bytes_done = 1 << 20;
+        state.saved = 512 << 10;
+        state.dir_started(Path::new("/work/target/debug"));
+        state.dir_started(Path::new("/work/target/release"));
+        state.dirs[0].bytes_seen = 2 << 20;
+        state.dirs[0].bytes_done = 1 << 20;
+        state.dir_finished(
+            Path::new("/work/target/release"),
+            Some(&CompressionStats {
+                bytes_in: 3 << 20,
+                bytes_out: 1 << 20,
+                files_compressed: 2,
+                files_skipped: 0,
+            }),
+        );
+        state.skipped(
+            Path::new("/work/target/debug/app.d"),
+            "Too small".to_owned(),
+        );
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        tui::draw(&mut terminal, &state).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(80)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+        assert!(
+            screen.contains("1.0 MiB / 4.0 MiB, saved 512.0 KiB"),
+            "{screen}"
+        );
+        assert!(screen.contains("/work/target/debug"), "{screen}");
+        assert!(screen.contains("50%"), "{screen}");
+        assert!(screen.contains("done"), "{screen}");
+        assert!(screen.contains("2.0 MiB"), "{screen}");
+        assert!(
+            screen.contains("/work/target/debug/app.d: Too small"),
+            "{screen}"
+        );
+    }
+
+    #[test]
+    fn parses_files_open_for_writing_from_lsof() {
+        let lsof = "p412\nfcwd\na \nn/\nf3\nar\nn/work/target/debug/deps/libserde.rlib\n\
+                    p977\nf5\naw\nn/work/target/debug/deps/app-1a2b\nf6\nau\n\
+                    n/work/target/debug/.cargo-lock\nf7\naw\nn/tmp/other.log\n";
+        let dir = Path::new("/work/target/debug");
+        let mut writers: Vec<_> = platform::parse_lsof_writers(lsof, dir)
+            .into_iter()
+            .collect();
+        writers.sort();
+        assert_eq!(
+            writers,
+            [dir.join(".cargo-lock"), dir.join("deps/app-1a2b")]
+        );
+    }
+
+    #[test]
+    fn finds_cargo_waiting_on_a_lock_from_lsof() {
+        let lsof = "p412\ncrust-analyzer\np977\nccargo-apfs-compress\n";
+        assert!(!platform::parse_lsof_cargo(lsof, 977));
+        let lsof = format!("{lsof}p1204\nccargo\n");
+        assert!(platform::parse_lsof_cargo(&lsof, 977));
+        assert!(!platform::parse_lsof_cargo(&lsof, 1204));
+
+        let run = StopSignal::default();
+        let dir = run.linked();
+        dir.stop();
+        assert!(dir.should_stop() && !run.should_stop());
+        let dir = run.linked();
+        run.stop();
+        assert!(dir.should_stop());
+    }
+
+    #[test]
+    fn parses_local_snapshot_list() {
+        let tmutil = "Snapshots for volume group containing disk /:\n\
+                      com.apple.TimeMachine.2026-10-14-091512.local\n\
+                      com.apple.TimeMachine.2026-10-14-101530.local\n";
+        assert_eq!(
+            platform::parse_local_snapshots(tmutil),
+            [
+                "com.apple.TimeMachine.2026-10-14-091512.local",
+                "com.apple.TimeMachine.2026-10-14-101530.local"
+            ]
+        );
+        assert!(platform::parse_local_snapshots("Snapshots for disk /:\n").is_empty());
+    }
+
+    #[test]
+    fn snapshot_note_explains_missing_free_space() {
+        assert_eq!(
+            summary::snapshot_note(5 << 30, 1 << 30, 2, SizeFormat::Human).as_deref(),
+            Some(
+                "note: 5.0 GiB reclaimed, but 4.0 GiB of it is held by 2 local snapshots until they expire"
+            )
+        );
+        assert_eq!(
+            summary::snapshot_note(5 << 30, 5 << 30, 2, SizeFormat::Human),
+            None
+        );
+        assert_eq!(
+            summary::snapshot_note(5 << 30, 0, 0, SizeFormat::Human),
+            None
+        );
+    }
+
+    #[test]
+    fn applies_dir_policies_from_config() {
+        let temp = tempdir().unwrap();
+        let project = temp.path().join("project");
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        fs::write(
+            project.join(".cargo").join("config.toml"),
+            "[apfs-compress.dir-policies]\n\
+             \"deps/\" = { compression = \"zlib\", level = \"high\" }\n\
+             \"incremental\" = \"skip\"\n\
+             \"build/*/out\" = \"lzvn\"\n",
+        )
+        .unwrap();
+        let files = config::config_files(&project, None).unwrap();
+        let settings =
+            config::resolve(config::CliSettings::default(), &files, &|_: &str| None).unwrap();
+        assert_eq!(settings.dir_policies.value.len(), 3);
+
+        let work_dir = temp.path().join("debug");
+        for dir in [
+            "deps",
+            "incremental/foo-1a2b",
+            "build/foo-3c4d/out",
+            "build/foo-5e6f",
+        ] {
+            fs::create_dir_all(work_dir.join(dir)).unwrap();
+        }
+        for file in [
+            "deps/libfoo.rlib",
+            "incremental/foo-1a2b/query.bin",
+            "build/foo-3c4d/out/bindings.txt",
+            "build/foo-5e6f/build-script-build",
+            "foo.txt",
+        ] {
+            fs::write(work_dir.join(file), b"artifact").unwrap();
+        }
+        let options = WorkDirOptions {
+            dir_policies: settings.dir_policies.value,
+            ..WorkDirOptions::default()
+        };
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        process_work_dir(&work_dir, &options, &progress, &compressor).unwrap();
+        assert_eq!(
+            *compressor.calls.lock().unwrap(),
+            [
+                vec![work_dir.join("build/foo-3c4d/out/bindings.txt")],
+                vec![
+                    work_dir.join("build/foo-5e6f/build-script-build"),
+                    work_dir.join("foo.txt"),
+                ],
+                vec![work_dir.join("deps/libfoo.rlib")],
+            ]
+        );
+        assert_eq!(
+            *compressor.kinds.lock().unwrap(),
+            [
+                (Kind::Lzvn, DEFAULT_COMPRESSION_LEVEL),
+                (Kind::Lzfse, DEFAULT_COMPRESSION_LEVEL),
+                (Kind::Zlib, 9),
+            ]
+        );
+
+        fs::write(
+            project.join(".cargo").join("config.toml"),
+            "[apfs-compress.dir-policies]\n\"deps\" = { compression = \"zlib\", level = 12 }\n",
+        )
+        .unwrap();
+        let files = config::config_files(&project, None).unwrap();
+        assert!(matches!(
+            config::resolve(config::CliSettings::default(), &files, &|_: &str| None),
+            Err(Error::InvalidConfig { key, .. }) if key == "apfs-compress.dir-policies.\"deps\""
+        ));
+    }
+
+    #[test]
+    fn user_config_stays_lowest_for_projects_in_the_home_dir() {
+        let temp = tempdir().unwrap();
+        let home = temp.path().join(".cargo");
+        let project = temp.path().join("src").join("app");
+        fs::create_dir_all(&home).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        let user_config = home.join("config.toml");
+        fs::write(
+            &user_config,
+            "[apfs-compress]\ncompression = \"zlib\"\nsmall-file-cutoff = \"4KiB\"\n",
+        )
+        .unwrap();
+        fs::write(
+            project.join(config::PROJECT_CONFIG_NAME),
+            "compression = \"lzvn\"\n",
+        )
+        .unwrap();
+
+        let files = config::config_files(&project, Some(&home)).unwrap();
+        assert_eq!(files[0].0, user_config);
+        assert_eq!(
+            files
+                .iter()
+                .filter(|(path, _)| *path == user_config)
+                .count(),
+            1
+        );
+        let no_env = |_: &str| None;
+        let settings = config::resolve(config::CliSettings::default(), &files, &no_env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Lzvn);
+        assert_eq!(
+            settings.compression.source,
+            config::Source::File(project.join(config::PROJECT_CONFIG_NAME))
+        );
+        assert_eq!(settings.small_file_cutoff.value, 4096);
+    }
+
+    #[test]
+    fn layers_settings_from_cli_env_project_and_user_config() {
+        let temp = tempdir().unwrap();
+        let home = temp.path().join("cargo-home");
+        let project = temp.path().join("project");
+        fs::create_dir_all(&home).unwrap();
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        fs::write(
+            home.join("config.toml"),
+            "[apfs-compress]\ncompression = \"zlib\"\nsmall-file-cutoff = \"4KiB\"\n\
+             [profile.dev]\ndir-name = \"user-debug\"\n",
+        )
+        .unwrap();
+        let project_config = project.join(".cargo").join("config.toml");
+        fs::write(
+            &project_config,
+            "[apfs-compress]\nsmall-file-cutoff = 8192\n[profile.dev]\ndir-name = \"my-debug\"\n",
+        )
+        .unwrap();
+
+        let files = config::config_files(&project, Some(&home)).unwrap();
+        let no_env = |_: &str| None;
+        let settings = config::resolve(config::CliSettings::default(), &files, &no_env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Zlib);
+        assert_eq!(
+            settings.compression.source,
+            config::Source::File(home.join("config.toml"))
+        );
+        assert_eq!(settings.small_file_cutoff.value, 8192);
+        assert_eq!(
+            settings.small_file_cutoff.source,
+            config::Source::File(project_config.clone())
+        );
+        assert_eq!(settings.small_file_compression.value, Kind::Lzvn);
+        assert_eq!(
+            settings.small_file_compression.source,
+            config::Source::Default
+        );
+
+        let env = |name: &str| (name == config::COMPRESSION_ENV).then(|| "lzvn".to_owned());
+        let settings = config::resolve(config::CliSettings::default(), &files, &env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Lzvn);
+        assert_eq!(
+            settings.compression.source,
+            config::Source::Env(config::COMPRESSION_ENV)
+        );
+
+        let cli = config::CliSettings {
+            compression: Some(CompressionArg::Lzfse),
+            ..config::CliSettings::default()
+        };
+        let settings = config::resolve(cli, &files, &env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Lzfse);
+        assert_eq!(settings.compression.source, config::Source::Cli);
+
+        let bad_env = |_: &str| Some("brotli".to_owned());
+        assert!(matches!(
+            config::resolve(config::CliSettings::default(), &files, &bad_env),
+            Err(Error::InvalidEnv { .. })
+        ));
+
+        let profiles = config::profile_settings(&files);
+        assert_eq!(profiles["profile.dev.dir-name"].value, "my-debug");
+        assert_eq!(
+            profiles["profile.dev.dir-name"].source,
+            config::Source::File(project_config)
+        );
+    }
+
+    #[test]
+    fn init_writes_a_config_the_settings_layering_reads() {
+        let probe = init::Probe {
+            declared: vec!["profiling".to_owned()],
+            work_dirs: vec![
+                "debug".to_owned(),
+                "aarch64-apple-darwin/release".to_owned(),
+            ],
+            other_dirs: vec!["clippy".to_owned()],
+            biggest: vec![("debug/deps".to_owned(), 3 << 30)],
+        };
+        let text = init::render(&probe, SizeFormat::Human);
+        assert!(text.contains("# Targets: aarch64-apple-darwin\n"), "{text}");
+        assert!(text.contains("# [profile.profiling]\n"), "{text}");
+
+        let temp = tempdir().unwrap();
+        let project = temp.path().join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        let path = project.join(config::PROJECT_CONFIG_NAME);
+        fs::write(&path, &text).unwrap();
+        fs::create_dir_all(project.join(".cargo")).unwrap();
+        fs::write(
+            project.join(".cargo").join("config.toml"),
+            "[apfs-compress]\ncompression = \"zlib\"\n",
+        )
+        .unwrap();
+
+        let files = config::config_files(&project.join("src"), None).unwrap();
+        let settings =
+            config::resolve(config::CliSettings::default(), &files, &|_: &str| None).unwrap();
+        assert_eq!(settings.compression.value, Kind::Zlib);
+        assert_eq!(settings.check_dirs.value, ["clippy"]);
+        assert_eq!(settings.check_dirs.source, config::Source::File(path));
+        assert_eq!(
+            settings
+                .dir_policies
+                .value
+                .iter()
+                .map(|policy| (policy.pattern(), policy.action()))
+                .collect::<Vec<_>>(),
+            [("incremental".to_owned(), PolicyAction::Skip)]
+        );
+
+        let defaults = load_profile_compression_defaults(&project).unwrap();
+        assert_eq!(defaults.get("dev"), Some(&Kind::Lzvn));
+        assert_eq!(defaults.get("release"), Some(&Kind::Lzfse));
+        assert_eq!(defaults.get("profiling"), None);
+    }
+
+    #[test]
+    fn reads_settings_from_manifest_metadata() {
+        let temp = tempdir().unwrap();
+        let member = temp.path().join("crates").join("member");
+        fs::create_dir_all(&member).unwrap();
+        fs::write(
+            temp.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/member\"]\n\
+             [workspace.metadata.apfs-compress]\ncompression = \"zlib\"\n\
+             small-file-compression = \"zlib\"\n",
+        )
+        .unwrap();
+        fs::write(
+            member.join("Cargo.toml"),
+            "[package]\nname = \"member\"\n\
+             [package.metadata.apfs-compress]\ncompression = \"lzvn\"\n\
+             skip-extensions = [\"wasm\", \"zip\"]\n",
+        )
+        .unwrap();
+        fs::create_dir_all(member.join(".cargo")).unwrap();
+        fs::write(
+            member.join(".cargo").join("config.toml"),
+            "[apfs-compress]\nsmall-file-compression = \"lzfse\"\n",
+        )
+        .unwrap();
+
+        let files = config::config_files(&member, None).unwrap();
+        let no_env = |_: &str| None;
+        let settings = config::resolve(config::CliSettings::default(), &files, &no_env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Lzvn);
+        assert_eq!(
+            settings.compression.source,
+            config::Source::File(member.join("Cargo.toml"))
+        );
+        assert_eq!(settings.small_file_compression.value, Kind::Lzfse);
+        assert_eq!(settings.skip_extensions.value, ["wasm", "zip"]);
+        assert_eq!(settings.check_dirs.value, Vec::<String>::new());
+
+        let files = config::config_files(temp.path(), None).unwrap();
+        let settings = config::resolve(config::CliSettings::default(), &files, &no_env).unwrap();
+        assert_eq!(settings.compression.value, Kind::Zlib);
+        assert_eq!(
+            settings.compression.source,
+            config::Source::File(temp.path().join("Cargo.toml"))
+        );
+    }
+
+    #[test]
+    fn migration_recompresses_only_other_algorithms() {
+        let options = WorkDirOptions {
+            compression: Kind::Lzfse,
+            small_file_compression: Some(Kind::Lzvn),
+            small_file_cutoff: 1024,
+            migrate_compression: true,
+            ..WorkDirOptions::default()
+        };
+        assert!(needs_recompression(&options, Some(Kind::Zlib), 4096));
+        assert!(needs_recompression(&options, Some(Kind::Lzvn), 4096));
+        assert!(!needs_recompression(&options, Some(Kind::Lzfse), 4096));
+        assert!(!needs_recompression(&options, Some(Kind::Lzvn), 100));
+        assert!(needs_recompression(&options, Some(Kind::Lzfse), 100));
+        assert!(!needs_recompression(&options, None, 4096));
+
+        let forced = WorkDirOptions {
+            force: true,
+            ..WorkDirOptions::default()
+        };
+        assert!(needs_recompression(&forced, None, 4096));
+        assert!(!needs_recompression(
+            &WorkDirOptions::default(),
+            Some(Kind::Zlib),
+            4096
+        ));
+    }
+
+    #[test]
+    fn parses_and_checks_min_savings() {
+        assert_eq!(MinSavings::parse("1GiB"), Ok(MinSavings::Bytes(1 << 30)));
+        assert_eq!(MinSavings::parse("20%"), Ok(MinSavings::Percent(20.0)));
+        assert!(MinSavings::parse("120%").is_err());
+        assert!(MinSavings::parse("some").is_err());
+
+        assert!(MinSavings::Bytes(100).is_met(1000, 100));
+        assert!(!MinSavings::Bytes(100).is_met(1000, 99));
+        assert!(MinSavings::Percent(20.0).is_met(1000, 200));
+        assert!(!MinSavings::Percent(20.0).is_met(1000, 199));
+        assert!(!MinSavings::Percent(0.0).is_met(0, 0));
+    }
+
+    #[test]
+    fn exact_size_format_applies_to_skip_messages() {
+        use crate::summary::describe_skip;
+
+        assert_eq!(SizeFormat::Bytes.format(50 << 30), "53687091200 B");
+        let why = SkipReason::TooLarge(8 << 30);
+        assert_eq!(
+            describe_skip(&why, SizeFormat::Human),
+            "File too large: 8.0 GiB > 4.0 GiB"
+        );
+        assert_eq!(
+            describe_skip(&why, SizeFormat::Bytes),
+            "File too large: 8589934592 B > 4294967295 B"
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn parses_small_file_policy() {
+        let options = Cli::try_parse_from(["cargo-apfs-compress"])
+            .unwrap()
+            .work_dir_options();
+        assert_eq!(options.small_file_compression, Some(Kind::Lzvn));
+        assert_eq!(options.small_file_cutoff, DEFAULT_SMALL_FILE_CUTOFF);
+
+        let options = Cli::try_parse_from([
+            "cargo-apfs-compress",
+            "--small-file-compression",
+            "zlib",
+            "--small-file-cutoff",
+            "16KiB",
+        ])
+        .unwrap()
+        .work_dir_options();
+        assert_eq!(options.small_file_compression, Some(Kind::Zlib));
+        assert_eq!(options.small_file_cutoff, 16 * 1024);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn parses_force_flag() {
+        let cli = Cli::try_parse_from(["cargo-apfs-compress"]).unwrap();
+        assert!(!cli.work_dir_options().force);
+
+        let cli = Cli::try_parse_from(["cargo-apfs-compress", "--force"]).unwrap();
+        assert!(cli.work_dir_options().force);
+    }
+
+    #[test]
+    fn skips_mach_o_binaries_when_requested() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("app"),
+            [0xcf, 0xfa, 0xed, 0xfe, 0x07, 0x00],
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("universal"),
+            [0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x02],
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("Main.class"),
+            [0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34],
+        )
+        .unwrap();
+        fs::write(temp.path().join("app.d"), b"app: src/main.rs").unwrap();
+        fs::write(temp.path().join("empty"), b"").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            skip_executables: true,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![temp.path().join("Main.class"), temp.path().join("app.d")]
+        );
+    }
+
+    #[test]
+    fn skips_only_top_level_binaries_with_skip_bins() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempdir().unwrap();
+        fs::create_dir(temp.path().join("deps")).unwrap();
+        for path in ["app", "deps/app-0a1b2c"] {
+            let path = temp.path().join(path);
+            fs::write(&path, b"binary").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::write(temp.path().join("app.d"), b"app: src/main.rs").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            skip_bins: true,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![
+                temp.path().join("app.d"),
+                temp.path().join("deps").join("app-0a1b2c")
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_inputs_by_package() {
+        let temp = tempdir().unwrap();
+        let root = temp.path();
+        for dir in [
+            "deps",
+            "build/my-app-0a1b/out",
+            "build/serde-2c3d/out",
+            "examples",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "my-app",
+            "my-app.d",
+            "libmy_app.rlib",
+            "other",
+            "deps/my_app-0a1b",
+            "deps/libmy_app-0a1b.rmeta",
+            "deps/libmy_app_macros-0a1b.rlib",
+            "deps/libserde-2c3d.rlib",
+            "build/my-app-0a1b/out/generated.rs",
+            "build/serde-2c3d/out/private.rs",
+        ] {
+            fs::write(root.join(file), b"x").unwrap();
+        }
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            packages: vec![PackageFilter::new("my-app", ["my-app".to_owned()])],
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(root, &options, &progress, &compressor).unwrap();
+        let calls = compressor.calls.lock().unwrap();
+        let inputs: Vec<_> = calls[0]
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                "build/my-app-0a1b/out/generated.rs",
+                "deps/libmy_app-0a1b.rmeta",
+                "deps/my_app-0a1b",
+                "libmy_app.rlib",
+                "my-app",
+                "my-app.d",
+            ]
+        );
+    }
+
+    #[test]
+    fn ratio_cache_skips_and_picks_algorithms_from_earlier_runs() {
+        let temp = tempdir().unwrap();
+        let root = temp.path();
+        let mut cache = ratio_cache::RatioCache::default();
+        for _ in 0..16 {
+            let class = |name| ratio_cache::Class::of(Path::new(name), 8000);
+            cache.record(class("data.bin"), Kind::Lzfse, 8192, 8192);
+            cache.record(class("libfoo.rlib"), Kind::Lzfse, 8192, 7372);
+            cache.record(class("libfoo.rlib"), Kind::Zlib, 8192, 4096);
+        }
+        cache.save(root).unwrap();
+        for name in ["data.bin", "libfoo.rlib", "main.o"] {
+            fs::write(root.join(name), vec![b'x'; 8000]).unwrap();
+        }
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            ratio_cache: true,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(root, &options, &progress, &compressor).unwrap();
+        let mut calls: Vec<_> = compressor
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(compressor.kinds.lock().unwrap().iter())
+            .map(|(paths, &(kind, _))| {
+                let names: Vec<_> = paths
+                    .iter()
+                    .map(|path| {
+                        path.strip_prefix(root)
+                            .unwrap()
+                            .to_str()
+                            .unwrap()
+                            .to_owned()
+                    })
+                    .collect();
+                (names, kind)
+            })
+            .collect();
+        calls.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            calls,
+            [
+                (vec!["libfoo.rlib".to_owned()], Kind::Zlib),
+                (vec!["main.o".to_owned()], Kind::Lzfse),
+            ]
+        );
+
+        let mut expected = cache;
+        expected.record(
+            ratio_cache::Class::of(Path::new("libfoo.rlib"), 8000),
+            Kind::Zlib,
+            8000,
+            4000,
+        );
+        // Small enough for the default small-file algorithm.
+        expected.record(
+            ratio_cache::Class::of(Path::new("main.o"), 8000),
+            Kind::Lzvn,
+            8000,
+            4000,
+        );
+        assert_eq!(ratio_cache::RatioCache::load(root), expected);
+    }
+
+    #[test]
+    fn leaves_recently_rebuilt_members_alone() {
+        let temp = tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("deps")).unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        for (file, idle) in [
+            ("my-app", false),
+            ("deps/libmy_app-0a1b.rlib", true),
+            ("deps/my_app-0a1b", false),
+            ("deps/libserde-2c3d.rlib", false),
+        ] {
+            fs::write(root.join(file), b"x").unwrap();
+            if idle {
+                fs::File::options()
+                    .write(true)
+                    .open(root.join(file))
+                    .unwrap()
+                    .set_modified(long_ago)
+                    .unwrap();
+            }
+        }
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            members_idle_for: Some(Duration::from_secs(10 * 60)),
+            members: vec![PackageFilter::new("my-app", ["my-app".to_owned()])],
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(root, &options, &progress, &compressor).unwrap();
+        let calls = compressor.calls.lock().unwrap();
+        let inputs: Vec<_> = calls[0]
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            inputs,
+            vec!["deps/libmy_app-0a1b.rlib", "deps/libserde-2c3d.rlib"]
+        );
+    }
+
+    #[test]
+    fn max_files_compresses_the_largest_uncompressed_files_first() {
+        let temp = tempdir().unwrap();
+        let root = temp.path().join("debug");
+        fs::create_dir_all(root.join("deps")).unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+        for (file, size, old) in [
+            ("deps/libbig.rlib", 9000, false),
+            ("deps/libdone.rlib", 20000, false),
+            ("deps/libnew.rlib", 5000, false),
+            ("deps/libold.rlib", 5000, true),
+            ("small", 10, false),
+        ] {
+            fs::write(root.join(file), vec![b'x'; size]).unwrap();
+            if old {
+                fs::File::options()
+                    .write(true)
+                    .open(root.join(file))
+                    .unwrap()
+                    .set_modified(long_ago)
+                    .unwrap();
+            }
+        }
+        let listing = |parent: &Path| {
+            Ok(if parent.ends_with("deps") {
+                HashSet::from([OsString::from("libdone.rlib")])
+            } else {
+                HashSet::new()
+            })
+        };
+        let options = WorkDirOptions::default();
+        let (selected, candidates) =
+            largest_files(std::slice::from_ref(&root), &options, 2, &listing);
+        assert_eq!(candidates, 4);
+        assert_eq!(
+            selected,
+            HashSet::from([root.join("deps/libbig.rlib"), root.join("deps/libold.rlib")])
+        );
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            selected: Some(Arc::new(selected)),
+            ..options
+        };
+        process_work_dir(&root, &options, &progress, &compressor).unwrap();
+        assert_eq!(
+            *compressor.calls.lock().unwrap(),
+            [vec![
+                root.join("deps/libbig.rlib"),
+                root.join("deps/libold.rlib")
+            ]]
+        );
+    }
+
+    #[test]
+    fn inventory_records_every_file_and_is_compressed_last() {
+        let temp = tempdir().unwrap();
+        let root = temp.path().join("debug");
+        fs::create_dir_all(root.join("deps")).unwrap();
+        fs::write(root.join("deps/libfoo.rlib"), vec![b'x'; 8000]).unwrap();
+        let path = temp.path().join("out/inventory.jsonl");
+        let inventory = Arc::new(Inventory::create(&path).unwrap());
+        let options = WorkDirOptions {
+            inventory: Some(Arc::clone(&inventory)),
+            ..WorkDirOptions::default()
+        };
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        process_work_dir(&root, &options, &progress, &compressor).unwrap();
+        finish_inventory(&inventory, &options, &compressor).unwrap();
+
+        let records: Vec<serde_json::Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            [serde_json::json!({
+                "path": root.join("deps/libfoo.rlib").to_string_lossy(),
+                "size": 8000,
+                "on_disk_before": 8000,
+                "on_disk_after": 4000,
+                "algorithm": "lzvn",
+                "outcome": "compressed",
+            })]
+        );
+        assert_eq!(compressor.calls.lock().unwrap().last().unwrap(), &[path]);
+    }
+
+    #[test]
+    fn sweeps_stale_units_before_compressing() {
+        let temp = tempdir().unwrap();
+        let root = temp.path();
+        for dir in [
+            ".fingerprint/old-0a1b",
+            ".fingerprint/new-2c3d",
+            "build/old-0a1b/out",
+            "deps",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            ".fingerprint/old-0a1b/lib-old",
+            ".fingerprint/new-2c3d/lib-new",
+            "build/old-0a1b/out/generated.rs",
+            "deps/libold-0a1b.rlib",
+            "deps/libnew-2c3d.rlib",
+        ] {
+            fs::write(root.join(file), b"x").unwrap();
+        }
+        let long_ago = std::time::SystemTime::now() - Duration::from_secs(60 * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(root.join(".fingerprint/old-0a1b/lib-old"))
+            .unwrap()
+            .set_times(
+                fs::FileTimes::new()
+                    .set_accessed(long_ago)
+                    .set_modified(long_ago),
+            )
+            .unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            sweep_older_than: Some(parse_duration("30d").unwrap()),
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(root, &options, &progress, &compressor).unwrap();
+        assert!(!root.join(".fingerprint/old-0a1b").exists());
+        assert!(!root.join("build/old-0a1b").exists());
+        assert!(!root.join("deps/libold-0a1b.rlib").exists());
+        assert!(root.join("deps/libnew-2c3d.rlib").exists());
+        assert_eq!(compressor.calls.lock().unwrap()[0].len(), 2);
+    }
+
+    #[test]
+    fn measures_allocated_size_in_blocks() {
+        let temp = tempdir().unwrap();
+        let empty = temp.path().join("empty");
+        let small = temp.path().join("small");
+        fs::write(&empty, b"").unwrap();
+        fs::write(&small, b"x").unwrap();
+        assert_eq!(disk::allocated_size(&empty).unwrap(), 0);
+        let size = disk::allocated_size(&small).unwrap();
+        assert!(size >= 512 && size.is_multiple_of(512), "{size}");
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(
+            parse_duration("30d"),
+            Ok(Duration::from_secs(30 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("2w"),
+            Ok(Duration::from_secs(14 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("7"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn resumes_from_checkpoint() {
+        let temp = tempdir().unwrap();
+        fs::create_dir(temp.path().join("deps")).unwrap();
+        fs::write(temp.path().join("deps").join("done.rlib"), b"done").unwrap();
+        fs::write(temp.path().join("todo.rlib"), b"todo").unwrap();
+        let checkpoint = temp.path().join(checkpoint::CHECKPOINT_NAME);
+        fs::write(&checkpoint, b"deps/done.rlib\0").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &progress,
+            &compressor,
+        )
+        .unwrap();
+        assert_eq!(compressor.calls.lock().unwrap()[0].len(), 2);
+        assert!(!checkpoint.exists());
+
+        let compressor = RecordingCompressor::default();
+        fs::write(&checkpoint, b"deps/done.rlib\0").unwrap();
+        let options = WorkDirOptions {
+            resume: true,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert_eq!(
+            compressor.calls.lock().unwrap()[0],
+            vec![temp.path().join("todo.rlib")]
+        );
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn keeps_checkpoint_when_compression_fails() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("bad.rlib"), b"bad").unwrap();
+
+        let compressor = RecordingCompressor {
+            fail_on: Some("bad".to_owned()),
+            ..RecordingCompressor::default()
+        };
+        let progress = ProgressBars::new(Verbosity::Normal);
+        // Only a run that may be resumed leaves one.
+        process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &progress,
+            &compressor,
+        )
+        .unwrap_err();
+        assert!(!temp.path().join(checkpoint::CHECKPOINT_NAME).exists());
+        let options = WorkDirOptions {
+            resume: true,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap_err();
+        assert!(temp.path().join(checkpoint::CHECKPOINT_NAME).exists());
+    }
+
+    #[test]
+    fn keeps_checkpoint_when_stopped_midway() {
+        /// Compresses the first file it is given, then stops the run as Ctrl-C would.
+        struct StoppingCompressor(StopSignal);
+
+        impl Compressor for StoppingCompressor {
+            fn compress_paths(
+                &self,
+                paths: &[PathBuf],
+                options: &WorkDirOptions,
+                progress: &dyn Reporter,
+            ) -> Result<CompressionStats, BoxError> {
+                let Some(first) = paths.first() else {
+                    return Ok(CompressionStats::default());
+                };
+                progress.file_compressed(first, 3, 1);
+                self.0.stop();
+                options.stop.mark_cut_short();
+                Ok(CompressionStats {
+                    files_compressed: 1,
+                    ..CompressionStats::default()
+                })
+            }
+        }
+
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("a.rlib"), b"aaa").unwrap();
+        fs::write(temp.path().join("b.rlib"), b"bbb").unwrap();
+
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            resume: true,
+            ..WorkDirOptions::default()
+        };
+        let compressor = StoppingCompressor(options.stop.clone());
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert!(options.stop.cancelled());
+        assert_eq!(
+            checkpoint::load(temp.path()).unwrap(),
+            HashSet::from([temp.path().join("a.rlib")])
+        );
+    }
+
+    #[test]
+    fn limits_walk_depth() {
+        let temp = tempdir().unwrap();
+        let deep = temp.path().join("build").join("sdk-0a1b").join("out");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(temp.path().join("top.rlib"), b"top").unwrap();
+        fs::write(temp.path().join("build").join("one"), b"one").unwrap();
+        fs::write(deep.join("three"), b"three").unwrap();
+
+        let progress = ProgressBars::new(Verbosity::Normal);
+        for (max_depth, expected) in [(Some(0), 1), (Some(1), 2), (Some(2), 2), (None, 3)] {
+            let compressor = RecordingCompressor::default();
+            let options = WorkDirOptions {
+                max_depth,
+                ..WorkDirOptions::default()
+            };
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+            assert_eq!(
+                compressor.calls.lock().unwrap()[0].len(),
+                expected,
+                "{max_depth:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn walks_wide_trees_in_sorted_order() {
+        let temp = tempdir().unwrap();
+        let mut expected = Vec::new();
+        for crate_dir in 0..20 {
+            let dir = temp
+                .path()
+                .join("build")
+                .join(format!("crate-{crate_dir:02}"));
+            for sub in ["out", "out/gen"] {
+                fs::create_dir_all(dir.join(sub)).unwrap();
+                let file = dir.join(sub).join("lib.rs");
+                fs::write(&file, b"x").unwrap();
+                expected.push(file);
+            }
+        }
+        expected.sort();
+
+        let inputs = walk::collect_inputs(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &PlainProgress::new(Verbosity::Quiet),
+        )
+        .unwrap();
+        assert_eq!(inputs, expected);
+    }
+
+    #[test]
+    fn skips_directories_unchanged_since_last_clean_run() {
+        let temp = tempdir().unwrap();
+        let deps = temp.path().join("deps");
+        fs::create_dir_all(&deps).unwrap();
+        fs::write(temp.path().join("top.bin"), b"top").unwrap();
+        fs::write(deps.join("a.rlib"), b"a").unwrap();
+
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            skip_unchanged_dirs: true,
+            ..WorkDirOptions::default()
+        };
+        let names = |compressor: &RecordingCompressor| -> Vec<String> {
+            compressor.calls.lock().unwrap()[0]
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let run = || {
+            let compressor = RecordingCompressor::default();
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+            names(&compressor)
+        };
+
+        assert_eq!(run(), ["a.rlib", "top.bin"]);
+        assert!(temp.path().join(dir_state::STATE_NAME).exists());
+        assert_eq!(run(), ["top.bin"]);
+
+        fs::write(deps.join("b.rlib"), b"b").unwrap();
+        assert_eq!(run(), ["a.rlib", "b.rlib", "top.bin"]);
+    }
+
+    #[test]
+    fn finds_identical_files_across_work_dirs() {
+        let temp = tempdir().unwrap();
+        let debug = temp.path().join("debug");
+        let release = temp.path().join("release");
+        fs::create_dir_all(debug.join("deps")).unwrap();
+        fs::create_dir_all(&release).unwrap();
+        let contents = vec![7u8; dedup::MIN_SIZE as usize];
+        let mut other = contents.clone();
+        other[100] = 8;
+        fs::write(debug.join("deps").join("tool-0a1b"), &contents).unwrap();
+        fs::hard_link(debug.join("deps").join("tool-0a1b"), debug.join("tool")).unwrap();
+        fs::write(release.join("tool"), &contents).unwrap();
+        fs::write(release.join("other"), &other).unwrap();
+        fs::write(release.join("small"), b"small").unwrap();
+        fs::write(debug.join("small"), b"small").unwrap();
 
-    #[test]
-    fn maps_builtin_profiles_to_dirs() {
-        let overrides = HashMap::new();
-        assert_eq!(resolve_profile_dir_name("dev", &overrides), "debug");
-        assert_eq!(resolve_profile_dir_name("test", &overrides), "debug");
-        assert_eq!(resolve_profile_dir_name("bench", &overrides), "release");
-        assert_eq!(resolve_profile_dir_name("release", &overrides), "release");
-        assert_eq!(resolve_profile_dir_name("custom", &overrides), "custom");
+        let groups = dedup::find_duplicates(&dedup::large_files(&[debug.clone(), release.clone()]));
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].paths,
+            [debug.join("deps").join("tool-0a1b"), release.join("tool")]
+        );
+        assert_eq!(groups[0].len, dedup::MIN_SIZE);
+
+        // A hardlinked copy is kept even when another sorts first, and is never replaced.
+        fs::remove_file(debug.join("tool")).unwrap();
+        fs::hard_link(release.join("tool"), release.join("tool-link")).unwrap();
+        let groups = dedup::find_duplicates(&dedup::large_files(&[debug.clone(), release.clone()]));
+        assert_eq!(groups[0].paths[0], release.join("tool"));
+        // A clone left over from a killed run doesn't get in the way.
+        let stale = debug.join("deps").join("tool-0a1b.apfs-compress-clone");
+        fs::write(&stale, b"partial").unwrap();
+        let outcome = dedup::dedup(&groups, &RecordingReporter::default());
+        assert!(!stale.exists());
+        if cfg!(not(target_os = "macos")) {
+            assert_eq!(outcome, dedup::Outcome::default());
+        }
     }
 
     #[test]
-    fn applies_profile_dir_name_override_from_config() {
+    fn analyze_duplicates_reports_identical_and_similar_artifacts() {
         let temp = tempdir().unwrap();
-        let cargo_dir = temp.path().join(".cargo");
-        fs::create_dir(&cargo_dir).unwrap();
+        let debug = temp.path().join("debug");
+        let release = temp.path().join("release");
+        for dir in [&debug, &release] {
+            fs::create_dir_all(dir.join("deps")).unwrap();
+        }
+        let size = dedup::MIN_SIZE as usize;
+        let foo = debug.join("deps").join("libfoo-0123456789abcdef.rlib");
+        fs::write(&foo, vec![1u8; size]).unwrap();
         fs::write(
-            cargo_dir.join("config.toml"),
-            "[profile.dev]\ndir-name = \"my-debug\"\nunknown = 1\n",
+            release.join("deps").join("libfoo-fedcba9876543210.rlib"),
+            vec![1u8; size],
         )
         .unwrap();
+        fs::write(
+            debug.join("deps").join("libbar-0123456789abcdef.rlib"),
+            vec![2u8; size + 1000],
+        )
+        .unwrap();
+        fs::write(
+            release.join("deps").join("libbar-fedcba9876543210.rlib"),
+            vec![3u8; size],
+        )
+        .unwrap();
+        fs::write(release.join("deps").join("libbaz.rlib"), vec![4u8; size]).unwrap();
 
-        let overrides = load_profile_dir_name_overrides(temp.path()).unwrap();
-        assert_eq!(overrides.get("dev"), Some(&"my-debug".to_owned()));
-    }
-
-    #[test]
-    fn resolves_dirs_without_target() {
-        let overrides = HashMap::new();
-        let dirs = resolve_work_dirs(
-            Path::new("/tmp/target"),
-            &["dev".to_owned(), "release".to_owned()],
-            &[],
-            &overrides,
+        let report = analyze::duplicates(
+            &[debug.clone(), release.clone()],
+            Some(temp.path()),
+            SizeFormat::Bytes,
         );
+        let wasted = disk::allocated_size(&foo).unwrap();
+        let lines: Vec<_> = report.lines().collect();
         assert_eq!(
-            dirs,
-            vec![
-                PathBuf::from("/tmp/target/debug"),
-                PathBuf::from("/tmp/target/release")
+            lines[0],
+            format!("Identical files (1 group(s), {wasted} B wasted):")
+        );
+        assert!(lines[1].ends_with(&format!("  {size} B  2 copies, {wasted} B wasted")));
+        assert_eq!(
+            lines[2..4],
+            [
+                "    debug/deps/libfoo-0123456789abcdef.rlib",
+                "    release/deps/libfoo-fedcba9876543210.rlib"
+            ]
+        );
+        assert_eq!(
+            lines[4..],
+            [
+                format!(
+                    "Similar artifacts in several work dirs (1 name(s), about {size} B if all \
+                     but the largest copy of each were pruned):"
+                ),
+                format!("  deps/libbar.rlib  {size} B"),
+                format!(
+                    "    debug/deps/libbar-0123456789abcdef.rlib  {} B",
+                    size + 1000
+                ),
+                format!("    release/deps/libbar-fedcba9876543210.rlib  {size} B"),
             ]
         );
-    }
 
-    #[test]
-    fn resolves_dirs_with_target() {
-        let overrides = HashMap::new();
-        let dirs = resolve_work_dirs(
-            Path::new("/tmp/target"),
-            &["dev".to_owned()],
-            &[
-                "aarch64-apple-darwin".to_owned(),
-                "x86_64-apple-darwin".to_owned(),
-            ],
-            &overrides,
+        assert_eq!(
+            analyze::unhashed(Path::new("build/foo-sys-0123456789abcdef/out/x.o")),
+            "build/foo-sys/out/x.o"
         );
         assert_eq!(
-            dirs,
-            vec![
-                PathBuf::from("/tmp/target/aarch64-apple-darwin/debug"),
-                PathBuf::from("/tmp/target/x86_64-apple-darwin/debug"),
-            ]
+            analyze::unhashed(Path::new("deps/foo-bar.d")),
+            "deps/foo-bar.d"
         );
     }
 
     #[test]
-    fn dedups_same_output_dir() {
-        let overrides = HashMap::new();
-        let dirs = resolve_work_dirs(
-            Path::new("/tmp/target"),
-            &["dev".to_owned(), "test".to_owned()],
-            &[],
-            &overrides,
+    fn analyze_crates_ranks_deps_and_build_by_crate() {
+        let temp = tempdir().unwrap();
+        let debug = temp.path().join("debug");
+        let release = temp.path().join("release");
+        let out = debug.join("build/foo-sys-0123456789abcdef/out");
+        fs::create_dir_all(&out).unwrap();
+        fs::create_dir_all(release.join("deps")).unwrap();
+        fs::create_dir_all(debug.join("deps")).unwrap();
+        let files = [
+            (out.join("libfoo.a"), 300_000),
+            (debug.join("deps/libfoo_sys-0123456789abcdef.rlib"), 100_000),
+            (
+                release.join("deps/libfoo_sys-fedcba9876543210.rlib"),
+                100_000,
+            ),
+            (debug.join("deps/libc-0123456789abcdef.d"), 10),
+            (debug.join("deps/liblibc-0123456789abcdef.rlib"), 50_000),
+            (debug.join("deps/stray.tmp"), 1_000_000),
+        ];
+        for (path, len) in &files {
+            fs::write(path, vec![1u8; *len]).unwrap();
+        }
+        // A hardlink counts once.
+        fs::hard_link(
+            debug.join("deps/liblibc-0123456789abcdef.rlib"),
+            debug.join("deps/liblibc-aaaaaaaaaaaaaaaa.rlib"),
+        )
+        .unwrap();
+        let size = |index: usize| disk::allocated_size(&files[index].0).unwrap();
+
+        let report = analyze::crates(&[debug, release], SizeFormat::Bytes);
+        let lines: Vec<_> = report.lines().collect();
+        let foo = size(0) + size(1) + size(2);
+        let libc = size(3) + size(4);
+        assert_eq!(
+            lines,
+            [
+                format!(
+                    "Size by crate in deps/ and build/ of 2 work dir(s) (2 crate(s), {} B):",
+                    foo + libc
+                ),
+                format!(
+                    "  foo_sys  {foo} B  deps {} B, build {} B, 3 file(s)",
+                    size(1) + size(2),
+                    size(0)
+                ),
+                format!("  libc     {libc} B  deps {libc} B, build 0 B, 2 file(s)"),
+            ]
+        );
+        assert_eq!(
+            analyze::crate_name("serde-0123456789abcdef"),
+            Some("serde".to_owned())
         );
-        assert_eq!(dirs, vec![PathBuf::from("/tmp/target/debug")]);
+        assert_eq!(analyze::crate_name("foo.d"), None);
     }
 
     #[test]
-    fn defaults_to_lzfse() {
-        let cli = Cli::try_parse_from(["cargo-apfs-compress"]).unwrap();
-        assert_eq!(cli.compression, CompressionArg::Lzfse);
-        assert!(cli.profiles.is_empty());
-        assert_eq!(cli.verbose, 0);
-        assert_eq!(cli.quiet, 0);
-    }
+    fn control_socket_answers_ctl_commands() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("ctl.sock");
+        let heartbeat = heartbeat::Heartbeat::default();
+        heartbeat.set_dirs_remaining(2);
+        let (stop, pause) = (StopSignal::default(), PauseSignal::default());
+        let control = control::Control {
+            heartbeat: &heartbeat,
+            stop: &stop,
+            pause: &pause,
+            sizes: SizeFormat::Bytes,
+        };
+        let listener = control::bind(&path).unwrap();
+        assert!(matches!(
+            control::bind(&path),
+            Err(Error::ControlSocketInUse { .. })
+        ));
+        let (done, stopped) = mpsc::channel::<()>();
+        std::thread::scope(|scope| {
+            let (listener, control) = (&listener, &control);
+            scope.spawn(move || control::serve(listener, control, &stopped));
+            let send = |command| control::send(&path, command);
+            assert_eq!(
+                send("status").unwrap(),
+                "running: compressed 0 B/0 B, 2 dirs remaining"
+            );
+            assert_eq!(send("pause").unwrap(), "paused");
+            assert!(pause.is_paused());
+            assert!(send("status").unwrap().starts_with("paused: "));
+            assert_eq!(send("trigger-now").unwrap(), "resumed");
+            assert!(!pause.is_paused());
+            assert_eq!(send("trigger-now").unwrap(), "already running");
+            assert_eq!(send("stop").unwrap(), "stopping");
+            assert!(stop.should_stop());
+            assert!(matches!(
+                send("reboot"),
+                Err(Error::ControlRefused { message }) if message == "unknown command `reboot`"
+            ));
+            drop(done);
+        });
+        drop(listener);
+        // A socket file left behind by a run that is gone is taken over.
+        control::bind(&path).unwrap();
 
-    #[test]
-    fn parses_verbose_flag() {
-        let cli = Cli::try_parse_from(["cargo-apfs-compress", "-v"]).unwrap();
-        assert_eq!(cli.verbose, 1);
-        assert_eq!(cli.quiet, 0);
-        assert_eq!(cli.verbosity(), Verbosity::Verbose);
+        // Anything else is refused and kept.
+        let file = temp.path().join("notes.txt");
+        fs::write(&file, b"keep").unwrap();
+        assert!(matches!(
+            control::bind(&file),
+            Err(Error::ControlSocketNotASocket { .. })
+        ));
+        assert_eq!(fs::read(&file).unwrap(), b"keep");
     }
 
     #[test]
-    fn parses_quiet_flag() {
-        let cli = Cli::try_parse_from(["cargo-apfs-compress", "-q"]).unwrap();
-        assert_eq!(cli.quiet, 1);
-        assert_eq!(cli.verbose, 0);
-        assert_eq!(cli.verbosity(), Verbosity::Quiet);
+    fn debounce_skips_dirs_that_finished_recently() {
+        let temp = tempdir().unwrap();
+        let deps = temp.path().join("deps");
+        fs::create_dir_all(&deps).unwrap();
+        fs::write(deps.join("a.rlib"), b"a").unwrap();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            skip_unchanged_dirs: true,
+            debounce: Some(Duration::from_secs(60)),
+            ..WorkDirOptions::default()
+        };
+        let calls = || {
+            let compressor = RecordingCompressor::default();
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+            compressor.calls.into_inner().unwrap().len()
+        };
+
+        assert_eq!(calls(), 1);
+        let finished = dir_state::last_finished(temp.path()).unwrap();
+        assert!(SystemTime::now().duration_since(finished).unwrap() < Duration::from_secs(60));
+        assert_eq!(calls(), 0);
+
+        dir_state::record_finished(temp.path(), finished - Duration::from_secs(61)).unwrap();
+        assert_eq!(calls(), 1);
+        // The listings saved alongside it still let unchanged dirs be skipped.
+        assert!(
+            dir_state::DirState::load(temp.path())
+                .unchanged("deps", dir_state::mtime_ns(&deps).unwrap())
+                .is_some()
+        );
     }
 
     #[test]
-    fn discovers_default_target_roots() {
-        let root = tempdir().unwrap();
-        let target = root.path().join("target");
-        fs::create_dir_all(target.join("debug")).unwrap();
-        fs::create_dir_all(target.join("release")).unwrap();
-        fs::create_dir_all(target.join("x86_64-apple-darwin").join("debug")).unwrap();
-        fs::create_dir_all(target.join("x86_64-apple-darwin").join("release")).unwrap();
-        fs::create_dir_all(target.join("doc")).unwrap();
-        fs::create_dir_all(target.join("package")).unwrap();
-        fs::create_dir_all(target.join("tmp")).unwrap();
+    fn excludes_cargo_lock_from_inputs() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
+        fs::write(temp.path().join(CARGO_LOCK_NAME), b"").unwrap();
 
-        let dirs = discover_default_work_dirs(&target, &[]).unwrap();
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &progress,
+            &compressor,
+        )
+        .unwrap();
 
-        assert!(dirs.contains(&target.join("debug")));
-        assert!(dirs.contains(&target.join("release")));
-        assert!(dirs.contains(&target.join("x86_64-apple-darwin").join("debug")));
-        assert!(dirs.contains(&target.join("x86_64-apple-darwin").join("release")));
-        assert!(dirs.contains(&target.join("doc")));
-        assert!(dirs.contains(&target.join("package")));
-        assert!(!dirs.contains(&target.join("tmp")));
+        let calls = compressor.calls.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].iter().any(|p| p.ends_with("artifact.bin")));
+        assert!(!calls[0].iter().any(|p| p.ends_with(CARGO_LOCK_NAME)));
     }
 
     #[test]
-    fn discovers_only_requested_targets_when_filtered() {
-        let root = tempdir().unwrap();
-        let target = root.path().join("target");
-        fs::create_dir_all(target.join("x86_64-apple-darwin").join("debug")).unwrap();
-        fs::create_dir_all(target.join("aarch64-apple-darwin").join("debug")).unwrap();
+    fn writes_spotlight_marker_without_compressing_it() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
 
-        let dirs =
-            discover_default_work_dirs(&target, &["x86_64-apple-darwin".to_owned()]).unwrap();
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            exclude_from_spotlight: true,
+            ..WorkDirOptions::default()
+        };
+        for _ in 0..2 {
+            process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        }
 
-        assert_eq!(dirs, vec![target.join("x86_64-apple-darwin").join("debug")]);
+        assert!(temp.path().join(".metadata_never_index").is_file());
+        for call in compressor.calls.lock().unwrap().iter() {
+            assert_eq!(call, &[temp.path().join("artifact.bin")]);
+        }
     }
 
-    #[derive(Default)]
-    struct RecordingCompressor {
-        calls: Mutex<Vec<Vec<PathBuf>>>,
-        delay: Duration,
-        fail_on: Option<String>,
-        starts: Mutex<Vec<Instant>>,
-        ends: Mutex<Vec<Instant>>,
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn backup_exclusion_is_idempotent() {
+        let temp = tempdir().unwrap();
+        assert!(hygiene::exclude_from_backup(temp.path()).unwrap());
+        assert!(!hygiene::exclude_from_backup(temp.path()).unwrap());
     }
 
-    impl Compressor for RecordingCompressor {
-        fn compress_paths(
-            &self,
-            paths: &[PathBuf],
-            _compression: Kind,
-            _progress: &ProgressBars,
-        ) -> Result<()> {
-            self.starts.lock().unwrap().push(Instant::now());
-            self.calls.lock().unwrap().push(paths.to_vec());
-            if self.delay > Duration::ZERO {
-                thread::sleep(self.delay);
-            }
-            self.ends.lock().unwrap().push(Instant::now());
+    #[test]
+    fn rejects_work_dir_that_is_a_file() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("debug");
+        fs::write(&path, b"not-a-dir").unwrap();
 
-            if let Some(fail_on) = &self.fail_on {
-                if paths
-                    .iter()
-                    .any(|path| path.to_string_lossy().contains(fail_on))
-                {
-                    return Err(anyhow!("intentional failure"));
-                }
-            }
-            Ok(())
-        }
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let error = process_work_dir(&path, &WorkDirOptions::default(), &progress, &compressor)
+            .unwrap_err();
+
+        assert!(matches!(error, Error::NotADirectory { path: p } if p == path));
+        assert!(compressor.calls.lock().unwrap().is_empty());
     }
 
     #[test]
-    fn excludes_cargo_lock_from_inputs() {
+    fn compression_errors_include_source_chain() {
         let temp = tempdir().unwrap();
-        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
-        fs::write(temp.path().join(CARGO_LOCK_NAME), b"").unwrap();
+        fs::write(temp.path().join("will-fail.bin"), b"f").unwrap();
 
-        let compressor = RecordingCompressor::default();
+        let compressor = RecordingCompressor {
+            fail_on: Some("will-fail".to_owned()),
+            ..RecordingCompressor::default()
+        };
         let progress = ProgressBars::new(Verbosity::Normal);
-        process_work_dir(temp.path(), Kind::Lzfse, &progress, &compressor).unwrap();
+        let error = process_work_dir(
+            temp.path(),
+            &WorkDirOptions::default(),
+            &progress,
+            &compressor,
+        )
+        .unwrap_err();
 
-        let calls = compressor.calls.lock().unwrap();
-        assert_eq!(calls.len(), 1);
-        assert!(calls[0].iter().any(|p| p.ends_with("artifact.bin")));
-        assert!(!calls[0].iter().any(|p| p.ends_with(CARGO_LOCK_NAME)));
+        assert!(matches!(error, Error::CompressionFailed { .. }));
+        assert_eq!(
+            error.chain().to_string(),
+            format!(
+                "compression failed for {}: intentional failure",
+                temp.path().display()
+            )
+        );
     }
 
     #[test]
@@ -580,12 +8416,12 @@ mod tests {
         let c2 = Arc::clone(&compressor);
         let t1 = thread::spawn(move || {
             let progress = ProgressBars::new(Verbosity::Normal);
-            process_work_dir(&d1, Kind::Lzfse, &progress, &*c1)
+            process_work_dir(&d1, &WorkDirOptions::default(), &progress, &*c1)
         });
         thread::sleep(Duration::from_millis(20));
         let t2 = thread::spawn(move || {
             let progress = ProgressBars::new(Verbosity::Normal);
-            process_work_dir(&d2, Kind::Lzfse, &progress, &*c2)
+            process_work_dir(&d2, &WorkDirOptions::default(), &progress, &*c2)
         });
         t1.join().unwrap().unwrap();
         t2.join().unwrap().unwrap();
@@ -597,6 +8433,151 @@ mod tests {
         assert!(starts[1] >= ends[0]);
     }
 
+    #[test]
+    fn shared_locks_coexist_but_exclude_writers() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join(CARGO_LOCK_NAME), b"").unwrap();
+        let fs = Filesystem::new(temp.path().to_path_buf());
+        let progress = ProgressBars::new(Verbosity::Quiet);
+
+        let first = fs
+            .open_ro_shared(CARGO_LOCK_NAME, "build directory", &progress)
+            .unwrap();
+        let second = fs
+            .open_ro_shared(CARGO_LOCK_NAME, "build directory", &progress)
+            .unwrap();
+        let writer = fs::File::open(temp.path().join(CARGO_LOCK_NAME)).unwrap();
+        assert!(matches!(
+            writer.try_lock(),
+            Err(std::fs::TryLockError::WouldBlock)
+        ));
+
+        drop(first);
+        drop(second);
+        writer.try_lock().unwrap();
+    }
+
+    #[test]
+    fn records_lock_holder_while_lock_is_held() {
+        let temp = tempdir().unwrap();
+        let fs = Filesystem::new(temp.path().to_path_buf());
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let lock = fs
+            .open_rw_exclusive_create(CARGO_LOCK_NAME, "build directory", &progress)
+            .unwrap();
+        let held = lock_holder::record(lock, "compressing");
+
+        let holder = lock_holder::current(&temp.path().join(CARGO_LOCK_NAME)).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        let description = holder.to_string();
+        assert!(
+            description.starts_with(&format!(
+                "held by cargo-apfs-compress pid {} since ",
+                std::process::id()
+            )),
+            "{description}"
+        );
+        assert!(description.ends_with(" (compressing)"), "{description}");
+
+        drop(held);
+        assert!(!temp.path().join(lock_holder::HOLDER_NAME).exists());
+        assert!(lock_holder::current(&temp.path().join(CARGO_LOCK_NAME)).is_none());
+    }
+
+    #[test]
+    fn locks_work_dir_with_custom_lock_name() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions {
+            lock_name: "build.lock".to_owned(),
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert!(temp.path().join("build.lock").exists());
+        assert!(!temp.path().join(CARGO_LOCK_NAME).exists());
+        let calls = compressor.calls.lock().unwrap();
+        assert!(!calls[0].iter().any(|path| path.ends_with("build.lock")));
+
+        assert!(parse_lock_name("build.lock").is_ok());
+        assert!(parse_lock_name("../build.lock").is_err());
+        assert!(parse_lock_name("..").is_err());
+    }
+
+    #[test]
+    fn skips_work_dir_lock_when_disabled() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Normal);
+        let options = WorkDirOptions {
+            lock_work_dir: false,
+            ..WorkDirOptions::default()
+        };
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert!(!temp.path().join(CARGO_LOCK_NAME).exists());
+        assert_eq!(compressor.calls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn skips_work_dir_once_stopped() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("artifact.bin"), b"abc").unwrap();
+
+        let compressor = RecordingCompressor::default();
+        let progress = ProgressBars::new(Verbosity::Quiet);
+        let options = WorkDirOptions::default();
+        options.stop.stop();
+        process_work_dir(temp.path(), &options, &progress, &compressor).unwrap();
+        assert!(compressor.calls.lock().unwrap().is_empty());
+        assert!(options.stop.was_cut_short());
+        assert!(!options.stop.timed_out());
+        assert!(options.stop.cancelled());
+    }
+
+    #[test]
+    fn panicking_worker_fails_only_its_dir() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("debug");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("will-panic.rlib"), b"abc").unwrap();
+
+        let progress = PlainProgress::new(Verbosity::Quiet);
+        let options = WorkDirOptions::default();
+        let compressor = RecordingCompressor {
+            panic_on: Some("will-panic".to_owned()),
+            ..RecordingCompressor::default()
+        };
+        let (result, _) = run_work_dir(&dir, &options, &progress, &compressor);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "worker panicked: intentional panic"
+        );
+
+        // The dir's lock went with the panic, so the next run doesn't wait for it.
+        let (result, _) = run_work_dir(&dir, &options, &progress, &RecordingCompressor::default());
+        assert_eq!(result.unwrap().files_compressed, 1);
+    }
+
+    #[test]
+    fn walk_stops_once_stopped() {
+        let temp = tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("deps")).unwrap();
+        fs::write(temp.path().join("deps/app.rlib"), b"abc").unwrap();
+
+        let options = WorkDirOptions::default();
+        options.stop.stop();
+        let inputs =
+            walk::collect_inputs(temp.path(), &options, &PlainProgress::new(Verbosity::Quiet))
+                .unwrap();
+        assert!(inputs.is_empty());
+        assert!(options.stop.cancelled());
+        assert_eq!(Error::Interrupted.exit_code(), 130);
+    }
+
     #[test]
     fn parallelizes_distinct_dirs() {
         let root = tempdir().unwrap();
@@ -619,11 +8600,11 @@ mod tests {
 
         let t1 = thread::spawn(move || {
             let progress = ProgressBars::new(Verbosity::Normal);
-            process_work_dir(&d1c, Kind::Lzfse, &progress, &*c1)
+            process_work_dir(&d1c, &WorkDirOptions::default(), &progress, &*c1)
         });
         let t2 = thread::spawn(move || {
             let progress = ProgressBars::new(Verbosity::Normal);
-            process_work_dir(&d2c, Kind::Lzfse, &progress, &*c2)
+            process_work_dir(&d2c, &WorkDirOptions::default(), &progress, &*c2)
         });
         t1.join().unwrap().unwrap();
         t2.join().unwrap().unwrap();
@@ -645,16 +8626,21 @@ mod tests {
         let target = root.path().join("target").join("debug");
         fs::create_dir_all(&target).unwrap();
         fs::write(target.join("will-fail.bin"), b"f").unwrap();
+        fs::write(
+            root.path().join("Cargo.toml"),
+            "[package]\nname = \"tmp-ws\"\nversion = \"0.1.0\"\nedition = \"2024\"\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.path().join("src")).unwrap();
+        fs::write(root.path().join("src").join("main.rs"), "fn main() {}\n").unwrap();
 
         let old = std::env::current_dir().unwrap();
         std::env::set_current_dir(root.path()).unwrap();
 
         let cli = Cli {
             profiles: vec!["dev".to_owned()],
-            targets: vec![],
-            compression: CompressionArg::Lzfse,
-            verbose: 0,
-            quiet: 0,
+            include_tiny_files: true,
+            ..Cli::default()
         };
 
         let compressor = RecordingCompressor {
@@ -662,8 +8648,22 @@ mod tests {
             ..RecordingCompressor::default()
         };
 
-        let result = run_with_compressor(cli, &compressor);
+        let metrics = Arc::new(RecordingMetrics::default());
+        let reporter = ProgressBars::new(Verbosity::Quiet);
+        let result = run_with_compressor(cli, &compressor, &reporter, metrics.clone());
         std::env::set_current_dir(old).unwrap();
-        assert!(result.is_err());
+        assert!(matches!(
+            result,
+            Err(Error::DirectoriesFailed { failed: 1 })
+        ));
+
+        let counters = metrics.counters.lock().unwrap();
+        assert_eq!(counters.get(&Counter::DirsFailed), Some(&1));
+        assert_eq!(counters.get(&Counter::Errors), Some(&1));
+        assert_eq!(counters.get(&Counter::DirsCompressed), None);
+        assert_eq!(
+            metrics.gauges.lock().unwrap().get(&Gauge::WorkDirs),
+            Some(&1)
+        );
     }
 }
@@ -0,0 +1,97 @@
+//! Who holds a lock we took: a sidecar next to the lock file naming this process, so another
+//! instance that blocks on it can say what it is waiting for instead of hanging silently.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::flock::FileLock;
+
+/// JSON describing the current holder, written beside the lock file while it is held.
+pub(crate) const HOLDER_NAME: &str = ".apfs-compress-lock-holder";
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Holder {
+    pub(crate) pid: u32,
+    /// Seconds since the Unix epoch.
+    pub(crate) started: u64,
+    pub(crate) operation: String,
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "held by cargo-apfs-compress pid {} since {} ({})",
+            self.pid,
+            local_clock_time(self.started),
+            self.operation
+        )
+    }
+}
+
+/// An exclusive lock plus the sidecar naming us, which is removed just before the lock is released.
+pub(crate) struct HeldLock {
+    _lock: FileLock,
+    sidecar: PathBuf,
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.sidecar);
+    }
+}
+
+/// Records this process as the holder of `lock` for `operation`. Failing to write the sidecar only
+/// loses the description, so it is ignored.
+pub(crate) fn record(lock: FileLock, operation: &str) -> HeldLock {
+    let sidecar = lock.parent().join(HOLDER_NAME);
+    let holder = Holder {
+        pid: std::process::id(),
+        started: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        operation: operation.to_owned(),
+    };
+    if let Ok(json) = serde_json::to_string(&holder) {
+        let _ = fs::write(&sidecar, json);
+    }
+    HeldLock {
+        _lock: lock,
+        sidecar,
+    }
+}
+
+/// The holder recorded beside `lock_path`, if there is one and that process is still running.
+/// Anything else holding the lock, like cargo itself, leaves no sidecar.
+pub(crate) fn current(lock_path: &Path) -> Option<Holder> {
+    let json = fs::read_to_string(lock_path.parent()?.join(HOLDER_NAME)).ok()?;
+    let holder: Holder = serde_json::from_str(&json).ok()?;
+    process_exists(holder.pid).then_some(holder)
+}
+
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: Signal 0 only checks whether the process exists and may be signalled.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// `secs` since the epoch as local `HH:MM`.
+fn local_clock_time(secs: u64) -> String {
+    let Ok(time) = libc::time_t::try_from(secs) else {
+        return "?".to_owned();
+    };
+    // SAFETY: `tm` is plain data, and `localtime_r` fills it in or returns null.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return "?".to_owned();
+    }
+    format!("{:02}:{:02}", tm.tm_hour, tm.tm_min)
+}
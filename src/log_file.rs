@@ -0,0 +1,172 @@
+//! `--log-file`: every log line a run prints, plus its errors, appended with timestamps to a file
+//! whatever the console shows, and rotated once it reaches `--log-file-max-size`.
+
+use applesauce::progress::{SkipReason, Task};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::log_format::{LogFormat, LogLevel};
+use crate::reporter::Reporter;
+use crate::stats::CompressionStats;
+use crate::summary::describe_skip;
+
+/// How big the log file may grow before it is rotated, unless `--log-file-max-size` says
+/// otherwise.
+pub const DEFAULT_LOG_FILE_MAX_SIZE: u64 = 10 << 20;
+
+/// How many rotated files are kept next to the log file, as `<name>.1` (the newest) and so on.
+const ROTATED_FILES: usize = 3;
+
+pub(crate) struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    format: LogFormat,
+    /// The open file and how long it is.
+    file: Mutex<(File, u64)>,
+}
+
+impl LogFile {
+    /// Opens `path` for appending, creating it and its dir if need be. Lines are written in
+    /// `format`, or as logfmt for [`LogFormat::Plain`], so they always carry a timestamp.
+    pub(crate) fn open(path: &Path, max_size: u64, format: LogFormat) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            format: match format {
+                LogFormat::Plain => LogFormat::Logfmt,
+                format => format,
+            },
+            file: Mutex::new(append(path)?),
+        })
+    }
+
+    pub(crate) fn log(&self, level: LogLevel, path: Option<&Path>, message: &str) {
+        let mut line = self.format.format_line(level, path, message);
+        line.push('\n');
+        // The console still has the line; a full disk shouldn't also fail the run.
+        let _ = self.write_line(&line);
+    }
+
+    /// Appends `line`, rotating first if it would take the file past its maximum size.
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (_, len) = &*file;
+        if *len > 0 && *len + line.len() as u64 > self.max_size {
+            self.rotate()?;
+            *file = append(&self.path)?;
+        }
+        let (handle, len) = &mut *file;
+        handle.write_all(line.as_bytes())?;
+        *len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts `<name>.1` to `<name>.2` and so on, dropping the oldest, and moves the log file to
+    /// `<name>.1`.
+    fn rotate(&self) -> io::Result<()> {
+        let rotated = |index: usize| {
+            let mut name = OsString::from(self.path.as_os_str());
+            name.push(format!(".{index}"));
+            PathBuf::from(name)
+        };
+        for index in (1..ROTATED_FILES).rev() {
+            match fs::rename(rotated(index), rotated(index + 1)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, rotated(1))
+    }
+}
+
+fn append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    Ok((file, len))
+}
+
+/// A [`Reporter`] that passes everything on to `inner` and also writes log lines and errors to a
+/// [`LogFile`]. The file gets normal messages even when `inner` is quiet, and verbose ones when
+/// `inner` wants them too.
+pub(crate) struct Logged<'a> {
+    pub(crate) inner: Box<dyn Reporter + 'a>,
+    pub(crate) log: &'a LogFile,
+}
+
+impl Reporter for Logged<'_> {
+    fn verbosity(&self) -> Verbosity {
+        self.inner.verbosity().max(Verbosity::Normal)
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.inner.size_format()
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        self.log
+            .log(LogLevel::for_verbosity(verbosity), None, message);
+        if self.inner.verbosity() >= verbosity {
+            self.inner.message(verbosity, message);
+        }
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.log.log(LogLevel::Error, Some(path), message);
+        self.inner.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        if self.inner.verbosity() >= Verbosity::for_skip_reason(&why) {
+            let message = describe_skip(&why, self.size_format());
+            self.log.log(LogLevel::Skipped, Some(path), &message);
+        }
+        self.inner.file_skipped(path, why);
+    }
+
+    fn file_too_small(&self, path: &Path) {
+        self.inner.file_too_small(path);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        self.inner.file_task(path, size)
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        self.inner.file_compressed(path, bytes_in, bytes_out);
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        self.inner.files_queued(bytes);
+    }
+
+    fn work_dir_started(&self, dir: &Path) {
+        self.inner.work_dir_started(dir);
+    }
+
+    fn work_dir_finished(&self, dir: &Path, stats: Option<&CompressionStats>) {
+        self.inner.work_dir_finished(dir, stats);
+    }
+
+    fn total_known(&self, bytes: u64) {
+        self.inner.total_known(bytes);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
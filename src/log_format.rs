@@ -0,0 +1,146 @@
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Verbosity;
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum LogFormat {
+    /// Bare human-readable lines, such as `Compressed target/debug`.
+    #[default]
+    Plain,
+    /// Timestamped `key=value` pairs.
+    Logfmt,
+    /// One timestamped JSON object per line.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogLevel {
+    Info,
+    Verbose,
+    Skipped,
+    Error,
+}
+
+impl LogLevel {
+    pub(crate) fn for_verbosity(verbosity: Verbosity) -> Self {
+        if verbosity >= Verbosity::Verbose {
+            Self::Verbose
+        } else {
+            Self::Info
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Verbose => "verbose",
+            Self::Skipped => "skipped",
+            Self::Error => "error",
+        }
+    }
+
+    /// What plain lines put between the path and the message.
+    fn plain_prefix(self) -> &'static str {
+        match self {
+            Self::Info | Self::Verbose => "",
+            Self::Skipped => "Skipped: ",
+            Self::Error => "error: ",
+        }
+    }
+}
+
+impl LogFormat {
+    /// Renders one log line, timestamped with the current time unless this is [`LogFormat::Plain`].
+    pub fn format_line(self, level: LogLevel, path: Option<&Path>, message: &str) -> String {
+        self.format_line_at(SystemTime::now(), level, path, message)
+    }
+
+    pub(crate) fn format_line_at(
+        self,
+        time: SystemTime,
+        level: LogLevel,
+        path: Option<&Path>,
+        message: &str,
+    ) -> String {
+        match self {
+            Self::Plain => match path {
+                Some(path) => format!("{}: {}{message}", path.display(), level.plain_prefix()),
+                None => message.to_owned(),
+            },
+            Self::Logfmt => {
+                let mut line = format!("time={} level={}", rfc3339(time), level.name());
+                if let Some(path) = path {
+                    write!(line, " path={}", logfmt_value(&path.to_string_lossy())).unwrap();
+                }
+                write!(line, " msg={}", logfmt_value(message)).unwrap();
+                line
+            }
+            Self::Json => {
+                let json = |value: &str| serde_json::Value::from(value).to_string();
+                let mut line =
+                    format!(r#"{{"time":"{}","level":"{}""#, rfc3339(time), level.name());
+                if let Some(path) = path {
+                    write!(line, r#","path":{}"#, json(&path.to_string_lossy())).unwrap();
+                }
+                write!(line, r#","msg":{}}}"#, json(message)).unwrap();
+                line
+            }
+        }
+    }
+}
+
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control())
+    {
+        return value.to_owned();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{{{:04x}}}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `time` as UTC with millisecond precision, e.g. `2024-05-01T12:34:56.789Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Howard Hinnant's `civil_from_days`, restricted to dates after the epoch.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis(),
+    )
+}
@@ -7,8 +7,22 @@ fn main() {
     }
 
     let cli = cargo_apfs_compress::Cli::parse_from(args);
+    let log_format = cli.log_format;
+    // The first Ctrl-C lets files in flight finish, releases the locks, and still writes the
+    // summary and report; a second one quits on the spot.
+    let stop = cli.stop.clone();
+    let _ = ctrlc::set_handler(move || {
+        if stop.should_stop() {
+            std::process::exit(130);
+        }
+        stop.stop();
+    });
     if let Err(error) = cargo_apfs_compress::run(cli) {
-        eprintln!("{error:#}");
-        std::process::exit(1);
+        let message = error.chain().to_string();
+        eprintln!(
+            "{}",
+            log_format.format_line(cargo_apfs_compress::LogLevel::Error, None, &message)
+        );
+        std::process::exit(error.exit_code());
     }
 }
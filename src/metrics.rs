@@ -0,0 +1,64 @@
+/// Monotonic counters reported during a run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Counter {
+    /// Work directories that were compressed successfully.
+    DirsCompressed,
+    /// Work directories that failed.
+    DirsFailed,
+    /// Files handed to the compressor.
+    FilesProcessed,
+    /// Files the compressor decided to leave alone.
+    FilesSkipped,
+    /// Errors reported for a directory or an individual file.
+    Errors,
+    /// Reduction in on-disk size, in bytes.
+    BytesSaved,
+}
+
+impl Counter {
+    /// A stable, statsd-friendly name for this counter.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DirsCompressed => "dirs_compressed",
+            Self::DirsFailed => "dirs_failed",
+            Self::FilesProcessed => "files_processed",
+            Self::FilesSkipped => "files_skipped",
+            Self::Errors => "errors",
+            Self::BytesSaved => "bytes_saved",
+        }
+    }
+}
+
+/// Point-in-time values reported during a run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Gauge {
+    /// Number of work directories resolved for this run.
+    WorkDirs,
+}
+
+impl Gauge {
+    /// A stable, statsd-friendly name for this gauge.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::WorkDirs => "work_dirs",
+        }
+    }
+}
+
+/// Receives counters and gauges from a run, for embedders that want to forward them to a metrics
+/// system. Every method defaults to a no-op.
+///
+/// Methods may be called concurrently from directory workers and compressor threads.
+pub trait MetricsSink: Send + Sync {
+    fn increment(&self, _counter: Counter, _amount: u64) {}
+
+    fn gauge(&self, _gauge: Gauge, _value: u64) {}
+}
+
+/// A [`MetricsSink`] that discards everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {}
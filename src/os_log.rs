@@ -0,0 +1,83 @@
+//! Run summaries and errors in the macOS unified log, for auditing scheduled runs with
+//! `log show --predicate 'subsystem == "io.github.bgw.cargo-apfs-compress"'`.
+
+/// The subsystem and category every entry is logged under. Changing these breaks people's
+/// `log show` predicates.
+#[cfg(target_os = "macos")]
+const SUBSYSTEM: &str = "io.github.bgw.cargo-apfs-compress";
+#[cfg(target_os = "macos")]
+const CATEGORY: &str = "run";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum OsLogLevel {
+    Default,
+    Error,
+}
+
+/// Whether launchd started this process, as its parent. A process launchd adopted after its
+/// parent exited counts too, which only ever happens to scheduled runs in practice.
+pub(crate) fn under_launchd() -> bool {
+    // SAFETY: `getppid` has no preconditions.
+    cfg!(target_os = "macos") && unsafe { libc::getppid() } == 1
+}
+
+/// Logs `message` as `os_log_with_type(log, type, "%{public}s", message)` would. `os_log` is a C
+/// macro, so this calls `_os_log_impl`, which it expands to, with the argument buffer it builds.
+#[cfg(target_os = "macos")]
+pub(crate) fn log(level: OsLogLevel, message: &str) {
+    use std::ffi::{CString, c_char, c_void};
+
+    /// `OS_LOG_TYPE_DEFAULT` and `OS_LOG_TYPE_ERROR` from `<os/log.h>`.
+    const OS_LOG_TYPE_DEFAULT: u8 = 0x00;
+    const OS_LOG_TYPE_ERROR: u8 = 0x10;
+
+    // The unified log reads format strings out of this section of the image `__dso_handle`
+    // names, relative to it, rather than copying them.
+    #[unsafe(link_section = "__TEXT,__oslogstring,cstring_literals")]
+    static FORMAT: [u8; 11] = *b"%{public}s\0";
+
+    unsafe extern "C" {
+        static __dso_handle: c_void;
+        fn os_log_create(subsystem: *const c_char, category: *const c_char) -> *mut c_void;
+        fn os_release(object: *mut c_void);
+        fn _os_log_impl(
+            dso: *const c_void,
+            log: *mut c_void,
+            kind: u8,
+            format: *const c_char,
+            buffer: *mut u8,
+            size: u32,
+        );
+    }
+
+    let subsystem = CString::new(SUBSYSTEM).unwrap();
+    let category = CString::new(CATEGORY).unwrap();
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    // One public string argument: a summary byte saying an argument isn't a scalar, the argument
+    // count, then the argument's descriptor (string, public), size, and pointer.
+    let mut buffer = [0u8; 12];
+    buffer[..4].copy_from_slice(&[0x02, 0x01, 0x22, 0x08]);
+    buffer[4..].copy_from_slice(&(message.as_ptr() as u64).to_ne_bytes());
+    let kind = match level {
+        OsLogLevel::Default => OS_LOG_TYPE_DEFAULT,
+        OsLogLevel::Error => OS_LOG_TYPE_ERROR,
+    };
+    // SAFETY: The strings are NUL-terminated and outlive the calls, `buffer` is laid out as
+    // `os_log` lays out its arguments for `FORMAT`, and `log` is released once, after its last use.
+    unsafe {
+        let log = os_log_create(subsystem.as_ptr(), category.as_ptr());
+        _os_log_impl(
+            &raw const __dso_handle,
+            log,
+            kind,
+            FORMAT.as_ptr().cast(),
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+        );
+        os_release(log);
+    }
+}
+
+/// There is no unified log elsewhere, so this does nothing.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn log(_level: OsLogLevel, _message: &str) {}
@@ -0,0 +1,69 @@
+use std::path::{Component, Path};
+
+/// Selects the artifacts in a work dir that cargo produced for one workspace package.
+///
+/// Matching follows cargo's layout: final artifacts at the top level are named after their
+/// target (`foo`, `libfoo.rlib`, `foo.d`), intermediate ones in `deps/`, `examples/`, and
+/// `incremental/` carry a `-<hash>` suffix, and `build/` and `.fingerprint/` directories are
+/// named `<package>-<hash>`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PackageFilter {
+    package: String,
+    target_names: Vec<String>,
+}
+
+impl PackageFilter {
+    /// `target_names` are the package's cargo target names, as listed by `cargo metadata`.
+    pub fn new(package: impl Into<String>, target_names: impl IntoIterator<Item = String>) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        for name in target_names {
+            let crate_name = name.replace('-', "_");
+            if crate_name != name {
+                names.push(crate_name);
+            }
+            names.push(name);
+        }
+        Self {
+            package: package.into(),
+            target_names: names,
+        }
+    }
+
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    fn is_target(&self, name: &str) -> bool {
+        let name = name.strip_prefix("lib").unwrap_or(name);
+        self.target_names.iter().any(|target| target == name)
+    }
+
+    /// Whether `relative`, a path inside a work dir, belongs to this package.
+    pub(crate) fn matches(&self, relative: &Path) -> bool {
+        let mut components = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            });
+        let (Some(first), second) = (components.next(), components.next()) else {
+            return false;
+        };
+        let Some(second) = second else {
+            let stem = first.split('.').next().unwrap_or(first);
+            return self.is_target(stem);
+        };
+        let hashed_name = second
+            .split('.')
+            .next()
+            .and_then(|stem| stem.rsplit_once('-'))
+            .map(|(name, _hash)| name);
+        match first {
+            "build" | ".fingerprint" => hashed_name == Some(self.package.as_str()),
+            "deps" | "examples" | "incremental" => {
+                hashed_name.is_some_and(|name| self.is_target(name))
+            }
+            _ => false,
+        }
+    }
+}
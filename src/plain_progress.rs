@@ -0,0 +1,104 @@
+//! Line-based stand-in for the `indicatif` progress bars in `progress.rs`, used for
+//! `--progress plain` and `--progress none`, and always when the crate is built without the `cli`
+//! feature.
+
+use applesauce::progress::{SkipReason, Task};
+use std::path::Path;
+
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::log_format::{LogFormat, LogLevel};
+use crate::reporter::Reporter;
+use crate::summary::describe_skip;
+
+pub struct PlainProgress {
+    verbosity: Verbosity,
+    log_format: LogFormat,
+    size_format: SizeFormat,
+}
+
+impl PlainProgress {
+    pub fn new(verbosity: Verbosity) -> Self {
+        Self {
+            verbosity,
+            log_format: LogFormat::Plain,
+            size_format: SizeFormat::Human,
+        }
+    }
+
+    pub fn with_log_format(self, log_format: LogFormat) -> Self {
+        Self { log_format, ..self }
+    }
+
+    pub fn with_size_format(self, size_format: SizeFormat) -> Self {
+        Self {
+            size_format,
+            ..self
+        }
+    }
+}
+
+pub struct PlainTask {
+    verbosity: Verbosity,
+    log_format: LogFormat,
+}
+
+impl Reporter for PlainProgress {
+    fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.size_format
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        let level = LogLevel::for_verbosity(verbosity);
+        println!("{}", self.log_format.format_line(level, None, message));
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        let line = self
+            .log_format
+            .format_line(LogLevel::Error, Some(path), message);
+        eprintln!("{line}");
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        if self.verbosity >= Verbosity::for_skip_reason(&why) {
+            let line = self.log_format.format_line(
+                LogLevel::Skipped,
+                Some(path),
+                &describe_skip(&why, self.size_format),
+            );
+            println!("{line}");
+        }
+    }
+
+    fn file_task(&self, _path: &Path, _size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(PlainTask {
+            verbosity: self.verbosity,
+            log_format: self.log_format,
+        })
+    }
+}
+
+impl Task for PlainTask {
+    fn increment(&self, _amt: u64) {}
+
+    fn error(&self, message: &str) {
+        let line = self.log_format.format_line(LogLevel::Error, None, message);
+        eprintln!("{line}");
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        if self.verbosity >= Verbosity::Verbose {
+            let line = self.log_format.format_line(
+                LogLevel::Verbose,
+                Some(path),
+                "Not compressible enough, file grew",
+            );
+            println!("{line}");
+        }
+    }
+}
@@ -0,0 +1,679 @@
+//! What the machine is doing right now, for deciding whether this is a good time to compress.
+//!
+//! These shell out to the tools macOS ships with or use libSystem rather than linking IOKit, and
+//! report [`io::ErrorKind::Unsupported`] elsewhere.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "macos")]
+use std::process::Command;
+use std::time::Duration;
+
+use crate::disk::Storage;
+
+/// How long it has been since the last keyboard, mouse, or trackpad input.
+#[cfg(target_os = "macos")]
+pub(crate) fn hid_idle_time() -> io::Result<Duration> {
+    let output = Command::new("ioreg")
+        .args(["-c", "IOHIDSystem", "-d", "4", "-r", "-k", "HIDIdleTime"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ioreg failed with {}",
+            output.status
+        )));
+    }
+    parse_hid_idle_time(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::other("ioreg did not report HIDIdleTime"))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn hid_idle_time() -> io::Result<Duration> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "input idle time is only available on macOS",
+    ))
+}
+
+/// Finds `"HIDIdleTime" = <nanoseconds>` in `ioreg` output.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_hid_idle_time(ioreg: &str) -> Option<Duration> {
+    ioreg.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim().trim_start_matches(['|', ' ']) != "\"HIDIdleTime\"" {
+            return None;
+        }
+        value.trim().parse().ok().map(Duration::from_nanos)
+    })
+}
+
+/// The one-minute load average divided by the number of logical CPUs, so `1.0` means every core
+/// has had something to run.
+pub(crate) fn load_per_cpu() -> io::Result<f64> {
+    let mut loads = [0.0; 3];
+    // SAFETY: `loads` has room for the one sample requested.
+    if unsafe { libc::getloadavg(loads.as_mut_ptr(), 1) } != 1 {
+        return Err(io::Error::other("getloadavg failed"));
+    }
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    Ok(loads[0] / cpus as f64)
+}
+
+/// Whether the machine is running on battery rather than AC power.
+#[cfg(target_os = "macos")]
+pub(crate) fn on_battery() -> io::Result<bool> {
+    let output = Command::new("pmset").args(["-g", "batt"]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "pmset failed with {}",
+            output.status
+        )));
+    }
+    parse_power_source(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::other("pmset did not report a power source"))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn on_battery() -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the power source is only available on macOS",
+    ))
+}
+
+/// Reads `Now drawing from 'Battery Power'` from `pmset -g batt` output, returning whether that is
+/// the battery.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_power_source(pmset: &str) -> Option<bool> {
+    let source = pmset
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Now drawing from "))?;
+    Some(source.trim_matches('\'') == "Battery Power")
+}
+
+/// Whether macOS reports heavy thermal pressure or worse (what `NSProcessInfo` calls serious or
+/// critical), at which point it is already throttling to cool down.
+#[cfg(target_os = "macos")]
+pub(crate) fn under_thermal_pressure() -> io::Result<bool> {
+    use std::ffi::{c_char, c_int};
+
+    // From <notify.h> and <libkern/OSThermalNotification.h>.
+    const NOTIFY_STATUS_OK: u32 = 0;
+    const PRESSURE_LEVEL_NAME: &[u8] = b"com.apple.system.thermalpressurelevel\0";
+    const PRESSURE_LEVEL_HEAVY: u64 = 2;
+    unsafe extern "C" {
+        fn notify_register_check(name: *const c_char, out_token: *mut c_int) -> u32;
+        fn notify_get_state(token: c_int, state: *mut u64) -> u32;
+        fn notify_cancel(token: c_int) -> u32;
+    }
+
+    let mut token = 0;
+    let mut level = 0;
+    // SAFETY: The name is NUL-terminated, and the token is cancelled once its state is read.
+    let status = unsafe {
+        if notify_register_check(PRESSURE_LEVEL_NAME.as_ptr().cast(), &mut token)
+            != NOTIFY_STATUS_OK
+        {
+            return Err(io::Error::other("notify_register_check failed"));
+        }
+        let status = notify_get_state(token, &mut level);
+        notify_cancel(token);
+        status
+    };
+    if status != NOTIFY_STATUS_OK {
+        return Err(io::Error::other("notify_get_state failed"));
+    }
+    Ok(level >= PRESSURE_LEVEL_HEAVY)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn under_thermal_pressure() -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thermal pressure is only available on macOS",
+    ))
+}
+
+/// Whether Low Power Mode is on.
+#[cfg(target_os = "macos")]
+pub(crate) fn low_power_mode() -> io::Result<bool> {
+    let output = Command::new("pmset").arg("-g").output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "pmset failed with {}",
+            output.status
+        )));
+    }
+    Ok(parse_low_power_mode(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn low_power_mode() -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Low Power Mode is only available on macOS",
+    ))
+}
+
+/// Reads the ` lowpowermode 1` setting from `pmset -g` output. Macs without the setting never
+/// have Low Power Mode on.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_low_power_mode(pmset: &str) -> bool {
+    pmset.lines().any(|line| {
+        let mut words = line.split_whitespace();
+        words.next() == Some("lowpowermode") && words.next() == Some("1")
+    })
+}
+
+/// The kind of device the volume mounted at `mount_point` is on, from `diskutil info`.
+#[cfg(target_os = "macos")]
+pub(crate) fn storage(mount_point: &Path) -> io::Result<Storage> {
+    let output = Command::new("diskutil")
+        .arg("info")
+        .arg(mount_point)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "diskutil failed with {}",
+            output.status
+        )));
+    }
+    parse_storage(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        io::Error::other(format!(
+            "diskutil doesn't say what {} is on",
+            mount_point.display()
+        ))
+    })
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn storage(_mount_point: &Path) -> io::Result<Storage> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "storage details are only available on macOS",
+    ))
+}
+
+/// Reads the `Solid State:` and `Device Location:` lines of `diskutil info` output. Disk images
+/// and network shares have no `Solid State:` line, so give `None`.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_storage(diskutil: &str) -> Option<Storage> {
+    let field = |name: &str| {
+        diskutil.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    };
+    match field("Solid State")? {
+        "No" => Some(Storage::Rotational),
+        _ if field("Device Location") == Some("External") => Some(Storage::External),
+        _ => Some(Storage::Internal),
+    }
+}
+
+/// The local APFS snapshots of the volume mounted at `mount_point`, such as Time Machine's
+/// hourly ones, which keep the blocks of files rewritten since they were taken.
+#[cfg(target_os = "macos")]
+pub(crate) fn local_snapshots(mount_point: &Path) -> io::Result<Vec<String>> {
+    let output = Command::new("tmutil")
+        .arg("listlocalsnapshots")
+        .arg(mount_point)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tmutil failed with {}",
+            output.status
+        )));
+    }
+    Ok(parse_local_snapshots(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn local_snapshots(_mount_point: &Path) -> io::Result<Vec<String>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "local snapshots are only available on macOS",
+    ))
+}
+
+/// Reads snapshot names from `tmutil listlocalsnapshots` output, which lists one per line under a
+/// `Snapshots for disk /:` heading.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_local_snapshots(tmutil: &str) -> Vec<String> {
+    tmutil
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with(':'))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Files under `dir` that some process has open for writing, from `lsof`. Listing every open file
+/// on the system and filtering is much faster than `lsof +D`, which stats the whole tree.
+#[cfg(target_os = "macos")]
+pub(crate) fn open_for_writing(dir: &Path) -> io::Result<HashSet<PathBuf>> {
+    let output = Command::new("lsof")
+        .args(["-n", "-w", "-F", "an"])
+        .output()?;
+    // lsof exits 1 when some file couldn't be listed, which is normal without root.
+    if output.stdout.is_empty() && !output.status.success() {
+        return Err(io::Error::other(format!(
+            "lsof failed with {}",
+            output.status
+        )));
+    }
+    Ok(parse_lsof_writers(
+        &String::from_utf8_lossy(&output.stdout),
+        dir,
+    ))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn open_for_writing(_dir: &Path) -> io::Result<HashSet<PathBuf>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "checking for open files is only available on macOS",
+    ))
+}
+
+/// Reads the paths under `dir` opened for writing from `lsof -F an` output, where each open file
+/// is an `f` line and then an `a` line with its access mode (`r`, `w`, or `u` for both) and an
+/// `n` line with its name.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_lsof_writers(lsof: &str, dir: &Path) -> HashSet<PathBuf> {
+    let mut writers = HashSet::new();
+    let mut writing = false;
+    for line in lsof.lines() {
+        match line.split_at_checked(1) {
+            Some(("f", _)) => writing = false,
+            Some(("a", access)) => writing = matches!(access, "w" | "u"),
+            Some(("n", name)) if writing && Path::new(name).starts_with(dir) => {
+                writers.insert(PathBuf::from(name));
+            }
+            _ => {}
+        }
+    }
+    writers
+}
+
+/// Whether a process other than this one named `cargo` has `path` open, from `lsof`.
+#[cfg(target_os = "macos")]
+pub(crate) fn cargo_has_open(path: &Path) -> io::Result<bool> {
+    let output = Command::new("lsof")
+        .args(["-n", "-w", "-F", "pc"])
+        .arg(path)
+        .output()?;
+    // lsof exits 1 when no process has the file open.
+    if output.stdout.is_empty() && !output.status.success() && !output.stderr.is_empty() {
+        return Err(io::Error::other(format!(
+            "lsof failed with {}",
+            output.status
+        )));
+    }
+    Ok(parse_lsof_cargo(
+        &String::from_utf8_lossy(&output.stdout),
+        std::process::id(),
+    ))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn cargo_has_open(_path: &Path) -> io::Result<bool> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "checking for cargo processes is only available on macOS",
+    ))
+}
+
+/// Whether `lsof -F pc` output, a `p` line with each process's ID and then a `c` line with its
+/// command name, lists a `cargo` other than process `own`.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_lsof_cargo(lsof: &str, own: u32) -> bool {
+    let mut pid = None;
+    lsof.lines().any(|line| match line.split_at_checked(1) {
+        Some(("p", id)) => {
+            pid = id.parse::<u32>().ok();
+            false
+        }
+        Some(("c", command)) => command == "cargo" && pid.is_some_and(|pid| pid != own),
+        _ => false,
+    })
+}
+
+/// How many logical efficiency cores the CPU has, from the `hw.perflevel*` sysctls Apple Silicon
+/// has. Intel Macs, which have one kind of core, report [`io::ErrorKind::Unsupported`].
+#[cfg(target_os = "macos")]
+pub(crate) fn efficiency_cores() -> io::Result<usize> {
+    let levels = sysctl_u32(c"hw.nperflevels").unwrap_or(1);
+    if levels < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this Mac has no efficiency cores",
+        ));
+    }
+    // Level 0 is the performance cores and the last level the efficiency ones.
+    let name = std::ffi::CString::new(format!("hw.perflevel{}.logicalcpu", levels - 1))
+        .expect("sysctl name has no NUL");
+    Ok(sysctl_u32(&name)? as usize)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn efficiency_cores() -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "efficiency cores are only available on Apple Silicon",
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn sysctl_u32(name: &std::ffi::CStr) -> io::Result<u32> {
+    let mut value = 0u32;
+    let mut len = size_of::<u32>();
+    // SAFETY: `name` is NUL-terminated and `value` is as big as `len` says.
+    let result = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            (&raw mut value).cast(),
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// Puts this thread, and the threads it starts from now on, in the background QoS class, which
+/// macOS schedules on the efficiency cores only and gives lower I/O priority.
+#[cfg(target_os = "macos")]
+pub(crate) fn prefer_efficiency_cores() -> io::Result<()> {
+    // SAFETY: Only changes the calling thread's scheduling class.
+    let result =
+        unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0) };
+    match result {
+        0 => Ok(()),
+        error => Err(io::Error::from_raw_os_error(error)),
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn prefer_efficiency_cores() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "QoS classes are only available on macOS",
+    ))
+}
+
+/// `UF_COMPRESSED` from `<sys/stat.h>`.
+const UF_COMPRESSED: u32 = 0x20;
+
+/// The names of the entries in `dir` that have `UF_COMPRESSED` set, read for the whole directory
+/// at once with `getattrlistbulk` instead of opening each file and reading its decmpfs xattr.
+pub(crate) fn compressed_entries(dir: &Path) -> io::Result<HashSet<OsString>> {
+    Ok(entry_flags(dir)?
+        .into_iter()
+        .filter(|(_, flags)| flags.is_some_and(|flags| flags & UF_COMPRESSED != 0))
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// The names of the entries in `dir` known to have `UF_COMPRESSED` clear, read like
+/// [`compressed_entries`]. An entry the volume returned no flags for isn't among them.
+pub(crate) fn uncompressed_entries(dir: &Path) -> io::Result<HashSet<OsString>> {
+    Ok(entry_flags(dir)?
+        .into_iter()
+        .filter(|(_, flags)| flags.is_some_and(|flags| flags & UF_COMPRESSED == 0))
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// Every entry in `dir` with its `st_flags`, if the volume returned them.
+#[cfg(target_os = "macos")]
+fn entry_flags(dir: &Path) -> io::Result<Vec<(OsString, Option<u32>)>> {
+    use std::os::fd::AsRawFd;
+
+    let dir = std::fs::File::open(dir)?;
+    let mut attrs = libc::attrlist {
+        bitmapcount: libc::ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: libc::ATTR_CMN_RETURNED_ATTRS | libc::ATTR_CMN_NAME | libc::ATTR_CMN_FLAGS,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    let mut buf = vec![0u8; 256 * 1024];
+    let mut all = Vec::new();
+    loop {
+        // SAFETY: `attrs` is a valid attribute list and `buf` is writable for its whole length.
+        let count = unsafe {
+            libc::getattrlistbulk(
+                dir.as_raw_fd(),
+                (&raw mut attrs).cast(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                0,
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if count == 0 {
+            return Ok(all);
+        }
+        let entries = parse_bulk_entries(&buf, count as usize).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "malformed attribute buffer")
+        })?;
+        all.extend(entries);
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn entry_flags(_dir: &Path) -> io::Result<Vec<(OsString, Option<u32>)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "bulk attribute reads are only available on macOS",
+    ))
+}
+
+/// Whether `codesign --verify` accepts `path`'s code signature, with what it said if not.
+#[cfg(target_os = "macos")]
+pub(crate) fn verify_code_signature(path: &Path) -> io::Result<Result<(), String>> {
+    let output = Command::new("codesign")
+        .arg("--verify")
+        .arg(path)
+        .output()?;
+    if output.status.success() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_owned()))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn verify_code_signature(_path: &Path) -> io::Result<Result<(), String>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "code signatures are only checked on macOS",
+    ))
+}
+
+/// Makes `dst`, which must not exist, an APFS clone of `src`: a new file sharing its blocks,
+/// compressed or not, until either is written.
+#[cfg(target_os = "macos")]
+pub(crate) fn clone_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    /// `CLONE_NOFOLLOW` from `<sys/clonefile.h>`, which the libc crate doesn't have.
+    const CLONE_NOFOLLOW: u32 = 0x0001;
+    let src = std::ffi::CString::new(src.as_os_str().as_bytes())?;
+    let dst = std::ffi::CString::new(dst.as_os_str().as_bytes())?;
+    // SAFETY: both paths are NUL-terminated.
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), CLONE_NOFOLLOW) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn clone_file(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "APFS clones are only available on macOS",
+    ))
+}
+
+/// The names of `path`'s extended attributes, not following a symlink.
+#[cfg(target_os = "macos")]
+pub(crate) fn xattr_names(path: &Path) -> io::Result<Vec<OsString>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is NUL-terminated, and a null buffer asks for the size of the list.
+    let size =
+        unsafe { libc::listxattr(path.as_ptr(), std::ptr::null_mut(), 0, libc::XATTR_NOFOLLOW) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut names = vec![0u8; size as usize];
+    // SAFETY: `names` is writable for its whole length.
+    let size = unsafe {
+        libc::listxattr(
+            path.as_ptr(),
+            names.as_mut_ptr().cast(),
+            names.len(),
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+    Ok(names
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| std::ffi::OsStr::from_bytes(name).to_owned())
+        .collect())
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn xattr_names(_path: &Path) -> io::Result<Vec<OsString>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are only read on macOS",
+    ))
+}
+
+/// The value of `path`'s extended attribute `name`, not following a symlink.
+#[cfg(target_os = "macos")]
+pub(crate) fn xattr(path: &Path, name: &std::ffi::OsStr) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let name = std::ffi::CString::new(name.as_bytes())?;
+    // SAFETY: `path` and `name` are NUL-terminated, and a null buffer asks for the value's size.
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut value = vec![0u8; size as usize];
+    // SAFETY: `value` is writable for its whole length.
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    value.truncate(size as usize);
+    Ok(value)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn xattr(_path: &Path, _name: &std::ffi::OsStr) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are only read on macOS",
+    ))
+}
+
+/// Splits the first `count` entries out of a `getattrlistbulk` buffer requested with
+/// `ATTR_CMN_RETURNED_ATTRS | ATTR_CMN_NAME | ATTR_CMN_FLAGS`, as names and `st_flags`, `None`
+/// for an entry the volume returned no flags for.
+#[cfg(any(target_os = "macos", test))]
+pub(crate) fn parse_bulk_entries(buf: &[u8], count: usize) -> Option<Vec<(OsString, Option<u32>)>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // From <sys/attr.h>: the entry length, then the returned `attribute_set_t` (five groups), then
+    // each attribute in bit order.
+    const CMN_FLAGS: u32 = 0x0004_0000;
+    const NAME_REF: usize = 4 + 5 * 4;
+    let u32_at = |entry: &[u8], at: usize| {
+        entry
+            .get(at..at + 4)
+            .map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut entries = Vec::with_capacity(count);
+    let mut rest = buf;
+    for _ in 0..count {
+        let length = u32_at(rest, 0)? as usize;
+        let entry = rest.get(..length)?;
+        rest = &rest[length..];
+        let returned = u32_at(entry, 4)?;
+        // The name's offset is relative to its `attrreference_t`, and its length counts the NUL.
+        let name_offset = u32_at(entry, NAME_REF)? as i32;
+        let name_length = u32_at(entry, NAME_REF + 4)? as usize;
+        let flags = if returned & CMN_FLAGS == 0 {
+            None
+        } else {
+            Some(u32_at(entry, NAME_REF + 8)?)
+        };
+        let start = NAME_REF.checked_add_signed(name_offset as isize)?;
+        let name = entry.get(start..start + name_length)?;
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+        entries.push((std::ffi::OsStr::from_bytes(name).to_owned(), flags));
+    }
+    Some(entries)
+}
+
+/// Whether `path`, or the nearest of its ancestors that exists, is protected by System Integrity
+/// Protection (has the `SF_RESTRICTED` flag), so not even root may change it.
+#[cfg(target_os = "macos")]
+pub(crate) fn sip_protected(path: &Path) -> bool {
+    use std::os::macos::fs::MetadataExt;
+
+    const SF_RESTRICTED: u32 = 0x0008_0000;
+    path.ancestors()
+        .find_map(|path| std::fs::symlink_metadata(path).ok())
+        .is_some_and(|metadata| metadata.st_flags() & SF_RESTRICTED != 0)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn sip_protected(_path: &Path) -> bool {
+    false
+}
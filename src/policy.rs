@@ -0,0 +1,200 @@
+//! Per-directory policies: the `[apfs-compress.dir-policies]` config table, which maps dirs under
+//! each work dir to their own algorithm and level, or to being left alone.
+//!
+//! ```toml
+//! [apfs-compress.dir-policies]
+//! "deps" = { compression = "zlib", level = "high" }
+//! "incremental" = "skip"
+//! "build/*/out" = "lzvn"
+//! ```
+
+use applesauce::compressor::Kind;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{CompressionArg, WorkDirOptions};
+
+/// The level `level = "low"` means.
+const LOW_LEVEL: u32 = 1;
+/// The level `level = "high"` means, and the highest accepted.
+const HIGH_LEVEL: u32 = 9;
+
+/// What a [`DirPolicy`] does with the files under its dirs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PolicyAction {
+    /// Leave the dirs out of the walk.
+    Skip,
+    /// Compress every file with `compression`, whatever its size, at `level` if given. Only zlib
+    /// has levels; the others ignore it.
+    Compress {
+        compression: Kind,
+        level: Option<u32>,
+    },
+}
+
+impl std::fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skip => f.write_str("skip"),
+            Self::Compress { compression, level } => {
+                f.write_str(&compression.name().to_ascii_lowercase())?;
+                match level {
+                    Some(level) => write!(f, " level {level}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// A pattern for dirs under a work dir and what to do with them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirPolicy {
+    components: Vec<String>,
+    action: PolicyAction,
+}
+
+impl DirPolicy {
+    /// `pattern` is a `/`-separated path relative to the work dir, where `*` within a component
+    /// matches any run of characters, such as `build/*/out`. A trailing `/` is ignored. Returns
+    /// `None` for an empty pattern or one with `.` or `..` in it.
+    pub fn new(pattern: &str, action: PolicyAction) -> Option<Self> {
+        let components: Vec<String> = pattern
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        if components.is_empty()
+            || components
+                .iter()
+                .any(|component| component == "." || component == "..")
+        {
+            return None;
+        }
+        Some(Self { components, action })
+    }
+
+    pub fn pattern(&self) -> String {
+        self.components.join("/")
+    }
+
+    pub fn action(&self) -> PolicyAction {
+        self.action
+    }
+
+    /// Whether `dir`, relative to the work dir, is one of the pattern's dirs or inside one.
+    fn matches(&self, dir: &Path) -> bool {
+        let mut names = dir.components().map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+        self.components.iter().all(|pattern| {
+            names
+                .next()
+                .flatten()
+                .is_some_and(|name| glob(pattern.as_bytes(), name.as_encoded_bytes()))
+        })
+    }
+
+    /// Longer patterns are more specific, then those with fewer wildcards.
+    fn specificity(&self) -> (usize, usize) {
+        let literal = self
+            .components
+            .iter()
+            .filter(|component| !component.contains('*'))
+            .count();
+        (self.components.len(), literal)
+    }
+}
+
+/// The most specific of `policies` that applies to `dir`, relative to the work dir.
+pub(crate) fn for_dir<'a>(policies: &'a [DirPolicy], dir: &Path) -> Option<&'a DirPolicy> {
+    policies
+        .iter()
+        .filter(|policy| policy.matches(dir))
+        .max_by_key(|policy| policy.specificity())
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of bytes.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+        Some((byte, rest)) => name
+            .split_first()
+            .is_some_and(|(first, name)| first == byte && glob(rest, name)),
+    }
+}
+
+/// Parses one `dir-policies` value: `"skip"`, an algorithm name, or a table with `compression` and
+/// optionally `level`, which is 1 to 9, `"low"`, or `"high"`.
+pub(crate) fn parse_action(value: &toml::Value) -> Option<PolicyAction> {
+    let kind = |name: &str| CompressionArg::from_name(name).map(CompressionArg::to_kind);
+    match value {
+        toml::Value::String(text) if text == "skip" => Some(PolicyAction::Skip),
+        toml::Value::String(text) => Some(PolicyAction::Compress {
+            compression: kind(text)?,
+            level: None,
+        }),
+        toml::Value::Table(table) => {
+            if table
+                .keys()
+                .any(|key| key != "compression" && key != "level")
+            {
+                return None;
+            }
+            let level = match table.get("level") {
+                None => None,
+                Some(toml::Value::String(text)) if text == "low" => Some(LOW_LEVEL),
+                Some(toml::Value::String(text)) if text == "high" => Some(HIGH_LEVEL),
+                Some(toml::Value::Integer(level)) => Some(
+                    u32::try_from(*level)
+                        .ok()
+                        .filter(|level| (LOW_LEVEL..=HIGH_LEVEL).contains(level))?,
+                ),
+                Some(_) => return None,
+            };
+            Some(PolicyAction::Compress {
+                compression: kind(table.get("compression")?.as_str()?)?,
+                level,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Splits `paths` in `dir` into groups to compress with the same options, by the policy for each
+/// file's parent dir. Files no policy covers keep `options`.
+pub(crate) fn split(
+    dir: &Path,
+    paths: &[PathBuf],
+    options: &WorkDirOptions,
+) -> Vec<(WorkDirOptions, Vec<PathBuf>)> {
+    let mut groups: Vec<(Option<PolicyAction>, Vec<PathBuf>)> = Vec::new();
+    for path in paths {
+        let parent = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(dir).ok())
+            .unwrap_or(Path::new(""));
+        let key = for_dir(&options.dir_policies, parent)
+            .map(DirPolicy::action)
+            .filter(|action| *action != PolicyAction::Skip);
+        match groups.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, group)) => group.push(path.clone()),
+            None => groups.push((key, vec![path.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(key, paths)| {
+            let mut options = options.clone();
+            if let Some(PolicyAction::Compress { compression, level }) = key {
+                options.compression = compression;
+                options.small_file_compression = None;
+                if let Some(level) = level {
+                    options.compression_level = level;
+                }
+            }
+            (options, paths)
+        })
+        .collect()
+}
@@ -6,8 +6,15 @@ use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressState, Progre
 use std::fmt;
 use std::path::Path;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::log_format::{LogFormat, LogLevel};
+use crate::reporter::Reporter;
+use crate::summary::describe_skip;
+
 /// Initial delay to wait before checking the expected remaining time
 ///
 /// See also [`MIN_ETA`]
@@ -19,6 +26,12 @@ const DELAY: Duration = Duration::from_millis(100);
 /// immediately finishes
 const MIN_ETA: Duration = Duration::from_secs(1);
 
+/// Smallest file to get a bar of its own
+///
+/// Smaller files finish well within [`MIN_ETA`] and would never be
+/// attached, so on dirs of many tiny files only the total bar is updated
+const SINGLE_BAR_MIN_SIZE: u64 = 1024 * 1024;
+
 fn truncate_path(path: &Path, max_len: usize) -> String {
     let text = path.display().to_string();
     if text.chars().count() <= max_len {
@@ -39,12 +52,37 @@ fn truncate_path(path: &Path, max_len: usize) -> String {
     out
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
-pub enum Verbosity {
-    Quiet,
-    #[default]
-    Normal,
-    Verbose,
+fn smoothed_eta(s: &ProgressState, w: &mut dyn fmt::Write) {
+    match (s.pos(), s.len()) {
+        (pos, Some(len)) if pos != 0 => write!(
+            w,
+            "{:#}",
+            HumanDuration(Duration::from_millis(
+                (s.elapsed().as_millis() * (len as u128 - pos as u128) / (pos as u128)) as u64
+            ))
+        )
+        .unwrap(),
+        _ => write!(w, "-").unwrap(),
+    }
+}
+
+/// Styles for the total bar and the per-file bars.
+fn bar_styles(size_format: SizeFormat) -> (ProgressStyle, ProgressStyle) {
+    let counts = match size_format {
+        SizeFormat::Human => "{bytes:>11}/{total_bytes:<11}",
+        SizeFormat::Bytes => "{pos:>11}/{len:<11}",
+    };
+    let total_style = ProgressStyle::with_template(&format!(
+        "{{prefix:>25.bold}} {{wide_bar:.green}} {counts} {{smoothed_eta:6}}"
+    ))
+    .unwrap()
+    .with_key("smoothed_eta", smoothed_eta);
+    let style = ProgressStyle::with_template(&format!(
+        "{{prefix:>25.dim}} {{wide_bar}} {counts} {{smoothed_eta:6}}"
+    ))
+    .unwrap()
+    .with_key("smoothed_eta", smoothed_eta);
+    (total_style, style)
 }
 
 pub struct ProgressBars {
@@ -52,14 +90,19 @@ pub struct ProgressBars {
     total_bar: ProgressBar,
     bars: MultiProgress,
     verbosity: Verbosity,
+    log_format: LogFormat,
+    size_format: SizeFormat,
+    /// Set once a pre-scan has fixed the total bar's length.
+    total_fixed: AtomicBool,
 }
 
 impl ProgressBars {
+    /// Prints above every bar, however many files have bars of their own at the moment.
     fn print_stdout_line(&self, message: String) {
         if self.total_bar.is_hidden() {
             println!("{message}");
         } else {
-            self.total_bar.println(message);
+            let _ = self.bars.println(message);
         }
     }
 
@@ -67,30 +110,7 @@ impl ProgressBars {
         if self.total_bar.is_hidden() {
             eprintln!("{message}");
         } else {
-            self.total_bar.println(message);
-        }
-    }
-
-    pub fn finish(&self) {
-        let _ = self.bars.clear();
-        self.total_bar.finish();
-    }
-
-    pub fn println_normal<F>(&self, message: F)
-    where
-        F: FnOnce() -> String,
-    {
-        if self.verbosity >= Verbosity::Normal {
-            self.print_stdout_line(message());
-        }
-    }
-
-    pub fn println_verbose<F>(&self, message: F)
-    where
-        F: FnOnce() -> String,
-    {
-        if self.verbosity >= Verbosity::Verbose {
-            self.print_stdout_line(message());
+            let _ = self.bars.println(message);
         }
     }
 }
@@ -103,36 +123,14 @@ impl ProgressBars {
                 total_bar: ProgressBar::hidden(),
                 bars: MultiProgress::new(),
                 verbosity,
+                log_format: LogFormat::Plain,
+                size_format: SizeFormat::Human,
+                total_fixed: AtomicBool::new(false),
             };
         }
 
         let bars = MultiProgress::new();
-        let smoothed_eta = |s: &ProgressState, w: &mut dyn fmt::Write| match (s.pos(), s.len()) {
-            (pos, Some(len)) if pos != 0 => write!(
-                w,
-                "{:#}",
-                HumanDuration(Duration::from_millis(
-                    (s.elapsed().as_millis() * (len as u128 - pos as u128) / (pos as u128)) as u64
-                ))
-            )
-            .unwrap(),
-            _ => write!(w, "-").unwrap(),
-        };
-        #[allow(unknown_lints)] // TODO: Remove this once this clippy check is on stable
-        #[allow(clippy::literal_string_with_formatting_args)]
-        let total_style = ProgressStyle::with_template(
-            "{prefix:>25.bold} {wide_bar:.green} {bytes:>11}/{total_bytes:<11} {smoothed_eta:6}",
-        )
-        .unwrap()
-        .with_key("smoothed_eta", smoothed_eta);
-
-        #[allow(unknown_lints)] // TODO: Remove this once this clippy check is on stable
-        #[allow(clippy::literal_string_with_formatting_args)]
-        let style = ProgressStyle::with_template(
-            "{prefix:>25.dim} {wide_bar} {bytes:>11}/{total_bytes:<11} {smoothed_eta:6}",
-        )
-        .unwrap()
-        .with_key("smoothed_eta", smoothed_eta);
+        let (total_style, style) = bar_styles(SizeFormat::Human);
 
         let total_bar = bars
             .add(ProgressBar::new(0))
@@ -144,6 +142,23 @@ impl ProgressBars {
             total_bar,
             bars,
             verbosity,
+            log_format: LogFormat::Plain,
+            size_format: SizeFormat::Human,
+            total_fixed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn with_log_format(self, log_format: LogFormat) -> Self {
+        Self { log_format, ..self }
+    }
+
+    pub fn with_size_format(self, size_format: SizeFormat) -> Self {
+        let (total_style, style) = bar_styles(size_format);
+        self.total_bar.set_style(total_style);
+        Self {
+            style,
+            size_format,
+            ..self
         }
     }
 
@@ -164,13 +179,17 @@ enum State {
 
 pub struct ProgressWithTotal {
     total: ProgressBar,
-    single: ProgressBar,
+    single: Option<ProgressBar>,
     state: Mutex<State>,
     verbosity: Verbosity,
+    log_format: LogFormat,
 }
 
 impl ProgressWithTotal {
     fn maybe_attach(&self) {
+        let Some(pb) = &self.single else {
+            return;
+        };
         let mut state = self.state.lock().unwrap();
         let now = Instant::now();
         if let State::Unattached {
@@ -179,7 +198,6 @@ impl ProgressWithTotal {
         } = *state
         {
             let first_tick = *first_tick.get_or_insert(now);
-            let pb = &self.single;
             let elapsed = now.saturating_duration_since(first_tick);
             if elapsed >= DELAY {
                 let length = pb.length().unwrap_or(1);
@@ -198,38 +216,42 @@ impl Progress for ProgressBars {
     type Task = ProgressWithTotal;
 
     fn error(&self, path: &Path, message: &str) {
-        self.print_stderr_line(format!("{}: error: {message}", path.display()))
+        self.print_stderr_line(
+            self.log_format
+                .format_line(LogLevel::Error, Some(path), message),
+        )
     }
 
     fn file_skipped(&self, path: &Path, why: SkipReason) {
-        let required_verbosity = match why {
-            SkipReason::NotFile
-            | SkipReason::AlreadyCompressed
-            | SkipReason::NotCompressed
-            | SkipReason::EmptyFile
-            // hard links are common in target directories -- ignore them by default
-            | SkipReason::HardLink => Verbosity::Verbose,
-            SkipReason::TooLarge(_)
-            | SkipReason::ReadError(_)
-            | SkipReason::ZfsFilesystem
-            | SkipReason::HasRequiredXattr
-            | SkipReason::FsNotSupported => Verbosity::Normal,
-        };
-        if self.verbosity >= required_verbosity {
-            self.print_stdout_line(format!("{}: Skipped: {why}", path.display()))
+        // A pre-scanned total includes skipped files, so they count towards it as done.
+        if self.total_fixed.load(Ordering::Relaxed)
+            && !matches!(why, SkipReason::NotFile)
+            && let Ok(metadata) = std::fs::symlink_metadata(path)
+        {
+            self.total_bar.inc(metadata.len());
+        }
+        if self.verbosity >= Verbosity::for_skip_reason(&why) {
+            self.print_stdout_line(self.log_format.format_line(
+                LogLevel::Skipped,
+                Some(path),
+                &describe_skip(&why, self.size_format),
+            ))
         }
     }
 
     fn file_task(&self, path: &Path, size: u64) -> Self::Task {
-        let prefix = truncate_path(path, self.prefix_len());
-
         let total = self.total_bar.clone();
-        let single = ProgressBar::hidden()
-            .with_style(self.style.clone())
-            .with_prefix(prefix);
+        let single = (size >= SINGLE_BAR_MIN_SIZE).then(|| {
+            let single = ProgressBar::hidden()
+                .with_style(self.style.clone())
+                .with_prefix(truncate_path(path, self.prefix_len()));
+            single.set_length(size);
+            single
+        });
 
-        single.set_length(size);
-        total.inc_length(size);
+        if !self.total_fixed.load(Ordering::Relaxed) {
+            total.inc_length(size);
+        }
         ProgressWithTotal {
             total,
             single,
@@ -238,24 +260,73 @@ impl Progress for ProgressBars {
                 first_tick: None,
             }),
             verbosity: self.verbosity,
+            log_format: self.log_format,
         }
     }
 }
 
+impl Reporter for ProgressBars {
+    fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.size_format
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        let level = LogLevel::for_verbosity(verbosity);
+        self.print_stdout_line(self.log_format.format_line(level, None, message));
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        Progress::error(self, path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        Progress::file_skipped(self, path, why);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(Progress::file_task(self, path, size))
+    }
+
+    fn total_known(&self, bytes: u64) {
+        self.total_fixed.store(true, Ordering::Relaxed);
+        self.total_bar.set_length(bytes);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.bars.suspend(f);
+    }
+
+    fn finish(&self) {
+        let _ = self.bars.clear();
+        self.total_bar.finish();
+    }
+}
+
 impl Task for ProgressWithTotal {
     fn increment(&self, amt: u64) {
         self.total.inc(amt);
-        self.single.inc(amt);
-        self.maybe_attach();
+        if let Some(single) = &self.single {
+            single.inc(amt);
+            self.maybe_attach();
+        }
     }
 
     fn error(&self, message: &str) {
-        self.total.println(message);
+        self.total
+            .println(self.log_format.format_line(LogLevel::Error, None, message));
     }
 
     fn not_compressible_enough(&self, path: &Path) {
         if self.verbosity >= Verbosity::Verbose {
-            let message = format!("{}: Not compressible enough, file grew", path.display());
+            let message = self.log_format.format_line(
+                LogLevel::Verbose,
+                Some(path),
+                "Not compressible enough, file grew",
+            );
             self.total.println(message);
         }
     }
@@ -0,0 +1,411 @@
+//! `--ratio-cache`: how well each class of file, by extension and size, has compressed in a work
+//! dir on earlier runs, kept in the work dir. Classes that have never saved much are left out of
+//! the walk, and classes that were compressed with more than one algorithm use whichever saved
+//! the most.
+
+use applesauce::compressor::Kind;
+use applesauce::progress::{SkipReason, Task};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::error::{Error, Result};
+use crate::policy::{self, PolicyAction};
+use crate::reporter::Reporter;
+use crate::{CompressionArg, WorkDirOptions, wanted_kind};
+
+/// JSON written in the work dir after each run with `--ratio-cache`.
+pub(crate) const RATIOS_NAME: &str = ".apfs-compress-ratios";
+
+/// How many files of a class must have been compressed with an algorithm before the cache acts on
+/// what they saved.
+const MIN_FILES: u64 = 16;
+
+/// Classes that saved less than this much of their size are left out of the walk.
+const NEVER_COMPRESSES: f64 = 0.02;
+
+/// Files with the same extension (lowercased, `""` for none) whose sizes share a power of two.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) struct Class {
+    extension: String,
+    /// Sizes from `2^(bucket - 1)` up to `2^bucket`.
+    bucket: u32,
+}
+
+impl Class {
+    pub(crate) fn of(path: &Path, size: u64) -> Self {
+        Self {
+            extension: path
+                .extension()
+                .map(OsStr::to_string_lossy)
+                .unwrap_or_default()
+                .to_ascii_lowercase(),
+            bucket: u64::BITS - size.leading_zeros(),
+        }
+    }
+
+    fn describe(&self, sizes: SizeFormat) -> String {
+        let extension = if self.extension.is_empty() {
+            "(none)"
+        } else {
+            &self.extension
+        };
+        let low = 1u64.checked_shl(self.bucket.saturating_sub(1)).unwrap_or(0);
+        let high = 1u64.checked_shl(self.bucket).unwrap_or(u64::MAX);
+        format!(
+            "{extension} {}-{}",
+            sizes.format(low.min(high)),
+            sizes.format(high)
+        )
+    }
+}
+
+/// What the files of a class compressed with one algorithm came to, in allocated bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Observed {
+    pub(crate) files: u64,
+    pub(crate) bytes_in: u64,
+    pub(crate) bytes_out: u64,
+}
+
+impl Observed {
+    fn saved(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 0.0;
+        }
+        self.bytes_in.saturating_sub(self.bytes_out) as f64 / self.bytes_in as f64
+    }
+}
+
+/// One line of the saved JSON.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    extension: String,
+    bucket: u32,
+    compression: CompressionArg,
+    #[serde(flatten)]
+    observed: Observed,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct RatioCache {
+    classes: BTreeMap<(Class, CompressionArg), Observed>,
+}
+
+impl RatioCache {
+    /// The cache saved in `dir`, or an empty one if there is none or it doesn't parse.
+    pub(crate) fn load(dir: &Path) -> Self {
+        let entries: Vec<Entry> = fs::read_to_string(dir.join(RATIOS_NAME))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let mut cache = Self::default();
+        for entry in entries {
+            let class = Class {
+                extension: entry.extension,
+                bucket: entry.bucket,
+            };
+            cache
+                .classes
+                .insert((class, entry.compression), entry.observed);
+        }
+        cache
+    }
+
+    pub(crate) fn save(&self, dir: &Path) -> Result<()> {
+        let entries: Vec<Entry> = self
+            .classes
+            .iter()
+            .map(|((class, compression), observed)| Entry {
+                extension: class.extension.clone(),
+                bucket: class.bucket,
+                compression: *compression,
+                observed: *observed,
+            })
+            .collect();
+        let path = dir.join(RATIOS_NAME);
+        let json = serde_json::to_string(&entries).map_err(io::Error::other);
+        json.and_then(|json| fs::write(&path, json))
+            .map_err(|source| Error::RatioCache { path, source })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, class: Class, kind: Kind, bytes_in: u64, bytes_out: u64) {
+        let Some(compression) = compression_arg(kind) else {
+            return;
+        };
+        let observed = self.classes.entry((class, compression)).or_default();
+        observed.files += 1;
+        observed.bytes_in += bytes_in;
+        observed.bytes_out += bytes_out;
+    }
+
+    /// Adds everything `other` observed to this cache.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (key, seen) in &other.classes {
+            let observed = self.classes.entry(key.clone()).or_default();
+            observed.files += seen.files;
+            observed.bytes_in += seen.bytes_in;
+            observed.bytes_out += seen.bytes_out;
+        }
+    }
+
+    /// What each algorithm with at least [`MIN_FILES`] files of `class` saved.
+    fn known(&self, class: &Class) -> impl Iterator<Item = (CompressionArg, &Observed)> {
+        CompressionArg::ALL
+            .into_iter()
+            .filter_map(move |compression| {
+                self.classes
+                    .get(&(class.clone(), compression))
+                    .filter(|observed| observed.files >= MIN_FILES)
+                    .map(|observed| (compression, observed))
+            })
+    }
+
+    /// Whether every algorithm tried on enough files of `class` saved less than
+    /// [`NEVER_COMPRESSES`].
+    pub(crate) fn never_compresses(&self, class: &Class) -> bool {
+        let mut known = self.known(class).peekable();
+        known.peek().is_some() && known.all(|(_, observed)| observed.saved() < NEVER_COMPRESSES)
+    }
+
+    /// The algorithm that saved the most on `class`, if more than one has been tried on enough of
+    /// its files.
+    pub(crate) fn best(&self, class: &Class) -> Option<Kind> {
+        let known: Vec<_> = self.known(class).collect();
+        if known.len() < 2 {
+            return None;
+        }
+        known
+            .into_iter()
+            .max_by(|a, b| a.1.saved().total_cmp(&b.1.saved()))
+            .map(|(compression, _)| compression.into())
+    }
+}
+
+/// The flag value for `kind`, if there is one.
+pub(crate) fn compression_arg(kind: Kind) -> Option<CompressionArg> {
+    CompressionArg::ALL
+        .into_iter()
+        .find(|&compression| Kind::from(compression) == kind)
+}
+
+/// `paths` under `dir` grouped by the algorithm `cache` picks for them, each with `options` set to
+/// use it. Files the cache has no preference for, and files under a dir policy that names an
+/// algorithm, keep `options` as they are.
+pub(crate) fn split(
+    dir: &Path,
+    paths: &[PathBuf],
+    options: &WorkDirOptions,
+    cache: &RatioCache,
+) -> Vec<(WorkDirOptions, Vec<PathBuf>)> {
+    let mut groups: Vec<(Option<Kind>, Vec<PathBuf>)> = Vec::new();
+    for path in paths {
+        let parent = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(dir).ok())
+            .unwrap_or(Path::new(""));
+        let pinned = policy::for_dir(&options.dir_policies, parent)
+            .is_some_and(|policy| matches!(policy.action(), PolicyAction::Compress { .. }));
+        let best = fs::symlink_metadata(path)
+            .ok()
+            .filter(|_| !pinned)
+            .and_then(|metadata| cache.best(&Class::of(path, metadata.len())));
+        match groups.iter_mut().find(|(kind, _)| *kind == best) {
+            Some((_, group)) => group.push(path.clone()),
+            None => groups.push((best, vec![path.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(kind, paths)| match kind {
+            Some(kind) => {
+                let options = WorkDirOptions {
+                    compression: kind,
+                    small_file_compression: None,
+                    ..options.clone()
+                };
+                (options, paths)
+            }
+            None => (options.clone(), paths),
+        })
+        .collect()
+}
+
+/// A [`Reporter`] that passes everything on to `inner` and records what each file compressed to
+/// in `cache`, as compressed with `options`. Files that didn't compress well enough count as
+/// having saved nothing.
+pub(crate) struct Recording<'a> {
+    pub(crate) inner: &'a dyn Reporter,
+    pub(crate) options: &'a WorkDirOptions,
+    pub(crate) cache: Arc<Mutex<RatioCache>>,
+}
+
+impl Recording<'_> {
+    fn record(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return;
+        };
+        let kind = wanted_kind(self.options, metadata.len());
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record(Class::of(path, metadata.len()), kind, bytes_in, bytes_out);
+    }
+}
+
+impl Reporter for Recording<'_> {
+    fn verbosity(&self) -> Verbosity {
+        self.inner.verbosity()
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.inner.size_format()
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        self.inner.message(verbosity, message);
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.inner.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        self.inner.file_skipped(path, why);
+    }
+
+    fn file_too_small(&self, path: &Path) {
+        self.inner.file_too_small(path);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(RecordingTask {
+            inner: self.inner.file_task(path, size),
+            recorded: Box::new({
+                let cache = Arc::clone(&self.cache);
+                let kind = wanted_kind(self.options, size);
+                move |path: &Path| {
+                    let allocated = crate::disk::allocated_size(path).unwrap_or(size);
+                    cache
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .record(Class::of(path, size), kind, allocated, allocated);
+                }
+            }),
+        })
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        self.record(path, bytes_in, bytes_out);
+        self.inner.file_compressed(path, bytes_in, bytes_out);
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        self.inner.files_queued(bytes);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+}
+
+struct RecordingTask {
+    inner: Box<dyn Task + Send + Sync>,
+    recorded: Box<dyn Fn(&Path) + Send + Sync>,
+}
+
+impl Task for RecordingTask {
+    fn increment(&self, amt: u64) {
+        self.inner.increment(amt);
+    }
+
+    fn error(&self, message: &str) {
+        self.inner.error(message);
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        (self.recorded)(path);
+        self.inner.not_compressible_enough(path);
+    }
+}
+
+/// `analyze ratios`: what the `--ratio-cache` of each work dir has seen, by class and algorithm,
+/// most files first, and which classes it leaves out.
+pub(crate) fn stats(dirs: &[PathBuf], sizes: SizeFormat) -> String {
+    let mut cache = RatioCache::default();
+    for dir in dirs {
+        cache.merge(&RatioCache::load(dir));
+    }
+    if cache.is_empty() {
+        return format!(
+            "No compression ratios recorded in {} work dir(s); runs with --ratio-cache record them.",
+            dirs.len()
+        );
+    }
+    let mut rows: Vec<_> = cache
+        .classes
+        .iter()
+        .map(|((class, compression), observed)| {
+            let note = if cache.never_compresses(class) {
+                ", skipped"
+            } else if cache.best(class) == Some((*compression).into()) {
+                ", preferred"
+            } else {
+                ""
+            };
+            (class.describe(sizes), *compression, *observed, note)
+        })
+        .collect();
+    rows.sort_by(|a, b| b.2.files.cmp(&a.2.files).then_with(|| a.0.cmp(&b.0)));
+    let width = rows
+        .iter()
+        .take(crate::analyze::TOP)
+        .map(|(class, ..)| class.len())
+        .max()
+        .unwrap_or(0);
+    let mut text = format!(
+        "Compression ratios in {} work dir(s) ({} class(es)):",
+        dirs.len(),
+        rows.len()
+    );
+    for (class, compression, observed, note) in rows.iter().take(crate::analyze::TOP) {
+        text.push_str(&format!(
+            "\n  {class:<width$}  {:<5}  {} file(s), {} -> {}, saved {:.0}%{note}",
+            compression.name(),
+            observed.files,
+            sizes.format(observed.bytes_in),
+            sizes.format(observed.bytes_out),
+            observed.saved() * 100.0
+        ));
+    }
+    if rows.len() > crate::analyze::TOP {
+        text.push_str(&format!(
+            "\n  ... and {} more",
+            rows.len() - crate::analyze::TOP
+        ));
+    }
+    text
+}
+
+/// Merged into the dir's saved cache and written back once the dir is done.
+pub(crate) fn finish(dir: &Path, observed: &Mutex<RatioCache>) -> Result<()> {
+    let observed = observed
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if observed.is_empty() {
+        return Ok(());
+    }
+    let mut cache = RatioCache::load(dir);
+    cache.merge(&observed);
+    cache.save(dir)
+}
@@ -0,0 +1,398 @@
+//! `--report`: a file describing what a run did, for people who don't read its logs.
+
+#[cfg(feature = "cli")]
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::artifact::ArtifactKind;
+use crate::disk::SizeFormat;
+use crate::stats::CompressionStats;
+use crate::summary::{self, IncompressibleSummary, RunSummary};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(ValueEnum))]
+pub enum ReportFormat {
+    /// A self-contained page with sortable per-dir and per-extension tables and a savings chart.
+    #[default]
+    Html,
+}
+
+/// How much space the files with one extension take, logically and on disk.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ExtensionUsage {
+    /// Lowercase and without the dot; empty for files without an extension.
+    pub(crate) extension: String,
+    /// Extensionless executables, such as cargo's final binaries and build scripts, which are
+    /// counted apart from other extensionless files.
+    pub(crate) binary: bool,
+    pub(crate) files: u64,
+    pub(crate) size: u64,
+    pub(crate) on_disk: u64,
+}
+
+/// Groups `files` by extension, largest first. Files that can't be read are left out.
+pub(crate) fn extension_usage(files: &[PathBuf]) -> Vec<ExtensionUsage> {
+    let mut by_extension: HashMap<(String, bool), ExtensionUsage> = HashMap::new();
+    for path in files {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            continue;
+        };
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let binary = extension.is_empty() && metadata.mode() & 0o111 != 0;
+        let usage = by_extension
+            .entry((extension.clone(), binary))
+            .or_insert_with(|| ExtensionUsage {
+                extension,
+                binary,
+                ..ExtensionUsage::default()
+            });
+        usage.files += 1;
+        usage.size += metadata.len();
+        usage.on_disk += metadata.blocks() * 512;
+    }
+    let mut usage: Vec<_> = by_extension.into_values().collect();
+    usage.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.extension.cmp(&b.extension))
+            .then_with(|| a.binary.cmp(&b.binary))
+    });
+    usage
+}
+
+/// One work dir's stats and how long it took.
+pub(crate) struct DirResult {
+    pub(crate) dir: PathBuf,
+    pub(crate) stats: CompressionStats,
+    pub(crate) elapsed: Duration,
+}
+
+pub(crate) struct Report<'a> {
+    pub(crate) summary: &'a RunSummary,
+    pub(crate) dirs: &'a [DirResult],
+    pub(crate) extensions: &'a [ExtensionUsage],
+    /// Stats of the files compressed, by artifact kind.
+    pub(crate) kinds: &'a [(ArtifactKind, CompressionStats)],
+    pub(crate) incompressible: &'a IncompressibleSummary,
+}
+
+impl Report<'_> {
+    pub(crate) fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Html => self.html(),
+        }
+    }
+
+    fn html(&self) -> String {
+        let sizes = self.summary.sizes;
+        let mut html = String::from(HTML_HEAD);
+        writeln!(
+            html,
+            "<h1>cargo apfs-compress</h1>\n<p>{}</p>",
+            escape(&self.summary.to_string())
+        )
+        .unwrap();
+
+        html.push_str("<h2>Savings by directory</h2>\n");
+        html.push_str(&savings_chart(self.dirs, sizes));
+
+        html.push_str("<h2>Directories</h2>\n");
+        let rows = self.dirs.iter().map(|result| {
+            let stats = &result.stats;
+            vec![
+                text_cell(&result.dir.display().to_string()),
+                count_cell(stats.files_compressed),
+                count_cell(stats.files_skipped),
+                size_cell(stats.bytes_in, sizes),
+                size_cell(stats.bytes_out, sizes),
+                size_cell(stats.bytes_saved(), sizes),
+                Cell {
+                    text: escape(&summary::format_elapsed(result.elapsed)),
+                    sort_key: result.elapsed.as_millis().to_string(),
+                },
+                throughput_cell(stats, result.elapsed, sizes),
+            ]
+        });
+        table(
+            &[
+                "Directory",
+                "Compressed",
+                "Skipped",
+                "Before",
+                "After",
+                "Saved",
+                "Time",
+                "Throughput",
+            ],
+            rows,
+            &mut html,
+        );
+
+        html.push_str("<h2>Extensions</h2>\n");
+        let rows = self.extensions.iter().map(|usage| {
+            let name = if usage.binary {
+                "(binary)".to_owned()
+            } else if usage.extension.is_empty() {
+                "(none)".to_owned()
+            } else {
+                format!(".{}", usage.extension)
+            };
+            vec![
+                text_cell(&name),
+                count_cell(usage.files),
+                size_cell(usage.size, sizes),
+                size_cell(usage.on_disk, sizes),
+                size_cell(usage.size.saturating_sub(usage.on_disk), sizes),
+                ratio_cell(usage.size, usage.on_disk),
+            ]
+        });
+        table(
+            &["Extension", "Files", "Size", "On disk", "Saved", "Ratio"],
+            rows,
+            &mut html,
+        );
+
+        html.push_str("<h2>Artifact kinds</h2>\n");
+        let rows = self.kinds.iter().map(|(kind, stats)| {
+            vec![
+                text_cell(kind.label()),
+                count_cell(stats.files_compressed),
+                size_cell(stats.bytes_in, sizes),
+                size_cell(stats.bytes_out, sizes),
+                size_cell(stats.bytes_saved(), sizes),
+                ratio_cell(stats.bytes_in, stats.bytes_out),
+            ]
+        });
+        table(
+            &["Kind", "Files", "Before", "After", "Saved", "Ratio"],
+            rows,
+            &mut html,
+        );
+
+        if !self.incompressible.is_empty() {
+            let (files, size) = self.incompressible.total();
+            writeln!(
+                html,
+                "<h2>Not compressible enough</h2>\n<p>{files} file(s), {} left as they were \
+                 because compressing them saved too little or made them grow.</p>",
+                escape(&sizes.format(size))
+            )
+            .unwrap();
+            let rows =
+                self.incompressible
+                    .by_extension()
+                    .into_iter()
+                    .map(|(extension, files, size)| {
+                        let name = if extension.is_empty() {
+                            "(none)".to_owned()
+                        } else {
+                            format!(".{extension}")
+                        };
+                        vec![text_cell(&name), count_cell(files), size_cell(size, sizes)]
+                    });
+            table(&["Extension", "Files", "Size"], rows, &mut html);
+            let rows = self
+                .incompressible
+                .largest(crate::INCOMPRESSIBLE_TOP)
+                .into_iter()
+                .map(|(path, size)| {
+                    vec![
+                        text_cell(&path.display().to_string()),
+                        size_cell(size, sizes),
+                    ]
+                });
+            table(&["Largest files", "Size"], rows, &mut html);
+        }
+
+        html.push_str(HTML_TAIL);
+        html
+    }
+}
+
+/// A table cell and the value its column sorts by.
+struct Cell {
+    text: String,
+    sort_key: String,
+}
+
+fn text_cell(text: &str) -> Cell {
+    Cell {
+        text: escape(text),
+        sort_key: escape(text),
+    }
+}
+
+fn count_cell(count: u64) -> Cell {
+    Cell {
+        text: count.to_string(),
+        sort_key: count.to_string(),
+    }
+}
+
+fn size_cell(bytes: u64, sizes: SizeFormat) -> Cell {
+    Cell {
+        text: escape(&sizes.format(bytes)),
+        sort_key: bytes.to_string(),
+    }
+}
+
+/// How many times smaller `on_disk` is than `size`, like `3.2x`.
+fn ratio_cell(size: u64, on_disk: u64) -> Cell {
+    let ratio = size as f64 / on_disk.max(1) as f64;
+    Cell {
+        text: format!("{ratio:.1}x"),
+        sort_key: ((ratio * 1000.0) as u64).to_string(),
+    }
+}
+
+/// Sorted by bytes read per second.
+fn throughput_cell(stats: &CompressionStats, elapsed: Duration, sizes: SizeFormat) -> Cell {
+    let millis = elapsed.as_millis().max(1);
+    Cell {
+        text: escape(&summary::throughput(stats, elapsed, sizes).unwrap_or_default()),
+        sort_key: (u128::from(stats.bytes_in) * 1000 / millis).to_string(),
+    }
+}
+
+fn table(headers: &[&str], rows: impl Iterator<Item = Vec<Cell>>, html: &mut String) {
+    html.push_str("<table class=\"sortable\">\n<thead><tr>");
+    for (column, header) in headers.iter().enumerate() {
+        // The first column is text; every other one is numeric.
+        let kind = if column == 0 { "text" } else { "number" };
+        write!(html, "<th data-kind=\"{kind}\">{}</th>", escape(header)).unwrap();
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            write!(
+                html,
+                "<td data-sort=\"{}\">{}</td>",
+                cell.sort_key, cell.text
+            )
+            .unwrap();
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// An inline SVG bar per dir: the full bar is the size before, the filled part what was saved.
+fn savings_chart(dirs: &[DirResult], sizes: SizeFormat) -> String {
+    const ROW_HEIGHT: usize = 24;
+    const LABEL_WIDTH: usize = 320;
+    const BAR_WIDTH: u64 = 480;
+
+    let largest = dirs
+        .iter()
+        .map(|result| result.stats.bytes_in)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut svg = format!(
+        "<svg class=\"chart\" width=\"{}\" height=\"{}\" role=\"img\">\n",
+        LABEL_WIDTH as u64 + BAR_WIDTH + 160,
+        dirs.len() * ROW_HEIGHT
+    );
+    for (row, DirResult { dir, stats, .. }) in dirs.iter().enumerate() {
+        let y = row * ROW_HEIGHT;
+        let before = stats.bytes_in * BAR_WIDTH / largest;
+        let saved = stats.bytes_saved() * BAR_WIDTH / largest;
+        writeln!(
+            svg,
+            "<text x=\"0\" y=\"{}\">{}</text>\
+             <rect class=\"before\" x=\"{LABEL_WIDTH}\" y=\"{}\" width=\"{before}\" height=\"16\"/>\
+             <rect class=\"saved\" x=\"{LABEL_WIDTH}\" y=\"{}\" width=\"{saved}\" height=\"16\"/>\
+             <text x=\"{}\" y=\"{}\">{} saved</text>",
+            y + 16,
+            escape(&short_name(dir)),
+            y + 4,
+            y + 4,
+            LABEL_WIDTH as u64 + before + 8,
+            y + 16,
+            escape(&sizes.format(stats.bytes_saved())),
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The last two components of `dir`, such as `aarch64-apple-darwin/release`, to fit the chart.
+fn short_name(dir: &Path) -> String {
+    let components: Vec<_> = dir.components().collect();
+    let start = components.len().saturating_sub(2);
+    components[start..]
+        .iter()
+        .collect::<PathBuf>()
+        .display()
+        .to_string()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>cargo apfs-compress report</title>
+<style>
+body { font: 14px -apple-system, BlinkMacSystemFont, sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 4px 12px; border-bottom: 1px solid #ddd; text-align: right; }
+th:first-child, td:first-child { text-align: left; font-family: ui-monospace, monospace; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+th[aria-sort="ascending"]::after { content: " \25B2"; }
+th[aria-sort="descending"]::after { content: " \25BC"; }
+.chart text { font-size: 12px; fill: #222; }
+.chart .before { fill: #ddd; }
+.chart .saved { fill: #2e9e4f; }
+</style>
+</head>
+<body>
+"#;
+
+const HTML_TAIL: &str = r#"<script>
+for (const table of document.querySelectorAll("table.sortable")) {
+  table.querySelectorAll("th").forEach((th, column) => {
+    th.addEventListener("click", () => {
+      const ascending = th.getAttribute("aria-sort") !== "ascending";
+      table.querySelectorAll("th").forEach((other) => other.removeAttribute("aria-sort"));
+      th.setAttribute("aria-sort", ascending ? "ascending" : "descending");
+      const numeric = th.dataset.kind === "number";
+      const body = table.tBodies[0];
+      const rows = Array.from(body.rows).sort((a, b) => {
+        const x = a.cells[column].dataset.sort;
+        const y = b.cells[column].dataset.sort;
+        const order = numeric ? Number(x) - Number(y) : x.localeCompare(y);
+        return ascending ? order : -order;
+      });
+      body.append(...rows);
+    });
+  });
+}
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,455 @@
+use applesauce::progress::{Progress, SkipReason, Task};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::Verbosity;
+use crate::artifact::KindSummary;
+use crate::disk::SizeFormat;
+use crate::fd_limit;
+use crate::heartbeat::Heartbeat;
+use crate::hints::{self, Hint};
+use crate::metrics::{Counter, MetricsSink};
+use crate::stats::CompressionStats;
+use crate::summary::{DeniedSummary, IncompressibleSummary, SkipSummary};
+
+/// Receives log lines and file-level progress from a run.
+///
+/// The CLI implements this with progress bars. Library callers can implement it directly, or wrap
+/// an existing [`applesauce::progress::Progress`] implementation in a [`ProgressReporter`].
+pub trait Reporter: Send + Sync {
+    /// The most detailed messages this reporter wants to receive via [`Reporter::message`].
+    fn verbosity(&self) -> Verbosity {
+        Verbosity::Normal
+    }
+
+    /// How byte counts in messages sent to this reporter should be written.
+    fn size_format(&self) -> SizeFormat {
+        SizeFormat::Human
+    }
+
+    /// A human-readable log line, such as a lock wait or a per-directory result.
+    fn message(&self, _verbosity: Verbosity, _message: &str) {}
+
+    fn error(&self, path: &Path, message: &str);
+
+    fn file_skipped(&self, _path: &Path, _why: SkipReason) {}
+
+    /// Called for each non-empty file the walk leaves out for being smaller than decmpfs's inline
+    /// threshold. Empty files go to [`Reporter::file_skipped`] instead.
+    fn file_too_small(&self, _path: &Path) {}
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync>;
+
+    /// Called by the [`Compressor`](crate::Compressor) as each file finishes, with the space it
+    /// occupied on disk (allocated blocks, not logical size) before and after.
+    fn file_compressed(&self, _path: &Path, _bytes_in: u64, _bytes_out: u64) {}
+
+    /// Called as each chunk of a work dir's files is enumerated, before it is compressed, with its
+    /// total logical size, and again for any files held back until the end of the dir.
+    fn files_queued(&self, _bytes: u64) {}
+
+    /// Called as a worker starts on the work dir `dir`.
+    fn work_dir_started(&self, _dir: &Path) {}
+
+    /// Called as a worker finishes with `dir`, with its stats if it succeeded.
+    fn work_dir_finished(&self, _dir: &Path, _stats: Option<&CompressionStats>) {}
+
+    /// Called before any work dir is processed when `--prescan` measured the whole run's logical
+    /// size up front. Totals should stay at `bytes` rather than grow with [`Reporter::files_queued`].
+    fn total_known(&self, _bytes: u64) {}
+
+    /// Runs `f`, which writes straight to the terminal, such as a prompt, with any bars this
+    /// reporter draws cleared out of the way until it returns.
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        f();
+    }
+
+    /// Called once at the end of a run.
+    fn finish(&self) {}
+}
+
+impl dyn Reporter + '_ {
+    pub fn format_bytes(&self, bytes: u64) -> String {
+        self.size_format().format(bytes)
+    }
+
+    pub fn println_normal<F>(&self, message: F)
+    where
+        F: FnOnce() -> String,
+    {
+        if self.verbosity() >= Verbosity::Normal {
+            self.message(Verbosity::Normal, &message());
+        }
+    }
+
+    /// [`Reporter::suspend`] for a closure that returns something.
+    pub fn suspended<T>(&self, f: impl FnOnce() -> T) -> T {
+        let (mut f, mut result) = (Some(f), None);
+        self.suspend(&mut || result = f.take().map(|f| f()));
+        result.expect("suspend runs its closure")
+    }
+
+    pub fn println_verbose<F>(&self, message: F)
+    where
+        F: FnOnce() -> String,
+    {
+        if self.verbosity() >= Verbosity::Verbose {
+            self.message(Verbosity::Verbose, &message());
+        }
+    }
+}
+
+/// Adapts an [`applesauce::progress::Progress`] implementation into a [`Reporter`].
+///
+/// Log lines are dropped; only file-level callbacks are forwarded.
+pub struct ProgressReporter<P>(pub P);
+
+impl<P> Reporter for ProgressReporter<P>
+where
+    P: Progress + Send + Sync,
+    P::Task: Send + Sync + 'static,
+{
+    fn error(&self, path: &Path, message: &str) {
+        self.0.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        self.0.file_skipped(path, why);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        Box::new(self.0.file_task(path, size))
+    }
+}
+
+/// Presents a [`Reporter`] to applesauce as a [`Progress`].
+///
+/// Tasks only live as long as applesauce is working on their file, so each one sends its path and
+/// size to `completed` when dropped.
+pub(crate) struct AsProgress<'a> {
+    reporter: &'a dyn Reporter,
+    completed: Sender<(PathBuf, u64)>,
+    exhausted: Option<Sender<PathBuf>>,
+}
+
+impl<'a> AsProgress<'a> {
+    pub(crate) fn new(reporter: &'a dyn Reporter, completed: Sender<(PathBuf, u64)>) -> Self {
+        Self {
+            reporter,
+            completed,
+            exhausted: None,
+        }
+    }
+
+    /// Sends files that fail for want of file descriptors to `exhausted`, to be retried, instead
+    /// of reporting them or counting them as completed.
+    pub(crate) fn retry_exhausted(self, exhausted: Sender<PathBuf>) -> Self {
+        Self {
+            exhausted: Some(exhausted),
+            ..self
+        }
+    }
+}
+
+pub(crate) struct BoxedTask {
+    inner: Box<dyn Task + Send + Sync>,
+    path: PathBuf,
+    size: u64,
+    completed: Sender<(PathBuf, u64)>,
+    exhausted: Option<Sender<PathBuf>>,
+    ran_out: AtomicBool,
+    /// Set once the task reports an error, so the file isn't reported as compressed too.
+    failed: AtomicBool,
+}
+
+impl Progress for AsProgress<'_> {
+    type Task = BoxedTask;
+
+    fn error(&self, path: &Path, message: &str) {
+        if let Some(exhausted) = &self.exhausted
+            && fd_limit::is_exhaustion(message)
+        {
+            let _ = exhausted.send(path.to_path_buf());
+            return;
+        }
+        self.reporter.error(path, message);
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        self.reporter.file_skipped(path, why);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Self::Task {
+        BoxedTask {
+            inner: self.reporter.file_task(path, size),
+            path: path.to_path_buf(),
+            size,
+            completed: self.completed.clone(),
+            exhausted: self.exhausted.clone(),
+            ran_out: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Task for BoxedTask {
+    fn increment(&self, amt: u64) {
+        self.inner.increment(amt);
+    }
+
+    fn error(&self, message: &str) {
+        if self.exhausted.is_some() && fd_limit::is_exhaustion(message) {
+            self.ran_out.store(true, Ordering::Relaxed);
+            return;
+        }
+        self.failed.store(true, Ordering::Relaxed);
+        self.inner.error(message);
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        self.inner.not_compressible_enough(path);
+    }
+}
+
+impl Drop for BoxedTask {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        if let Some(exhausted) = &self.exhausted
+            && self.ran_out.load(Ordering::Relaxed)
+        {
+            let _ = exhausted.send(path);
+            return;
+        }
+        if !self.failed.load(Ordering::Relaxed) {
+            let _ = self.completed.send((path, self.size));
+        }
+    }
+}
+
+/// Presents only the error callbacks of a [`Reporter`] to applesauce, for auxiliary passes that
+/// should not show up as file progress.
+///
+/// Tasks must be `'static`, so task errors are sent to `errors` for the caller to forward.
+pub(crate) struct ErrorsOnly<'a> {
+    reporter: &'a dyn Reporter,
+    errors: Sender<(PathBuf, String)>,
+}
+
+impl<'a> ErrorsOnly<'a> {
+    pub(crate) fn new(reporter: &'a dyn Reporter, errors: Sender<(PathBuf, String)>) -> Self {
+        Self { reporter, errors }
+    }
+}
+
+pub(crate) struct ErrorsOnlyTask {
+    path: PathBuf,
+    errors: Sender<(PathBuf, String)>,
+}
+
+impl Progress for ErrorsOnly<'_> {
+    type Task = ErrorsOnlyTask;
+
+    fn error(&self, path: &Path, message: &str) {
+        self.reporter.error(path, message);
+    }
+
+    fn file_task(&self, path: &Path, _size: u64) -> Self::Task {
+        ErrorsOnlyTask {
+            path: path.to_path_buf(),
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+impl Task for ErrorsOnlyTask {
+    fn increment(&self, _amt: u64) {}
+
+    fn error(&self, message: &str) {
+        let _ = self.errors.send((self.path.clone(), message.to_owned()));
+    }
+}
+
+/// Wraps the caller's [`Reporter`] so that file and error callbacks also feed a [`MetricsSink`]
+/// and the run's [`SkipSummary`] and [`Heartbeat`].
+pub(crate) struct Metered<'a> {
+    inner: &'a dyn Reporter,
+    metrics: Arc<dyn MetricsSink>,
+    skips: SkipSummary,
+    kinds: KindSummary,
+    incompressible: Arc<IncompressibleSummary>,
+    denied: Arc<DeniedSummary>,
+    heartbeat: Arc<Heartbeat>,
+    /// Hints already added to a file's error, each of which is only worth giving once a run.
+    hinted: Mutex<HashSet<Hint>>,
+}
+
+impl<'a> Metered<'a> {
+    pub(crate) fn new(inner: &'a dyn Reporter, metrics: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            inner,
+            metrics,
+            skips: SkipSummary::default(),
+            kinds: KindSummary::default(),
+            incompressible: Arc::default(),
+            denied: Arc::default(),
+            heartbeat: Arc::default(),
+            hinted: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn skips(&self) -> &SkipSummary {
+        &self.skips
+    }
+
+    pub(crate) fn kinds(&self) -> &KindSummary {
+        &self.kinds
+    }
+
+    pub(crate) fn incompressible(&self) -> &IncompressibleSummary {
+        &self.incompressible
+    }
+
+    pub(crate) fn denied(&self) -> &DeniedSummary {
+        &self.denied
+    }
+
+    pub(crate) fn heartbeat(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+}
+
+struct MeteredTask {
+    inner: Box<dyn Task + Send + Sync>,
+    metrics: Arc<dyn MetricsSink>,
+    heartbeat: Arc<Heartbeat>,
+    size: u64,
+    incompressible: Arc<IncompressibleSummary>,
+    path: PathBuf,
+    denied: Arc<DeniedSummary>,
+}
+
+impl Reporter for Metered<'_> {
+    fn verbosity(&self) -> Verbosity {
+        self.inner.verbosity()
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.inner.size_format()
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        self.inner.message(verbosity, message);
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.metrics.increment(Counter::Errors, 1);
+        if hints::is_permission_denied(message) {
+            self.denied.record(path);
+        }
+        let hint = hints::for_message(message, path).filter(|&hint| {
+            self.hinted
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert(hint)
+        });
+        match hint {
+            Some(hint) => self.inner.error(path, &format!("{message}; hint: {hint}")),
+            None => self.inner.error(path, message),
+        }
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        self.metrics.increment(Counter::FilesSkipped, 1);
+        self.skips.record(&why);
+        if let SkipReason::ReadError(error) = &why
+            && error.kind() == io::ErrorKind::PermissionDenied
+        {
+            self.denied.record(path);
+        }
+        // Files the walk already rejected were never queued.
+        if !matches!(why, SkipReason::NotFile)
+            && let Ok(metadata) = fs::symlink_metadata(path)
+            && !metadata.is_dir()
+        {
+            self.heartbeat.done(metadata.len());
+        }
+        self.inner.file_skipped(path, why);
+    }
+
+    fn file_too_small(&self, path: &Path) {
+        self.metrics.increment(Counter::FilesSkipped, 1);
+        self.skips.record_tiny();
+        self.inner.file_too_small(path);
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        self.metrics.increment(Counter::FilesProcessed, 1);
+        Box::new(MeteredTask {
+            inner: self.inner.file_task(path, size),
+            metrics: Arc::clone(&self.metrics),
+            heartbeat: Arc::clone(&self.heartbeat),
+            size,
+            incompressible: Arc::clone(&self.incompressible),
+            path: path.to_path_buf(),
+            denied: Arc::clone(&self.denied),
+        })
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        self.metrics
+            .increment(Counter::BytesSaved, bytes_in.saturating_sub(bytes_out));
+        self.kinds.record(path, bytes_in, bytes_out);
+        self.inner.file_compressed(path, bytes_in, bytes_out);
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        self.heartbeat.queued(bytes);
+        self.inner.files_queued(bytes);
+    }
+
+    fn total_known(&self, bytes: u64) {
+        self.heartbeat.set_total(bytes);
+        self.inner.total_known(bytes);
+    }
+
+    fn work_dir_started(&self, dir: &Path) {
+        self.inner.work_dir_started(dir);
+    }
+
+    fn work_dir_finished(&self, dir: &Path, stats: Option<&CompressionStats>) {
+        self.inner.work_dir_finished(dir, stats);
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.inner.suspend(f);
+    }
+
+    fn finish(&self) {
+        self.inner.finish();
+    }
+}
+
+impl Task for MeteredTask {
+    fn increment(&self, amt: u64) {
+        self.heartbeat.done(amt);
+        self.inner.increment(amt);
+    }
+
+    fn error(&self, message: &str) {
+        self.metrics.increment(Counter::Errors, 1);
+        if hints::is_permission_denied(message) {
+            self.denied.record(&self.path);
+        }
+        self.inner.error(message);
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        self.incompressible.record(path, self.size);
+        self.inner.not_compressible_enough(path);
+    }
+}
@@ -0,0 +1,94 @@
+//! `--interactive`: a checklist of the discovered work dirs to pick from before compressing.
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::disk::SizeFormat;
+
+/// Shows `dirs` with their on-disk `sizes`, all checked, and reads toggles from `input` until an
+/// empty line, returning the dirs still checked. `q` or end of input returns none.
+pub(crate) fn choose(
+    dirs: Vec<PathBuf>,
+    sizes: &[u64],
+    cwd: Option<&Path>,
+    format: SizeFormat,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> io::Result<Vec<PathBuf>> {
+    let mut checked = vec![true; dirs.len()];
+    let names: Vec<_> = dirs
+        .iter()
+        .map(|dir| crate::summary::relative_to(dir, cwd).display().to_string())
+        .collect();
+    let name_width = names.iter().map(String::len).max().unwrap_or(0);
+    let number_width = dirs.len().to_string().len();
+    loop {
+        writeln!(output, "Work dirs:")?;
+        for (index, name) in names.iter().enumerate() {
+            writeln!(
+                output,
+                "  [{}] {:>number_width$}  {name:<name_width$}  {}",
+                if checked[index] { 'x' } else { ' ' },
+                index + 1,
+                format.format(sizes[index]),
+            )?;
+        }
+        write!(
+            output,
+            "Toggle with numbers or ranges (1,3-5), `a` for all, `n` for none, Enter to start, `q` to quit: "
+        )?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(Vec::new());
+        }
+        match line.trim() {
+            "" => break,
+            "q" => return Ok(Vec::new()),
+            "a" => checked.fill(true),
+            "n" => checked.fill(false),
+            toggles => match parse_toggles(toggles, dirs.len()) {
+                Some(indices) => {
+                    for index in indices {
+                        checked[index] = !checked[index];
+                    }
+                }
+                None => writeln!(
+                    output,
+                    "Not a number or range of 1-{}: {toggles}",
+                    dirs.len()
+                )?,
+            },
+        }
+    }
+    Ok(dirs
+        .into_iter()
+        .zip(checked)
+        .filter_map(|(dir, checked)| checked.then_some(dir))
+        .collect())
+}
+
+/// Zero-based indices from a list like `1, 3-5`, or `None` if any item isn't a number or range
+/// within `1..=len`.
+pub(crate) fn parse_toggles(text: &str, len: usize) -> Option<Vec<usize>> {
+    let mut indices = Vec::new();
+    for item in text
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let (start, end): (usize, usize) = match item.split_once('-') {
+            Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+            None => {
+                let number = item.parse().ok()?;
+                (number, number)
+            }
+        };
+        if start == 0 || start > end || end > len {
+            return None;
+        }
+        indices.extend(start - 1..end);
+    }
+    Some(indices)
+}
@@ -0,0 +1,128 @@
+//! `--since` and `--before`: only compressing files modified after, or before, a point in time
+//! given as a date or as a git ref whose commit time is used.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+
+/// A point in time on the command line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TimeRef {
+    At(SystemTime),
+    /// A commit-ish such as `HEAD~20` or `main`, resolved to its committer time in the current
+    /// dir's repository when the run starts.
+    GitRef(String),
+}
+
+impl TimeRef {
+    /// Parses `YYYY-MM-DD`, `YYYY-MM-DDTHH:MM[:SS]` (UTC, with an optional trailing `Z`), or
+    /// `@<seconds since the epoch>`. Anything else that could be a git ref is taken as one.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let invalid = || format!("invalid date `{value}`; expected YYYY-MM-DD[THH:MM[:SS]]");
+        if let Some(seconds) = value.strip_prefix('@') {
+            let seconds: u64 = seconds
+                .parse()
+                .map_err(|_| format!("invalid timestamp `{value}`"))?;
+            return Ok(Self::At(UNIX_EPOCH + Duration::from_secs(seconds)));
+        }
+        let looks_like_date = value.len() >= 5
+            && value[..4].bytes().all(|byte| byte.is_ascii_digit())
+            && value.as_bytes()[4] == b'-';
+        if looks_like_date {
+            return parse_date_time(value).map(Self::At).ok_or_else(invalid);
+        }
+        if value.is_empty() || value.starts_with('-') || value.contains(char::is_whitespace) {
+            return Err(format!("`{value}` is neither a date nor a git ref"));
+        }
+        Ok(Self::GitRef(value.to_owned()))
+    }
+
+    /// The point in time, asking git in `cwd` for a ref's commit time.
+    pub(crate) fn resolve(&self, cwd: &Path) -> Result<SystemTime> {
+        let reference = match self {
+            Self::At(time) => return Ok(*time),
+            Self::GitRef(reference) => reference,
+        };
+        let failed = |message: String| Error::GitRef {
+            reference: reference.clone(),
+            message,
+        };
+        let output = Command::new("git")
+            .args(["show", "--no-patch", "--format=%ct"])
+            .arg(format!("{reference}^{{commit}}"))
+            .arg("--")
+            .current_dir(cwd)
+            .output()
+            .map_err(|error| failed(error.to_string()))?;
+        if !output.status.success() {
+            return Err(failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let seconds: u64 = stdout
+            .trim()
+            .parse()
+            .map_err(|_| failed(format!("unexpected output `{}`", stdout.trim())))?;
+        Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+}
+
+/// `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM[:SS]`, as UTC.
+fn parse_date_time(value: &str) -> Option<SystemTime> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = value
+        .split_once(['T', ' '])
+        .map_or((value, None), |(date, time)| (date, Some(time)));
+    let mut date = date.split('-').map(str::parse::<u64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if date.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds_of_day = 0;
+    if let Some(time) = time {
+        let parts: Vec<u64> = time
+            .split(':')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let (hours, minutes, seconds) = match parts[..] {
+            [hours, minutes] => (hours, minutes, 0),
+            [hours, minutes, seconds] => (hours, minutes, seconds),
+            _ => return None,
+        };
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return None;
+        }
+        seconds_of_day = hours * 3600 + minutes * 60 + seconds;
+    }
+    let days = days_from_civil(year, month, day)?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + seconds_of_day))
+}
+
+/// Howard Hinnant's `days_from_civil`, for dates from the epoch on.
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146_097 + doe).checked_sub(719_468)
+}
+
+/// Whether a file last modified at `modified` is inside the window `--since` and `--before` set.
+pub(crate) fn in_window(
+    modified: SystemTime,
+    since: Option<SystemTime>,
+    before: Option<SystemTime>,
+) -> bool {
+    since.is_none_or(|since| modified >= since) && before.is_none_or(|before| modified < before)
+}
@@ -0,0 +1,47 @@
+use std::ops::AddAssign;
+
+/// What a [`Compressor`](crate::Compressor) did to one set of paths.
+///
+/// Byte counts are on-disk sizes, before and after compression, of every file the compressor
+/// looked at, including ones it skipped. Blocks a compressed file shared with APFS clones are left
+/// out of `bytes_in`, since rewriting the file frees none of them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompressionStats {
+    pub files_compressed: u64,
+    pub files_skipped: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl CompressionStats {
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_in.saturating_sub(self.bytes_out)
+    }
+}
+
+impl From<&applesauce::Stats> for CompressionStats {
+    fn from(stats: &applesauce::Stats) -> Self {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let files = stats.files.load(Relaxed);
+        let files_compressed = stats
+            .compressed_file_count_final
+            .load(Relaxed)
+            .saturating_sub(stats.compressed_file_count_start.load(Relaxed));
+        Self {
+            files_compressed,
+            files_skipped: files.saturating_sub(files_compressed),
+            bytes_in: stats.compressed_size_start.load(Relaxed),
+            bytes_out: stats.compressed_size_final.load(Relaxed),
+        }
+    }
+}
+
+impl AddAssign for CompressionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.files_compressed += other.files_compressed;
+        self.files_skipped += other.files_skipped;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+    }
+}
@@ -0,0 +1,468 @@
+use applesauce::progress::SkipReason;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::CompressionStats;
+use crate::disk::SizeFormat;
+use crate::hints::Hint;
+use crate::report::DirResult;
+
+pub(crate) const READ_ERRORS: &str = "read errors";
+pub(crate) const ZFS_FILESYSTEM: &str = "ZFS filesystem";
+pub(crate) const UNSUPPORTED_FILESYSTEM: &str = "unsupported filesystem";
+pub(crate) const TINY_FILES: &str = "tiny";
+
+/// A short, plural-friendly label used to group skipped files in the end-of-run summary.
+fn skip_reason_label(why: &SkipReason) -> &'static str {
+    match why {
+        SkipReason::NotFile => "not a regular file",
+        SkipReason::AlreadyCompressed => "already compressed",
+        SkipReason::NotCompressed => "not compressed",
+        SkipReason::EmptyFile => "empty",
+        SkipReason::TooLarge(_) => "too large",
+        SkipReason::ReadError(_) => READ_ERRORS,
+        SkipReason::ZfsFilesystem => ZFS_FILESYSTEM,
+        SkipReason::HasRequiredXattr => "has required xattr",
+        SkipReason::FsNotSupported => UNSUPPORTED_FILESYSTEM,
+        SkipReason::HardLink => "hard links",
+    }
+}
+
+/// A skipped file's reason as shown in its log line, with sizes written in `sizes`.
+pub(crate) fn describe_skip(why: &SkipReason, sizes: SizeFormat) -> String {
+    match why {
+        SkipReason::TooLarge(size) => format!(
+            "File too large: {} > {}",
+            sizes.format(*size),
+            sizes.format(u32::MAX.into())
+        ),
+        why => why.to_string(),
+    }
+}
+
+/// Counts skipped files by reason over a whole run.
+#[derive(Default)]
+pub(crate) struct SkipSummary {
+    counts: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl SkipSummary {
+    pub(crate) fn record(&self, why: &SkipReason) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .entry(skip_reason_label(why))
+            .or_default() += 1;
+    }
+
+    /// Counts a file the walk left out as smaller than decmpfs's inline threshold.
+    pub(crate) fn record_tiny(&self) {
+        *self.counts.lock().unwrap().entry(TINY_FILES).or_default() += 1;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.counts.lock().unwrap().is_empty()
+    }
+
+    /// How many files were skipped under `label`, one of the labels this summary groups by.
+    pub(crate) fn count(&self, label: &str) -> u64 {
+        self.counts.lock().unwrap().get(label).copied().unwrap_or(0)
+    }
+}
+
+/// Renders one line per reason, most common first.
+impl fmt::Display for SkipSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = self.counts.lock().unwrap();
+        let mut rows: Vec<_> = counts.iter().collect();
+        rows.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let label_width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+        let count_width = rows
+            .iter()
+            .map(|(_, count)| count.to_string().len())
+            .max()
+            .unwrap_or(0);
+
+        write!(f, "Skipped files:")?;
+        for (label, count) in rows {
+            write!(f, "\n  {label:<label_width$}  {count:>count_width$}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Files that were left uncompressed because compressing them didn't save enough, or made them
+/// grow, over a whole run, with their logical sizes.
+#[derive(Default)]
+pub(crate) struct IncompressibleSummary {
+    files: Mutex<Vec<(PathBuf, u64)>>,
+}
+
+impl IncompressibleSummary {
+    pub(crate) fn record(&self, path: &Path, size: u64) {
+        self.files.lock().unwrap().push((path.to_path_buf(), size));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.files.lock().unwrap().is_empty()
+    }
+
+    /// How many files there were, and their total size.
+    pub(crate) fn total(&self) -> (u64, u64) {
+        let files = self.files.lock().unwrap();
+        (files.len() as u64, files.iter().map(|(_, size)| size).sum())
+    }
+
+    /// Lowercase extension (`""` for none), file count, and total size, largest total first.
+    pub(crate) fn by_extension(&self) -> Vec<(String, u64, u64)> {
+        let mut groups: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for (path, size) in self.files.lock().unwrap().iter() {
+            let extension = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default();
+            let group = groups.entry(extension).or_default();
+            group.0 += 1;
+            group.1 += size;
+        }
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(extension, (files, size))| (extension, files, size))
+            .collect();
+        groups.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        groups
+    }
+
+    /// The `top` largest files, ties in path order.
+    pub(crate) fn largest(&self, top: usize) -> Vec<(PathBuf, u64)> {
+        let mut files = self.files.lock().unwrap().clone();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        files.truncate(top);
+        files
+    }
+
+    /// Aligned tables of [`Self::by_extension`] and the `top` [`Self::largest`] files under a
+    /// heading with the totals.
+    pub(crate) fn render(&self, top: usize, cwd: Option<&Path>, sizes: SizeFormat) -> String {
+        let (files, size) = self.total();
+        let mut text = format!(
+            "Not compressible enough ({files} file(s), {}):",
+            sizes.format(size)
+        );
+        let groups: Vec<_> = self
+            .by_extension()
+            .into_iter()
+            .map(|(extension, files, size)| {
+                let name = if extension.is_empty() {
+                    "(none)".to_owned()
+                } else {
+                    format!(".{extension}")
+                };
+                (name, files.to_string(), sizes.format(size))
+            })
+            .collect();
+        let name_width = groups.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let files_width = groups.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        for (name, files, size) in groups {
+            text.push_str(&format!(
+                "\n  {name:<name_width$}  {files:>files_width$}  {size}"
+            ));
+        }
+        let largest: Vec<_> = self
+            .largest(top)
+            .into_iter()
+            .map(|(path, size)| {
+                (
+                    relative_to(&path, cwd).display().to_string(),
+                    sizes.format(size),
+                )
+            })
+            .collect();
+        let path_width = largest.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        text.push_str("\nLargest:");
+        for (path, size) in largest {
+            text.push_str(&format!("\n  {path:<path_width$}  {size}"));
+        }
+        text
+    }
+}
+
+/// Files and dirs a run couldn't read or change because it had no permission, which it left
+/// alone instead of failing their work dir over.
+#[derive(Default)]
+pub(crate) struct DeniedSummary {
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+impl DeniedSummary {
+    pub(crate) fn record(&self, path: &Path) {
+        self.paths.lock().unwrap().push(path.to_path_buf());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.paths.lock().unwrap().is_empty()
+    }
+
+    /// A heading with the count and then the first `top` paths in order, relative to `cwd`.
+    pub(crate) fn render(&self, top: usize, cwd: Option<&Path>) -> String {
+        let mut paths = self.paths.lock().unwrap().clone();
+        paths.sort();
+        paths.dedup();
+        let mut text = format!("Permission denied ({}):", paths.len());
+        for path in paths.iter().take(top) {
+            text.push_str(&format!("\n  {}", relative_to(path, cwd).display()));
+        }
+        if paths.len() > top {
+            text.push_str(&format!("\n  ... and {} more", paths.len() - top));
+        }
+        text.push_str(&format!("\nhint: {}", Hint::Permissions));
+        text
+    }
+}
+
+/// The line printed as each work dir finishes.
+pub(crate) struct DirSummary<'a> {
+    pub(crate) dir: &'a Path,
+    pub(crate) stats: CompressionStats,
+    pub(crate) elapsed: Duration,
+    pub(crate) sizes: SizeFormat,
+}
+
+impl fmt::Display for DirSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files = self.stats.files_compressed + self.stats.files_skipped;
+        write!(
+            f,
+            "{}: {} -> {}, {} file{}, {}",
+            self.dir.display(),
+            self.sizes.format(self.stats.bytes_in),
+            self.sizes.format(self.stats.bytes_out),
+            files,
+            if files == 1 { "" } else { "s" },
+            format_elapsed(self.elapsed),
+        )
+    }
+}
+
+/// `dir` relative to `cwd` when it is inside it, which keeps per-dir lines short.
+pub(crate) fn relative_to<'a>(dir: &'a Path, cwd: Option<&Path>) -> &'a Path {
+    cwd.and_then(|cwd| dir.strip_prefix(cwd).ok())
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .unwrap_or(dir)
+}
+
+/// The `top` dirs of `dirs` that saved the most, as an aligned table under a heading, largest
+/// savings first and ties in path order.
+pub(crate) fn top_dirs(
+    dirs: &[DirResult],
+    top: usize,
+    cwd: Option<&Path>,
+    sizes: SizeFormat,
+) -> String {
+    let mut ranked: Vec<_> = dirs.iter().collect();
+    ranked.sort_by(|a, b| {
+        b.stats
+            .bytes_saved()
+            .cmp(&a.stats.bytes_saved())
+            .then_with(|| a.dir.cmp(&b.dir))
+    });
+    ranked.truncate(top);
+    let rows: Vec<_> = ranked
+        .iter()
+        .map(|result| {
+            (
+                relative_to(&result.dir, cwd).display().to_string(),
+                sizes.format(result.stats.bytes_saved()),
+                format!(
+                    "({} -> {})",
+                    sizes.format(result.stats.bytes_in),
+                    sizes.format(result.stats.bytes_out)
+                ),
+            )
+        })
+        .collect();
+    let dir_width = rows.iter().map(|(dir, ..)| dir.len()).max().unwrap_or(0);
+    let saved_width = rows
+        .iter()
+        .map(|(_, saved, _)| saved.len())
+        .max()
+        .unwrap_or(0);
+    let mut table = format!("Most saved ({} of {} dirs):", rows.len(), dirs.len());
+    for (dir, saved, sizes) in rows {
+        table.push_str(&format!(
+            "\n  {dir:<dir_width$}  {saved:>saved_width$} {sizes}"
+        ));
+    }
+    table
+}
+
+/// Why `freed` falls short of `saved`, if local snapshots are the likely reason: their blocks
+/// are only released when the snapshots expire or are deleted.
+pub(crate) fn snapshot_note(
+    saved: u64,
+    freed: u64,
+    snapshots: usize,
+    sizes: SizeFormat,
+) -> Option<String> {
+    let held = saved.saturating_sub(freed);
+    if snapshots == 0 || held == 0 {
+        return None;
+    }
+    Some(format!(
+        "note: {} reclaimed, but {} of it is held by {snapshots} local snapshot{} until they expire",
+        sizes.format(saved),
+        sizes.format(held),
+        if snapshots == 1 { "" } else { "s" },
+    ))
+}
+
+/// The closing paragraph of a run.
+pub(crate) struct RunSummary {
+    pub(crate) stats: CompressionStats,
+    pub(crate) dirs: usize,
+    pub(crate) failed: usize,
+    pub(crate) elapsed: Duration,
+    pub(crate) sizes: SizeFormat,
+    /// Bytes done and the pre-scanned total, for a run that was stopped before finishing.
+    pub(crate) completed: Option<(u64, u64)>,
+    /// Free space on the work dirs' volumes before and after the run.
+    pub(crate) free_space: Option<(u64, u64)>,
+    /// How much of the work dirs was compressed before the run started.
+    pub(crate) baseline: Option<Baseline>,
+}
+
+/// The logical size of the files a run looks at, and how much of it was already compressed
+/// before the run touched anything. Repeat runs save little because most of this was done by
+/// earlier ones, which this makes visible.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Baseline {
+    pub(crate) bytes: u64,
+    pub(crate) compressed: u64,
+}
+
+impl Baseline {
+    /// Like `82% of 20.0 GiB was already compressed`, or `None` if there was nothing to measure.
+    pub(crate) fn text(&self, sizes: SizeFormat) -> Option<String> {
+        if self.bytes == 0 {
+            return None;
+        }
+        let percent = self.compressed.min(self.bytes) as u128 * 100 / self.bytes as u128;
+        Some(format!(
+            "{percent}% of {} was already compressed",
+            sizes.format(self.bytes)
+        ))
+    }
+}
+
+impl RunSummary {
+    pub(crate) fn elapsed_text(&self) -> String {
+        format_elapsed(self.elapsed)
+    }
+
+    /// Like `120.3 GiB -> 128.9 GiB (+8.6 GiB)`. The change can differ from the bytes saved, as
+    /// freed blocks may still be held by snapshots, and anything else writing to the volume
+    /// meanwhile counts too.
+    pub(crate) fn free_space_text(&self) -> Option<String> {
+        let (before, after) = self.free_space?;
+        let change = if after >= before {
+            format!("+{}", self.sizes.format(after - before))
+        } else {
+            format!("-{}", self.sizes.format(before - after))
+        };
+        Some(format!(
+            "{} -> {} ({change})",
+            self.sizes.format(before),
+            self.sizes.format(after)
+        ))
+    }
+
+    /// How fast files were read and written, like `37.0 MiB/s in, 12.3 MiB/s out`, or `None` for
+    /// a run too short or empty to measure.
+    pub(crate) fn throughput_text(&self) -> Option<String> {
+        throughput(&self.stats, self.elapsed, self.sizes)
+    }
+}
+
+/// `stats`' bytes before and after compression per second of `elapsed`.
+pub(crate) fn throughput(
+    stats: &CompressionStats,
+    elapsed: Duration,
+    sizes: SizeFormat,
+) -> Option<String> {
+    let secs = elapsed.as_secs_f64();
+    if secs < 0.001 || stats.bytes_in == 0 {
+        return None;
+    }
+    let per_sec = |bytes: u64| sizes.format((bytes as f64 / secs) as u64);
+    Some(format!(
+        "{}/s in, {}/s out",
+        per_sec(stats.bytes_in),
+        per_sec(stats.bytes_out)
+    ))
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: u64, noun: &str| {
+            if count == 1 {
+                format!("{count} {noun}")
+            } else {
+                format!("{count} {noun}s")
+            }
+        };
+        write!(
+            f,
+            "Compressed {} ({} skipped) in {}",
+            plural(self.stats.files_compressed, "file"),
+            self.stats.files_skipped,
+            plural(self.dirs as u64, "dir"),
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        write!(
+            f,
+            ", saving {} ({} -> {}) in {}",
+            self.sizes.format(self.stats.bytes_saved()),
+            self.sizes.format(self.stats.bytes_in),
+            self.sizes.format(self.stats.bytes_out),
+            format_elapsed(self.elapsed),
+        )?;
+        if let Some(throughput) = self.throughput_text() {
+            write!(f, " ({throughput})")?;
+        }
+        if let Some(baseline) = self.baseline.and_then(|baseline| baseline.text(self.sizes)) {
+            write!(f, "; {baseline}")?;
+        }
+        if let Some(free_space) = self.free_space_text() {
+            write!(f, "; free space {free_space}")?;
+        }
+        if let Some((done, total)) = self.completed
+            && total > 0
+        {
+            write!(
+                f,
+                "; stopped {}% of the way through {}",
+                (done.min(total) as u128 * 100 / total as u128),
+                self.sizes.format(total)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// `elapsed` rounded to tenths of a second below a minute, and to whole seconds above.
+pub(crate) fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::error::{Error, Result};
+use crate::reporter::Reporter;
+
+/// Parses an age such as `30d`, `12h`, `2w`, or `90m`. A bare number is taken as days.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{value}`"))?;
+    let seconds = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid duration `{value}`")),
+    };
+    Ok(Duration::from_secs(number.saturating_mul(seconds)))
+}
+
+/// Most recent access or modification time of any file directly inside `dir`.
+fn last_used(dir: &Path) -> Result<Option<SystemTime>> {
+    let mut latest = None;
+    for entry in fs::read_dir(dir).map_err(Error::read(dir))? {
+        let entry = entry.map_err(Error::read(dir))?;
+        let metadata = entry.metadata().map_err(Error::read(entry.path()))?;
+        for time in [metadata.accessed(), metadata.modified()]
+            .into_iter()
+            .flatten()
+        {
+            latest = latest.max(Some(time));
+        }
+    }
+    Ok(latest)
+}
+
+fn remove(path: &Path, progress: &dyn Reporter) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path).map_err(Error::read(path))?;
+    progress.println_verbose(|| format!("sweep {}", path.display()));
+    let mut size = 0;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path).map_err(Error::read(path))? {
+            size += remove(&entry.map_err(Error::read(path))?.path(), progress)?;
+        }
+        fs::remove_dir(path)
+    } else {
+        size = crate::disk::allocated_size(path).unwrap_or(metadata.len());
+        fs::remove_file(path)
+    }
+    .map_err(|source| Error::Remove {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(size)
+}
+
+/// Splits a cargo unit file name such as `libserde-0a1b2c3d.rlib` into its hash (`0a1b2c3d`).
+fn unit_hash(name: &str) -> Option<&str> {
+    let stem = name.split('.').next()?;
+    stem.rsplit_once('-').map(|(_, hash)| hash)
+}
+
+/// Deletes the artifacts of every unit in `dir` whose fingerprint has not been used within
+/// `older_than`, in the spirit of `cargo sweep --time`. Returns the number of bytes removed.
+///
+/// A unit's age comes from the newest access or modification time inside its `.fingerprint`
+/// directory; its outputs in `deps/`, `build/`, `examples/`, and `incremental/` share that
+/// directory's hash. Must be called with the work dir's lock held.
+pub(crate) fn sweep(dir: &Path, older_than: Duration, progress: &dyn Reporter) -> Result<u64> {
+    let fingerprints = dir.join(".fingerprint");
+    if !fingerprints.is_dir() {
+        return Ok(0);
+    }
+    let cutoff = SystemTime::now()
+        .checked_sub(older_than)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let mut stale = HashSet::new();
+    for entry in fs::read_dir(&fingerprints).map_err(Error::read(&fingerprints))? {
+        let entry = entry.map_err(Error::read(&fingerprints))?;
+        let name = entry.file_name();
+        // Cargo names units in UTF-8; anything else was not created by it.
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if !entry.path().is_dir() {
+            continue;
+        }
+        if last_used(&entry.path())?.is_none_or(|time| time < cutoff)
+            && let Some(hash) = unit_hash(name)
+        {
+            stale.insert(hash.to_owned());
+        }
+    }
+    if stale.is_empty() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for subdir in [".fingerprint", "build", "deps", "examples", "incremental"] {
+        let subdir = dir.join(subdir);
+        let Ok(entries) = fs::read_dir(&subdir) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry.map_err(Error::read(&subdir))?;
+            let name = entry.file_name();
+            if name
+                .to_str()
+                .and_then(unit_hash)
+                .is_some_and(|hash| stale.contains(hash))
+            {
+                removed += remove(&entry.path(), progress)?;
+            }
+        }
+    }
+    Ok(removed)
+}
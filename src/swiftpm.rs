@@ -0,0 +1,40 @@
+//! Swift Package Manager build dirs, which pile up next to Rust projects on the same machine and
+//! compress about as well as cargo's target dirs.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cargo_home::subdirs;
+
+/// The manifest that marks a SwiftPM package root.
+const MANIFEST_NAME: &str = "Package.swift";
+
+/// Where `swift build` puts everything, next to the manifest.
+const BUILD_DIR_NAME: &str = ".build";
+
+/// Directories not to descend into: `repositories` holds bare git mirrors of each dependency and
+/// `.git` the history of each checkout, whose packfiles are compressed already.
+pub(crate) const SKIP_DIRS: &[&str] = &["repositories", ".git"];
+
+/// The `.build` dir of the package containing `path`, or `None` if no directory from `path` up has
+/// a `Package.swift`.
+pub(crate) fn build_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| dir.join(MANIFEST_NAME).is_file())
+        .map(|root| root.join(BUILD_DIR_NAME))
+}
+
+/// Each Xcode project's `SourcePackages` dir under DerivedData, where Xcode resolves SwiftPM
+/// dependencies instead of `.build`.
+pub(crate) fn xcode_source_packages(home: &Path) -> io::Result<Vec<PathBuf>> {
+    let derived_data = home
+        .join("Library")
+        .join("Developer")
+        .join("Xcode")
+        .join("DerivedData");
+    Ok(subdirs(&derived_data)?
+        .into_iter()
+        .map(|project| project.join("SourcePackages"))
+        .filter(|dir| dir.is_dir())
+        .collect())
+}
@@ -0,0 +1,50 @@
+//! `tool-caches`: what Rust tools other than cargo keep under `~/Library/Caches`, which on a
+//! long-lived machine can rival the target dirs themselves.
+
+use std::path::{Path, PathBuf};
+
+/// A tool's cache and where it lives under `~/Library/Caches`.
+pub(crate) struct ToolCache {
+    pub(crate) name: &'static str,
+    /// Directory names the tool has used, newest first; each one found is compressed.
+    dirs: &'static [&'static str],
+}
+
+/// Every cache `tool-caches` knows the layout of.
+pub(crate) const KNOWN: &[ToolCache] = &[
+    // Proc-macro server builds and the sysroot metadata it caches between sessions.
+    ToolCache {
+        name: "rust-analyzer",
+        dirs: &["rust-analyzer"],
+    },
+    ToolCache {
+        name: "cargo-nextest",
+        dirs: &["nextest", "cargo-nextest"],
+    },
+    // Instrumented builds and profraw data kept between coverage runs.
+    ToolCache {
+        name: "cargo-tarpaulin",
+        dirs: &["cargo-tarpaulin", "tarpaulin"],
+    },
+    // The wasm-bindgen, wasm-opt, and cargo-generate binaries wasm-pack downloads.
+    ToolCache {
+        name: "wasm-pack",
+        dirs: &[".wasm-pack"],
+    },
+];
+
+/// `~/Library/Caches` under `home`.
+pub(crate) fn caches_dir(home: &Path) -> PathBuf {
+    home.join("Library").join("Caches")
+}
+
+impl ToolCache {
+    /// Those of this tool's cache dirs that exist in `caches`.
+    pub(crate) fn existing_dirs(&self, caches: &Path) -> Vec<PathBuf> {
+        self.dirs
+            .iter()
+            .map(|name| caches.join(name))
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+}
@@ -0,0 +1,445 @@
+//! `--tui`: a full-screen dashboard in place of the stacked progress bars, with a row per work dir,
+//! the run's throughput and savings so far, and the most recent skips.
+//!
+//! Log lines are held back while the dashboard is up and printed once it closes, so the output
+//! left in the terminal matches `--progress plain`.
+
+use applesauce::progress::{SkipReason, Task};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::crossterm::cursor::{Hide, Show};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Gauge, List, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::Verbosity;
+use crate::disk::SizeFormat;
+use crate::log_format::{LogFormat, LogLevel};
+use crate::reporter::Reporter;
+use crate::stats::CompressionStats;
+use crate::summary::describe_skip;
+
+/// How often the dashboard redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many of the latest skips the dashboard lists.
+const RECENT_SKIPS: usize = 8;
+
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    renderer: Mutex<Option<(Sender<()>, JoinHandle<()>)>>,
+    verbosity: Verbosity,
+    log_format: LogFormat,
+    size_format: SizeFormat,
+}
+
+/// Everything the dashboard shows, updated by the workers and read by the render thread.
+pub(crate) struct State {
+    pub(crate) started: Instant,
+    pub(crate) dirs: Vec<DirRow>,
+    pub(crate) bytes_done: u64,
+    pub(crate) bytes_total: u64,
+    /// Set once a pre-scan has fixed `bytes_total`.
+    pub(crate) total_fixed: bool,
+    pub(crate) saved: u64,
+    pub(crate) errors: usize,
+    pub(crate) sizes: SizeFormat,
+    pub(crate) recent_skips: VecDeque<String>,
+    /// Lines to print once the dashboard closes, and whether each goes to stderr.
+    log: Vec<(bool, String)>,
+    /// Set once the dashboard has closed; lines print straight away from then on.
+    closed: bool,
+}
+
+pub(crate) struct DirRow {
+    pub(crate) dir: PathBuf,
+    pub(crate) bytes_done: u64,
+    pub(crate) bytes_seen: u64,
+    pub(crate) files: u64,
+    pub(crate) saved: u64,
+    pub(crate) status: DirStatus,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum DirStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+impl State {
+    pub(crate) fn new(started: Instant) -> Self {
+        Self {
+            started,
+            dirs: Vec::new(),
+            bytes_done: 0,
+            bytes_total: 0,
+            total_fixed: false,
+            saved: 0,
+            errors: 0,
+            sizes: SizeFormat::Human,
+            recent_skips: VecDeque::new(),
+            log: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// The running work dir `path` is in: the deepest, as a `--generic` dir can contain a target
+    /// dir.
+    fn dir_of(&self, path: &Path) -> Option<usize> {
+        self.dirs
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.status == DirStatus::Running && path.starts_with(&row.dir))
+            .max_by_key(|(_, row)| row.dir.components().count())
+            .map(|(index, _)| index)
+    }
+
+    pub(crate) fn dir_started(&mut self, dir: &Path) {
+        self.dirs.push(DirRow {
+            dir: dir.to_path_buf(),
+            bytes_done: 0,
+            bytes_seen: 0,
+            files: 0,
+            saved: 0,
+            status: DirStatus::Running,
+        });
+    }
+
+    pub(crate) fn dir_finished(&mut self, dir: &Path, stats: Option<&CompressionStats>) {
+        if let Some(row) = self
+            .dirs
+            .iter_mut()
+            .find(|row| row.status == DirStatus::Running && row.dir == dir)
+        {
+            row.status = match stats {
+                Some(stats) => {
+                    row.saved = stats.bytes_saved();
+                    DirStatus::Done
+                }
+                None => DirStatus::Failed,
+            };
+        }
+    }
+
+    /// Holds `line` back until the dashboard closes, or prints it if it has.
+    fn print_line(&mut self, stderr: bool, line: String) {
+        if !self.closed {
+            self.log.push((stderr, line));
+        } else if stderr {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    }
+
+    pub(crate) fn skipped(&mut self, path: &Path, message: String) {
+        if self.recent_skips.len() == RECENT_SKIPS {
+            self.recent_skips.pop_front();
+        }
+        self.recent_skips
+            .push_back(format!("{}: {message}", path.display()));
+    }
+}
+
+impl Dashboard {
+    /// Takes over the terminal until [`Reporter::finish`], or until dropped.
+    pub fn new(verbosity: Verbosity) -> io::Result<Self> {
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        let state = Arc::new(Mutex::new(State::new(Instant::now())));
+        let (stop, stopped) = mpsc::channel();
+        let renderer = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                let Ok(mut terminal) = Terminal::new(CrosstermBackend::new(io::stdout())) else {
+                    return;
+                };
+                loop {
+                    let _ = draw(&mut terminal, &state.lock().unwrap());
+                    match stopped.recv_timeout(REDRAW_INTERVAL) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => break,
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            state,
+            renderer: Mutex::new(Some((stop, renderer))),
+            verbosity,
+            log_format: LogFormat::Plain,
+            size_format: SizeFormat::Human,
+        })
+    }
+
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    pub fn with_size_format(mut self, size_format: SizeFormat) -> Self {
+        self.state.lock().unwrap().sizes = size_format;
+        self.size_format = size_format;
+        self
+    }
+
+    fn print_line(&self, stderr: bool, line: String) {
+        self.state.lock().unwrap().print_line(stderr, line);
+    }
+
+    /// Stops the render thread, gives the terminal back, and prints the held-back log lines.
+    fn close(&self) {
+        let Some((stop, renderer)) = self.renderer.lock().unwrap().take() else {
+            return;
+        };
+        let _ = stop.send(());
+        let _ = renderer.join();
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let log = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            std::mem::take(&mut state.log)
+        };
+        for (stderr, line) in log {
+            if stderr {
+                eprintln!("{line}");
+            } else {
+                println!("{line}");
+            }
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct DashboardTask {
+    state: Arc<Mutex<State>>,
+    dir: Option<usize>,
+    verbosity: Verbosity,
+    log_format: LogFormat,
+}
+
+impl Reporter for Dashboard {
+    fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    fn size_format(&self) -> SizeFormat {
+        self.size_format
+    }
+
+    fn message(&self, verbosity: Verbosity, message: &str) {
+        let level = LogLevel::for_verbosity(verbosity);
+        self.print_line(false, self.log_format.format_line(level, None, message));
+    }
+
+    fn error(&self, path: &Path, message: &str) {
+        self.state.lock().unwrap().errors += 1;
+        self.print_line(
+            true,
+            self.log_format
+                .format_line(LogLevel::Error, Some(path), message),
+        );
+    }
+
+    fn file_skipped(&self, path: &Path, why: SkipReason) {
+        // Files the walk already rejected were never queued, and are mostly directories.
+        if !matches!(why, SkipReason::NotFile) {
+            let mut state = self.state.lock().unwrap();
+            if state.total_fixed
+                && let Ok(metadata) = std::fs::symlink_metadata(path)
+            {
+                state.bytes_done += metadata.len();
+            }
+            state.skipped(path, describe_skip(&why, self.size_format));
+        }
+        if self.verbosity >= Verbosity::for_skip_reason(&why) {
+            self.print_line(
+                false,
+                self.log_format.format_line(
+                    LogLevel::Skipped,
+                    Some(path),
+                    &describe_skip(&why, self.size_format),
+                ),
+            );
+        }
+    }
+
+    fn file_task(&self, path: &Path, size: u64) -> Box<dyn Task + Send + Sync> {
+        let mut state = self.state.lock().unwrap();
+        let dir = state.dir_of(path);
+        if let Some(index) = dir {
+            let row = &mut state.dirs[index];
+            row.bytes_seen += size;
+            row.files += 1;
+        }
+        Box::new(DashboardTask {
+            state: Arc::clone(&self.state),
+            dir,
+            verbosity: self.verbosity,
+            log_format: self.log_format,
+        })
+    }
+
+    fn file_compressed(&self, path: &Path, bytes_in: u64, bytes_out: u64) {
+        let saved = bytes_in.saturating_sub(bytes_out);
+        let mut state = self.state.lock().unwrap();
+        state.saved += saved;
+        if let Some(index) = state.dir_of(path) {
+            state.dirs[index].saved += saved;
+        }
+    }
+
+    fn files_queued(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        if !state.total_fixed {
+            state.bytes_total += bytes;
+        }
+    }
+
+    fn total_known(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total_fixed = true;
+        state.bytes_total = bytes;
+    }
+
+    fn work_dir_started(&self, dir: &Path) {
+        self.state.lock().unwrap().dir_started(dir);
+    }
+
+    fn work_dir_finished(&self, dir: &Path, stats: Option<&CompressionStats>) {
+        self.state.lock().unwrap().dir_finished(dir, stats);
+    }
+
+    fn finish(&self) {
+        self.close();
+    }
+}
+
+impl Task for DashboardTask {
+    fn increment(&self, amt: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes_done += amt;
+        if let Some(index) = self.dir {
+            state.dirs[index].bytes_done += amt;
+        }
+    }
+
+    fn error(&self, message: &str) {
+        let line = self.log_format.format_line(LogLevel::Error, None, message);
+        let mut state = self.state.lock().unwrap();
+        state.errors += 1;
+        state.print_line(true, line);
+    }
+
+    fn not_compressible_enough(&self, path: &Path) {
+        if self.verbosity >= Verbosity::Verbose {
+            let line = self.log_format.format_line(
+                LogLevel::Verbose,
+                Some(path),
+                "Not compressible enough, file grew",
+            );
+            self.state.lock().unwrap().print_line(false, line);
+        }
+    }
+}
+
+/// Draws one frame of the dashboard for `state` on `terminal`.
+pub(crate) fn draw<B: Backend>(terminal: &mut Terminal<B>, state: &State) -> Result<(), B::Error> {
+    terminal.draw(|frame| render(frame, state)).map(|_| ())
+}
+
+fn render(frame: &mut Frame, state: &State) {
+    let sizes = state.sizes;
+    let [header, dirs, skips] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Length(RECENT_SKIPS as u16 + 2),
+    ])
+    .areas(frame.area());
+
+    let elapsed = state.started.elapsed();
+    let mut label = format!(
+        "{} / {}, saved {}",
+        sizes.format(state.bytes_done),
+        sizes.format(state.bytes_total),
+        sizes.format(state.saved),
+    );
+    if elapsed >= Duration::from_secs(1) {
+        let per_second = (state.bytes_done as f64 / elapsed.as_secs_f64()) as u64;
+        label.push_str(&format!(", {}/s", sizes.format(per_second)));
+    }
+    if state.errors > 0 {
+        label.push_str(&format!(", {} error(s)", state.errors));
+    }
+    let ratio = if state.bytes_total == 0 {
+        0.0
+    } else {
+        (state.bytes_done as f64 / state.bytes_total as f64).min(1.0)
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(format!(" Total, {}s ", elapsed.as_secs())))
+            .gauge_style(Style::new().fg(Color::Green))
+            .ratio(ratio)
+            .label(label),
+        header,
+    );
+
+    let rows = state.dirs.iter().map(|row| {
+        let status = match row.status {
+            DirStatus::Running if row.bytes_seen == 0 => "scanning".to_owned(),
+            DirStatus::Running => format!("{}%", row.bytes_done * 100 / row.bytes_seen),
+            DirStatus::Done => "done".to_owned(),
+            DirStatus::Failed => "failed".to_owned(),
+        };
+        let style = match row.status {
+            DirStatus::Running => Style::new(),
+            DirStatus::Done => Style::new().fg(Color::DarkGray),
+            DirStatus::Failed => Style::new().fg(Color::Red),
+        };
+        Row::new([
+            row.dir.display().to_string(),
+            status,
+            row.files.to_string(),
+            sizes.format(row.saved),
+        ])
+        .style(style)
+    });
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(11),
+            ],
+        )
+        .header(
+            Row::new(["Dir", "Progress", "Files", "Saved"])
+                .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(" Work dirs ")),
+        dirs,
+    );
+
+    frame.render_widget(
+        List::new(state.recent_skips.iter().map(String::as_str))
+            .block(Block::bordered().title(" Recent skips ")),
+        skips,
+    );
+}
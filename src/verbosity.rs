@@ -0,0 +1,28 @@
+use applesauce::progress::SkipReason;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+impl Verbosity {
+    /// The minimum verbosity at which a skipped file is worth reporting.
+    pub(crate) fn for_skip_reason(why: &SkipReason) -> Self {
+        match why {
+            SkipReason::NotFile
+            | SkipReason::AlreadyCompressed
+            | SkipReason::NotCompressed
+            | SkipReason::EmptyFile
+            // hard links are common in target directories -- ignore them by default
+            | SkipReason::HardLink => Verbosity::Verbose,
+            SkipReason::TooLarge(_)
+            | SkipReason::ReadError(_)
+            | SkipReason::ZfsFilesystem
+            | SkipReason::HasRequiredXattr
+            | SkipReason::FsNotSupported => Verbosity::Normal,
+        }
+    }
+}
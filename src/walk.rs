@@ -0,0 +1,466 @@
+use applesauce::progress::SkipReason;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Condvar, Mutex};
+use std::time::SystemTime;
+
+use crate::checkpoint::CHECKPOINT_NAME;
+use crate::dir_state::{self, DirState, STATE_NAME};
+use crate::error::{Error, Result};
+use crate::fd_limit;
+use crate::hygiene::NEVER_INDEX_NAME;
+use crate::lock_holder::HOLDER_NAME;
+use crate::policy::{self, PolicyAction};
+use crate::ratio_cache::{Class, RATIOS_NAME, RatioCache};
+use crate::reporter::Reporter;
+use crate::since;
+use crate::{CARGO_LOCK_NAME, FINGERPRINT_DIR, WorkDirOptions};
+
+/// Extensions of files that are almost always compressed already, such as archives, images, and
+/// fonts that build scripts download or generate into `OUT_DIR`.
+///
+/// Matched case-insensitively against the end of the file name, so `br` also covers `.wasm.br`.
+pub const DEFAULT_SKIP_EXTENSIONS: &[&str] = &[
+    "7z", "br", "bz2", "crate", "gif", "gz", "jpeg", "jpg", "lz4", "png", "tgz", "webp", "woff",
+    "woff2", "xz", "zip", "zst",
+];
+
+fn has_skipped_extension(name: &OsStr, extensions: &[String]) -> bool {
+    let name = name.as_encoded_bytes().to_ascii_lowercase();
+    extensions.iter().any(|extension| {
+        name.strip_suffix(extension.as_bytes())
+            .is_some_and(|stem| stem.len() > 1 && stem.ends_with(b"."))
+    })
+}
+
+/// Mach-O magic numbers for 32- and 64-bit images in either byte order.
+const MACH_O_MAGICS: &[[u8; 4]] = &[
+    [0xfe, 0xed, 0xfa, 0xce],
+    [0xce, 0xfa, 0xed, 0xfe],
+    [0xfe, 0xed, 0xfa, 0xcf],
+    [0xcf, 0xfa, 0xed, 0xfe],
+];
+
+/// Universal binaries' magic, which Java class files share. What follows it tells them apart:
+/// a universal binary's arch count is small, a class file's version is at least 45.
+const FAT_MAGIC: [u8; 4] = [0xca, 0xfe, 0xba, 0xbe];
+const FAT_CIGAM: [u8; 4] = [0xbe, 0xba, 0xfe, 0xca];
+const MAX_FAT_ARCHS: u32 = 30;
+
+pub(crate) fn is_mach_o(path: &Path) -> Result<bool> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        // The compressor can't read it either, and reports that for just this file.
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => return Ok(false),
+        Err(error) => return Err(Error::read(path)(error)),
+    };
+    let mut header = Vec::with_capacity(8);
+    file.take(8)
+        .read_to_end(&mut header)
+        .map_err(Error::read(path))?;
+    let Some((&magic, rest)) = header.split_first_chunk::<4>() else {
+        return Ok(false);
+    };
+    let archs = |count: u32| (1..=MAX_FAT_ARCHS).contains(&count);
+    Ok(match (magic, <[u8; 4]>::try_from(rest)) {
+        (FAT_MAGIC, Ok(count)) => archs(u32::from_be_bytes(count)),
+        (FAT_CIGAM, Ok(count)) => archs(u32::from_le_bytes(count)),
+        (magic, _) => MACH_O_MAGICS.contains(&magic),
+    })
+}
+
+/// decmpfs keeps compressed data up to this size inline in its xattr. Files smaller than this
+/// occupy one block at most, so compressing one saves no more than that while costing as much
+/// per-file work as a large file.
+pub const INLINE_THRESHOLD: u64 = 3802;
+
+/// The dirs directly inside a profile dir that hold its dependencies and build script output,
+/// which rarely change once built. `--deps-only` compresses only these; `--artifacts-only` leaves
+/// them out.
+pub const DEPS_DIRS: &[&str] = &["deps", "build"];
+
+/// How many threads read directories at once within one work dir. More than this mostly contends
+/// on the filesystem's locks rather than finishing sooner.
+pub(crate) const WALK_THREADS: usize = 8;
+
+/// What every directory read during one [`collect_inputs`] shares.
+struct Walk<'a> {
+    dir: &'a Path,
+    options: &'a WorkDirOptions,
+    progress: &'a dyn Reporter,
+    skip_extensions: Vec<String>,
+    device: u64,
+    /// With `--skip-unchanged-dirs`, the state saved by the last clean run.
+    previous: Option<&'a DirState>,
+    /// With `--ratio-cache`, what earlier runs saw each class of file compress to.
+    ratios: Option<&'a RatioCache>,
+}
+
+/// Directories waiting to be read, and how many are being read right now, so idle threads know
+/// whether more may turn up.
+struct Queue {
+    pending: Vec<(PathBuf, usize)>,
+    busy: usize,
+    error: Option<Error>,
+    /// Set once whoever is receiving the files stops listening.
+    abandoned: bool,
+}
+
+/// One directory's files to compress, its subdirectories with their depth, and what to record
+/// about it for the next run.
+struct DirContents {
+    files: Vec<PathBuf>,
+    subdirs: Vec<(PathBuf, usize)>,
+    recorded: Option<(String, dir_state::Listing)>,
+}
+
+/// Recursively lists the regular files under `dir` that should be handed to the compressor.
+///
+/// Directories are read on up to [`WALK_THREADS`] threads, since on large trees the walk alone
+/// would otherwise take minutes. The result is sorted, so it doesn't depend on their timing.
+pub(crate) fn collect_inputs(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+) -> Result<Vec<PathBuf>> {
+    let inputs = Mutex::new(Vec::new());
+    walk(dir, options, progress, None, None, &|files| {
+        inputs.lock().unwrap().extend(files);
+        true
+    })?;
+    let mut inputs = inputs.into_inner().unwrap();
+    inputs.sort();
+    Ok(inputs)
+}
+
+/// [`collect_inputs`], sending each file to `files` as it is found instead of gathering them, so
+/// a full queue holds the walk back until the compressor catches up. Skips directories that
+/// `previous` recorded and that haven't been modified since, and returns the state to save if
+/// this run finishes cleanly. Stops early if `files` is disconnected.
+pub(crate) fn stream_inputs(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+    previous: Option<&DirState>,
+    ratios: Option<&RatioCache>,
+    files: SyncSender<PathBuf>,
+) -> Result<DirState> {
+    walk(dir, options, progress, previous, ratios, &|found| {
+        found.into_iter().all(|file| files.send(file).is_ok())
+    })
+}
+
+/// Walks `dir`, handing each directory's files to `emit`, which returns whether to carry on.
+fn walk(
+    dir: &Path,
+    options: &WorkDirOptions,
+    progress: &dyn Reporter,
+    previous: Option<&DirState>,
+    ratios: Option<&RatioCache>,
+    emit: &(dyn Fn(Vec<PathBuf>) -> bool + Sync),
+) -> Result<DirState> {
+    let walk = Walk {
+        dir,
+        options,
+        progress,
+        skip_extensions: options
+            .skip_extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_ascii_lowercase())
+            .collect(),
+        device: fs::metadata(dir).map_err(Error::read(dir))?.dev(),
+        previous,
+        ratios,
+    };
+
+    let queue = Mutex::new(Queue {
+        pending: vec![(dir.to_path_buf(), 0)],
+        busy: 0,
+        error: None,
+        abandoned: false,
+    });
+    let changed = Condvar::new();
+    let state = Mutex::new(DirState::default());
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |cpus| cpus.get().min(options.walk_threads))
+        .max(1);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                loop {
+                    let (current, depth) = {
+                        let mut queue = queue.lock().unwrap();
+                        loop {
+                            if queue.error.is_some() || queue.abandoned {
+                                return;
+                            }
+                            // Whatever was found so far is left to the stop check before the
+                            // first batch, so the dir is reported as cut short.
+                            if walk.options.stop.should_stop() {
+                                walk.options.stop.mark_cut_short();
+                                return;
+                            }
+                            if let Some(next) = queue.pending.pop() {
+                                queue.busy += 1;
+                                break next;
+                            }
+                            if queue.busy == 0 {
+                                return;
+                            }
+                            queue = changed.wait(queue).unwrap();
+                        }
+                    };
+                    let result = read_dir(&walk, &current, depth);
+                    // Files go out before the queue is locked, so a full stream only holds up
+                    // this thread.
+                    let result = result.map(|contents| {
+                        let carry_on = emit(contents.files);
+                        (contents.subdirs, contents.recorded, carry_on)
+                    });
+                    let mut queue = queue.lock().unwrap();
+                    queue.busy -= 1;
+                    match result {
+                        Ok((subdirs, recorded, carry_on)) => {
+                            queue.pending.extend(subdirs);
+                            queue.abandoned |= !carry_on;
+                            if let Some((key, listing)) = recorded {
+                                state.lock().unwrap().insert(key, listing);
+                            }
+                        }
+                        Err(error) => {
+                            queue.error.get_or_insert(error);
+                        }
+                    }
+                    changed.notify_all();
+                }
+            });
+        }
+    });
+
+    if let Some(error) = queue.into_inner().unwrap().error {
+        return Err(error);
+    }
+    Ok(state.into_inner().unwrap())
+}
+
+/// Reads one directory, returning the files in it to compress and the subdirectories to read next.
+fn read_dir(walk: &Walk<'_>, current: &Path, depth: usize) -> Result<DirContents> {
+    let options = walk.options;
+    let progress = walk.progress;
+    let key = walk
+        .previous
+        .and_then(|_| dir_state::relative_key(walk.dir, current));
+    if let (Some(previous), Some(key)) = (walk.previous, &key)
+        && let Ok(mtime) = dir_state::mtime_ns(current)
+        && let Some(listing) = previous.unchanged(key, mtime)
+    {
+        progress.println_verbose(|| {
+            format!("{}: Skipped: Unchanged since last run", current.display())
+        });
+        let subdirs = listing
+            .subdirs
+            .iter()
+            .map(|name| current.join(name))
+            .filter(|path| path.is_dir())
+            .map(|path| (path, depth + 1))
+            .collect();
+        return Ok(DirContents {
+            files: Vec::new(),
+            subdirs,
+            recorded: Some((key.clone(), listing.clone())),
+        });
+    }
+    let entries = match fd_limit::retrying(|| fs::read_dir(current)) {
+        Ok(entries) => entries,
+        // Root-owned dirs left by a `sudo` build are common, and shouldn't fail the whole work dir.
+        Err(error) if current != walk.dir && error.kind() == io::ErrorKind::PermissionDenied => {
+            progress.file_skipped(current, SkipReason::ReadError(error));
+            return Ok(DirContents {
+                files: Vec::new(),
+                subdirs: Vec::new(),
+                recorded: None,
+            });
+        }
+        Err(error) => return Err(Error::read(current)(error)),
+    };
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(Error::read(current))?;
+        let path = entry.path();
+        if current == walk.dir
+            && [CARGO_LOCK_NAME, options.lock_name.as_str()]
+                .iter()
+                .any(|name| entry.file_name() == OsStr::new(name))
+        {
+            progress.println_verbose(|| {
+                format!(
+                    "exclude {} from {}",
+                    entry.file_name().to_string_lossy(),
+                    walk.dir.display()
+                )
+            });
+            continue;
+        }
+        if current == walk.dir
+            && [
+                CHECKPOINT_NAME,
+                NEVER_INDEX_NAME,
+                HOLDER_NAME,
+                STATE_NAME,
+                RATIOS_NAME,
+            ]
+            .iter()
+            .any(|name| entry.file_name() == OsStr::new(name))
+        {
+            continue;
+        }
+
+        let file_type = entry.file_type().map_err(Error::read(&path))?;
+        if current == walk.dir && (options.deps_only || options.artifacts_only) {
+            let deps = file_type.is_dir()
+                && DEPS_DIRS
+                    .iter()
+                    .any(|name| entry.file_name() == OsStr::new(name));
+            if options.deps_only && !deps {
+                progress.println_verbose(|| format!("{}: Skipped: Not deps", path.display()));
+                continue;
+            }
+            let build_state = file_type.is_dir()
+                && [FINGERPRINT_DIR, "incremental"]
+                    .iter()
+                    .any(|name| entry.file_name() == OsStr::new(name));
+            if options.artifacts_only && (deps || build_state) {
+                progress.println_verbose(|| {
+                    format!("{}: Skipped: Not a final artifact", path.display())
+                });
+                continue;
+            }
+        }
+        if file_type.is_dir() {
+            if options
+                .skip_dirs
+                .iter()
+                .any(|name| entry.file_name() == OsStr::new(name))
+            {
+                progress
+                    .println_verbose(|| format!("{}: Skipped: Excluded directory", path.display()));
+            } else if path.strip_prefix(walk.dir).is_ok_and(|relative| {
+                policy::for_dir(&options.dir_policies, relative)
+                    .is_some_and(|policy| policy.action() == PolicyAction::Skip)
+            }) {
+                progress.println_verbose(|| {
+                    format!("{}: Skipped: Excluded by dir policy", path.display())
+                });
+            } else if let Some(device) = (!options.cross_device)
+                .then(|| fs::symlink_metadata(&path).map(|metadata| metadata.dev()))
+                && !device.as_ref().is_ok_and(|&device| device == walk.device)
+            {
+                match device {
+                    Ok(_) => progress.println_normal(|| {
+                        format!("{}: Skipped: On another filesystem", path.display())
+                    }),
+                    // Gone or unreadable since it was listed, which is no reason to give up on
+                    // the rest of the work dir.
+                    Err(error) => progress.file_skipped(&path, SkipReason::ReadError(error)),
+                }
+            } else if options
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+            {
+                progress.println_verbose(|| {
+                    format!("{}: Skipped: Deeper than maximum depth", path.display())
+                });
+            } else {
+                subdirs.push((path, depth + 1));
+            }
+        } else if !file_type.is_file() {
+            progress.file_skipped(&path, SkipReason::NotFile);
+        } else if has_skipped_extension(&entry.file_name(), &walk.skip_extensions) {
+            progress.println_verbose(|| {
+                format!("{}: Skipped: Incompressible extension", path.display())
+            });
+        } else if !options.packages.is_empty()
+            && !path.strip_prefix(walk.dir).is_ok_and(|relative| {
+                options
+                    .packages
+                    .iter()
+                    .any(|package| package.matches(relative))
+            })
+        {
+            continue;
+        } else {
+            let metadata = entry.metadata().map_err(Error::read(&path))?;
+            if metadata.len() == 0 {
+                progress.file_skipped(&path, SkipReason::EmptyFile);
+            } else if metadata.len() < options.min_file_size {
+                progress.println_verbose(|| format!("{}: Skipped: Tiny file", path.display()));
+                progress.file_too_small(&path);
+            } else if walk
+                .ratios
+                .is_some_and(|ratios| ratios.never_compresses(&Class::of(&path, metadata.len())))
+            {
+                progress.println_verbose(|| {
+                    format!("{}: Skipped: Never compressed well before", path.display())
+                });
+            } else if (options.modified_since.is_some() || options.modified_before.is_some())
+                && !metadata.modified().is_ok_and(|modified| {
+                    since::in_window(modified, options.modified_since, options.modified_before)
+                })
+            {
+                progress.println_verbose(|| {
+                    format!(
+                        "{}: Skipped: Modified outside --since/--before",
+                        path.display()
+                    )
+                });
+            } else if let Some(idle) = options.members_idle_for
+                && SystemTime::now()
+                    .duration_since(metadata.modified().map_err(Error::read(&path))?)
+                    .map_or(true, |age| age < idle)
+                && path.strip_prefix(walk.dir).is_ok_and(|relative| {
+                    options
+                        .members
+                        .iter()
+                        .any(|member| member.matches(relative))
+                })
+            {
+                progress.println_verbose(|| {
+                    format!(
+                        "{}: Skipped: Workspace member rebuilt recently",
+                        path.display()
+                    )
+                });
+            } else if options.skip_bins && current == walk.dir && metadata.mode() & 0o111 != 0 {
+                progress.println_verbose(|| format!("{}: Skipped: Final binary", path.display()));
+            } else if options.skip_executables && is_mach_o(&path)? {
+                progress.println_verbose(|| format!("{}: Skipped: Mach-O binary", path.display()));
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    // A listing is only worth recording if every subdirectory name can be written back.
+    let recorded = key.and_then(|key| {
+        let subdirs = subdirs
+            .iter()
+            .map(|(path, _)| path.file_name()?.to_str().map(ToOwned::to_owned))
+            .collect::<Option<Vec<_>>>()?;
+        Some((
+            key,
+            dir_state::Listing {
+                mtime_ns: 0,
+                subdirs,
+            },
+        ))
+    });
+    Ok(DirContents {
+        files,
+        subdirs,
+        recorded,
+    })
+}
@@ -0,0 +1,49 @@
+//! `--wasm-dist`: the output dirs wasm toolchains write next to a package, outside the target dir,
+//! where they keep another copy of the `.wasm` and JS glue already built under
+//! `target/wasm32-*`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Trunk's config file; its presence, or an `index.html`, marks a trunk app.
+const TRUNK_CONFIG: &str = "Trunk.toml";
+
+/// wasm-pack's default `--out-dir`.
+const WASM_PACK_OUT_DIR: &str = "pkg";
+
+/// The trunk `dist` dir and wasm-pack `pkg` dir of the package in `package_dir`, where each exists.
+pub(crate) fn output_dirs(package_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if package_dir.join(TRUNK_CONFIG).is_file() || package_dir.join("index.html").is_file() {
+        let dist = package_dir.join(trunk_dist(package_dir).unwrap_or_else(|| "dist".to_owned()));
+        if dist.is_dir() {
+            dirs.push(dist);
+        }
+    }
+    let pkg = package_dir.join(WASM_PACK_OUT_DIR);
+    if pkg.join("package.json").is_file() && contains_wasm(&pkg) {
+        dirs.push(pkg);
+    }
+    dirs
+}
+
+/// `build.dist` from the package's `Trunk.toml`, if it sets one.
+fn trunk_dist(package_dir: &Path) -> Option<String> {
+    let content = fs::read_to_string(package_dir.join(TRUNK_CONFIG)).ok()?;
+    let config: toml::Value = toml::from_str(&content).ok()?;
+    config
+        .get("build")?
+        .get("dist")?
+        .as_str()
+        .map(ToOwned::to_owned)
+}
+
+/// Whether `dir` has a `.wasm` file directly inside it, which tells wasm-pack's output apart from
+/// an unrelated `pkg` dir.
+fn contains_wasm(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.path().extension().is_some_and(|ext| ext == "wasm"))
+    })
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "cli")]
+
 use std::fs;
 use std::process::Command;
 
@@ -31,9 +33,41 @@ fn command_succeeds_and_prints_compressed() {
     assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Compressed"));
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("target/debug: ") && line.contains(" -> ")),
+        "{stdout}"
+    );
     assert!(!stdout.contains("exclude .cargo-lock"));
 }
 
+#[test]
+fn dir_summaries_are_printed_in_order_of_dir() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    for profile in ["release", "debug"] {
+        let dir = temp.path().join("target").join(profile);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("artifact.bin"), b"artifact").unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--profile", "release", "--profile", "dev"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let dirs: Vec<_> = stdout
+        .lines()
+        .filter_map(|line| line.split_once(": ").map(|(dir, _)| dir))
+        .filter(|dir| dir.starts_with("target/"))
+        .collect();
+    assert_eq!(dirs, ["target/debug", "target/release"], "{stdout}");
+}
+
 #[test]
 fn command_verbose_prints_lockfile_exclusion() {
     let temp = tempdir().unwrap();
@@ -71,3 +105,1098 @@ fn command_returns_non_zero_on_failure() {
 
     assert!(!output.status.success());
 }
+
+#[test]
+fn command_skips_when_free_space_is_above_threshold() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    fs::create_dir_all(temp.path().join("target")).unwrap();
+    fs::write(temp.path().join("target").join("debug"), b"not-a-dir").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--profile", "dev", "--only-if-free-below", "1B"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("skip:"));
+
+    // Before the first build, the free space is measured where the target dir will be.
+    fs::remove_dir_all(temp.path().join("target")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--profile", "dev", "--only-if-free-below", "1B"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("skip:"));
+}
+
+#[test]
+fn command_prints_nothing_with_progress_none() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--profile", "dev", "--only-if-free-below", "1B"])
+        .args(["--progress", "none", "-v"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.is_empty(), "{output:?}");
+}
+
+#[test]
+fn command_lists_work_dirs_without_compressing() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    let debug_dir = temp.path().join("target").join("debug");
+    fs::create_dir_all(&debug_dir).unwrap();
+    fs::create_dir_all(temp.path().join("target").join("release")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--list")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    assert!(lines[0].ends_with("debug"));
+    assert!(lines[1].ends_with("release"));
+    assert!(!debug_dir.join(".cargo-lock").exists());
+}
+
+#[test]
+fn command_lists_wasm_dist_dirs_when_asked() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+    fs::write(
+        temp.path().join("Trunk.toml"),
+        "[build]\ndist = \"public\"\n",
+    )
+    .unwrap();
+    fs::create_dir_all(temp.path().join("public")).unwrap();
+    let pkg = temp.path().join("pkg");
+    fs::create_dir_all(&pkg).unwrap();
+    fs::write(pkg.join("package.json"), "{}").unwrap();
+    fs::write(pkg.join("tmp_ws_bg.wasm"), b"\0asm").unwrap();
+
+    let list = |extra: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .arg("--list")
+            .args(extra)
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = list(&["--wasm-dist"]);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{stdout}");
+    assert!(lines[0].ends_with("pkg"), "{stdout}");
+    assert!(lines[1].ends_with("public"), "{stdout}");
+    assert!(lines[2].ends_with("debug"), "{stdout}");
+    assert_eq!(list(&[]).lines().count(), 1);
+}
+
+#[test]
+fn command_interactive_needs_a_terminal() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--interactive")
+        .current_dir(temp.path())
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--interactive needs a terminal"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn command_prints_target_dir() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--print-target-dir")
+        .env("CARGO_TARGET_DIR", temp.path().join("elsewhere"))
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.trim_end(),
+        temp.path().join("elsewhere").display().to_string()
+    );
+}
+
+#[test]
+fn command_summary_only_prints_one_line() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    let debug_dir = temp.path().join("target").join("debug");
+    fs::create_dir_all(&debug_dir).unwrap();
+    fs::write(debug_dir.join("artifact.bin"), b"artifact").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--summary-only")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{stdout}");
+    assert!(lines[0].contains("in 1 dir, saving"), "{stdout}");
+}
+
+#[test]
+fn command_adds_missing_cachedir_tag() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    let debug_dir = temp.path().join("target").join("debug");
+    fs::create_dir_all(&debug_dir).unwrap();
+    fs::write(debug_dir.join("artifact.bin"), b"artifact").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--cachedir-tag")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let tag = fs::read_to_string(temp.path().join("target").join("CACHEDIR.TAG")).unwrap();
+    assert!(tag.starts_with("Signature: 8a477f597d28d172789f06886806bc55"));
+}
+
+#[test]
+fn command_selects_host_or_cross_dirs() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+
+    let target = temp.path().join("target");
+    fs::create_dir_all(target.join("debug")).unwrap();
+    fs::create_dir_all(target.join("thumbv7em-none-eabihf").join("release")).unwrap();
+
+    let list = |flag: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .args(["--list", flag])
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let host = list("--host-only");
+    assert_eq!(host.lines().count(), 1, "{host}");
+    assert!(host.trim_end().ends_with("debug"), "{host}");
+
+    let cross = list("--cross-only");
+    assert_eq!(cross.lines().count(), 1, "{cross}");
+    assert!(cross.trim_end().ends_with("release"), "{cross}");
+
+    // A target dir named like a triple is still the host's.
+    fs::rename(&target, temp.path().join("my-build-out")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--list", "--host-only"])
+        .env("CARGO_TARGET_DIR", temp.path().join("my-build-out"))
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let host = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(host.lines().count(), 1, "{host}");
+    assert!(host.trim_end().ends_with("debug"), "{host}");
+}
+
+#[test]
+fn command_dedups_shared_target_dir_across_projects() {
+    let temp = tempdir().unwrap();
+    for project in ["a", "b"] {
+        fs::create_dir(temp.path().join(project)).unwrap();
+        write_workspace(&temp.path().join(project));
+    }
+    let shared = temp.path().join("shared-target");
+    fs::create_dir_all(shared.join("debug")).unwrap();
+    fs::create_dir_all(temp.path().join("b").join("target").join("release")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--list", "--project", "a", "--project", "b"])
+        .env("CARGO_TARGET_DIR", &shared)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{stdout}");
+    assert!(lines[0].ends_with("debug"), "{stdout}");
+}
+
+#[test]
+fn doctor_reports_findings() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("doctor")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("ok: cargo "), "{stdout}");
+    assert!(stdout.contains("ok: target directory is "), "{stdout}");
+    assert_eq!(
+        output.status.success(),
+        !stdout.lines().any(|line| line.starts_with("error: ")),
+        "{stdout}"
+    );
+    assert!(
+        !temp
+            .path()
+            .join("target")
+            .join(".apfs-compress-doctor")
+            .exists()
+    );
+}
+
+#[test]
+fn command_strict_fails_when_nothing_is_compressed() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--strict")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("strict mode: no files were compressed"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn command_writes_gha_annotations_and_job_summary() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+    let step_summary = temp.path().join("step-summary.md");
+    fs::write(&step_summary, "earlier step\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--output", "gha"])
+        .env("GITHUB_STEP_SUMMARY", &step_summary)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("::notice title=cargo apfs-compress::Compressed ")),
+        "{stdout}"
+    );
+    let markdown = fs::read_to_string(&step_summary).unwrap();
+    assert!(
+        markdown.starts_with("earlier step\n### cargo apfs-compress\n"),
+        "{markdown}"
+    );
+}
+
+#[test]
+fn command_writes_html_report() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    let debug = temp.path().join("target").join("debug");
+    fs::create_dir_all(&debug).unwrap();
+    fs::write(debug.join("libfoo.rlib"), vec![b'a'; 8192]).unwrap();
+    let report = temp.path().join("report.html");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--report")
+        .arg(&report)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let html = fs::read_to_string(&report).unwrap();
+    assert!(html.contains("<h2>Directories</h2>"), "{html}");
+    assert!(html.contains(".rlib"), "{html}");
+}
+
+#[test]
+fn command_shows_history_trend_after_second_run() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+    let history = temp.path().join("history.jsonl");
+
+    let run = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .arg("--history")
+            .arg(&history)
+            .current_dir(temp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert!(!run().contains("Last "));
+    let stdout = run();
+    assert!(stdout.contains("Last 2 runs: saved ▁▁"), "{stdout}");
+    assert_eq!(fs::read_to_string(&history).unwrap().lines().count(), 2);
+}
+
+#[test]
+fn config_show_prints_values_with_sources() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join(".cargo")).unwrap();
+    fs::write(
+        temp.path().join(".cargo").join("config.toml"),
+        "[apfs-compress]\ncompression = \"zlib\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args([
+            "--small-file-cutoff",
+            "4KiB",
+            "--no-metadata",
+            "config",
+            "show",
+        ])
+        .env("CARGO_HOME", temp.path().join("no-cargo-home"))
+        .env("CARGO_APFS_COMPRESS_SMALL_FILE_COMPRESSION", "zlib")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("compression = \"zlib\"  # ") && stdout.contains(".cargo/config.toml\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "small-file-compression = \"zlib\"  # env CARGO_APFS_COMPRESS_SMALL_FILE_COMPRESSION\n"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("small-file-cutoff = \"4096\"  # command line\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("target-dir = "), "{stdout}");
+}
+
+#[test]
+fn command_uses_check_dirs_from_manifest_metadata() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    let manifest = temp.path().join("Cargo.toml");
+    let mut content = fs::read_to_string(&manifest).unwrap();
+    content.push_str("\n[package.metadata.apfs-compress]\ncheck-dirs = [\"clippy\"]\n");
+    fs::write(&manifest, content).unwrap();
+    fs::create_dir_all(temp.path().join("target").join("clippy").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--list")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|line| line.ends_with("clippy/debug")),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn command_compresses_registry_index_under_package_cache_lock() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+    let home = temp.path().join("cargo-home");
+    let index = home
+        .join("registry")
+        .join("index")
+        .join("index.crates.io-1949cf8c6b5b557f");
+    fs::create_dir_all(index.join(".cache").join("se").join("rd")).unwrap();
+    fs::write(
+        index.join(".cache").join("se").join("rd").join("serde"),
+        "{}",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--registry-index")
+        .env("CARGO_HOME", &home)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|line| line
+            .split_once(": ")
+            .is_some_and(|(dir, _)| index.ends_with(dir))),
+        "{stdout}"
+    );
+    assert!(home.join(".package-cache").exists());
+    assert!(!index.join(".cargo-lock").exists());
+}
+
+#[test]
+fn cache_subcommand_compresses_chosen_components_outside_a_project() {
+    let temp = tempdir().unwrap();
+    let home = temp.path().join("cargo-home");
+    let checkout = home
+        .join("git")
+        .join("checkouts")
+        .join("serde-0123456789abcdef");
+    fs::create_dir_all(checkout.join("a1b2c3d").join("src")).unwrap();
+    fs::write(checkout.join("a1b2c3d").join("src").join("lib.rs"), "").unwrap();
+    let index = home
+        .join("registry")
+        .join("index")
+        .join("index.crates.io-1949cf8c6b5b557f");
+    fs::create_dir_all(&index).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["cache", "--git"])
+        .env("CARGO_HOME", &home)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("git checkouts: Compressed ") && line.contains("1 dir")),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("registry index:"), "{stdout}");
+    assert!(home.join(".package-cache").exists());
+}
+
+#[test]
+fn swiftpm_subcommand_compresses_build_dir_but_not_repository_mirrors() {
+    let temp = tempdir().unwrap();
+    let package = temp.path().join("Greeter");
+    fs::create_dir_all(package.join("Sources").join("Greeter")).unwrap();
+    fs::write(
+        package.join("Package.swift"),
+        "// swift-tools-version:5.9\n",
+    )
+    .unwrap();
+    let build = package.join(".build");
+    fs::create_dir_all(build.join("debug")).unwrap();
+    fs::write(build.join("debug").join("Greeter.swiftmodule"), "module").unwrap();
+    let mirror = build
+        .join("repositories")
+        .join("swift-argument-parser-1a2b3c4d");
+    fs::create_dir_all(&mirror).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["-v", "swiftpm"])
+        .current_dir(package.join("Sources").join("Greeter"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("swiftpm .build: Compressed ") && line.contains("1 dir")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("repositories: Skipped: Excluded directory"),
+        "{stdout}"
+    );
+    assert!(!build.join(".cargo-lock").exists());
+}
+
+#[test]
+fn swiftpm_subcommand_fails_outside_a_package() {
+    let temp = tempdir().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("swiftpm")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("could not find `Package.swift`"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn tool_caches_subcommand_compresses_the_named_caches_that_exist() {
+    let temp = tempdir().unwrap();
+    let caches = temp.path().join("Library").join("Caches");
+    fs::create_dir_all(caches.join("rust-analyzer")).unwrap();
+    fs::write(caches.join("rust-analyzer").join("sysroot.json"), "{}").unwrap();
+    fs::create_dir_all(caches.join(".wasm-pack").join("wasm-bindgen-0.2.100")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["-v", "tool-caches", "rust-analyzer", "cargo-nextest"])
+        .env("HOME", temp.path())
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("rust-analyzer: Compressed ") && line.contains("1 dir")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("skip cargo-nextest (no cache found)"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("wasm-pack"), "{stdout}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["tool-caches", "sccache"])
+        .env("HOME", temp.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("unknown tool cache"),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn generic_dir_is_compressed_without_cargo_or_locks() {
+    let temp = tempdir().unwrap();
+    let build = temp.path().join("cmake-build-debug");
+    fs::create_dir_all(build.join("CMakeFiles").join("app.dir")).unwrap();
+    fs::write(
+        build.join("CMakeFiles").join("app.dir").join("main.o"),
+        "obj",
+    )
+    .unwrap();
+    fs::create_dir_all(build.join("_deps").join("fmt-src")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["-v", "--generic"])
+        .arg(&build)
+        .args(["--generic-skip", "_deps"])
+        .env("CARGO", "/nonexistent/cargo")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("generic: Compressed ") && line.contains("1 dir")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("_deps: Skipped: Excluded directory"),
+        "{stdout}"
+    );
+    assert!(!build.join(".cargo-lock").exists());
+}
+
+#[test]
+fn command_timeout_exits_with_partial_status() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--timeout", "0s"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(124));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the run is partial"), "{stderr}");
+}
+
+#[test]
+fn command_min_savings_fails_when_too_little_is_saved() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    fs::create_dir_all(temp.path().join("target").join("debug")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--min-savings", "1KiB"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("less than --min-savings 1.0 KiB"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn command_follows_symlinked_target_dir() {
+    let temp = tempdir().unwrap();
+    let project = temp.path().join("project");
+    fs::create_dir_all(&project).unwrap();
+    write_workspace(&project);
+    let real = fs::canonicalize(temp.path()).unwrap().join("elsewhere");
+    fs::create_dir_all(real.join("debug")).unwrap();
+    fs::write(real.join("debug").join("artifact.bin"), b"artifact").unwrap();
+    std::os::unix::fs::symlink(&real, project.join("target")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--list", "--no-metadata"])
+        .current_dir(&project)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [real.join("debug").display().to_string()]
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--verbose", "--no-metadata"])
+        .current_dir(&project)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("-> {}", real.display())),
+        "{stdout}"
+    );
+    assert!(real.join("debug").join(".cargo-lock").exists());
+
+    fs::remove_dir_all(&real).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--list", "--no-metadata"])
+        .current_dir(&project)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("which does not exist"), "{stderr}");
+}
+
+#[test]
+fn command_reads_profile_dir_names_from_cargo_home_config() {
+    let temp = tempdir().unwrap();
+    let project = temp.path().join("project");
+    let home = temp.path().join("cargo-home");
+    fs::create_dir_all(&project).unwrap();
+    fs::create_dir_all(&home).unwrap();
+    write_workspace(&project);
+    fs::write(
+        home.join("config.toml"),
+        "[profile.dev]\ndir-name = \"machine-debug\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--list", "--no-metadata", "--profile", "dev"])
+        .env("CARGO_HOME", &home)
+        .current_dir(&project)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [project
+            .join("target")
+            .join("machine-debug")
+            .display()
+            .to_string()]
+    );
+}
+
+#[test]
+fn command_lists_work_dirs_with_provenance_as_json() {
+    let temp = tempdir().unwrap();
+    let project = fs::canonicalize(temp.path()).unwrap();
+    write_workspace(&project);
+    let target = project.join("target");
+    fs::create_dir_all(target.join("debug")).unwrap();
+    fs::create_dir_all(target.join("x86_64-apple-darwin").join("release")).unwrap();
+
+    let list = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .args(["--list", "--no-metadata", "--log-format", "json"])
+            .args(args)
+            .current_dir(&project)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>()
+    };
+    assert_eq!(
+        list(&[]),
+        [
+            serde_json::json!({
+                "path": target.join("debug"),
+                "profile": "debug",
+                "target": null,
+                "source": "discovered",
+            }),
+            serde_json::json!({
+                "path": target.join("x86_64-apple-darwin").join("release"),
+                "profile": "release",
+                "target": "x86_64-apple-darwin",
+                "source": "discovered",
+            }),
+        ]
+    );
+    assert_eq!(
+        list(&["--profile", "dev"]),
+        [serde_json::json!({
+            "path": target.join("debug"),
+            "profile": "dev",
+            "target": null,
+            "source": "requested",
+        })]
+    );
+}
+
+#[test]
+fn command_recurses_into_path_dependency_and_nested_workspaces() {
+    let temp = tempdir().unwrap();
+    let root = fs::canonicalize(temp.path()).unwrap();
+    let app = root.join("app");
+    let lib = root.join("lib");
+    let tools = app.join("tools");
+    for dir in [&app, &lib, &tools] {
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target").join("debug")).unwrap();
+        fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+    }
+    fs::write(
+        app.join("Cargo.toml"),
+        "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\
+         [dependencies]\nlib = { path = \"../lib\" }\n",
+    )
+    .unwrap();
+    fs::write(
+        lib.join("Cargo.toml"),
+        "[package]\nname = \"lib\"\nversion = \"0.1.0\"\nedition = \"2024\"\n",
+    )
+    .unwrap();
+    fs::write(
+        tools.join("Cargo.toml"),
+        "[package]\nname = \"tools\"\nversion = \"0.1.0\"\nedition = \"2024\"\n[workspace]\n",
+    )
+    .unwrap();
+
+    let list = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .arg("--list")
+            .args(args)
+            .current_dir(&app)
+            .env_remove("CARGO_TARGET_DIR")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let debug = |dir: &std::path::Path| dir.join("target").join("debug").display().to_string();
+    assert_eq!(list(&[]), [debug(&app)]);
+    assert_eq!(
+        list(&["--recurse-workspaces"]),
+        [debug(&app), debug(&tools), debug(&lib)]
+    );
+}
+
+#[test]
+fn explain_says_how_each_work_dir_was_found() {
+    let temp = tempdir().unwrap();
+    let project = fs::canonicalize(temp.path()).unwrap();
+    write_workspace(&project);
+    fs::create_dir_all(project.join(".cargo")).unwrap();
+    fs::write(
+        project.join(".cargo").join("config.toml"),
+        "[profile.dev]\ndir-name = \"machine-debug\"\n",
+    )
+    .unwrap();
+    let target = project.join("target");
+    for dir in [
+        "debug",
+        "tmp",
+        ".hidden",
+        "x86_64-apple-darwin/release/deps",
+    ] {
+        fs::create_dir_all(target.join(dir)).unwrap();
+    }
+
+    let explain = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .args(["--no-metadata"])
+            .args(args)
+            .arg("explain")
+            .current_dir(&project)
+            .env_remove("CARGO_TARGET_DIR")
+            .env_remove("CARGO_BUILD_TARGET_DIR")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let stdout = explain(&[]);
+    let config = project.join(".cargo").join("config.toml");
+    for line in [
+        format!("project {}", project.display()),
+        format!(
+            "  target dir {} (guessed from Cargo.toml, as with --no-metadata)  # default",
+            target.display()
+        ),
+        format!(
+            "  profile.dev.dir-name = \"machine-debug\"  # {}",
+            config.display()
+        ),
+        "  check dir rust-analyzer  # default".to_owned(),
+        "    .hidden: skipped: hidden".to_owned(),
+        "    debug: work dir".to_owned(),
+        "    tmp: skipped: cargo's scratch dir".to_owned(),
+        "    x86_64-apple-darwin: target triple: looked inside for profiles".to_owned(),
+        "    x86_64-apple-darwin/release: work dir".to_owned(),
+        format!(
+            "  {}  # discovered, profile debug",
+            target.join("debug").display()
+        ),
+        format!(
+            "  {}  # discovered, profile release, target x86_64-apple-darwin",
+            target.join("x86_64-apple-darwin/release").display()
+        ),
+    ] {
+        assert!(
+            stdout.lines().any(|found| found == line),
+            "{line}\n{stdout}"
+        );
+    }
+
+    let stdout = explain(&["--profile", "dev"]);
+    assert!(
+        stdout.contains("  building for the host  # default\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("  profile dev builds into machine-debug (its dir-name)\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn command_init_alias_adds_the_resolved_settings_to_cargo_config() {
+    let temp = tempdir().unwrap();
+    let home = temp.path().join("cargo-home");
+    let project = temp.path().join("project");
+    fs::create_dir_all(&home).unwrap();
+    fs::create_dir_all(project.join(".cargo")).unwrap();
+    write_workspace(&project);
+    let config = project.join(".cargo").join("config.toml");
+    fs::write(&config, "# ours\n[alias]\nxt = \"run -p xtask --\"\n").unwrap();
+
+    let init_alias = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+            .args(["--compression", "zlib", "--profile", "release"])
+            .arg("init-alias")
+            .args(args)
+            .env("CARGO_HOME", &home)
+            .env_remove("CARGO_APFS_COMPRESS_COMPRESSION")
+            .current_dir(project.join("src"))
+            .output()
+            .unwrap()
+    };
+    let output = init_alias(&[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        fs::read_to_string(&config).unwrap(),
+        "# ours\n[alias]\ncompress = [\"apfs-compress\", \"--compression\", \"zlib\", \"--profile\", \
+         \"release\"]\nxt = \"run -p xtask --\"\n"
+    );
+
+    let output = init_alias(&[]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("alias `compress` is already defined"),
+        "{output:?}"
+    );
+
+    let output = init_alias(&["--name", "b-and-compress", "--print"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[alias]\nb-and-compress = [\"apfs-compress\", \"--compression\", \"zlib\", \"--profile\", \
+         \"release\"]\n"
+    );
+}
+
+#[test]
+fn command_null_compressor_times_each_stage() {
+    let temp = tempdir().unwrap();
+    write_workspace(temp.path());
+    let debug_dir = temp.path().join("target").join("debug");
+    fs::create_dir_all(&debug_dir).unwrap();
+    fs::write(debug_dir.join("artifact.bin"), vec![7u8; 64 * 1024]).unwrap();
+    fs::write(debug_dir.join(".cargo-lock"), b"").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .args(["--compressor", "null", "--skip-unchanged-dirs"])
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("timings: found 1 work dir(s) in ")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.lines().any(|line| {
+            line.starts_with("timings ")
+                && line.contains("target/debug: lock ")
+                && line.contains(", walk ")
+                && line.contains(", compress ")
+                && line.contains(", total ")
+        }),
+        "{stdout}"
+    );
+    assert!(!debug_dir.join(".apfs-compress-state").exists());
+}
+
+#[test]
+fn command_jobs_file_runs_each_job_and_sums_them_up() {
+    let temp = tempdir().unwrap();
+    let project = temp.path().join("project");
+    fs::create_dir_all(&project).unwrap();
+    write_workspace(&project);
+    let debug_dir = project.join("target").join("debug");
+    fs::create_dir_all(&debug_dir).unwrap();
+    fs::write(debug_dir.join("artifact.bin"), b"artifact").unwrap();
+    let generic = temp.path().join("cmake-build");
+    fs::create_dir_all(generic.join("CMakeFiles")).unwrap();
+    fs::write(generic.join("app"), b"binary").unwrap();
+    fs::write(generic.join("CMakeFiles").join("app.o"), b"object").unwrap();
+    let jobs = temp.path().join("jobs.toml");
+    fs::write(
+        &jobs,
+        "[[job]]\nworkspace = \"project\"\nprofiles = [\"debug\"]\ncompression = \"zlib\"\n\n\
+         [[job]]\npath = \"cmake-build\"\nexclude = [\"CMakeFiles\"]\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--jobs-file")
+        .arg(&jobs)
+        .arg("-v")
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let job_lines: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.starts_with("job "))
+        .collect();
+    assert_eq!(job_lines.len(), 2, "{stdout}");
+    assert!(job_lines[0].starts_with("job 1/2: ") && job_lines[0].ends_with("project"));
+    assert!(job_lines[1].starts_with("job 2/2: ") && job_lines[1].ends_with("cmake-build"));
+    assert!(stdout.contains("Excluded by dir policy"), "{stdout}");
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("Ran 2 jobs from ") && line.contains(", 0 failed: ")),
+        "{stdout}"
+    );
+
+    fs::write(
+        &jobs,
+        "[[job]]\npath = \"cmake-build\"\ncompression = \"zstd\"\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-apfs-compress"))
+        .arg("--jobs-file")
+        .arg(&jobs)
+        .current_dir(temp.path())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("invalid value \"zstd\" for `job[0].compression`"),
+        "{output:?}"
+    );
+}